@@ -0,0 +1,428 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Delimiter, Ident, Literal, Spacing, Span, TokenTree};
+use quote::quote;
+use rust_runes_grl::ast::Expression;
+use rust_runes_grl::parser::GrlParser;
+use rust_runes_grl::rule::{Accumulate, Binding, Cron, Pattern, Rule, Schedule};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, LitStr};
+
+/// Implement `rust_runes::IntoFact` for a struct with named fields, turning
+/// it into a `FactValue::Object` with one entry per field and giving facts
+/// made from it the struct's name as their type.
+///
+/// Fields can be renamed with `#[fact(rename = "maxSpeed")]`.
+#[proc_macro_derive(IntoFact, attributes(fact))]
+pub fn derive_into_fact(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "IntoFact can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "IntoFact can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut inserts = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let mut key = ident.to_string();
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("fact"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    key = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported fact attribute"))
+                }
+            })?;
+        }
+        inserts.push(quote! {
+            fields.insert(
+                #key.to_string(),
+                ::rust_runes::IntoFact::into_fact_value(self.#ident),
+            );
+        });
+    }
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::rust_runes::IntoFact));
+    }
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rust_runes::IntoFact for #name #ty_generics #where_clause {
+            fn into_fact_value(self) -> ::rust_runes::FactValue {
+                let mut fields = ::std::collections::HashMap::new();
+                #(#inserts)*
+                ::rust_runes::FactValue::Object(fields)
+            }
+
+            fn fact_type() -> ::std::option::Option<&'static str> {
+                ::std::option::Option::Some(#type_name)
+            }
+        }
+    })
+}
+
+/// Parse a GRL rule at compile time with the same parser as
+/// `GrlParser::parse_rule`, expanding to the `rust_runes::Rule` built field by
+/// field, so syntax errors fail the build and nothing is parsed at runtime:
+///
+/// ```ignore
+/// let rule = grl! {
+///     rule Discount "10% off" salience 5 {
+///         when order.total > 100
+///         then order.discount = 10;
+///     }
+/// };
+/// ```
+///
+/// Rules containing `script """..."""` blocks, which are not Rust tokens, can
+/// be given as a string literal instead: `grl!(r#"rule ..."#)`.
+#[proc_macro]
+pub fn grl(input: TokenStream) -> TokenStream {
+    let input = proc_macro2::TokenStream::from(input);
+    let (text, span) = match syn::parse2::<LitStr>(input.clone()) {
+        Ok(literal) => (literal.value(), literal.span()),
+        Err(_) => (grl_text(input), Span::call_site()),
+    };
+    match GrlParser::new().parse_rule(&text) {
+        Ok(rule) => rule_tokens(&rule),
+        Err(error) => {
+            syn::Error::new(span, format!("invalid GRL rule: {}", error)).into_compile_error()
+        }
+    }
+    .into()
+}
+
+/// The GRL source for a token stream, spaced so the parser accepts it:
+/// `order.total` and `billing::Invoice` stay joined, as do operators such as
+/// `>=` and `&&`
+fn grl_text(tokens: proc_macro2::TokenStream) -> String {
+    let mut text = String::new();
+    // Whether the next token joins the previous one without a space
+    let mut glue = true;
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == '.' => {
+                text.push('.');
+                glue = true;
+                continue;
+            }
+            TokenTree::Punct(punct)
+                if punct.as_char() == ':'
+                    && punct.spacing() == Spacing::Joint
+                    && matches!(tokens.peek(), Some(TokenTree::Punct(next)) if next.as_char() == ':') =>
+            {
+                tokens.next();
+                text.push_str("::");
+                glue = true;
+                continue;
+            }
+            _ => {}
+        }
+        if !glue {
+            text.push(' ');
+        }
+        glue = false;
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{ ", " }"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                text.push_str(open);
+                text.push_str(&grl_text(group.stream()));
+                text.push_str(close);
+            }
+            TokenTree::Punct(punct) => {
+                text.push(punct.as_char());
+                glue = punct.spacing() == Spacing::Joint;
+            }
+            TokenTree::Ident(ident) => text.push_str(&ident.to_string()),
+            TokenTree::Literal(literal) => text.push_str(&literal.to_string()),
+        }
+    }
+    text
+}
+
+/// Tokens building `rule` field by field, so the expansion parses nothing at
+/// runtime
+fn rule_tokens(rule: &Rule) -> proc_macro2::TokenStream {
+    // Destructured so a new field fails to compile until it is quoted here
+    let Rule {
+        name,
+        description,
+        salience,
+        when_condition,
+        then_actions,
+        schedule,
+        patterns,
+        accumulates,
+        bindings,
+        tags,
+        package,
+        metadata,
+        effective_from,
+        expires_at,
+    } = rule;
+    let name = string(name);
+    let description = option(description.as_deref().map(string));
+    let when_condition = expression(when_condition);
+    let then_actions = expressions(then_actions);
+    let schedule = option(schedule.as_ref().map(|schedule| match schedule {
+        Schedule::Interval(interval) => {
+            let (secs, nanos) = (interval.as_secs(), interval.subsec_nanos());
+            quote!(::rust_runes::rule::Schedule::Interval(
+                ::std::time::Duration::new(#secs, #nanos)
+            ))
+        }
+        Schedule::Cron(Cron {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            either_day,
+        }) => quote!(::rust_runes::rule::Schedule::Cron(::rust_runes::rule::Cron {
+            minutes: #minutes,
+            hours: #hours,
+            days: #days,
+            months: #months,
+            weekdays: #weekdays,
+            either_day: #either_day,
+        })),
+    }));
+    let patterns = patterns.iter().map(pattern);
+    let accumulates = accumulates.iter().map(|accumulate| {
+        let Accumulate {
+            name,
+            pattern: matched,
+            function,
+            value,
+        } = accumulate;
+        let (name, matched) = (string(name), pattern(matched));
+        let (function, value) = (string(function), expression(value));
+        quote!(::rust_runes::rule::Accumulate {
+            name: #name,
+            pattern: #matched,
+            function: #function,
+            value: #value,
+        })
+    });
+    let bindings = bindings.iter().map(|Binding { name, value }| {
+        let (name, value) = (string(name), expression(value));
+        quote!(::rust_runes::rule::Binding { name: #name, value: #value })
+    });
+    let tags = tags.iter().map(|tag| string(tag));
+    let package = option(package.as_deref().map(string));
+    let metadata = metadata.iter().map(|(key, value)| {
+        let (key, value) = (string(key), string(value));
+        quote!((#key, #value))
+    });
+    let effective_from = option(effective_from.map(|millis| quote!(#millis)));
+    let expires_at = option(expires_at.map(|millis| quote!(#millis)));
+    quote! {
+        ::rust_runes::rule::Rule {
+            name: #name,
+            description: #description,
+            salience: #salience,
+            when_condition: #when_condition,
+            then_actions: #then_actions,
+            schedule: #schedule,
+            patterns: ::std::vec![#(#patterns),*],
+            accumulates: ::std::vec![#(#accumulates),*],
+            bindings: ::std::vec![#(#bindings),*],
+            tags: ::std::collections::BTreeSet::from([#(#tags),*]),
+            package: #package,
+            metadata: ::std::collections::BTreeMap::from([#(#metadata),*]),
+            effective_from: #effective_from,
+            expires_at: #expires_at,
+        }
+    }
+}
+
+fn pattern(pattern: &Pattern) -> proc_macro2::TokenStream {
+    let Pattern {
+        binding,
+        fact_type,
+        constraint,
+    } = pattern;
+    let (binding, fact_type) = (string(binding), string(fact_type));
+    let constraint = option(constraint.as_ref().map(expression));
+    quote!(::rust_runes::rule::Pattern {
+        binding: #binding,
+        fact_type: #fact_type,
+        constraint: #constraint,
+    })
+}
+
+fn expression(expr: &Expression) -> proc_macro2::TokenStream {
+    let boxed = |expr: &Expression| {
+        let expr = expression(expr);
+        quote!(::std::boxed::Box::new(#expr))
+    };
+    // `Variant(Box<Expression>, Box<Expression>)`
+    let binary = |variant: &str, left: &Expression, right: &Expression| {
+        let variant = Ident::new(variant, Span::call_site());
+        let (left, right) = (boxed(left), boxed(right));
+        quote!(::rust_runes::ast::Expression::#variant(#left, #right))
+    };
+    let tokens = match expr {
+        Expression::String(s) => {
+            let s = string(s);
+            quote!(String(#s))
+        }
+        Expression::Number(n) => {
+            let n = float(*n);
+            quote!(Number(#n))
+        }
+        Expression::Boolean(b) => quote!(Boolean(#b)),
+        Expression::Duration(ms) => quote!(Duration(#ms)),
+        Expression::Null => quote!(Null),
+        Expression::Variable(name) => {
+            let name = string(name);
+            quote!(Variable(#name))
+        }
+        Expression::FieldAccess(object, field) => {
+            let (object, field) = (boxed(object), string(field));
+            quote!(FieldAccess(#object, #field))
+        }
+        Expression::OptionalFieldAccess(object, field) => {
+            let (object, field) = (boxed(object), string(field));
+            quote!(OptionalFieldAccess(#object, #field))
+        }
+        Expression::Index(l, r) => return binary("Index", l, r),
+        Expression::Array(items) => {
+            let items = expressions(items);
+            quote!(Array(#items))
+        }
+        Expression::Add(l, r) => return binary("Add", l, r),
+        Expression::Subtract(l, r) => return binary("Subtract", l, r),
+        Expression::Multiply(l, r) => return binary("Multiply", l, r),
+        Expression::Divide(l, r) => return binary("Divide", l, r),
+        Expression::Modulo(l, r) => return binary("Modulo", l, r),
+        Expression::Equal(l, r) => return binary("Equal", l, r),
+        Expression::NotEqual(l, r) => return binary("NotEqual", l, r),
+        Expression::LessThan(l, r) => return binary("LessThan", l, r),
+        Expression::LessEqual(l, r) => return binary("LessEqual", l, r),
+        Expression::GreaterThan(l, r) => return binary("GreaterThan", l, r),
+        Expression::GreaterEqual(l, r) => return binary("GreaterEqual", l, r),
+        Expression::And(l, r) => return binary("And", l, r),
+        Expression::Or(l, r) => return binary("Or", l, r),
+        Expression::Not(inner) => {
+            let inner = boxed(inner);
+            quote!(Not(#inner))
+        }
+        Expression::Negate(inner) => {
+            let inner = boxed(inner);
+            quote!(Negate(#inner))
+        }
+        Expression::Coalesce(l, r) => return binary("Coalesce", l, r),
+        Expression::In(l, r) => return binary("In", l, r),
+        Expression::Matches(l, r) => return binary("Matches", l, r),
+        Expression::EventCount(event_type, window_ms) => {
+            let event_type = string(event_type);
+            quote!(EventCount(#event_type, #window_ms))
+        }
+        Expression::FunctionCall(name, args) => {
+            let (name, args) = (string(name), expressions(args));
+            quote!(FunctionCall(#name, #args))
+        }
+        Expression::MethodCall(receiver, name, args) => {
+            let (receiver, name, args) = (boxed(receiver), string(name), expressions(args));
+            quote!(MethodCall(#receiver, #name, #args))
+        }
+        Expression::Assignment(name, value) => {
+            let (name, value) = (string(name), boxed(value));
+            quote!(Assignment(#name, #value))
+        }
+        Expression::FieldAssignment(name, field, value) => {
+            let (name, field, value) = (string(name), string(field), boxed(value));
+            quote!(FieldAssignment(#name, #field, #value))
+        }
+        Expression::InsertLogical(name, value) => {
+            let (name, value) = (string(name), boxed(value));
+            quote!(InsertLogical(#name, #value))
+        }
+        Expression::Update(name) => {
+            let name = string(name);
+            quote!(Update(#name))
+        }
+        Expression::Let(name, value) => {
+            let (name, value) = (string(name), boxed(value));
+            quote!(Let(#name, #value))
+        }
+        Expression::LetIn(name, value, body) => {
+            let (name, value, body) = (string(name), boxed(value), boxed(body));
+            quote!(LetIn(#name, #value, #body))
+        }
+        Expression::Script(source) => {
+            let source = string(source);
+            quote!(Script(#source))
+        }
+        Expression::If(condition, then_actions, else_actions) => {
+            let condition = boxed(condition);
+            let (then_actions, else_actions) =
+                (expressions(then_actions), expressions(else_actions));
+            quote!(If(#condition, #then_actions, #else_actions))
+        }
+        Expression::ForEach(item, collection, actions) => {
+            let (item, collection, actions) =
+                (string(item), boxed(collection), expressions(actions));
+            quote!(ForEach(#item, #collection, #actions))
+        }
+    };
+    quote!(::rust_runes::ast::Expression::#tokens)
+}
+
+fn expressions(exprs: &[Expression]) -> proc_macro2::TokenStream {
+    let exprs = exprs.iter().map(expression);
+    quote!(::std::vec![#(#exprs),*])
+}
+
+fn string(s: &str) -> proc_macro2::TokenStream {
+    quote!(::std::string::String::from(#s))
+}
+
+fn option(value: Option<proc_macro2::TokenStream>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote!(::std::option::Option::Some(#value)),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn float(n: f64) -> proc_macro2::TokenStream {
+    if n.is_nan() {
+        quote!(::std::f64::NAN)
+    } else if n.is_infinite() && n > 0.0 {
+        quote!(::std::f64::INFINITY)
+    } else if n.is_infinite() {
+        quote!(::std::f64::NEG_INFINITY)
+    } else if n.is_sign_negative() {
+        let n = Literal::f64_suffixed(-n);
+        quote!(-#n)
+    } else {
+        let n = Literal::f64_suffixed(n);
+        quote!(#n)
+    }
+}
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Abstract Syntax Tree nodes for rule expressions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expression {
+    // Literals
+    String(String),
+    Number(f64),
+    Boolean(bool),
+    /// A duration literal such as `30d` or `2h`, in milliseconds
+    Duration(u64),
+    /// `null`, evaluating to `FactValue::Null`
+    Null,
+
+    // Variables and field access
+    Variable(String),
+    FieldAccess(Box<Expression>, String),
+    // `object?.field`: null if the object is null or missing, or lacks the field
+    OptionalFieldAccess(Box<Expression>, String),
+    /// `items[0]`; negative indices count from the end
+    Index(Box<Expression>, Box<Expression>),
+    /// `[1, 2, 3]`, evaluating to a `FactValue::Array`
+    Array(Vec<Expression>),
+
+    // Binary operations
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+    Modulo(Box<Expression>, Box<Expression>),
+
+    // Comparison operations
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    LessThan(Box<Expression>, Box<Expression>),
+    LessEqual(Box<Expression>, Box<Expression>),
+    GreaterThan(Box<Expression>, Box<Expression>),
+    GreaterEqual(Box<Expression>, Box<Expression>),
+
+    // Logical operations
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    /// `-x`, numeric negation
+    Negate(Box<Expression>),
+    // `value ?? default`: the default if the value is null or missing
+    Coalesce(Box<Expression>, Box<Expression>),
+    /// `needle in haystack`: whether an array has an item equal to the needle,
+    /// or a string contains it
+    In(Box<Expression>, Box<Expression>),
+    /// `value matches "pattern"`: whether a regular expression matches
+    /// anywhere in a string. Literal patterns are compiled when the rule is added.
+    Matches(Box<Expression>, Box<Expression>),
+
+    // Temporal operations
+    /// Number of events of the given type that occurred within the last `window_ms` milliseconds
+    EventCount(String, u64),
+
+    // Call to a function registered with the engine
+    FunctionCall(String, Vec<Expression>),
+    /// `receiver.name(args)`, calling a builtin for the receiver's type such as
+    /// `cart.items.len()` or `name.toUpper()`
+    MethodCall(Box<Expression>, String, Vec<Expression>),
+
+    // Assignment
+    Assignment(String, Box<Expression>),
+    /// `fact.field = value`, where the field may be a dot-separated path
+    FieldAssignment(String, String, Box<Expression>),
+    // Insert a fact that is retracted again once the inserting rule no longer matches
+    InsertLogical(String, Box<Expression>),
+    // Declare that a fact has changed so rules depending on it are re-evaluated
+    Update(String),
+    // A value local to the activation's actions, not stored in working memory
+    Let(String, Box<Expression>),
+    /// `let name = value in body`: the body, evaluated with `name` bound to
+    /// the value so it is computed once however often the body uses it
+    LetIn(String, Box<Expression>, Box<Expression>),
+    // Source run by the engine's script engine
+    Script(String),
+    // `if (condition) { actions } else { actions }`. Used as a value, each
+    // branch holds at most one expression, and an empty branch gives null
+    If(Box<Expression>, Vec<Expression>, Vec<Expression>),
+    // `foreach item in collection { actions }`, modifying the array in place
+    ForEach(String, Box<Expression>, Vec<Expression>),
+}
+
+impl Expression {
+    /// Number of nodes on the longest path from this node to a leaf
+    pub fn depth(&self) -> usize {
+        self.children()
+            .into_iter()
+            .map(Expression::depth)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    /// The expressions directly inside this one, including the actions of
+    /// `if` and `foreach`
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::String(_)
+            | Expression::Number(_)
+            | Expression::Boolean(_)
+            | Expression::Duration(_)
+            | Expression::Null
+            | Expression::Variable(_)
+            | Expression::EventCount(_, _)
+            | Expression::Update(_)
+            | Expression::Script(_) => Vec::new(),
+            Expression::FieldAccess(inner, _)
+            | Expression::OptionalFieldAccess(inner, _)
+            | Expression::Not(inner)
+            | Expression::Negate(inner)
+            | Expression::Assignment(_, inner)
+            | Expression::FieldAssignment(_, _, inner)
+            | Expression::InsertLogical(_, inner)
+            | Expression::Let(_, inner) => vec![inner],
+            Expression::Add(l, r)
+            | Expression::Subtract(l, r)
+            | Expression::Multiply(l, r)
+            | Expression::Divide(l, r)
+            | Expression::Modulo(l, r)
+            | Expression::Equal(l, r)
+            | Expression::NotEqual(l, r)
+            | Expression::LessThan(l, r)
+            | Expression::LessEqual(l, r)
+            | Expression::GreaterThan(l, r)
+            | Expression::GreaterEqual(l, r)
+            | Expression::And(l, r)
+            | Expression::Or(l, r)
+            | Expression::Coalesce(l, r)
+            | Expression::In(l, r)
+            | Expression::Matches(l, r)
+            | Expression::Index(l, r)
+            | Expression::LetIn(_, l, r) => vec![l, r],
+            Expression::If(condition, then_actions, else_actions) => {
+                std::iter::once(condition.as_ref())
+                    .chain(then_actions)
+                    .chain(else_actions)
+                    .collect()
+            }
+            Expression::ForEach(_, collection, actions) => std::iter::once(collection.as_ref())
+                .chain(actions)
+                .collect(),
+            Expression::FunctionCall(_, args) | Expression::Array(args) => args.iter().collect(),
+            Expression::MethodCall(receiver, _, args) => {
+                std::iter::once(receiver.as_ref()).chain(args).collect()
+            }
+        }
+    }
+}
+
+impl Expression {
+    /// The expression in GRL syntax, as rendered by `Display`
+    pub fn to_grl_string(&self) -> String {
+        self.to_string()
+    }
+
+    /// The operator of a binary expression and its operands
+    fn binary(&self) -> Option<(&'static str, &Expression, &Expression)> {
+        let (op, l, r) = match self {
+            Expression::Add(l, r) => ("+", l, r),
+            Expression::Subtract(l, r) => ("-", l, r),
+            Expression::Multiply(l, r) => ("*", l, r),
+            Expression::Divide(l, r) => ("/", l, r),
+            Expression::Modulo(l, r) => ("%", l, r),
+            Expression::Equal(l, r) => ("==", l, r),
+            Expression::NotEqual(l, r) => ("!=", l, r),
+            Expression::LessThan(l, r) => ("<", l, r),
+            Expression::LessEqual(l, r) => ("<=", l, r),
+            Expression::GreaterThan(l, r) => (">", l, r),
+            Expression::GreaterEqual(l, r) => (">=", l, r),
+            Expression::And(l, r) => ("&&", l, r),
+            Expression::Or(l, r) => ("||", l, r),
+            Expression::Coalesce(l, r) => ("??", l, r),
+            Expression::In(l, r) => ("in", l, r),
+            Expression::Matches(l, r) => ("matches", l, r),
+            _ => return None,
+        };
+        Some((op, l, r))
+    }
+}
+
+/// How tightly binary operators bind, following `GrlParser`: `||` loosest,
+/// then `&&`, comparisons, `??`, `+`/`-` and `*`/`/`/`%`
+fn precedence(op: &str) -> u8 {
+    match op {
+        "||" => 1,
+        "&&" => 2,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" | "in" | "matches" => 3,
+        "??" => 4,
+        "+" | "-" => 5,
+        _ => 6,
+    }
+}
+
+/// Whether `child`, an operand of `parent` on the given side, needs
+/// parentheses to keep the tree's shape
+fn needs_parens(parent: &str, child: &Expression, right: bool) -> bool {
+    let Some((op, _, _)) = child.binary() else {
+        // A let's body would otherwise take in the rest of the parent
+        return matches!(child, Expression::LetIn(..));
+    };
+    let (parent_level, level) = (precedence(parent), precedence(op));
+    match level.cmp(&parent_level) {
+        std::cmp::Ordering::Less => true,
+        std::cmp::Ordering::Greater => false,
+        std::cmp::Ordering::Equal => match level {
+            // The parser splits `??` at its first occurrence
+            4 => !right,
+            // Comparisons do not chain
+            3 => true,
+            // Logic and arithmetic associate to the left
+            _ => right,
+        },
+    }
+}
+
+/// An operand of a postfix or unary operator, parenthesized if it is a binary
+/// expression or a let
+struct Operand<'a>(&'a Expression);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.binary().is_some() || matches!(self.0, Expression::LetIn(..)) {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+fn write_duration(f: &mut fmt::Formatter<'_>, ms: u64) -> fmt::Result {
+    let units = [
+        ("d", 86_400_000),
+        ("h", 3_600_000),
+        ("m", 60_000),
+        ("s", 1_000),
+    ];
+    match units
+        .iter()
+        .find(|(_, unit_ms)| ms > 0 && ms.is_multiple_of(*unit_ms))
+    {
+        Some((unit, unit_ms)) => write!(f, "{}{}", ms / unit_ms, unit),
+        None => write!(f, "{}ms", ms),
+    }
+}
+
+/// A string literal, escaped the way `GrlParser` reads it back
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_block(f: &mut fmt::Formatter<'_>, actions: &[Expression]) -> fmt::Result {
+    write!(f, "{{")?;
+    for action in actions {
+        write!(f, " {};", action)?;
+    }
+    write!(f, " }}")
+}
+
+/// Renders the expression in GRL syntax, with parentheses only where the
+/// operators' precedence would otherwise change its meaning
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((op, l, r)) = self.binary() {
+            let side = |expr: &Expression, right: bool| {
+                if needs_parens(op, expr, right) {
+                    format!("({})", expr)
+                } else {
+                    expr.to_string()
+                }
+            };
+            return write!(f, "{} {} {}", side(l, false), op, side(r, true));
+        }
+        match self {
+            Expression::String(s) => write_string(f, s),
+            // `1e999` overflows back to infinity when parsed. NaN has no
+            // literal, and rules holding one are rejected when added.
+            Expression::Number(n) if n.is_infinite() => {
+                write!(f, "{}1e999", if *n < 0.0 { "-" } else { "" })
+            }
+            Expression::Number(n) => write!(f, "{}", n),
+            Expression::Boolean(b) => write!(f, "{}", b),
+            Expression::Duration(ms) => write_duration(f, *ms),
+            Expression::Null => write!(f, "null"),
+            Expression::Variable(name) => write!(f, "{}", name),
+            Expression::FieldAccess(object, field) => write!(f, "{}.{}", Operand(object), field),
+            Expression::OptionalFieldAccess(object, field) => {
+                write!(f, "{}?.{}", Operand(object), field)
+            }
+            Expression::Index(object, index) => write!(f, "{}[{}]", Operand(object), index),
+            Expression::Not(inner) => write!(f, "!{}", Operand(inner)),
+            Expression::Negate(inner) => write!(f, "-{}", Operand(inner)),
+            Expression::EventCount(event_type, window_ms) => {
+                write!(f, "count of {} events in last ", event_type)?;
+                write_duration(f, *window_ms)
+            }
+            Expression::FunctionCall(name, args) => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expression::MethodCall(receiver, name, args) => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{}.{}({})", Operand(receiver), name, args.join(", "))
+            }
+            Expression::Array(items) => {
+                let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Expression::Assignment(name, value) => write!(f, "{} = {}", name, value),
+            Expression::FieldAssignment(name, field, value) => {
+                write!(f, "{}.{} = {}", name, field, value)
+            }
+            Expression::InsertLogical(name, value) => {
+                write!(f, "insertLogical({}, {})", name, value)
+            }
+            Expression::Update(name) => write!(f, "update({})", name),
+            Expression::Let(name, value) => write!(f, "let {} = {}", name, value),
+            Expression::LetIn(name, value, body) => {
+                write!(f, "let {} = {} in {}", name, value, body)
+            }
+            Expression::Script(source) => write!(f, "script \"\"\"{}\"\"\"", source),
+            Expression::If(condition, then_actions, else_actions) => {
+                write!(f, "if ({}) ", condition)?;
+                write_block(f, then_actions)?;
+                if !else_actions.is_empty() {
+                    write!(f, " else ")?;
+                    write_block(f, else_actions)?;
+                }
+                Ok(())
+            }
+            Expression::ForEach(item, collection, actions) => {
+                write!(f, "foreach {} in {} ", item, collection)?;
+                write_block(f, actions)
+            }
+            _ => unreachable!("binary expressions are written above"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleAst {
+    /// The `migration::FORMAT_VERSION` the AST was written in, so a stored
+    /// AST can be upgraded with `Migrations` once the format moves on
+    #[serde(default = "first_format_version")]
+    pub format_version: u32,
+    pub name: String,
+    pub description: Option<String>,
+    pub salience: i32,
+    pub when_condition: Expression,
+    pub then_actions: Vec<Expression>,
+}
+
+/// ASTs serialized before they were tagged are in the first format
+fn first_format_version() -> u32 {
+    1
+}
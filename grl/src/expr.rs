@@ -0,0 +1,217 @@
+use crate::ast::Expression;
+use std::ops;
+
+/// A variable or fact, e.g. `var("x").gt(5.0)`
+pub fn var(name: impl Into<String>) -> Expression {
+    Expression::Variable(name.into())
+}
+
+/// A field of a fact: `field("Car", "Speed")` is `Car.Speed`
+pub fn field(fact: impl Into<String>, field: impl Into<String>) -> Expression {
+    var(fact).field(field)
+}
+
+/// A call to a built-in or registered function
+pub fn call(name: impl Into<String>, args: impl IntoIterator<Item = Expression>) -> Expression {
+    Expression::FunctionCall(name.into(), args.into_iter().collect())
+}
+
+/// An array literal: `array([1.into(), var("x")])` is `[1, x]`
+pub fn array(items: impl IntoIterator<Item = Expression>) -> Expression {
+    Expression::Array(items.into_iter().collect())
+}
+
+/// `if (condition) { then } else { otherwise }`, giving `then` or `otherwise`
+/// when evaluated as a value and running it when used as an action
+pub fn if_else(
+    condition: impl Into<Expression>,
+    then: impl Into<Expression>,
+    otherwise: impl Into<Expression>,
+) -> Expression {
+    Expression::If(
+        Box::new(condition.into()),
+        vec![then.into()],
+        vec![otherwise.into()],
+    )
+}
+
+/// `let name = value in body`, e.g.
+/// `let_in("t", field("o", "qty") * field("o", "price"), var("t").gt(100.0))`
+pub fn let_in(
+    name: impl Into<String>,
+    value: impl Into<Expression>,
+    body: impl Into<Expression>,
+) -> Expression {
+    Expression::LetIn(name.into(), Box::new(value.into()), Box::new(body.into()))
+}
+
+fn binary(
+    op: fn(Box<Expression>, Box<Expression>) -> Expression,
+    left: Expression,
+    right: impl Into<Expression>,
+) -> Expression {
+    op(Box::new(left), Box::new(right.into()))
+}
+
+impl Expression {
+    pub fn field(self, field: impl Into<String>) -> Expression {
+        Expression::FieldAccess(Box::new(self), field.into())
+    }
+
+    pub fn index(self, index: impl Into<Expression>) -> Expression {
+        binary(Expression::Index, self, index)
+    }
+
+    /// `self.name(args)`, e.g. `field("cart", "items").method("len", [])`
+    pub fn method(
+        self,
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = Expression>,
+    ) -> Expression {
+        Expression::MethodCall(Box::new(self), name.into(), args.into_iter().collect())
+    }
+
+    pub fn equals(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::Equal, self, other)
+    }
+
+    pub fn not_equals(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::NotEqual, self, other)
+    }
+
+    pub fn lt(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::LessThan, self, other)
+    }
+
+    pub fn le(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::LessEqual, self, other)
+    }
+
+    pub fn gt(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::GreaterThan, self, other)
+    }
+
+    pub fn ge(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::GreaterEqual, self, other)
+    }
+
+    /// `self in haystack`, for an array or string haystack
+    pub fn is_in(self, haystack: impl Into<Expression>) -> Expression {
+        binary(Expression::In, self, haystack)
+    }
+
+    /// `self matches pattern`, a regular expression searched for in a string
+    pub fn matches(self, pattern: impl Into<Expression>) -> Expression {
+        binary(Expression::Matches, self, pattern)
+    }
+
+    pub fn and(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::And, self, other)
+    }
+
+    pub fn or(self, other: impl Into<Expression>) -> Expression {
+        binary(Expression::Or, self, other)
+    }
+
+    /// `self ?? default`
+    pub fn or_else(self, default: impl Into<Expression>) -> Expression {
+        binary(Expression::Coalesce, self, default)
+    }
+
+    /// An action assigning `value` to this variable or field
+    ///
+    /// # Panics
+    ///
+    /// If `self` is neither a variable nor a (nested) field of one.
+    pub fn assign(self, value: impl Into<Expression>) -> Expression {
+        let value = Box::new(value.into());
+        match self {
+            Expression::Variable(name) => Expression::Assignment(name, value),
+            Expression::FieldAccess(..) => {
+                let (fact, path) =
+                    field_path(&self).unwrap_or_else(|| panic!("cannot assign to {}", self));
+                Expression::FieldAssignment(fact, path, value)
+            }
+            other => panic!("cannot assign to {}", other),
+        }
+    }
+}
+
+/// The fact and dot-separated path of a field access on a variable
+fn field_path(expr: &Expression) -> Option<(String, String)> {
+    match expr {
+        Expression::FieldAccess(object, field) => match object.as_ref() {
+            Expression::Variable(fact) => Some((fact.clone(), field.clone())),
+            object => {
+                let (fact, parent) = field_path(object)?;
+                Some((fact, format!("{}.{}", parent, field)))
+            }
+        },
+        _ => None,
+    }
+}
+
+impl From<f64> for Expression {
+    fn from(value: f64) -> Self {
+        Expression::Number(value)
+    }
+}
+
+impl From<i32> for Expression {
+    fn from(value: i32) -> Self {
+        Expression::Number(value.into())
+    }
+}
+
+impl From<bool> for Expression {
+    fn from(value: bool) -> Self {
+        Expression::Boolean(value)
+    }
+}
+
+/// A string literal; use `var` for a variable
+impl From<&str> for Expression {
+    fn from(value: &str) -> Self {
+        Expression::String(value.to_string())
+    }
+}
+
+impl From<String> for Expression {
+    fn from(value: String) -> Self {
+        Expression::String(value)
+    }
+}
+
+macro_rules! operator {
+    ($trait:ident, $method:ident, $variant:ident) => {
+        impl<T: Into<Expression>> ops::$trait<T> for Expression {
+            type Output = Expression;
+
+            fn $method(self, other: T) -> Expression {
+                binary(Expression::$variant, self, other)
+            }
+        }
+    };
+}
+
+operator!(Add, add, Add);
+operator!(Sub, sub, Subtract);
+operator!(Mul, mul, Multiply);
+operator!(Div, div, Divide);
+operator!(Rem, rem, Modulo);
+
+impl ops::Neg for Expression {
+    type Output = Expression;
+
+    fn neg(self) -> Expression {
+        Expression::Negate(Box::new(self))
+    }
+}
+
+impl ops::Not for Expression {
+    type Output = Expression;
+
+    fn not(self) -> Expression {
+        Expression::Not(Box::new(self))
+    }
+}
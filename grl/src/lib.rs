@@ -0,0 +1,6 @@
+// The rule AST and GRL parser, shared by rust-runes and the `grl!` macro in
+// rust-runes-derive, which builds rules from it at compile time
+pub mod ast;
+pub mod expr;
+pub mod parser;
+pub mod rule;
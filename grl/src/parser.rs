@@ -0,0 +1,998 @@
+use crate::ast::Expression;
+use crate::rule::{Cron, Pattern, Rule, Schedule};
+use regex::Regex;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ParseError {
+    #[error("Invalid GRL syntax")]
+    InvalidSyntax,
+    #[error("Unknown operator: {0}")]
+    UnknownOperator(String),
+    #[error("Cannot parse condition: {0}")]
+    InvalidCondition(String),
+    #[error("Cannot parse value: {0}")]
+    InvalidValue(String),
+    #[error("{0} require the `{1}` feature")]
+    FeatureDisabled(String, String),
+}
+
+pub struct GrlParser {
+    rule_pattern: Regex,
+    function_call_pattern: Regex,
+    insert_logical_pattern: Regex,
+    event_count_pattern: Regex,
+    fact_pattern: Regex,
+    binding_pattern: Regex,
+    accumulate_pattern: Regex,
+    update_pattern: Regex,
+    let_pattern: Regex,
+    script_pattern: Regex,
+    script_placeholder_pattern: Regex,
+}
+
+impl GrlParser {
+    pub fn new() -> Self {
+        // Simple regex patterns for basic GRL parsing
+        // In a production system, you'd want a proper parser generator
+        let rule_pattern = Regex::new(
+            r#"rule\s+(\w+)\s*(?:"([^"]*)")?\s*(?:salience\s+(\d+))?\s*(?:every\s+(\d+(?:ms|s|m|h|d))|cron\s+"([^"]*)")?\s*\{\s*when\s+(.*?)\s+then\s+(.*)\}"#
+        ).unwrap();
+
+        // discount(customer.tier, order.total)
+        let function_call_pattern = Regex::new(r#"^(\w+)\s*\((.*)\)$"#).unwrap();
+
+        let insert_logical_pattern =
+            Regex::new(r#"^insertLogical\s*\(\s*(\w+(?:::\w+)*)\s*,\s*(.+)\)$"#).unwrap();
+
+        // count of Login events in last 5m > 3
+        let event_count_pattern = Regex::new(
+            r#"^count\s+of\s+(\w+(?:::\w+)*)\s+events\s+in\s+last\s+(\d+(?:ms|s|m|h|d))\s*(==|!=|<=|>=|<|>)\s*(.+)$"#,
+        )
+        .unwrap();
+
+        // c: Car(c.Speed > 100), optionally followed by more patterns or `&& condition`.
+        // Types may be namespaced: i: billing::Invoice
+        let fact_pattern =
+            Regex::new(r#"^(\w+)\s*:\s*(\w+(?:::\w+)*)\s*(?:\(([^()]*)\))?\s*(,|&&|$)"#).unwrap();
+
+        // total := order.price * order.qty
+        let binding_pattern = Regex::new(r#"^(\w+)\s*:=\s*(.+)$"#).unwrap();
+
+        // accumulate(o: Order(o.customer == c.id), sum(o.amount))
+        let accumulate_pattern = Regex::new(
+            r#"^accumulate\s*\(\s*(\w+)\s*:\s*(\w+(?:::\w+)*)\s*(?:\(([^()]*)\))?\s*,\s*(\w+)\s*\((.*)\)\s*\)$"#,
+        )
+        .unwrap();
+
+        let update_pattern = Regex::new(r#"^update\s*\(\s*(\w+(?:::\w+)*)\s*\)$"#).unwrap();
+
+        let let_pattern = Regex::new(r#"^let\s+(\w+)\s*=\s*(.+)$"#).unwrap();
+
+        // script """ ... """, which may span lines and contain `;` and `}`
+        let script_pattern = Regex::new(r#"(?s)\bscript\s*"""(.*?)""""#).unwrap();
+        // What a script is replaced with until its action is parsed
+        let script_placeholder_pattern = Regex::new(r#"^script\s+#(\d+)$"#).unwrap();
+
+        Self {
+            rule_pattern,
+            function_call_pattern,
+            insert_logical_pattern,
+            event_count_pattern,
+            fact_pattern,
+            binding_pattern,
+            accumulate_pattern,
+            update_pattern,
+            let_pattern,
+            script_pattern,
+            script_placeholder_pattern,
+        }
+    }
+
+    /// Parse every rule in `grl_text`, such as the contents of a `.grl` file
+    pub fn parse_rules(&self, grl_text: &str) -> Result<Vec<Rule>, ParseError> {
+        let mut rules = Vec::new();
+        let mut rest = grl_text.trim_start();
+        while !rest.is_empty() {
+            if !starts_with_keyword(rest, "rule") {
+                return Err(ParseError::InvalidSyntax);
+            }
+            let mut strings = Strings::default();
+            let body = rest
+                .char_indices()
+                .find(|&(_, c)| !strings.step(c) && c == '{')
+                .map(|(pos, _)| pos)
+                .ok_or(ParseError::InvalidSyntax)?;
+            let end = body + matching(&rest[body..], '{', '}').ok_or(ParseError::InvalidSyntax)?;
+            rules.push(self.parse_rule(&rest[..=end])?);
+            rest = rest[end + 1..].trim_start();
+        }
+        Ok(rules)
+    }
+
+    pub fn parse_rule(&self, grl_text: &str) -> Result<Rule, ParseError> {
+        // Set script bodies aside so their contents are not parsed as GRL
+        let mut scripts = Vec::new();
+        let grl_text = self
+            .script_pattern
+            .replace_all(grl_text, |captures: &regex::Captures| {
+                scripts.push(captures.get(1).unwrap().as_str().trim().to_string());
+                format!("script #{}", scripts.len() - 1)
+            });
+        let normalized = grl_text.replace('\n', " ").replace('\r', "");
+
+        if let Some(captures) = self.rule_pattern.captures(&normalized) {
+            let name = captures.get(1).unwrap().as_str().to_string();
+            let description = captures.get(2).map(|m| m.as_str().to_string());
+            let salience: i32 = captures
+                .get(3)
+                .map(|m| m.as_str().parse().unwrap_or(0))
+                .unwrap_or(0);
+            let schedule = match (captures.get(4), captures.get(5)) {
+                (Some(m), _) => {
+                    let interval_ms = parse_duration_millis(m.as_str())
+                        .ok_or_else(|| ParseError::InvalidValue(m.as_str().to_string()))?;
+                    Some(Schedule::Interval(Duration::from_millis(interval_ms)))
+                }
+                (None, Some(m)) => Some(Schedule::Cron(
+                    Cron::parse(m.as_str())
+                        .ok_or_else(|| ParseError::InvalidValue(m.as_str().to_string()))?,
+                )),
+                (None, None) => None,
+            };
+            let when_clause = captures.get(6).unwrap().as_str();
+            let then_clause = captures.get(7).unwrap().as_str();
+
+            // Bindings are `;`-separated from the patterns and condition around them
+            let mut accumulates = Vec::new();
+            let mut bindings = Vec::new();
+            let mut match_parts = Vec::new();
+            let mut start = 0;
+            let parts = top_level_matches(when_clause, ";")
+                .chain([when_clause.len()])
+                .map(|pos| {
+                    let part = when_clause[start..pos].trim();
+                    start = pos + 1;
+                    part
+                });
+            for part in parts {
+                if let Some(binding) = self.binding_pattern.captures(part) {
+                    let binding_name = binding.get(1).unwrap().as_str().to_string();
+                    let value_text = binding.get(2).unwrap().as_str().trim();
+                    if let Some(accumulate) = self.accumulate_pattern.captures(value_text) {
+                        let pattern = Pattern {
+                            binding: accumulate.get(1).unwrap().as_str().to_string(),
+                            fact_type: accumulate.get(2).unwrap().as_str().to_string(),
+                            constraint: match accumulate.get(3).map(|m| m.as_str().trim()) {
+                                Some(text) if !text.is_empty() => Some(self.parse_condition(text)?),
+                                _ => None,
+                            },
+                        };
+                        let function = accumulate.get(4).unwrap().as_str().to_string();
+                        // `count()` counts the matched facts themselves
+                        let value = match accumulate.get(5).unwrap().as_str().trim() {
+                            "" => Expression::Variable(pattern.binding.clone()),
+                            text => self.parse_value(text)?,
+                        };
+                        accumulates.push((binding_name, pattern, function, value));
+                    } else {
+                        bindings.push((binding_name, self.parse_value(value_text)?));
+                    }
+                } else if !part.is_empty() {
+                    match_parts.push(part);
+                }
+            }
+            let match_text = match_parts.join(" && ");
+
+            let (patterns, condition_text) = self.parse_patterns(&match_text)?;
+            let when_condition = match condition_text {
+                Some(text) => self.parse_condition(text)?,
+                None => Expression::Boolean(true),
+            };
+            let then_actions = self.parse_actions(then_clause, &mut scripts)?;
+
+            let mut rule = Rule::new(name, salience, when_condition, then_actions);
+            for pattern in patterns {
+                rule = rule.with_pattern(pattern.binding, pattern.fact_type, pattern.constraint);
+            }
+            for (binding_name, pattern, function, value) in accumulates {
+                rule = rule.with_accumulate(binding_name, pattern, function, value);
+            }
+            for (binding_name, value) in bindings {
+                rule = rule.with_binding(binding_name, value);
+            }
+            if let Some(desc) = description {
+                rule = rule.with_description(desc);
+            }
+            if let Some(schedule) = schedule {
+                rule = rule.with_schedule(schedule);
+            }
+
+            Ok(rule)
+        } else {
+            Err(ParseError::InvalidSyntax)
+        }
+    }
+
+    /// Split leading fact patterns off a when clause, returning them along with
+    /// the remaining condition (if any)
+    fn parse_patterns<'a>(
+        &self,
+        when_clause: &'a str,
+    ) -> Result<(Vec<Pattern>, Option<&'a str>), ParseError> {
+        let mut patterns = Vec::new();
+        let mut rest = when_clause.trim();
+
+        while let Some(captures) = self.fact_pattern.captures(rest) {
+            let constraint = match captures.get(3).map(|m| m.as_str().trim()) {
+                Some(text) if !text.is_empty() => Some(self.parse_condition(text)?),
+                _ => None,
+            };
+            patterns.push(Pattern {
+                binding: captures.get(1).unwrap().as_str().to_string(),
+                fact_type: captures.get(2).unwrap().as_str().to_string(),
+                constraint,
+            });
+            rest = rest[captures.get(0).unwrap().end()..].trim();
+            if captures.get(4).unwrap().as_str() == "&&" {
+                break;
+            }
+        }
+
+        if rest.is_empty() && !patterns.is_empty() {
+            Ok((patterns, None))
+        } else {
+            Ok((patterns, Some(rest)))
+        }
+    }
+
+    fn parse_condition(&self, condition_text: &str) -> Result<Expression, ParseError> {
+        let trimmed = condition_text.trim();
+
+        // A let binding extends as far right as possible, over any `&&` or `||`
+        if let Some((name, value_text, body_text)) = split_let(trimmed) {
+            return Ok(Expression::LetIn(
+                name.to_string(),
+                Box::new(self.parse_value(value_text)?),
+                Box::new(self.parse_condition(body_text)?),
+            ));
+        }
+
+        // Handle logical operators outside parentheses and strings, `||`
+        // binding looser than `&&`, and chains associating to the left
+        if let Some(or_pos) = top_level_matches(trimmed, " || ").last() {
+            let left = self.parse_condition(&trimmed[..or_pos])?;
+            let right = self.parse_condition(&trimmed[or_pos + 4..])?;
+            return Ok(Expression::Or(Box::new(left), Box::new(right)));
+        }
+
+        if let Some(and_pos) = top_level_matches(trimmed, " && ").last() {
+            let left = self.parse_condition(&trimmed[..and_pos])?;
+            let right = self.parse_condition(&trimmed[and_pos + 4..])?;
+            return Ok(Expression::And(Box::new(left), Box::new(right)));
+        }
+
+        if let Some(inner) = group(trimmed) {
+            return self.parse_condition(inner);
+        }
+
+        // Handle temporal event counts
+        if let Some(captures) = self.event_count_pattern.captures(trimmed) {
+            let event_type = captures.get(1).unwrap().as_str().to_string();
+            let window_text = captures.get(2).unwrap().as_str();
+            let window_ms = parse_duration_millis(window_text)
+                .ok_or_else(|| ParseError::InvalidValue(window_text.to_string()))?;
+            let operator = captures.get(3).unwrap().as_str();
+            let right_expr = self.parse_value(captures.get(4).unwrap().as_str())?;
+
+            return self.build_comparison(
+                operator,
+                Expression::EventCount(event_type, window_ms),
+                right_expr,
+            );
+        }
+
+        // Handle simple comparisons, matching two-character operators first so
+        // `<=` is not read as `<`
+        let comparison = ["==", "!=", "<=", ">=", "<", ">", " in ", " matches "]
+            .iter()
+            .filter_map(|op| top_level_matches(trimmed, op).next().map(|pos| (pos, *op)))
+            .min_by_key(|(pos, _)| *pos);
+        if let Some((pos, operator)) = comparison {
+            let left_expr = self.parse_value(&trimmed[..pos])?;
+            let right_expr = self.parse_value(&trimmed[pos + operator.len()..])?;
+
+            return self.build_comparison(operator, left_expr, right_expr);
+        }
+
+        // Any other value, such as a call or `!flag`, is tested for truthiness
+        self.parse_value(trimmed)
+            .map_err(|_| ParseError::InvalidCondition(trimmed.to_string()))
+    }
+
+    fn build_comparison(
+        &self,
+        operator: &str,
+        left_expr: Expression,
+        right_expr: Expression,
+    ) -> Result<Expression, ParseError> {
+        match operator {
+            "==" => Ok(Expression::Equal(Box::new(left_expr), Box::new(right_expr))),
+            " in " => Ok(Expression::In(Box::new(left_expr), Box::new(right_expr))),
+            " matches " => Ok(Expression::Matches(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            "!=" => Ok(Expression::NotEqual(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            "<" => Ok(Expression::LessThan(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            "<=" => Ok(Expression::LessEqual(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            ">" => Ok(Expression::GreaterThan(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            ">=" => Ok(Expression::GreaterEqual(
+                Box::new(left_expr),
+                Box::new(right_expr),
+            )),
+            _ => Err(ParseError::UnknownOperator(operator.to_string())),
+        }
+    }
+
+    fn parse_actions(
+        &self,
+        actions_text: &str,
+        scripts: &mut [String],
+    ) -> Result<Vec<Expression>, ParseError> {
+        let mut actions = Vec::new();
+
+        // Split by semicolon (outside of blocks) and parse each action
+        for action_text in split_actions(actions_text) {
+            let trimmed = action_text.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if let Some((item, collection_text, body)) = split_foreach(trimmed) {
+                // Loop: foreach item in collection { actions }
+                let collection = self.parse_value(collection_text)?;
+                let body_actions = self.parse_actions(body, scripts)?;
+                actions.push(Expression::ForEach(
+                    item.to_string(),
+                    Box::new(collection),
+                    body_actions,
+                ));
+            } else if let Some((condition_text, then_text, else_text)) = split_if(trimmed) {
+                // Conditional: if (condition) { actions } else { actions }
+                let condition = self.parse_condition(condition_text)?;
+                let then_actions = self.parse_actions(then_text, scripts)?;
+                let else_actions = match else_text {
+                    Some(text) => self.parse_actions(text, scripts)?,
+                    None => Vec::new(),
+                };
+                actions.push(Expression::If(
+                    Box::new(condition),
+                    then_actions,
+                    else_actions,
+                ));
+            } else if let Some(captures) = self.script_placeholder_pattern.captures(trimmed) {
+                if !cfg!(feature = "script") {
+                    return Err(ParseError::FeatureDisabled(
+                        "script actions".to_string(),
+                        "script".to_string(),
+                    ));
+                }
+                let script = captures
+                    .get(1)
+                    .unwrap()
+                    .as_str()
+                    .parse()
+                    .ok()
+                    .and_then(|index: usize| scripts.get_mut(index))
+                    .ok_or(ParseError::InvalidSyntax)?;
+                actions.push(Expression::Script(std::mem::take(script)));
+            } else if let Some(captures) = self.let_pattern.captures(trimmed) {
+                // Local value: let name = value
+                let name = captures.get(1).unwrap().as_str().to_string();
+                let value_expr = self.parse_value(captures.get(2).unwrap().as_str())?;
+                actions.push(Expression::Let(name, Box::new(value_expr)));
+            } else if let Some(action) = self.parse_array_mutation(trimmed)? {
+                actions.push(action);
+            } else if let Some(captures) = self.update_pattern.captures(trimmed) {
+                // Declared modification: update(name)
+                let fact_name = captures.get(1).unwrap().as_str().to_string();
+                actions.push(Expression::Update(fact_name));
+            } else if let Some(captures) = self.insert_logical_pattern.captures(trimmed) {
+                // Logical insertion: insertLogical(name, value)
+                let fact_name = captures.get(1).unwrap().as_str().to_string();
+                let value_expr = self.parse_value(captures.get(2).unwrap().as_str())?;
+                actions.push(Expression::InsertLogical(fact_name, Box::new(value_expr)));
+            } else if let Some(eq_pos) = trimmed.find(" = ") {
+                let left = trimmed[..eq_pos].trim();
+                let right = trimmed[eq_pos + 3..].trim();
+
+                if let Some((obj_name, field_path)) = left.split_once('.') {
+                    // Field assignment: Object.Field = value, or a nested
+                    // Object.Field.Subfield = value
+                    let value_expr = self.parse_value(right)?;
+                    actions.push(Expression::FieldAssignment(
+                        obj_name.to_string(),
+                        field_path.to_string(),
+                        Box::new(value_expr),
+                    ));
+                } else {
+                    // Variable assignment: variable = value
+                    let var_name = left.to_string();
+                    let value_expr = self.parse_value(right)?;
+                    actions.push(Expression::Assignment(var_name, Box::new(value_expr)));
+                }
+            }
+        }
+
+        Ok(actions)
+    }
+
+    /// `push(cart.items, item)` or `remove(cart.items, item)`, assigning the
+    /// result of the builtin of the same name back to the array
+    fn parse_array_mutation(&self, text: &str) -> Result<Option<Expression>, ParseError> {
+        let is_mutation = self
+            .function_call_pattern
+            .captures(text)
+            .is_some_and(|captures| matches!(&captures[1], "push" | "remove"));
+        if !is_mutation {
+            return Ok(None);
+        }
+        let call = self.parse_value(text)?;
+        let Expression::FunctionCall(_, args) = &call else {
+            return Err(ParseError::InvalidValue(text.to_string()));
+        };
+        let value = Box::new(call.clone());
+        match args.first() {
+            Some(Expression::Variable(name)) => {
+                Ok(Some(Expression::Assignment(name.clone(), value)))
+            }
+            Some(Expression::FieldAccess(object, field)) => match object.as_ref() {
+                Expression::Variable(name) => Ok(Some(Expression::FieldAssignment(
+                    name.clone(),
+                    field.clone(),
+                    value,
+                ))),
+                _ => Err(ParseError::InvalidValue(text.to_string())),
+            },
+            _ => Err(ParseError::InvalidValue(text.to_string())),
+        }
+    }
+
+    fn parse_variable_or_field(&self, var_text: &str) -> Expression {
+        if let Some(dot_pos) = var_text.find('.') {
+            let obj_name = var_text[..dot_pos].to_string();
+            let field_name = var_text[dot_pos + 1..].to_string();
+            Expression::FieldAccess(Box::new(Expression::Variable(obj_name)), field_name)
+        } else {
+            Expression::Variable(var_text.to_string())
+        }
+    }
+
+    fn parse_optional_chain(&self, chain_text: &str) -> Result<Expression, ParseError> {
+        let invalid = || ParseError::InvalidValue(chain_text.to_string());
+        let mut segments = chain_text.split('.');
+        let name = |segment: &str| {
+            let name = segment.trim_end_matches('?');
+            if name.is_empty() || name.contains('?') {
+                Err(invalid())
+            } else {
+                Ok(name.to_string())
+            }
+        };
+        let first = segments.next().ok_or_else(invalid)?;
+        let mut optional = first.ends_with('?');
+        let mut expr = Expression::Variable(name(first)?);
+        for segment in segments {
+            let field = name(segment)?;
+            expr = if optional {
+                Expression::OptionalFieldAccess(Box::new(expr), field)
+            } else {
+                Expression::FieldAccess(Box::new(expr), field)
+            };
+            optional = segment.ends_with('?');
+        }
+        if optional {
+            return Err(invalid());
+        }
+        Ok(expr)
+    }
+
+    /// Parse index access such as `items[0]`, `order.lines[i].price` or
+    /// `split(s)[0]`, or `None` if the text is not a variable, call or group
+    /// followed by indices and fields
+    fn parse_indexed(&self, text: &str) -> Result<Option<Expression>, ParseError> {
+        let is_name = |c: char| c.is_alphanumeric() || c == '_';
+        let Some(open) = top_level_matches(text, "[").next() else {
+            return Ok(None);
+        };
+        let base = &text[..open];
+        let mut expr = if is_reference(base) {
+            self.parse_variable_or_field(base)
+        } else if group(base).is_some() || self.function_call_pattern.is_match(base) {
+            self.parse_value(base)?
+        } else {
+            return Ok(None);
+        };
+        let mut rest = &text[open..];
+        while !rest.is_empty() {
+            if let Some(close) = matching(rest, '[', ']') {
+                let index = self.parse_value(&rest[1..close])?;
+                expr = Expression::Index(Box::new(expr), Box::new(index));
+                rest = &rest[close + 1..];
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+                if end == 0 {
+                    return Ok(None);
+                }
+                expr = Expression::FieldAccess(Box::new(expr), after[..end].to_string());
+                rest = &after[end..];
+            } else {
+                return Ok(None);
+            }
+        }
+        Ok(Some(expr))
+    }
+
+    /// Parse a method call such as `cart.items.len()` or `name.toUpper()`, or
+    /// a field of a call's result such as `f(x).total`, or `None` if the text
+    /// does not end in either
+    fn parse_method_call(&self, text: &str) -> Result<Option<Expression>, ParseError> {
+        let Some(dot) = top_level_matches(text, ".").last() else {
+            return Ok(None);
+        };
+        let (receiver, call) = (&text[..dot], &text[dot + 1..]);
+        // `a + b.len()` is arithmetic on the call, not a call on `a + b`
+        if receiver.is_empty() || top_level_matches(receiver, " ").next().is_some() {
+            return Ok(None);
+        }
+        let Some(captures) = self.function_call_pattern.captures(call) else {
+            let is_name = |c: char| c.is_alphanumeric() || c == '_';
+            if receiver.ends_with(')') && !call.is_empty() && call.chars().all(is_name) {
+                let receiver = Box::new(self.parse_value(receiver)?);
+                return Ok(Some(Expression::FieldAccess(receiver, call.to_string())));
+            }
+            return Ok(None);
+        };
+        let args_text = captures.get(2).unwrap().as_str();
+        if !is_balanced(args_text) {
+            return Ok(None);
+        }
+        Ok(Some(Expression::MethodCall(
+            Box::new(self.parse_value(receiver)?),
+            captures.get(1).unwrap().as_str().to_string(),
+            self.parse_list(args_text)?,
+        )))
+    }
+
+    /// Parse the comma-separated values of an argument list or array literal
+    fn parse_list(&self, text: &str) -> Result<Vec<Expression>, ParseError> {
+        let mut values = Vec::new();
+        if !text.trim().is_empty() {
+            let mut start = 0;
+            for pos in top_level_matches(text, ",").chain([text.len()]) {
+                values.push(self.parse_value(&text[start..pos])?);
+                start = pos + 1;
+            }
+        }
+        Ok(values)
+    }
+
+    fn parse_value(&self, value_text: &str) -> Result<Expression, ParseError> {
+        let trimmed = value_text.trim();
+
+        // Local value: let name = value in body
+        if let Some((name, value_text, body_text)) = split_let(trimmed) {
+            return Ok(Expression::LetIn(
+                name.to_string(),
+                Box::new(self.parse_value(value_text)?),
+                Box::new(self.parse_value(body_text)?),
+            ));
+        }
+
+        // Conditional value: if (condition) { value } else { value }
+        if let Some((condition_text, then_text, else_text)) = split_if(trimmed) {
+            let condition = self.parse_condition(condition_text)?;
+            let branch = |text: &str| match text.trim().trim_end_matches(';') {
+                "" => Ok(Vec::new()),
+                value => self.parse_value(value).map(|value| vec![value]),
+            };
+            let else_branch = match else_text {
+                Some(text) => branch(text)?,
+                None => Vec::new(),
+            };
+            return Ok(Expression::If(
+                Box::new(condition),
+                branch(then_text)?,
+                else_branch,
+            ));
+        }
+
+        // Null coalescing binds loosest: `a + b ?? 0` is `(a + b) ?? 0`
+        if let Some(pos) = top_level_matches(trimmed, " ?? ").next() {
+            let value = Box::new(self.parse_value(&trimmed[..pos])?);
+            let default = Box::new(self.parse_value(&trimmed[pos + 4..])?);
+            return Ok(Expression::Coalesce(value, default));
+        }
+
+        // A parenthesized group may hold any condition: `(a > 1) == flag`
+        if let Some(inner) = group(trimmed) {
+            return self.parse_condition(inner);
+        }
+
+        // Try to parse as number
+        if let Ok(num) = trimmed.parse::<f64>() {
+            return Ok(Expression::Number(num));
+        }
+
+        if let Some(ms) = parse_duration_millis(trimmed) {
+            return Ok(Expression::Duration(ms));
+        }
+
+        // Try to parse as boolean
+        if trimmed == "true" {
+            return Ok(Expression::Boolean(true));
+        } else if trimmed == "false" {
+            return Ok(Expression::Boolean(false));
+        } else if trimmed == "null" {
+            return Ok(Expression::Null);
+        }
+
+        // Try to parse as string literal
+        if let Some(value) = string_literal(trimmed) {
+            return Ok(Expression::String(value));
+        }
+
+        // Array literal: ["gold", "platinum"]
+        if trimmed.starts_with('[') && matching(trimmed, '[', ']') == Some(trimmed.len() - 1) {
+            let items = self.parse_list(&trimmed[1..trimmed.len() - 1])?;
+            return Ok(Expression::Array(items));
+        }
+
+        // Optional chaining: customer?.address?.zip
+        if trimmed.contains("?.") && is_reference(&trimmed.replace('?', "")) {
+            return self.parse_optional_chain(trimmed);
+        }
+
+        // Check if it's a variable or field access
+        if is_reference(trimmed) {
+            return Ok(self.parse_variable_or_field(trimmed));
+        }
+
+        if let Some(expr) = self.parse_indexed(trimmed)? {
+            return Ok(expr);
+        }
+
+        // Try to parse as a function call. The parentheses must enclose all of
+        // the arguments, so `f(a) + g(b)` is left to the arithmetic below.
+        if let Some(captures) = self.function_call_pattern.captures(trimmed) {
+            let args_text = captures.get(2).unwrap().as_str();
+            if is_balanced(args_text) {
+                let name = captures.get(1).unwrap().as_str().to_string();
+                return Ok(Expression::FunctionCall(name, self.parse_list(args_text)?));
+            }
+        }
+
+        if let Some(call) = self.parse_method_call(trimmed)? {
+            return Ok(call);
+        }
+
+        // Try to parse as arithmetic expression, splitting on the rightmost
+        // lowest-precedence operator so chains associate to the left
+        for operators in [&[" + ", " - "][..], &[" * ", " / ", " % "]] {
+            let split = operators
+                .iter()
+                .filter_map(|op| top_level_matches(trimmed, op).last().map(|pos| (pos, *op)))
+                .max_by_key(|(pos, _)| *pos);
+            if let Some((pos, op)) = split {
+                let left = Box::new(self.parse_value(&trimmed[..pos])?);
+                let right = Box::new(self.parse_value(&trimmed[pos + 3..])?);
+                return Ok(match op {
+                    " + " => Expression::Add(left, right),
+                    " - " => Expression::Subtract(left, right),
+                    " * " => Expression::Multiply(left, right),
+                    " % " => Expression::Modulo(left, right),
+                    _ => Expression::Divide(left, right),
+                });
+            }
+        }
+
+        // Negation binds tighter than any binary operator: `-a * b` is `(-a) * b`
+        if let Some(operand) = trimmed.strip_prefix('-') {
+            if !operand.is_empty() && !operand.starts_with(char::is_whitespace) {
+                return Ok(Expression::Negate(Box::new(self.parse_value(operand)?)));
+            }
+        }
+        if let Some(operand) = trimmed.strip_prefix('!') {
+            if !operand.is_empty() && !operand.starts_with(char::is_whitespace) {
+                return Ok(Expression::Not(Box::new(self.parse_value(operand)?)));
+            }
+        }
+
+        Err(ParseError::InvalidValue(trimmed.to_string()))
+    }
+}
+
+/// Whether `text` names a fact or local value, optionally in a namespace
+/// (`billing::invoice`) and followed by fields (`billing::invoice.total`)
+fn is_reference(text: &str) -> bool {
+    let is_name =
+        |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let (namespace, path) = text.rsplit_once("::").unwrap_or(("", text));
+    (namespace.is_empty() || namespace.split("::").all(is_name))
+        && path.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && path
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '.' || c == '_')
+}
+
+/// Byte offsets of `pattern` in `text` that are outside parentheses,
+/// brackets and string literals
+fn top_level_matches<'a>(text: &'a str, pattern: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let mut depth = 0;
+    let mut strings = Strings::default();
+    text.char_indices()
+        .filter(move |&(pos, c)| {
+            let top_level = depth == 0 && !strings.in_string;
+            if !strings.step(c) {
+                match c {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth -= 1,
+                    _ => {}
+                }
+            }
+            top_level && text[pos..].starts_with(pattern)
+        })
+        .map(|(pos, _)| pos)
+}
+
+/// Split a then clause into actions at each `;` outside of `{ }` blocks, and
+/// after each block that is not followed by `else`
+fn split_actions(text: &str) -> Vec<&str> {
+    let mut actions = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut strings = Strings::default();
+    for (pos, c) in text.char_indices() {
+        if strings.step(c) {
+            continue;
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                let next = text[pos + 1..].trim_start();
+                if depth == 0 && !starts_with_keyword(next, "else") {
+                    actions.push(&text[start..pos + 1]);
+                    start = pos + 1;
+                }
+            }
+            ';' if depth == 0 => {
+                actions.push(&text[start..pos]);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    actions.push(&text[start..]);
+    actions
+}
+
+/// The condition, then block and else block (if any) of an
+/// `if (condition) { ... } else { ... }` action. An `else if` chain is
+/// returned whole as the else block.
+fn split_if(text: &str) -> Option<(&str, &str, Option<&str>)> {
+    if !starts_with_keyword(text, "if") {
+        return None;
+    }
+    let rest = text[2..].trim_start();
+    let condition_end = matching(rest, '(', ')')?;
+    let condition = &rest[1..condition_end];
+
+    let rest = rest[condition_end + 1..].trim_start();
+    let block_end = matching(rest, '{', '}')?;
+    let then_block = &rest[1..block_end];
+
+    let rest = rest[block_end + 1..].trim();
+    if rest.is_empty() {
+        return Some((condition, then_block, None));
+    }
+    if !starts_with_keyword(rest, "else") {
+        return None;
+    }
+    let rest = rest[4..].trim_start();
+    if starts_with_keyword(rest, "if") {
+        return Some((condition, then_block, Some(rest)));
+    }
+    let block_end = matching(rest, '{', '}')?;
+    rest[block_end + 1..]
+        .trim()
+        .is_empty()
+        .then(|| (condition, then_block, Some(&rest[1..block_end])))
+}
+
+/// The name, value and body of a `let name = value in body` expression. The
+/// value ends at the first `in`, so a membership test there is not possible.
+fn split_let(text: &str) -> Option<(&str, &str, &str)> {
+    if !starts_with_keyword(text, "let") {
+        return None;
+    }
+    let (name, rest) = text[3..].split_once('=')?;
+    let name = name.trim();
+    if name.is_empty()
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        || rest.starts_with('=')
+    {
+        return None;
+    }
+    let body = top_level_matches(rest, " in ").next()?;
+    Some((name, &rest[..body], &rest[body + 4..]))
+}
+
+/// The item name, collection and body of a
+/// `foreach item in collection { ... }` action
+fn split_foreach(text: &str) -> Option<(&str, &str, &str)> {
+    if !starts_with_keyword(text, "foreach") {
+        return None;
+    }
+    let rest = text[7..].trim_start();
+    let item_end = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let item = &rest[..item_end];
+    let rest = rest[item_end..].trim_start();
+    if item.is_empty() || !starts_with_keyword(rest, "in") {
+        return None;
+    }
+    let rest = rest[2..].trim_start();
+    let body_start = rest.find('{')?;
+    let body_end = body_start + matching(&rest[body_start..], '{', '}')?;
+    rest[body_end + 1..].trim().is_empty().then(|| {
+        (
+            item,
+            rest[..body_start].trim(),
+            &rest[body_start + 1..body_end],
+        )
+    })
+}
+
+/// Whether `text` starts with `keyword` as a whole word
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.strip_prefix(keyword)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+/// Offset of the `close` matching the `open` that `text` starts with,
+/// ignoring any inside string literals
+fn matching(text: &str, open: char, close: char) -> Option<usize> {
+    if !text.starts_with(open) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut strings = Strings::default();
+    for (pos, c) in text.char_indices() {
+        if strings.step(c) {
+            continue;
+        } else if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(pos);
+            }
+        }
+    }
+    None
+}
+
+/// Whether every parenthesis and bracket in `text` outside string literals
+/// is closed, and none is closed before it is opened
+fn is_balanced(text: &str) -> bool {
+    let mut depth = 0;
+    let mut strings = Strings::default();
+    for c in text.chars() {
+        if strings.step(c) {
+            continue;
+        }
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// Tracks whether a scan over GRL text is inside a string literal, where
+/// `\"` does not end the string
+#[derive(Default)]
+struct Strings {
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Strings {
+    /// Move past `c`, returning whether it belongs to a string literal
+    fn step(&mut self, c: char) -> bool {
+        let quoted = self.in_string || c == '"';
+        if self.escaped {
+            self.escaped = false;
+        } else if self.in_string && c == '\\' {
+            self.escaped = true;
+        } else if c == '"' {
+            self.in_string = !self.in_string;
+        }
+        quoted
+    }
+}
+
+/// The value of `text` if it is a single string literal. `\"`, `\\`, `\n`,
+/// `\t` and `\r` are unescaped; any other backslash is kept as written.
+fn string_literal(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?;
+    let mut value = String::new();
+    let mut chars = inner.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return (pos + 1 == inner.len()).then_some(value),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                escaped @ ('"' | '\\') => value.push(escaped),
+                other => value.extend(['\\', other]),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// The text inside the parentheses if all of `text` is one parenthesized
+/// group, as in `(a || b)` but not `(a) || (b)`
+fn group(text: &str) -> Option<&str> {
+    (matching(text, '(', ')')? == text.len() - 1).then(|| &text[1..text.len() - 1])
+}
+
+/// Parse a duration literal such as `500ms`, `30s`, `5m`, `2h` or `7d` into
+/// milliseconds, or `None` if it is longer than a `FactValue::Duration` holds
+pub fn parse_duration_millis(text: &str) -> Option<u64> {
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = text.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return None,
+    };
+    amount
+        .checked_mul(unit_ms)
+        .filter(|&ms| i64::try_from(ms).is_ok())
+}
+
+impl Default for GrlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
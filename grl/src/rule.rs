@@ -0,0 +1,305 @@
+use crate::ast::{Expression, RuleAst};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+/// When a rule should be re-run by the scheduler
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Cron),
+}
+
+/// A five-field cron expression, `minute hour day-of-month month day-of-week`,
+/// as in `*/15 9-17 * * 1-5`. Each field is `*`, a number or a range `a-b`,
+/// optionally with a step `/n`, or a comma-separated list of these. Days of
+/// the week run from 0 for Sunday to 6, with 7 also meaning Sunday. As in
+/// cron, when both day fields are restricted a time matching either runs.
+///
+/// Each field is kept as a bit set, bit `n` standing for the value `n`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cron {
+    pub minutes: u64,
+    pub hours: u64,
+    pub days: u64,
+    pub months: u64,
+    pub weekdays: u64,
+    /// Whether both `days` and `weekdays` were restricted
+    pub either_day: bool,
+}
+
+impl Cron {
+    /// Parse a cron expression, or `None` if it is not five valid fields
+    pub fn parse(text: &str) -> Option<Self> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+        let either_day = !days.starts_with('*') && !weekdays.starts_with('*');
+        let weekdays = cron_field(weekdays, 0, 7)?;
+        Some(Self {
+            minutes: cron_field(minutes, 0, 59)?,
+            hours: cron_field(hours, 0, 23)?,
+            days: cron_field(days, 1, 31)?,
+            months: cron_field(months, 1, 12)?,
+            // Fold 7 onto Sunday
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            either_day,
+        })
+    }
+
+    /// Whether the schedule runs at the given minute, with `weekday` from 0
+    /// for Sunday
+    pub fn matches(&self, minute: u32, hour: u32, day: u32, month: u32, weekday: u32) -> bool {
+        let has = |set: u64, value: u32| value < 64 && set & (1 << value) != 0;
+        let day_of_month = has(self.days, day);
+        let day_of_week = has(self.weekdays, weekday);
+        let day_matches = if self.either_day {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        };
+        has(self.minutes, minute) && has(self.hours, hour) && has(self.months, month) && day_matches
+    }
+}
+
+/// The values from `min` to `max` a cron field selects, as a bit set
+fn cron_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                // `5/10` runs from 5 to the end of the range
+                None if step > 1 => (range.parse().ok()?, max),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            set |= 1 << value;
+        }
+    }
+    Some(set)
+}
+
+/// Matches every fact of `fact_type` (optionally filtered by `constraint`),
+/// making the matched fact available to the rule under `binding`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub binding: String,
+    pub fact_type: String,
+    pub constraint: Option<Expression>,
+}
+
+/// A value computed once per activation (`total := order.price * order.qty`)
+/// and visible by name to the rule's condition and actions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub name: String,
+    pub value: Expression,
+}
+
+/// An aggregate over every fact matching `pattern`, bound by name like a
+/// `Binding`: `total := accumulate(o: Order(o.customer == c.id), sum(o.amount))`.
+/// `function` is called with the array of `value` evaluated for each match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Accumulate {
+    pub name: String,
+    pub pattern: Pattern,
+    pub function: String,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub description: Option<String>,
+    pub salience: i32,
+    pub when_condition: Expression,
+    pub then_actions: Vec<Expression>,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Fact patterns the rule fires once per combination of matching facts for
+    #[serde(default)]
+    pub patterns: Vec<Pattern>,
+    /// Aggregates bound once the patterns match, before `bindings`
+    #[serde(default)]
+    pub accumulates: Vec<Accumulate>,
+    /// Values bound before the condition is evaluated, in order
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+    /// Labels for selecting groups of rules, e.g. with `RuleEngine::execute_group`
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// The package the rule belongs to, like the module of a GRL file, so
+    /// rules can be added, removed and executed a package at a time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    /// Application-defined key/value information, such as an owner or ticket
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// When the rule starts to fire, in milliseconds since the Unix epoch as
+    /// read from the engine's `Clock`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_from: Option<i64>,
+    /// When the rule stops firing, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl Rule {
+    pub fn new(
+        name: String,
+        salience: i32,
+        when_condition: Expression,
+        then_actions: Vec<Expression>,
+    ) -> Self {
+        Self {
+            name,
+            description: None,
+            salience,
+            when_condition,
+            then_actions,
+            schedule: None,
+            patterns: Vec::new(),
+            accumulates: Vec::new(),
+            bindings: Vec::new(),
+            tags: BTreeSet::new(),
+            package: None,
+            metadata: BTreeMap::new(),
+            effective_from: None,
+            expires_at: None,
+        }
+    }
+
+    pub fn with_description(mut self, description: String) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    pub fn with_pattern(
+        mut self,
+        binding: String,
+        fact_type: String,
+        constraint: Option<Expression>,
+    ) -> Self {
+        self.patterns.push(Pattern {
+            binding,
+            fact_type,
+            constraint,
+        });
+        self
+    }
+
+    pub fn with_accumulate(
+        mut self,
+        name: String,
+        pattern: Pattern,
+        function: String,
+        value: Expression,
+    ) -> Self {
+        self.accumulates.push(Accumulate {
+            name,
+            pattern,
+            function,
+            value,
+        });
+        self
+    }
+
+    pub fn with_binding(mut self, name: String, value: Expression) -> Self {
+        self.bindings.push(Binding { name, value });
+        self
+    }
+
+    pub fn with_tag(mut self, tag: String) -> Self {
+        self.tags.insert(tag);
+        self
+    }
+
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_package(mut self, package: impl Into<String>) -> Self {
+        self.package = Some(package.into());
+        self
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    pub fn with_effective_from(mut self, millis: i64) -> Self {
+        self.effective_from = Some(millis);
+        self
+    }
+
+    pub fn with_expires_at(mut self, millis: i64) -> Self {
+        self.expires_at = Some(millis);
+        self
+    }
+
+    /// Whether the rule may fire at `now_millis`: from `effective_from`
+    /// onwards and before `expires_at`
+    pub fn is_effective_at(&self, now_millis: i64) -> bool {
+        self.effective_from.is_none_or(|from| from <= now_millis)
+            && self.expires_at.is_none_or(|until| now_millis < until)
+    }
+
+    /// Every expression at the top of the rule: pattern constraints,
+    /// accumulated values, bindings, the condition and the actions
+    pub fn expressions(&self) -> impl Iterator<Item = &Expression> {
+        self.patterns
+            .iter()
+            .chain(self.accumulates.iter().map(|a| &a.pattern))
+            .filter_map(|p| p.constraint.as_ref())
+            .chain(self.accumulates.iter().map(|a| &a.value))
+            .chain(self.bindings.iter().map(|b| &b.value))
+            .chain(std::iter::once(&self.when_condition))
+            .chain(&self.then_actions)
+    }
+}
+
+impl From<RuleAst> for Rule {
+    fn from(ast: RuleAst) -> Self {
+        Self {
+            name: ast.name,
+            description: ast.description,
+            salience: ast.salience,
+            when_condition: ast.when_condition,
+            then_actions: ast.then_actions,
+            schedule: None,
+            patterns: Vec::new(),
+            accumulates: Vec::new(),
+            bindings: Vec::new(),
+            tags: BTreeSet::new(),
+            package: None,
+            metadata: BTreeMap::new(),
+            effective_from: None,
+            expires_at: None,
+        }
+    }
+}
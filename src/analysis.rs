@@ -0,0 +1,529 @@
+use crate::ast::Expression;
+use crate::knowledge_base::KnowledgeBase;
+use crate::rule::Rule;
+use crate::visit::{walk_expression, ExpressionVisitor};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A fact, or a (possibly nested) field of a fact, referenced by a rule
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FieldRef {
+    pub fact: String,
+    /// Dot-separated field path, `None` when the whole fact is referenced
+    pub field: Option<String>,
+}
+
+impl FieldRef {
+    pub fn fact(fact: impl Into<String>) -> Self {
+        Self {
+            fact: fact.into(),
+            field: None,
+        }
+    }
+
+    pub fn field(fact: impl Into<String>, field: impl Into<String>) -> Self {
+        Self {
+            fact: fact.into(),
+            field: Some(field.into()),
+        }
+    }
+
+    /// Whether a write to `self` can change the value read through `other`
+    pub fn overlaps(&self, other: &FieldRef) -> bool {
+        if self.fact != other.fact {
+            return false;
+        }
+        match (&self.field, &other.field) {
+            (Some(a), Some(b)) => {
+                a == b || a.starts_with(&format!("{}.", b)) || b.starts_with(&format!("{}.", a))
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for FieldRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{}.{}", self.fact, field),
+            None => write!(f, "{}", self.fact),
+        }
+    }
+}
+
+/// The facts and fields a rule reads (in its condition or action values) and writes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleDependencies {
+    pub reads: BTreeSet<FieldRef>,
+    /// The subset of `reads` referenced by the condition
+    pub condition_reads: BTreeSet<FieldRef>,
+    pub writes: BTreeSet<FieldRef>,
+    /// The condition depends on the clock (e.g. event windows) or calls custom
+    /// functions, and may change without any fact being written
+    pub time_dependent: bool,
+    /// Fact types matched by the rule's patterns
+    pub fact_types: BTreeSet<String>,
+}
+
+impl RuleDependencies {
+    pub fn of(rule: &Rule) -> Self {
+        let mut deps = Self::default();
+        collect_reads(&rule.when_condition, &mut deps.condition_reads);
+        for pattern in &rule.patterns {
+            deps.fact_types.insert(pattern.fact_type.clone());
+            if let Some(constraint) = &pattern.constraint {
+                collect_reads(constraint, &mut deps.condition_reads);
+            }
+        }
+        for accumulate in &rule.accumulates {
+            deps.fact_types.insert(accumulate.pattern.fact_type.clone());
+            if let Some(constraint) = &accumulate.pattern.constraint {
+                collect_reads(constraint, &mut deps.condition_reads);
+            }
+            collect_reads(&accumulate.value, &mut deps.condition_reads);
+            deps.time_dependent |= is_time_dependent(&accumulate.value);
+        }
+        for binding in &rule.bindings {
+            collect_reads(&binding.value, &mut deps.condition_reads);
+            deps.time_dependent |= is_time_dependent(&binding.value);
+        }
+        deps.reads = deps.condition_reads.clone();
+        deps.time_dependent |= is_time_dependent(&rule.when_condition);
+        for action in &rule.then_actions {
+            collect_writes(action, &mut deps.writes);
+            collect_reads(action, &mut deps.reads);
+        }
+
+        let mut locals = BTreeSet::new();
+        for action in &rule.then_actions {
+            collect_locals(action, &mut locals);
+        }
+
+        // Bindings name a matched fact or a computed value, not a fact called by that name
+        let is_binding = |field_ref: &FieldRef| {
+            locals.contains(&field_ref.fact)
+                || rule.patterns.iter().any(|p| p.binding == field_ref.fact)
+                || rule
+                    .accumulates
+                    .iter()
+                    .any(|a| a.name == field_ref.fact || a.pattern.binding == field_ref.fact)
+                || rule.bindings.iter().any(|b| b.name == field_ref.fact)
+        };
+        deps.reads.retain(|read| !is_binding(read));
+        deps.condition_reads.retain(|read| !is_binding(read));
+        deps.writes.retain(|write| !is_binding(write));
+        deps
+    }
+
+    /// Whether the rule's condition may have changed after `modified` were written
+    pub fn condition_affected_by<'a>(
+        &self,
+        mut modified: impl Iterator<Item = &'a FieldRef>,
+    ) -> bool {
+        // Any write may add, remove or change a fact of a matched type
+        self.time_dependent
+            || !self.fact_types.is_empty()
+            || modified.any(|write| self.condition_reads.iter().any(|read| write.overlaps(read)))
+    }
+}
+
+/// The patterns of a rule's `matches` expressions that are string literals,
+/// and so can be compiled once
+pub(crate) fn constant_patterns(rule: &Rule) -> BTreeSet<String> {
+    struct Patterns(BTreeSet<String>);
+
+    impl ExpressionVisitor for Patterns {
+        fn visit_expression(&mut self, expr: &Expression) {
+            if let Expression::Matches(_, pattern) = expr {
+                if let Expression::String(source) = pattern.as_ref() {
+                    self.0.insert(source.clone());
+                }
+            }
+            walk_expression(self, expr);
+        }
+    }
+
+    let mut visitor = Patterns(BTreeSet::new());
+    for expr in rule.expressions() {
+        visitor.visit_expression(expr);
+    }
+    visitor.0
+}
+
+/// The fields of pattern-matched facts a rule reads, named by the fact type
+/// rather than the binding
+pub(crate) fn matched_reads(rule: &Rule) -> BTreeSet<FieldRef> {
+    let patterns: Vec<_> = rule
+        .patterns
+        .iter()
+        .chain(rule.accumulates.iter().map(|a| &a.pattern))
+        .collect();
+    let mut reads = BTreeSet::new();
+    for pattern in &patterns {
+        if let Some(constraint) = &pattern.constraint {
+            collect_reads(constraint, &mut reads);
+        }
+    }
+    for accumulate in &rule.accumulates {
+        collect_reads(&accumulate.value, &mut reads);
+    }
+    for binding in &rule.bindings {
+        collect_reads(&binding.value, &mut reads);
+    }
+    collect_reads(&rule.when_condition, &mut reads);
+    for action in &rule.then_actions {
+        collect_reads(action, &mut reads);
+    }
+    reads
+        .into_iter()
+        .filter_map(|read| {
+            let pattern = patterns.iter().find(|p| p.binding == read.fact)?;
+            Some(FieldRef {
+                fact: pattern.fact_type.clone(),
+                field: read.field,
+            })
+        })
+        .collect()
+}
+
+/// The fact or field an action assigns to
+pub(crate) fn written_field(action: &Expression) -> Option<FieldRef> {
+    match action {
+        Expression::Assignment(name, _)
+        | Expression::InsertLogical(name, _)
+        | Expression::Update(name) => Some(FieldRef::fact(name.clone())),
+        Expression::FieldAssignment(name, field, _) => {
+            Some(FieldRef::field(name.clone(), field.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Every fact or field an action may assign to, whichever branches it takes
+pub(crate) fn collect_writes(action: &Expression, writes: &mut BTreeSet<FieldRef>) {
+    match action {
+        Expression::If(_, then_actions, else_actions) => {
+            for action in then_actions.iter().chain(else_actions) {
+                collect_writes(action, writes);
+            }
+        }
+        Expression::ForEach(item, collection, actions) => {
+            writes.extend(field_path(collection));
+            let mut item_writes = BTreeSet::new();
+            for action in actions {
+                collect_writes(action, &mut item_writes);
+            }
+            writes.extend(item_writes.into_iter().filter(|write| write.fact != *item));
+        }
+        _ => writes.extend(written_field(action)),
+    }
+}
+
+/// Names of the local values an action declares with `let`
+fn collect_locals(action: &Expression, locals: &mut BTreeSet<String>) {
+    match action {
+        Expression::Let(name, _) => {
+            locals.insert(name.clone());
+        }
+        Expression::If(_, then_actions, else_actions) => {
+            for action in then_actions.iter().chain(else_actions) {
+                collect_locals(action, locals);
+            }
+        }
+        Expression::ForEach(_, _, actions) => {
+            for action in actions {
+                collect_locals(action, locals);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_time_dependent(expr: &Expression) -> bool {
+    struct TimeDependence(bool);
+
+    impl ExpressionVisitor for TimeDependence {
+        fn visit_expression(&mut self, expr: &Expression) {
+            match expr {
+                // Custom functions may consult the clock or the evaluation context
+                Expression::EventCount(_, _) | Expression::FunctionCall(_, _) => self.0 = true,
+                _ => walk_expression(self, expr),
+            }
+        }
+    }
+
+    let mut visitor = TimeDependence(false);
+    visitor.visit_expression(expr);
+    visitor.0
+}
+
+/// Whether `expr` reads fact `name` or any of its fields
+pub(crate) fn reads_fact(expr: &Expression, name: &str) -> bool {
+    let mut reads = BTreeSet::new();
+    collect_reads(expr, &mut reads);
+    reads.iter().any(|read| read.fact == name)
+}
+
+fn collect_reads(expr: &Expression, reads: &mut BTreeSet<FieldRef>) {
+    if let Some(field_ref) = field_path(expr) {
+        reads.insert(field_ref);
+        return;
+    }
+
+    match expr {
+        Expression::String(_)
+        | Expression::Number(_)
+        | Expression::Boolean(_)
+        | Expression::Duration(_)
+        | Expression::Null
+        | Expression::EventCount(_, _)
+        | Expression::Update(_)
+        | Expression::Script(_) => {}
+        Expression::Variable(name) => {
+            reads.insert(FieldRef::fact(name.clone()));
+        }
+        Expression::FieldAccess(object, _) | Expression::OptionalFieldAccess(object, _) => {
+            collect_reads(object, reads)
+        }
+        Expression::Add(l, r)
+        | Expression::Subtract(l, r)
+        | Expression::Multiply(l, r)
+        | Expression::Divide(l, r)
+        | Expression::Modulo(l, r)
+        | Expression::Equal(l, r)
+        | Expression::NotEqual(l, r)
+        | Expression::LessThan(l, r)
+        | Expression::LessEqual(l, r)
+        | Expression::GreaterThan(l, r)
+        | Expression::GreaterEqual(l, r)
+        | Expression::And(l, r)
+        | Expression::Or(l, r)
+        | Expression::Coalesce(l, r)
+        | Expression::In(l, r)
+        | Expression::Matches(l, r)
+        | Expression::Index(l, r) => {
+            collect_reads(l, reads);
+            collect_reads(r, reads);
+        }
+        Expression::Not(inner) | Expression::Negate(inner) => collect_reads(inner, reads),
+        Expression::FunctionCall(_, args) | Expression::Array(args) => {
+            for arg in args {
+                collect_reads(arg, reads);
+            }
+        }
+        Expression::MethodCall(receiver, _, args) => {
+            collect_reads(receiver, reads);
+            for arg in args {
+                collect_reads(arg, reads);
+            }
+        }
+        Expression::Assignment(_, value)
+        | Expression::FieldAssignment(_, _, value)
+        | Expression::InsertLogical(_, value)
+        | Expression::Let(_, value) => collect_reads(value, reads),
+        Expression::If(condition, then_actions, else_actions) => {
+            collect_reads(condition, reads);
+            for action in then_actions.iter().chain(else_actions) {
+                collect_reads(action, reads);
+            }
+        }
+        Expression::LetIn(name, value, body) => {
+            collect_reads(value, reads);
+            let mut body_reads = BTreeSet::new();
+            collect_reads(body, &mut body_reads);
+            reads.extend(body_reads.into_iter().filter(|read| read.fact != *name));
+        }
+        Expression::ForEach(item, collection, actions) => {
+            collect_reads(collection, reads);
+            let mut item_reads = BTreeSet::new();
+            for action in actions {
+                collect_reads(action, &mut item_reads);
+            }
+            reads.extend(item_reads.into_iter().filter(|read| read.fact != *item));
+        }
+    }
+}
+
+/// Resolve `fact.a.b` style access chains to a single field reference
+fn field_path(expr: &Expression) -> Option<FieldRef> {
+    match expr {
+        Expression::FieldAccess(object, field) | Expression::OptionalFieldAccess(object, field) => {
+            match field_path(object)? {
+                FieldRef {
+                    fact,
+                    field: Some(parent),
+                } => Some(FieldRef::field(fact, format!("{}.{}", parent, field))),
+                FieldRef { fact, field: None } => Some(FieldRef::field(fact, field.clone())),
+            }
+        }
+        Expression::Variable(name) => Some(FieldRef::fact(name.clone())),
+        _ => None,
+    }
+}
+
+/// `producer` writes `field`, which `consumer` reads
+#[derive(Debug, Clone, PartialEq)]
+pub struct DependencyEdge {
+    pub producer: String,
+    pub consumer: String,
+    pub field: FieldRef,
+}
+
+/// Producer/consumer relationships between the rules of a knowledge base
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    pub rules: BTreeMap<String, RuleDependencies>,
+    pub edges: Vec<DependencyEdge>,
+}
+
+impl DependencyGraph {
+    pub fn build(knowledge_base: &KnowledgeBase) -> Self {
+        let rules: BTreeMap<String, RuleDependencies> = knowledge_base
+            .get_rules()
+            .iter()
+            .filter_map(|rule| {
+                let deps = knowledge_base.get_dependencies(&rule.name)?;
+                Some((rule.name.clone(), deps.clone()))
+            })
+            .collect();
+
+        let mut edges = Vec::new();
+        for (producer, producer_deps) in &rules {
+            for (consumer, consumer_deps) in &rules {
+                for write in &producer_deps.writes {
+                    if consumer_deps.reads.iter().any(|read| write.overlaps(read)) {
+                        edges.push(DependencyEdge {
+                            producer: producer.clone(),
+                            consumer: consumer.clone(),
+                            field: write.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Self { rules, edges }
+    }
+
+    /// Rules whose conditions or actions read something `rule` writes
+    pub fn consumers_of(&self, rule: &str) -> BTreeSet<&str> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.producer == rule)
+            .map(|edge| edge.consumer.as_str())
+            .collect()
+    }
+
+    /// Rules that write something `rule` reads
+    pub fn producers_of(&self, rule: &str) -> BTreeSet<&str> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.consumer == rule)
+            .map(|edge| edge.producer.as_str())
+            .collect()
+    }
+
+    /// A Graphviz DOT graph of the rules (boxes) and the facts and fields
+    /// (ellipses) they read and write. Render it with e.g. `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph rules {\n    rankdir=LR;\n");
+        let fields: BTreeSet<&FieldRef> = self
+            .rules
+            .values()
+            .flat_map(|deps| deps.reads.iter().chain(&deps.writes))
+            .collect();
+        for rule in self.rules.keys() {
+            dot += &format!("    {} [shape=box];\n", quote(&format!("rule {}", rule)));
+        }
+        for field in fields {
+            dot += &format!(
+                "    {} [shape=ellipse, label={}];\n",
+                quote(&format!("field {}", field)),
+                quote(&field.to_string())
+            );
+        }
+        for (rule, deps) in &self.rules {
+            let rule = quote(&format!("rule {}", rule));
+            for read in &deps.reads {
+                let field = quote(&format!("field {}", read));
+                dot += &format!("    {} -> {} [label=\"reads\"];\n", field, rule);
+            }
+            for write in &deps.writes {
+                let field = quote(&format!("field {}", write));
+                dot += &format!("    {} -> {} [label=\"writes\"];\n", rule, field);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Width of the salience bands `KnowledgeBaseStats` counts rules in
+pub const SALIENCE_BAND_WIDTH: i32 = 10;
+
+/// Size and complexity figures for a knowledge base, see `KnowledgeBase::stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KnowledgeBaseStats {
+    pub rule_count: usize,
+    /// Rules per salience band, keyed by the band's lowest salience: 0 counts
+    /// salience 0 to 9, -10 counts -10 to -1
+    pub rules_per_salience_band: BTreeMap<i32, usize>,
+    pub average_condition_depth: f64,
+    pub max_condition_depth: usize,
+    /// Facts with the number of rules reading, writing or matching them, the
+    /// most referenced first
+    pub most_referenced_facts: Vec<(String, usize)>,
+    pub rules_without_actions: Vec<String>,
+}
+
+impl KnowledgeBaseStats {
+    pub fn collect(knowledge_base: &KnowledgeBase) -> Self {
+        let rules = knowledge_base.get_rules();
+        let mut stats = Self {
+            rule_count: rules.len(),
+            ..Self::default()
+        };
+
+        let mut total_depth = 0;
+        let mut references: BTreeMap<&str, usize> = BTreeMap::new();
+        for rule in rules {
+            let band = rule.salience.div_euclid(SALIENCE_BAND_WIDTH) * SALIENCE_BAND_WIDTH;
+            *stats.rules_per_salience_band.entry(band).or_default() += 1;
+
+            let depth = rule.when_condition.depth();
+            total_depth += depth;
+            stats.max_condition_depth = stats.max_condition_depth.max(depth);
+
+            if rule.then_actions.is_empty() {
+                stats.rules_without_actions.push(rule.name.clone());
+            }
+
+            if let Some(deps) = knowledge_base.get_dependencies(&rule.name) {
+                let facts: BTreeSet<&str> = deps
+                    .reads
+                    .iter()
+                    .chain(&deps.writes)
+                    .map(|field_ref| field_ref.fact.as_str())
+                    .chain(deps.fact_types.iter().map(String::as_str))
+                    .collect();
+                for fact in facts {
+                    *references.entry(fact).or_default() += 1;
+                }
+            }
+        }
+        if !rules.is_empty() {
+            stats.average_condition_depth = total_depth as f64 / rules.len() as f64;
+        }
+
+        stats.most_referenced_facts = references
+            .into_iter()
+            .map(|(fact, count)| (fact.to_string(), count))
+            .collect();
+        // Stable, so facts referenced equally often stay in name order
+        stats
+            .most_referenced_facts
+            .sort_by(|(_, a), (_, b)| b.cmp(a));
+        stats
+    }
+}
@@ -0,0 +1,78 @@
+use crate::facts::FactValue;
+use crate::report::ValueChange;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// What an execution was given, what it decided and what it changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When the execution started, by the engine's clock
+    pub timestamp_ms: i64,
+    /// Every fact in working memory before execution
+    pub input: BTreeMap<String, FactValue>,
+    pub rules_fired: Vec<String>,
+    pub changes: Vec<ValueChange>,
+    /// Why the execution failed, in which case its changes were rolled back
+    pub error: Option<String>,
+}
+
+/// Destination for audit records, such as a file, database or queue
+pub trait AuditWriter: Send + Sync {
+    fn write(&self, record: &AuditRecord) -> io::Result<()>;
+}
+
+/// Writes each record as a line of JSON
+pub struct JsonLinesWriter<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<W: Write + Send> AuditWriter for JsonLinesWriter<W> {
+    fn write(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        serde_json::to_writer(&mut *out, record)?;
+        out.write_all(b"\n")?;
+        out.flush()
+    }
+}
+
+/// Records every execution of an engine it is configured on. If a record
+/// cannot be written the execution fails and its changes are rolled back.
+#[derive(Clone)]
+pub struct AuditLog {
+    writer: Arc<dyn AuditWriter>,
+}
+
+impl AuditLog {
+    pub fn new(writer: impl AuditWriter + 'static) -> Self {
+        Self {
+            writer: Arc::new(writer),
+        }
+    }
+
+    pub fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        self.writer.write(record)
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,191 @@
+use serde::de::Error as _;
+use serde_json::{Map, Number, Value};
+
+// Leading bytes of every encoding, the last one being the format's version
+const MAGIC: &[u8; 4] = b"RRB\x01";
+// Deepest nesting accepted when decoding, as for serde_json text
+const MAX_DEPTH: usize = 128;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UNSIGNED: u8 = 3;
+const SIGNED: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const ARRAY: u8 = 7;
+const OBJECT: u8 = 8;
+
+/// A compact binary encoding of the serde JSON data model. Integers are
+/// variable-length and floats keep their exact bits, so anything serialized
+/// through `serde_json::Value` restores exactly as it would from JSON text.
+pub(crate) fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    write_value(&mut bytes, value);
+    bytes
+}
+
+/// Decode bytes written by `encode`
+pub(crate) fn decode(bytes: &[u8]) -> Result<Value, serde_json::Error> {
+    let rest = bytes
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| serde_json::Error::custom("not a rust-runes binary encoding"))?;
+    let mut reader = Reader {
+        bytes: rest,
+        pos: 0,
+    };
+    let value = reader.value(0)?;
+    if reader.pos != rest.len() {
+        return Err(reader.error("trailing bytes"));
+    }
+    Ok(value)
+}
+
+fn write_value(bytes: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => bytes.push(NULL),
+        Value::Bool(false) => bytes.push(FALSE),
+        Value::Bool(true) => bytes.push(TRUE),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                bytes.push(UNSIGNED);
+                write_varint(bytes, n);
+            } else if let Some(n) = n.as_i64() {
+                bytes.push(SIGNED);
+                // Zigzag, so small negative numbers stay short
+                write_varint(bytes, ((n << 1) ^ (n >> 63)) as u64);
+            } else {
+                bytes.push(FLOAT);
+                bytes.extend(n.as_f64().unwrap_or_default().to_le_bytes());
+            }
+        }
+        Value::String(s) => {
+            bytes.push(STRING);
+            write_str(bytes, s);
+        }
+        Value::Array(items) => {
+            bytes.push(ARRAY);
+            write_varint(bytes, items.len() as u64);
+            for item in items {
+                write_value(bytes, item);
+            }
+        }
+        Value::Object(fields) => {
+            bytes.push(OBJECT);
+            write_varint(bytes, fields.len() as u64);
+            for (key, value) in fields {
+                write_str(bytes, key);
+                write_value(bytes, value);
+            }
+        }
+    }
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_varint(bytes, s.len() as u64);
+    bytes.extend(s.as_bytes());
+}
+
+/// LEB128: seven bits per byte, the high bit set on all but the last
+fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, reason: &str) -> serde_json::Error {
+        serde_json::Error::custom(format!("{} at byte {}", reason, self.pos + MAGIC.len()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], serde_json::Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64, serde_json::Error> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                break;
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.error("integer too large"))
+    }
+
+    /// A length, checked against the bytes left so a corrupt one cannot
+    /// reserve huge amounts of memory
+    fn len(&mut self) -> Result<usize, serde_json::Error> {
+        let len = self.varint()?;
+        usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.bytes.len() - self.pos)
+            .ok_or_else(|| self.error("length out of range"))
+    }
+
+    fn string(&mut self) -> Result<String, serde_json::Error> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, serde_json::Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        Ok(match self.take(1)?[0] {
+            NULL => Value::Null,
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            UNSIGNED => Value::Number(self.varint()?.into()),
+            SIGNED => {
+                let n = self.varint()?;
+                Value::Number((((n >> 1) as i64) ^ -((n & 1) as i64)).into())
+            }
+            FLOAT => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(self.take(8)?);
+                let n = f64::from_le_bytes(bits);
+                Value::Number(Number::from_f64(n).ok_or_else(|| self.error("non-finite float"))?)
+            }
+            STRING => Value::String(self.string()?),
+            ARRAY => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            OBJECT => {
+                let len = self.len()?;
+                let mut fields = Map::new();
+                for _ in 0..len {
+                    let key = self.string()?;
+                    fields.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(fields)
+            }
+            _ => return Err(self.error("unknown tag")),
+        })
+    }
+}
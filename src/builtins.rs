@@ -0,0 +1,439 @@
+use crate::base64;
+use crate::clock::Clock;
+use crate::datetime::{self, Civil};
+use crate::decimal::Decimal;
+use crate::engine::{values_equal, EngineError};
+use crate::facts::FactValue;
+use sha2::{Digest, Sha256};
+
+/// Call the named builtin function, available to every rule without being
+/// registered, or `None` if there is no builtin by that name
+pub(crate) fn call(
+    name: &str,
+    args: &[FactValue],
+    clock: &dyn Clock,
+) -> Option<Result<FactValue, EngineError>> {
+    match name {
+        "now" => return Some(Ok(FactValue::DateTime(clock.now_millis()))),
+        "date" => return Some(date(args)),
+        "decimal" => return Some(decimal(args)),
+        "money" => return Some(money(args)),
+        "isNull" | "isDefined" => return Some(null_check(name, args)),
+        "geopoint" => return Some(geopoint(args)),
+        "distanceKm" => return Some(distance_km(args)),
+        "len" => return Some(len(args)),
+        "contains" => return Some(contains(args)),
+        "slice" => return Some(slice(args)),
+        "push" | "remove" => return Some(push_or_remove(name, args)),
+        "bytes" => return Some(bytes(args)),
+        "base64_encode" => return Some(base64_encode(args)),
+        "base64_decode" => return Some(base64_decode(args)),
+        "sha256" => return Some(sha256(args)),
+        "toUpper" | "toLower" | "trim" => return Some(transform_string(name, args)),
+        "startsWith" | "endsWith" => return Some(string_affix(name, args)),
+        "year" | "month" | "day" | "hour" | "minute" | "second" | "weekday" => {
+            return Some(date_field(name, args))
+        }
+        _ => {}
+    }
+    let aggregate: fn(Vec<f64>) -> FactValue = match name {
+        "sum" => |values| FactValue::Number(values.iter().sum()),
+        "avg" => |values| {
+            if values.is_empty() {
+                FactValue::Null
+            } else {
+                FactValue::Number(values.iter().sum::<f64>() / values.len() as f64)
+            }
+        },
+        "min" => |values| {
+            values
+                .into_iter()
+                .reduce(f64::min)
+                .map_or(FactValue::Null, FactValue::Number)
+        },
+        "max" => |values| {
+            values
+                .into_iter()
+                .reduce(f64::max)
+                .map_or(FactValue::Null, FactValue::Number)
+        },
+        "count" => {
+            return Some(selected(name, args).map(|items| FactValue::Number(items.len() as f64)))
+        }
+        _ => return None,
+    };
+    Some(numbers(name, args).map(aggregate))
+}
+
+/// Call the method `args[0].name(args[1..])`, or `None` if the receiver's
+/// type has no method by that name. A type's methods are the builtins that
+/// take it as their first argument.
+pub(crate) fn call_method(
+    name: &str,
+    args: &[FactValue],
+    clock: &dyn Clock,
+) -> Option<Result<FactValue, EngineError>> {
+    let methods: &[&str] = match args.first()? {
+        FactValue::String(_) => &[
+            "len",
+            "contains",
+            "toUpper",
+            "toLower",
+            "trim",
+            "startsWith",
+            "endsWith",
+            "bytes",
+            "sha256",
+        ],
+        FactValue::Array(_) => &[
+            "len", "contains", "slice", "sum", "avg", "min", "max", "count",
+        ],
+        FactValue::Object(_) => &["len"],
+        FactValue::Bytes(_) => &["len", "base64_encode", "sha256"],
+        FactValue::DateTime(_) => &[
+            "year", "month", "day", "hour", "minute", "second", "weekday",
+        ],
+        FactValue::GeoPoint { .. } => &["distanceKm"],
+        _ => &[],
+    };
+    if methods.contains(&name) {
+        call(name, args, clock)
+    } else {
+        None
+    }
+}
+
+/// The items of the array argument, or the given field of each item when
+/// called as `name(array, "field")`
+fn selected<'a>(name: &str, args: &'a [FactValue]) -> Result<Vec<&'a FactValue>, EngineError> {
+    let (items, field) = match args {
+        [FactValue::Array(items)] => (items, None),
+        [FactValue::Array(items), FactValue::String(field)] => (items, Some(field)),
+        _ => {
+            return Err(EngineError::TypeError(format!(
+                "{} expects an array and an optional field name",
+                name
+            )))
+        }
+    };
+    let Some(field) = field else {
+        return Ok(items.iter().collect());
+    };
+    items
+        .iter()
+        .map(|item| match item {
+            FactValue::Object(object) => object.get(field).ok_or_else(|| {
+                EngineError::EvaluationError(format!("Field '{}' not found", field))
+            }),
+            _ => Err(EngineError::TypeError(
+                "Cannot access field on non-object".to_string(),
+            )),
+        })
+        .collect()
+}
+
+fn numbers(name: &str, args: &[FactValue]) -> Result<Vec<f64>, EngineError> {
+    selected(name, args)?
+        .into_iter()
+        .map(|value| {
+            value
+                .as_number()
+                .ok_or_else(|| EngineError::TypeError(format!("{} expects numbers", name)))
+        })
+        .collect()
+}
+
+/// A DateTime from an ISO 8601 string or milliseconds since the Unix epoch
+fn date(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    match args {
+        [FactValue::String(text)] => datetime::parse(text)
+            .map(FactValue::DateTime)
+            .ok_or_else(|| EngineError::EvaluationError(format!("Invalid date '{}'", text))),
+        [FactValue::DateTime(millis) | FactValue::Integer(millis)] => {
+            Ok(FactValue::DateTime(*millis))
+        }
+        // `as` would saturate out-of-range values and read NaN as the epoch
+        [FactValue::Number(millis)] if (i64::MIN as f64..i64::MAX as f64).contains(millis) => {
+            Ok(FactValue::DateTime(*millis as i64))
+        }
+        [FactValue::Number(millis)] => Err(EngineError::EvaluationError(format!(
+            "Invalid date {}",
+            millis
+        ))),
+        _ => Err(EngineError::TypeError(
+            "date expects a string or a number of milliseconds".to_string(),
+        )),
+    }
+}
+
+/// An exact Decimal from a string such as `"19.99"` or a number
+fn decimal(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let decimal = match args {
+        [FactValue::String(text)] => text.parse().ok(),
+        [FactValue::Decimal(d)] => Some(*d),
+        [FactValue::Integer(n)] => Some(Decimal::from(*n)),
+        [FactValue::Number(n)] => Decimal::from_f64(*n),
+        _ => {
+            return Err(EngineError::TypeError(
+                "decimal expects a string or a number".to_string(),
+            ))
+        }
+    };
+    decimal
+        .map(FactValue::Decimal)
+        .ok_or_else(|| EngineError::EvaluationError(format!("Invalid decimal {:?}", args[0])))
+}
+
+fn null_check(name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [value] = args else {
+        return Err(EngineError::TypeError(format!(
+            "{} expects one value",
+            name
+        )));
+    };
+    let is_null = matches!(value, FactValue::Null);
+    Ok(FactValue::Boolean(is_null == (name == "isNull")))
+}
+
+/// `money(amount, currency)`, the amount given as for `decimal`
+fn money(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [amount, FactValue::String(currency)] = args else {
+        return Err(EngineError::TypeError(
+            "money expects an amount and a currency code".to_string(),
+        ));
+    };
+    let FactValue::Decimal(amount) = decimal(std::slice::from_ref(amount))? else {
+        unreachable!("decimal returns a Decimal");
+    };
+    Ok(FactValue::Money {
+        amount,
+        currency: currency.to_uppercase(),
+    })
+}
+
+fn geopoint(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let point = match args {
+        [lat, lon] => lat.as_number().zip(lon.as_number()),
+        _ => None,
+    };
+    match point {
+        Some((lat, lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => {
+            Ok(FactValue::GeoPoint { lat, lon })
+        }
+        Some((lat, lon)) => Err(EngineError::EvaluationError(format!(
+            "Invalid coordinates ({}, {})",
+            lat, lon
+        ))),
+        None => Err(EngineError::TypeError(
+            "geopoint expects a latitude and a longitude".to_string(),
+        )),
+    }
+}
+
+/// The great-circle distance between two points, by the haversine formula
+fn distance_km(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    const EARTH_RADIUS_KM: f64 = 6371.0088;
+    let [FactValue::GeoPoint {
+        lat: lat1,
+        lon: lon1,
+    }, FactValue::GeoPoint {
+        lat: lat2,
+        lon: lon2,
+    }] = args
+    else {
+        return Err(EngineError::TypeError(
+            "distanceKm expects two geo points".to_string(),
+        ));
+    };
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let half_dlat = (lat2 - lat1) / 2.0;
+    let half_dlon = (lon2 - lon1).to_radians() / 2.0;
+    let h = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlon.sin().powi(2);
+    Ok(FactValue::Number(
+        2.0 * EARTH_RADIUS_KM * h.sqrt().min(1.0).asin(),
+    ))
+}
+
+/// A UTC calendar field of a DateTime argument
+fn date_field(name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [FactValue::DateTime(millis)] = args else {
+        return Err(EngineError::TypeError(format!("{} expects a date", name)));
+    };
+    let civil = Civil::from_millis(*millis);
+    let field = match name {
+        "year" => civil.year,
+        "month" => civil.month.into(),
+        "day" => civil.day.into(),
+        "hour" => civil.hour.into(),
+        "minute" => civil.minute.into(),
+        "second" => civil.second.into(),
+        _ => civil.weekday().into(),
+    };
+    Ok(FactValue::Integer(field))
+}
+
+/// A whole-number array index or bound
+pub(crate) fn index(value: &FactValue) -> Result<i64, EngineError> {
+    match value {
+        FactValue::Integer(n) => Ok(*n),
+        FactValue::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+        _ => Err(EngineError::TypeError(
+            "Array indices must be whole numbers".to_string(),
+        )),
+    }
+}
+
+/// The number of items in an array or object, characters in a string or
+/// bytes in binary data
+fn len(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let len = match args {
+        [FactValue::Array(items)] => items.len(),
+        [FactValue::Object(object)] => object.len(),
+        [FactValue::String(text)] => text.chars().count(),
+        [FactValue::Bytes(bytes)] => bytes.len(),
+        _ => {
+            return Err(EngineError::TypeError(
+                "len expects an array, object, string or bytes".to_string(),
+            ))
+        }
+    };
+    Ok(FactValue::Integer(len as i64))
+}
+
+/// Whether an array has an item equal to the value, or a string contains
+/// the substring
+pub(crate) fn contains(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let found = match args {
+        [FactValue::Array(items), value] => items.iter().any(|item| values_equal(item, value)),
+        [FactValue::String(text), FactValue::String(part)] => text.contains(part.as_str()),
+        _ => {
+            return Err(EngineError::TypeError(
+                "contains expects an array and a value, or two strings".to_string(),
+            ))
+        }
+    };
+    Ok(FactValue::Boolean(found))
+}
+
+/// `slice(items, start, end)`: the items from `start` up to but not including
+/// `end` (or the end of the array). Negative bounds count from the end and
+/// out-of-range bounds are clamped, as in Python.
+fn slice(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let (items, start, end) = match args {
+        [FactValue::Array(items), start] => (items, start, None),
+        [FactValue::Array(items), start, end] => (items, start, Some(end)),
+        _ => {
+            return Err(EngineError::TypeError(
+                "slice expects an array, a start and an optional end".to_string(),
+            ))
+        }
+    };
+    let len = items.len() as i64;
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 { bound + len } else { bound };
+        bound.clamp(0, len) as usize
+    };
+    let start = clamp(index(start)?);
+    let end = match end {
+        Some(end) => clamp(index(end)?),
+        None => items.len(),
+    };
+    Ok(FactValue::Array(
+        items
+            .get(start..end.max(start))
+            .unwrap_or_default()
+            .to_vec(),
+    ))
+}
+
+/// A copy of the array with the value appended, or with its first item equal
+/// to the value removed. The `push` and `remove` actions assign the result
+/// back to the array.
+fn push_or_remove(name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [FactValue::Array(items), value] = args else {
+        return Err(EngineError::TypeError(format!(
+            "{} expects an array and a value",
+            name
+        )));
+    };
+    let mut items = items.clone();
+    if name == "push" {
+        items.push(value.clone());
+    } else if let Some(position) = items.iter().position(|item| values_equal(item, value)) {
+        items.remove(position);
+    }
+    Ok(FactValue::Array(items))
+}
+
+/// A string in upper or lower case, or without leading and trailing whitespace
+fn transform_string(name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [FactValue::String(text)] = args else {
+        return Err(EngineError::TypeError(format!("{} expects a string", name)));
+    };
+    Ok(FactValue::String(match name {
+        "toUpper" => text.to_uppercase(),
+        "toLower" => text.to_lowercase(),
+        _ => text.trim().to_string(),
+    }))
+}
+
+/// Whether a string starts or ends with another
+fn string_affix(name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [FactValue::String(text), FactValue::String(affix)] = args else {
+        return Err(EngineError::TypeError(format!(
+            "{} expects two strings",
+            name
+        )));
+    };
+    Ok(FactValue::Boolean(if name == "startsWith" {
+        text.starts_with(affix.as_str())
+    } else {
+        text.ends_with(affix.as_str())
+    }))
+}
+
+/// The UTF-8 encoding of a string as bytes
+fn bytes(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    match args {
+        [FactValue::String(text)] => Ok(FactValue::Bytes(text.as_bytes().to_vec())),
+        [FactValue::Bytes(bytes)] => Ok(FactValue::Bytes(bytes.clone())),
+        _ => Err(EngineError::TypeError("bytes expects a string".to_string())),
+    }
+}
+
+fn base64_encode(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    match args {
+        [FactValue::Bytes(bytes)] => Ok(FactValue::String(base64::encode(bytes))),
+        _ => Err(EngineError::TypeError(
+            "base64_encode expects bytes".to_string(),
+        )),
+    }
+}
+
+fn base64_decode(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let [FactValue::String(text)] = args else {
+        return Err(EngineError::TypeError(
+            "base64_decode expects a string".to_string(),
+        ));
+    };
+    base64::decode(text)
+        .map(FactValue::Bytes)
+        .ok_or_else(|| EngineError::EvaluationError(format!("Invalid base64 '{}'", text)))
+}
+
+/// The SHA-256 digest of bytes, or of a string's UTF-8 encoding, as lowercase hex
+fn sha256(args: &[FactValue]) -> Result<FactValue, EngineError> {
+    let data = match args {
+        [FactValue::Bytes(bytes)] => bytes.as_slice(),
+        [FactValue::String(text)] => text.as_bytes(),
+        _ => {
+            return Err(EngineError::TypeError(
+                "sha256 expects bytes or a string".to_string(),
+            ))
+        }
+    };
+    let digest = Sha256::digest(data);
+    Ok(FactValue::String(
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    ))
+}
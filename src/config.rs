@@ -0,0 +1,227 @@
+use crate::audit::AuditLog;
+use crate::clock::{Clock, SystemClock};
+use crate::engine::RuleEngine;
+use crate::facts::CoercionPolicy;
+use crate::resolver::FactResolver;
+#[cfg(feature = "script")]
+use crate::script::ScriptEngine;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Order in which matched rules are fired within a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictResolution {
+    /// Higher salience first, ties broken by insertion order
+    #[default]
+    Salience,
+    /// The order rules were added to the knowledge base
+    InsertionOrder,
+}
+
+/// Bounds on the work untrusted rules can make the engine do. `None` means
+/// unlimited. Exceeding a limit fails with `EngineError::ResourceLimit`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Deepest expression tree a rule may contain, checked when the rule is added
+    pub max_expression_depth: Option<usize>,
+    /// Longest string an expression may produce
+    pub max_string_length: Option<usize>,
+    /// Largest array an expression may produce
+    pub max_array_size: Option<usize>,
+    /// Most custom function calls a single execution may make
+    pub max_function_calls: Option<usize>,
+}
+
+/// Order used by `<`, `<=`, `>` and `>=` on strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// By Unicode code point, so `"B" < "a"`
+    #[default]
+    Binary,
+    /// By Unicode code point, ignoring case
+    CaseInsensitive,
+}
+
+impl Collation {
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::CaseInsensitive => a
+                .chars()
+                .flat_map(char::to_lowercase)
+                .cmp(b.chars().flat_map(char::to_lowercase)),
+        }
+    }
+}
+
+/// What happens when a NaN is used in or produced by an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NanHandling {
+    /// Follow IEEE 754: comparisons with NaN are false and arithmetic yields NaN
+    #[default]
+    False,
+    /// Fail with `EngineError::InvalidNumber`
+    Error,
+}
+
+/// How numbers are compared and checked, so financial rules behave predictably
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NumericPolicy {
+    /// Numbers at most this far apart are equal for `==` and `!=`
+    pub epsilon: Option<f64>,
+    pub nan: NanHandling,
+    /// Fail with `EngineError::InvalidNumber` when an expression uses or
+    /// produces an infinite number, e.g. on overflow
+    pub reject_infinity: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Maximum number of match-fire cycles per execution. Execution stops
+    /// early once a cycle fires no rules.
+    pub max_cycles: usize,
+    /// When false, type errors raised while evaluating a condition make the
+    /// rule not match instead of failing the execution
+    pub strict_types: bool,
+    pub conflict_resolution: ConflictResolution,
+    /// Time source for temporal conditions
+    pub clock: Arc<dyn Clock>,
+    pub limits: ResourceLimits,
+    pub numeric: NumericPolicy,
+    pub collation: Collation,
+    /// Comparison of values of different types, such as `"5" == 5`
+    pub coercion: CoercionPolicy,
+    /// When true, null stands for an unknown value: comparisons with null
+    /// give null, `&&`, `||` and `!` follow Kleene's three-valued logic, and
+    /// missing fields read as null. A condition that is unknown does not match.
+    pub three_valued_logic: bool,
+    /// When true, modifying a fact does not by itself cause dependent rules to
+    /// be re-evaluated in later cycles; rules must declare it with `update(fact)`
+    pub explicit_updates: bool,
+    /// Consulted for facts a rule reads that are not in working memory
+    pub fact_resolver: Option<Arc<dyn FactResolver>>,
+    /// Runs `script` actions in place of the built-in `ScriptInterpreter`
+    #[cfg(feature = "script")]
+    pub script_engine: Option<Arc<dyn ScriptEngine>>,
+    pub audit_log: Option<AuditLog>,
+    /// When true, each execution lists the values it changed in
+    /// `ExecutionResult::changes`
+    pub track_changes: bool,
+    /// When true, rules record which of them last wrote each field, see
+    /// `Fact::provenance`
+    pub track_provenance: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            max_cycles: 1,
+            strict_types: true,
+            conflict_resolution: ConflictResolution::default(),
+            clock: Arc::new(SystemClock),
+            limits: ResourceLimits::default(),
+            numeric: NumericPolicy::default(),
+            collation: Collation::default(),
+            coercion: CoercionPolicy::default(),
+            three_valued_logic: false,
+            explicit_updates: false,
+            fact_resolver: None,
+            #[cfg(feature = "script")]
+            script_engine: None,
+            audit_log: None,
+            track_changes: false,
+            track_provenance: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RuleEngineBuilder {
+    config: EngineConfig,
+}
+
+impl RuleEngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_cycles(mut self, max_cycles: usize) -> Self {
+        self.config.max_cycles = max_cycles;
+        self
+    }
+
+    pub fn strict_types(mut self, strict_types: bool) -> Self {
+        self.config.strict_types = strict_types;
+        self
+    }
+
+    pub fn conflict_resolution(mut self, conflict_resolution: ConflictResolution) -> Self {
+        self.config.conflict_resolution = conflict_resolution;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    pub fn numeric(mut self, numeric: NumericPolicy) -> Self {
+        self.config.numeric = numeric;
+        self
+    }
+
+    pub fn collation(mut self, collation: Collation) -> Self {
+        self.config.collation = collation;
+        self
+    }
+
+    pub fn coercion(mut self, coercion: CoercionPolicy) -> Self {
+        self.config.coercion = coercion;
+        self
+    }
+
+    pub fn three_valued_logic(mut self, three_valued_logic: bool) -> Self {
+        self.config.three_valued_logic = three_valued_logic;
+        self
+    }
+
+    pub fn explicit_updates(mut self, explicit_updates: bool) -> Self {
+        self.config.explicit_updates = explicit_updates;
+        self
+    }
+
+    pub fn fact_resolver(mut self, fact_resolver: Arc<dyn FactResolver>) -> Self {
+        self.config.fact_resolver = Some(fact_resolver);
+        self
+    }
+
+    #[cfg(feature = "script")]
+    pub fn script_engine(mut self, script_engine: Arc<dyn ScriptEngine>) -> Self {
+        self.config.script_engine = Some(script_engine);
+        self
+    }
+
+    pub fn audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.config.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn track_changes(mut self, track_changes: bool) -> Self {
+        self.config.track_changes = track_changes;
+        self
+    }
+
+    pub fn track_provenance(mut self, track_provenance: bool) -> Self {
+        self.config.track_provenance = track_provenance;
+        self
+    }
+
+    pub fn build(self) -> RuleEngine {
+        RuleEngine::with_config(self.config)
+    }
+}
@@ -0,0 +1,67 @@
+use crate::engine::EngineError;
+use crate::facts::FactValue;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A function rules can call by name, registered with
+/// `RuleEngine::register_function`
+pub type CustomFunction =
+    Arc<dyn Fn(&EvalContext, &[FactValue]) -> Result<FactValue, EngineError> + Send + Sync>;
+
+/// Ambient data for a single execution, passed as the first argument to every
+/// custom function.
+///
+/// Holds named values (a tenant id, say) and arbitrary typed extensions such
+/// as caches or clients, looked up by type.
+#[derive(Default)]
+pub struct EvalContext {
+    values: HashMap<String, FactValue>,
+    extensions: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl EvalContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_value(mut self, name: impl Into<String>, value: FactValue) -> Self {
+        self.set_value(name, value);
+        self
+    }
+
+    pub fn set_value(&mut self, name: impl Into<String>, value: FactValue) {
+        self.values.insert(name.into(), value);
+    }
+
+    pub fn value(&self, name: &str) -> Option<&FactValue> {
+        self.values.get(name)
+    }
+
+    pub fn with_extension<T: Any + Send + Sync>(mut self, extension: T) -> Self {
+        self.insert_extension(extension);
+        self
+    }
+
+    /// Store an extension, replacing any existing one of the same type
+    pub fn insert_extension<T: Any + Send + Sync>(&mut self, extension: T) {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(extension));
+    }
+
+    pub fn extension<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|extension| extension.downcast_ref())
+    }
+}
+
+impl fmt::Debug for EvalContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvalContext")
+            .field("values", &self.values)
+            .field("extensions", &self.extensions.len())
+            .finish()
+    }
+}
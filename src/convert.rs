@@ -0,0 +1,152 @@
+use crate::decimal::Decimal;
+use crate::facts::{Fact, FactValue};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+/// A fact value that does not have the shape of the requested type
+#[derive(Debug, Error)]
+#[error("Cannot convert fact value: {0}")]
+pub struct FromFactError(#[from] serde_json::Error);
+
+/// Extraction of application values back out of facts, e.g. after rules have
+/// modified them. Implemented for every type that implements `Deserialize`,
+/// reading values as they convert to JSON (see `FactValue`'s
+/// `From<FactValue> for serde_json::Value`).
+pub trait FromFact: Sized {
+    fn from_fact_value(value: &FactValue) -> Result<Self, FromFactError>;
+
+    fn from_fact(fact: &Fact) -> Result<Self, FromFactError> {
+        Self::from_fact_value(&fact.value)
+    }
+}
+
+impl<T: DeserializeOwned> FromFact for T {
+    fn from_fact_value(value: &FactValue) -> Result<Self, FromFactError> {
+        Ok(serde_json::from_value(value.clone().into())?)
+    }
+}
+
+/// Conversion of application values into facts. Derive it for structs with
+/// `#[derive(IntoFact)]` (the `derive` feature).
+pub trait IntoFact {
+    fn into_fact_value(self) -> FactValue;
+
+    /// The type given to facts made with `into_fact`, e.g. the struct name
+    fn fact_type() -> Option<&'static str>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    fn into_fact(self, name: impl Into<String>) -> Fact
+    where
+        Self: Sized,
+    {
+        let fact = Fact::new(name.into(), self.into_fact_value());
+        match Self::fact_type() {
+            Some(fact_type) => fact.with_type(fact_type.to_string()),
+            None => fact,
+        }
+    }
+}
+
+impl IntoFact for FactValue {
+    fn into_fact_value(self) -> FactValue {
+        self
+    }
+}
+
+impl IntoFact for bool {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Boolean(self)
+    }
+}
+
+impl IntoFact for String {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::String(self)
+    }
+}
+
+impl IntoFact for &str {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::String(self.to_string())
+    }
+}
+
+impl IntoFact for f64 {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Number(self)
+    }
+}
+
+impl IntoFact for f32 {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Number(self.into())
+    }
+}
+
+impl IntoFact for Decimal {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Decimal(self)
+    }
+}
+
+macro_rules! into_integer {
+    ($($ty:ty),*) => {
+        $(impl IntoFact for $ty {
+            fn into_fact_value(self) -> FactValue {
+                FactValue::Integer(self.into())
+            }
+        })*
+    };
+}
+
+into_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+macro_rules! into_wide_integer {
+    ($($ty:ty),*) => {
+        $(impl IntoFact for $ty {
+            /// An `Integer`, or a `Number` if the value does not fit an `i64`
+            fn into_fact_value(self) -> FactValue {
+                i64::try_from(self).map_or(FactValue::Number(self as f64), FactValue::Integer)
+            }
+        })*
+    };
+}
+
+into_wide_integer!(u64, usize, isize);
+
+impl<T: IntoFact> IntoFact for Option<T> {
+    fn into_fact_value(self) -> FactValue {
+        self.map_or(FactValue::Null, IntoFact::into_fact_value)
+    }
+}
+
+impl<T: IntoFact> IntoFact for Vec<T> {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Array(self.into_iter().map(IntoFact::into_fact_value).collect())
+    }
+}
+
+impl<T: IntoFact> IntoFact for HashMap<String, T> {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Object(
+            self.into_iter()
+                .map(|(name, value)| (name, value.into_fact_value()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: IntoFact> IntoFact for BTreeMap<String, T> {
+    fn into_fact_value(self) -> FactValue {
+        FactValue::Object(
+            self.into_iter()
+                .map(|(name, value)| (name, value.into_fact_value()))
+                .collect(),
+        )
+    }
+}
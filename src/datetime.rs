@@ -0,0 +1,160 @@
+const MILLIS_PER_DAY: i64 = 86_400_000;
+/// The latest year `parse` accepts, keeping every date well within `i64`
+/// milliseconds
+const MAX_YEAR: i64 = 9999;
+
+/// A UTC date and time broken into calendar fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Civil {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl Civil {
+    pub fn from_millis(millis: i64) -> Self {
+        let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+        let time = millis.rem_euclid(MILLIS_PER_DAY) as u32;
+        Self {
+            year,
+            month,
+            day,
+            hour: time / 3_600_000,
+            minute: time / 60_000 % 60,
+            second: time / 1000 % 60,
+        }
+    }
+
+    /// ISO 8601 weekday, from 1 for Monday to 7 for Sunday
+    pub fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as u32 + 1
+    }
+}
+
+/// Format as RFC 3339 in UTC, e.g. `2024-03-01T09:30:00Z`, with milliseconds
+/// only when there are some
+pub(crate) fn format(millis: i64) -> String {
+    let civil = Civil::from_millis(millis);
+    let fraction = match millis.rem_euclid(1000) {
+        0 => String::new(),
+        ms => format!(".{:03}", ms),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        civil.year, civil.month, civil.day, civil.hour, civil.minute, civil.second, fraction
+    )
+}
+
+/// Parse an ISO 8601 date (`2024-03-01`) or date and time
+/// (`2024-03-01T09:30:00Z`, `2024-03-01 09:30:00.250+02:00`). Times without
+/// an offset are taken to be UTC. Years above 9999 and offsets beyond ±23:59
+/// are rejected.
+pub(crate) fn parse(text: &str) -> Option<i64> {
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut fields = date.splitn(3, '-');
+    let year: i64 = fields.next()?.parse().ok()?;
+    let month: u32 = fields.next()?.parse().ok()?;
+    let day: u32 = fields.next()?.parse().ok()?;
+    if year > MAX_YEAR || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month)
+    {
+        return None;
+    }
+    let millis = days_from_civil(year, month, day).checked_mul(MILLIS_PER_DAY)?;
+    match time {
+        Some(time) => {
+            let (time, offset) = split_offset(time)?;
+            millis.checked_add(parse_time(time)?.checked_sub(offset)?)
+        }
+        None => Some(millis),
+    }
+}
+
+/// The time of day and its UTC offset in milliseconds
+fn split_offset(time: &str) -> Option<(&str, i64)> {
+    if let Some(time) = time.strip_suffix('Z') {
+        return Some((time, 0));
+    }
+    let Some(pos) = time.rfind(['+', '-']) else {
+        return Some((time, 0));
+    };
+    let (hours, minutes) = time[pos + 1..].split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+        return None;
+    }
+    let offset = (hours * 60 + minutes) * 60_000;
+    let sign = if time[pos..].starts_with('-') { -1 } else { 1 };
+    Some((&time[..pos], sign * offset))
+}
+
+fn parse_time(time: &str) -> Option<i64> {
+    let mut fields = time.splitn(3, ':');
+    let hour: i64 = fields.next()?.parse().ok()?;
+    let minute: i64 = fields.next()?.parse().ok()?;
+    let (second, millisecond) = match fields.next() {
+        Some(seconds) => match seconds.split_once('.') {
+            Some((second, fraction)) => {
+                // Keep millisecond precision, padding or truncating the fraction
+                let digits: String = fraction.chars().chain("000".chars()).take(3).collect();
+                (second.parse().ok()?, digits.parse().ok()?)
+            }
+            None => (seconds.parse().ok()?, 0),
+        },
+        None => (0, 0),
+    };
+    if !(0..=23).contains(&hour) || !(0..=59).contains(&minute) || !(0..=59).contains(&second) {
+        return None;
+    }
+    Some(((hour * 60 + minute) * 60 + second) * 1000 + millisecond)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar, after
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,181 @@
+use crate::analysis::{self, FieldRef};
+use crate::ast::Expression;
+use crate::engine::{Cycles, ExecutionResult, RuleEngine, Scope};
+use crate::rule::Rule;
+use crate::working_memory::WorkingMemory;
+use std::collections::BTreeSet;
+use std::time::Instant;
+
+/// Where a debug session should pause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// Pause before the named rule fires
+    Rule(String),
+    /// Pause before any rule that writes to the fact (or one of its fields) fires.
+    /// A `None` field matches every write to the fact.
+    FieldWrite { fact: String, field: Option<String> },
+}
+
+impl Breakpoint {
+    fn matches(&self, rule: &Rule) -> bool {
+        match self {
+            Breakpoint::Rule(name) => &rule.name == name,
+            Breakpoint::FieldWrite { fact, field } => {
+                let target = FieldRef {
+                    fact: fact.clone(),
+                    field: field.clone(),
+                };
+                let mut writes = BTreeSet::new();
+                for action in &rule.then_actions {
+                    analysis::collect_writes(action, &mut writes);
+                }
+                writes.iter().any(|write| write.overlaps(&target))
+                    || rule.then_actions.iter().any(runs_script)
+            }
+        }
+    }
+}
+
+/// Whether an action runs a script, which may write to any fact
+fn runs_script(action: &Expression) -> bool {
+    matches!(action, Expression::Script(_)) || action.children().into_iter().any(runs_script)
+}
+
+#[derive(Debug, Clone)]
+pub enum DebugEvent {
+    /// Execution stopped before `rule` fired because of `breakpoint`
+    Paused {
+        rule: String,
+        breakpoint: Breakpoint,
+    },
+    /// Execution stopped after a single step
+    Stepped { rule: String, fired: bool },
+    /// All rules have been processed (or the session was aborted)
+    Finished(ExecutionResult),
+}
+
+/// Step-wise execution of a rule engine against a set of facts.
+///
+/// Rules are visited in the same order and over the same cycles as
+/// `RuleEngine::execute`, including the retraction of logically inserted
+/// facts. The caller drives the session with `resume`, `step` and `abort`,
+/// and can inspect working memory whenever the session is paused.
+pub struct DebugSession<'a> {
+    engine: &'a RuleEngine,
+    facts: &'a mut WorkingMemory,
+    cycles: Cycles<'a>,
+    breakpoints: Vec<Breakpoint>,
+    // Set when paused on a breakpoint so resuming fires the rule instead of pausing again
+    paused: Option<(&'a Rule, Vec<Scope>)>,
+    started: Instant,
+    finished: bool,
+}
+
+impl<'a> DebugSession<'a> {
+    pub(crate) fn new(engine: &'a RuleEngine, facts: &'a mut WorkingMemory) -> Self {
+        Self {
+            engine,
+            facts,
+            cycles: Cycles::new(engine.agenda(), Scope::default()),
+            breakpoints: Vec::new(),
+            paused: None,
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn memory(&self) -> &WorkingMemory {
+        self.facts
+    }
+
+    /// Name of the rule the session is paused on, if any
+    pub fn paused_at(&self) -> Option<&str> {
+        self.paused.as_ref().map(|(rule, _)| rule.name.as_str())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Run until the next breakpoint is hit or no rule is left to fire
+    pub fn resume(&mut self) -> crate::Result<DebugEvent> {
+        while !self.finished {
+            let (rule, activations) = match self.paused.take() {
+                Some(paused) => paused,
+                None => {
+                    let Some((rule, activations)) = self.next()? else {
+                        break;
+                    };
+                    if let Some(breakpoint) = self.breakpoints.iter().find(|b| b.matches(rule)) {
+                        let breakpoint = breakpoint.clone();
+                        self.paused = Some((rule, activations));
+                        return Ok(DebugEvent::Paused {
+                            rule: rule.name.clone(),
+                            breakpoint,
+                        });
+                    }
+                    (rule, activations)
+                }
+            };
+            self.engine
+                .fire_next(&mut self.cycles, rule, activations, self.facts)?;
+        }
+
+        Ok(DebugEvent::Finished(self.finish()))
+    }
+
+    /// Fire the next rule that has activations, ignoring breakpoints
+    pub fn step(&mut self) -> crate::Result<DebugEvent> {
+        let next = match self.paused.take() {
+            Some(paused) => Some(paused),
+            None if self.finished => None,
+            None => self.next()?,
+        };
+        let Some((rule, activations)) = next else {
+            return Ok(DebugEvent::Finished(self.finish()));
+        };
+        let fired = self
+            .engine
+            .fire_next(&mut self.cycles, rule, activations, self.facts)?;
+
+        Ok(DebugEvent::Stepped {
+            rule: rule.name.clone(),
+            fired,
+        })
+    }
+
+    /// Stop the session without processing the remaining rules
+    pub fn abort(mut self) -> ExecutionResult {
+        self.finish()
+    }
+
+    fn next(&mut self) -> crate::Result<Option<(&'a Rule, Vec<Scope>)>> {
+        Ok(self
+            .engine
+            .next_activations(&mut self.cycles, self.facts, None)?)
+    }
+
+    fn finish(&mut self) -> ExecutionResult {
+        self.finished = true;
+        self.paused = None;
+        let mut result = self.cycles.result();
+        result.execution_time_ms = self.started.elapsed().as_millis();
+        result
+    }
+}
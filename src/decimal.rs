@@ -0,0 +1,264 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Most digits a `Decimal` keeps after the decimal point. Results needing
+/// more, such as `1 / 3`, are rounded half to even.
+pub const MAX_SCALE: u32 = 28;
+
+/// An exact base-10 number for money and other values where `f64` rounding
+/// would give cents-off results: `mantissa * 10^-scale`.
+///
+/// Equality and ordering are numeric, so `1.10 == 1.1`; the scale is kept so
+/// prices display as written. Serialized as a string, e.g. `"19.99"`.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal {
+        mantissa: 0,
+        scale: 0,
+    };
+
+    /// `mantissa * 10^-scale`, or `None` if `scale` exceeds `MAX_SCALE`
+    pub fn new(mantissa: i128, scale: u32) -> Option<Self> {
+        (scale <= MAX_SCALE).then_some(Self { mantissa, scale })
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    /// The shortest decimal that round-trips to `value`, so `0.1` becomes
+    /// exactly `0.1`. `None` for NaN, infinities and values needing more than
+    /// `MAX_SCALE` decimal places.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        value.to_string().parse().ok()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Going through the string gives the nearest f64
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        Some(Self {
+            mantissa: self.mantissa.checked_neg()?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = align(self, other)?;
+        Some(Self {
+            mantissa: a.checked_add(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = align(self, other)?;
+        Some(Self {
+            mantissa: a.checked_sub(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let product = self.mantissa.checked_mul(other.mantissa)?;
+        Some(round(product, self.scale + other.scale))
+    }
+
+    /// `None` on division by zero or overflow
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+        // Scale the dividend up as far as it goes so the quotient keeps up to
+        // MAX_SCALE digits
+        let mut dividend = self.mantissa;
+        let mut scale = i64::from(self.scale) - i64::from(other.scale);
+        while scale < i64::from(MAX_SCALE) {
+            let Some(scaled) = dividend.checked_mul(10) else {
+                break;
+            };
+            dividend = scaled;
+            scale += 1;
+        }
+        let mut quotient = round_div(dividend, other.mantissa);
+        while scale < 0 {
+            quotient = quotient.checked_mul(10)?;
+            scale += 1;
+        }
+        Some(
+            Self {
+                mantissa: quotient,
+                scale: scale as u32,
+            }
+            .normalize(),
+        )
+    }
+
+    /// The remainder of truncating division, with the sign of `self`. `None`
+    /// on division by zero.
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = align(self, other)?;
+        Some(Self {
+            mantissa: a.checked_rem(b)?,
+            scale,
+        })
+    }
+
+    /// Without trailing zeros after the decimal point
+    pub fn normalize(mut self) -> Self {
+        while self.scale > 0 && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Self {
+            mantissa: value.into(),
+            scale: 0,
+        }
+    }
+}
+
+/// Both mantissas at the larger of the two scales
+fn align(a: Decimal, b: Decimal) -> Option<(i128, i128, u32)> {
+    let scale = a.scale.max(b.scale);
+    let widen = |d: Decimal| d.mantissa.checked_mul(10i128.checked_pow(scale - d.scale)?);
+    Some((widen(a)?, widen(b)?, scale))
+}
+
+/// `mantissa * 10^-scale`, rounded to at most `MAX_SCALE` places
+fn round(mantissa: i128, scale: u32) -> Decimal {
+    if scale <= MAX_SCALE {
+        return Decimal { mantissa, scale };
+    }
+    Decimal {
+        mantissa: round_div(mantissa, 10i128.pow(scale - MAX_SCALE)),
+        scale: MAX_SCALE,
+    }
+}
+
+/// `value / divisor`, rounded half to even
+fn round_div(value: i128, divisor: i128) -> i128 {
+    let quotient = value / divisor;
+    let remainder = (value % divisor).unsigned_abs();
+    let half = divisor.unsigned_abs() - remainder;
+    if remainder > half || (remainder == half && quotient % 2 != 0) {
+        quotient + value.signum() * divisor.signum()
+    } else {
+        quotient
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match align(*self, *other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // Widening overflowed, so the magnitudes are far apart
+            None => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError(String);
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid decimal '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseDecimalError(text.to_string());
+        let (negative, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let scale = u32::try_from(fraction.len()).map_err(|_| invalid())?;
+        let mantissa: i128 = format!("{}{}", whole, fraction)
+            .parse()
+            .map_err(|_| invalid())?;
+        let mantissa = if negative { -mantissa } else { mantissa };
+        Decimal::new(mantissa, scale).ok_or_else(invalid)
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
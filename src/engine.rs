@@ -1,8 +1,34 @@
+use crate::analysis::{self, FieldRef};
 use crate::ast::Expression;
-use crate::facts::{Fact, FactValue};
-use crate::knowledge_base::KnowledgeBase;
-use crate::rule::Rule;
+use crate::audit::{AuditLog, AuditRecord};
+use crate::builtins;
+use crate::cancel::CancelToken;
+use crate::config::{
+    Collation, ConflictResolution, EngineConfig, NanHandling, NumericPolicy, RuleEngineBuilder,
+};
+use crate::context::{CustomFunction, EvalContext};
+use crate::debugger::DebugSession;
+use crate::decimal::Decimal;
+use crate::facts::{
+    self, decimal_operands, integer_operands, CoercionPolicy, Fact, FactChange, FactValue,
+    Provenance,
+};
+use crate::knowledge_base::{CompiledKnowledgeBase, KnowledgeBase, KnowledgeBaseError};
+use crate::middleware::{FireContext, Middleware, Next};
+use crate::optimize::RuleWarning;
+use crate::report::{self, ExecutionReport, SuppressedError};
+use crate::rule::{Accumulate, Pattern, Rule};
+use crate::schema::{EnumType, FactSchema};
+#[cfg(feature = "script")]
+use crate::script::{ScriptEngine, ScriptInterpreter};
+use crate::session::Session;
+use crate::typecheck;
+use crate::working_memory::WorkingMemory;
+use regex::Regex;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,16 +37,473 @@ pub enum EngineError {
     EvaluationError(String),
     #[error("Unknown variable: {0}")]
     UnknownVariable(String),
+    #[error("Unknown function: {0}")]
+    UnknownFunction(String),
+    #[error("Global is read-only: {0}")]
+    ReadOnlyGlobal(String),
     #[error("Type error: {0}")]
     TypeError(String),
     #[error("Division by zero")]
     DivisionByZero,
+    #[error("Execution was cancelled")]
+    Cancelled,
+    #[error("Resource limit exceeded: {0}")]
+    ResourceLimit(String),
+    #[error("Invalid number: {0}")]
+    InvalidNumber(String),
+    #[error("Failed to write audit record: {0}")]
+    Audit(String),
+    #[error("Fact '{fact}' does not match its schema: {reason}")]
+    Validation { fact: String, reason: String },
+    #[error("Unknown variant '{variant}' of enum {enum_type}")]
+    UnknownEnumVariant { enum_type: String, variant: String },
+    #[error("Currency mismatch: {0} and {1}")]
+    CurrencyMismatch(String, String),
+}
+
+/// Operators taking two evaluated operands, shared by the interpreter and the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessEqual,
+    GreaterThan,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// The operator and operands of a binary expression
+    pub(crate) fn split(expr: &Expression) -> Option<(Self, &Expression, &Expression)> {
+        let (op, left, right) = match expr {
+            Expression::Add(l, r) => (BinaryOp::Add, l, r),
+            Expression::Subtract(l, r) => (BinaryOp::Subtract, l, r),
+            Expression::Multiply(l, r) => (BinaryOp::Multiply, l, r),
+            Expression::Divide(l, r) => (BinaryOp::Divide, l, r),
+            Expression::Modulo(l, r) => (BinaryOp::Modulo, l, r),
+            Expression::Equal(l, r) => (BinaryOp::Equal, l, r),
+            Expression::NotEqual(l, r) => (BinaryOp::NotEqual, l, r),
+            Expression::LessThan(l, r) => (BinaryOp::LessThan, l, r),
+            Expression::LessEqual(l, r) => (BinaryOp::LessEqual, l, r),
+            Expression::GreaterThan(l, r) => (BinaryOp::GreaterThan, l, r),
+            Expression::GreaterEqual(l, r) => (BinaryOp::GreaterEqual, l, r),
+            Expression::And(l, r) => (BinaryOp::And, l, r),
+            Expression::Or(l, r) => (BinaryOp::Or, l, r),
+            _ => return None,
+        };
+        Some((op, left.as_ref(), right.as_ref()))
+    }
+
+    /// Build the expression applying this operator to `left` and `right`
+    pub(crate) fn join(self, left: Expression, right: Expression) -> Expression {
+        let (left, right) = (Box::new(left), Box::new(right));
+        match self {
+            BinaryOp::Add => Expression::Add(left, right),
+            BinaryOp::Subtract => Expression::Subtract(left, right),
+            BinaryOp::Multiply => Expression::Multiply(left, right),
+            BinaryOp::Divide => Expression::Divide(left, right),
+            BinaryOp::Modulo => Expression::Modulo(left, right),
+            BinaryOp::Equal => Expression::Equal(left, right),
+            BinaryOp::NotEqual => Expression::NotEqual(left, right),
+            BinaryOp::LessThan => Expression::LessThan(left, right),
+            BinaryOp::LessEqual => Expression::LessEqual(left, right),
+            BinaryOp::GreaterThan => Expression::GreaterThan(left, right),
+            BinaryOp::GreaterEqual => Expression::GreaterEqual(left, right),
+            BinaryOp::And => Expression::And(left, right),
+            BinaryOp::Or => Expression::Or(left, right),
+        }
+    }
+
+    /// Apply the operator, checking numeric operands and results against
+    /// `policy`, ordering strings by `collation` and comparing values of
+    /// different types according to `coercion`
+    pub(crate) fn apply(
+        self,
+        left: FactValue,
+        right: FactValue,
+        policy: &NumericPolicy,
+        collation: Collation,
+        coercion: CoercionPolicy,
+    ) -> Result<FactValue, EngineError> {
+        if let (Some(a), Some(b)) = (left.as_number(), right.as_number()) {
+            check_number(a, policy)?;
+            check_number(b, policy)?;
+            if let (Some(epsilon), BinaryOp::Equal | BinaryOp::NotEqual) = (policy.epsilon, self) {
+                let equal = (a - b).abs() <= epsilon;
+                return Ok(FactValue::Boolean(equal == (self == BinaryOp::Equal)));
+            }
+        }
+        let result = self.compute(left, right, collation, coercion)?;
+        if let FactValue::Number(n) = result {
+            check_number(n, policy)?;
+        }
+        Ok(result)
+    }
+
+    fn compute(
+        self,
+        left: FactValue,
+        right: FactValue,
+        collation: Collation,
+        coercion: CoercionPolicy,
+    ) -> Result<FactValue, EngineError> {
+        if let Some(result) = money(self, &left, &right) {
+            return result;
+        }
+        if let Some(result) = decimal_arithmetic(self, &left, &right) {
+            return result;
+        }
+        match self {
+            BinaryOp::Add => match (left, right) {
+                (FactValue::String(a), FactValue::String(b)) => Ok(FactValue::String(a + &b)),
+                (left, right) => temporal(self, &left, &right)
+                    .or_else(|| arithmetic(&left, &right, i64::checked_add, |a, b| a + b))
+                    .ok_or_else(|| EngineError::TypeError("Cannot add these types".to_string()))?,
+            },
+            BinaryOp::Subtract => temporal(self, &left, &right)
+                .or_else(|| arithmetic(&left, &right, i64::checked_sub, |a, b| a - b))
+                .ok_or_else(|| EngineError::TypeError("Cannot subtract these types".to_string()))?,
+            BinaryOp::Multiply => arithmetic(&left, &right, i64::checked_mul, |a, b| a * b)
+                .ok_or_else(|| EngineError::TypeError("Cannot multiply these types".to_string()))?,
+            // Division is always fractional, so `7 / 2` is 3.5 even for integers
+            BinaryOp::Divide => match (left.as_number(), right.as_number()) {
+                (Some(_), Some(0.0)) => Err(EngineError::DivisionByZero),
+                (Some(a), Some(b)) => Ok(FactValue::Number(a / b)),
+                _ => Err(EngineError::TypeError(
+                    "Cannot divide these types".to_string(),
+                )),
+            },
+            BinaryOp::Modulo => match (left.as_number(), right.as_number()) {
+                (Some(_), Some(0.0)) => Err(EngineError::DivisionByZero),
+                _ => {
+                    arithmetic(&left, &right, i64::checked_rem, |a, b| a % b).ok_or_else(|| {
+                        EngineError::TypeError(
+                            "Cannot take the remainder of these types".to_string(),
+                        )
+                    })?
+                }
+            },
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                let ordering = left.compare(&right, coercion);
+                if ordering.is_none()
+                    && coercion == CoercionPolicy::Strict
+                    && !matches!(left, FactValue::Null)
+                    && !matches!(right, FactValue::Null)
+                    && (left.as_number().is_none() || right.as_number().is_none())
+                    && std::mem::discriminant(&left) != std::mem::discriminant(&right)
+                {
+                    return Err(mismatched_types(&left, &right));
+                }
+                let equal = ordering == Some(std::cmp::Ordering::Equal);
+                Ok(FactValue::Boolean(equal == (self == BinaryOp::Equal)))
+            }
+            BinaryOp::LessThan
+            | BinaryOp::LessEqual
+            | BinaryOp::GreaterThan
+            | BinaryOp::GreaterEqual => {
+                let ordering = match (&left, &right) {
+                    (FactValue::String(a), FactValue::String(b)) => Some(collation.compare(a, b)),
+                    _ => match left.compare(&right, coercion) {
+                        // Unordered numbers are NaN, other values do not compare
+                        None if coercion != CoercionPolicy::Lenient
+                            && (left.as_number().is_none() || right.as_number().is_none()) =>
+                        {
+                            return Err(mismatched_types(&left, &right))
+                        }
+                        ordering => ordering,
+                    },
+                };
+                // NaN is unordered, so every comparison with it is false
+                Ok(FactValue::Boolean(ordering.is_some_and(
+                    |ordering| match self {
+                        BinaryOp::LessThan => ordering.is_lt(),
+                        BinaryOp::LessEqual => ordering.is_le(),
+                        BinaryOp::GreaterThan => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    },
+                )))
+            }
+            BinaryOp::And => Ok(FactValue::Boolean(left.is_truthy() && right.is_truthy())),
+            BinaryOp::Or => Ok(FactValue::Boolean(left.is_truthy() || right.is_truthy())),
+        }
+    }
+}
+
+/// `op` under three-valued logic, where null is unknown, or `None` if the
+/// result does not depend on it. Comparisons with an unknown value are
+/// unknown; `false && unknown` is false and `true || unknown` is true.
+fn kleene(op: BinaryOp, left: &FactValue, right: &FactValue) -> Option<FactValue> {
+    let unknown = matches!(left, FactValue::Null) || matches!(right, FactValue::Null);
+    if !unknown {
+        return None;
+    }
+    match op {
+        BinaryOp::Equal
+        | BinaryOp::NotEqual
+        | BinaryOp::LessThan
+        | BinaryOp::LessEqual
+        | BinaryOp::GreaterThan
+        | BinaryOp::GreaterEqual => Some(FactValue::Null),
+        BinaryOp::And => Some(
+            if [left, right]
+                .iter()
+                .any(|v| !matches!(v, FactValue::Null) && !v.is_truthy())
+            {
+                FactValue::Boolean(false)
+            } else {
+                FactValue::Null
+            },
+        ),
+        BinaryOp::Or => Some(if [left, right].iter().any(|v| v.is_truthy()) {
+            FactValue::Boolean(true)
+        } else {
+            FactValue::Null
+        }),
+        _ => None,
+    }
+}
+
+fn mismatched_types(left: &FactValue, right: &FactValue) -> EngineError {
+    EngineError::TypeError(format!(
+        "Cannot compare {} with {}",
+        left.type_name(),
+        right.type_name()
+    ))
+}
+
+/// Add or subtract dates and durations, or `None` if neither operand is one
+fn temporal(
+    op: BinaryOp,
+    left: &FactValue,
+    right: &FactValue,
+) -> Option<Result<FactValue, EngineError>> {
+    let (result, wrap): (Option<i64>, fn(i64) -> FactValue) = match (op, left, right) {
+        (BinaryOp::Add, FactValue::DateTime(a), FactValue::Duration(b))
+        | (BinaryOp::Add, FactValue::Duration(b), FactValue::DateTime(a)) => {
+            (a.checked_add(*b), FactValue::DateTime)
+        }
+        (BinaryOp::Add, FactValue::Duration(a), FactValue::Duration(b)) => {
+            (a.checked_add(*b), FactValue::Duration)
+        }
+        (BinaryOp::Subtract, FactValue::DateTime(a), FactValue::Duration(b)) => {
+            (a.checked_sub(*b), FactValue::DateTime)
+        }
+        (BinaryOp::Subtract, FactValue::DateTime(a), FactValue::DateTime(b))
+        | (BinaryOp::Subtract, FactValue::Duration(a), FactValue::Duration(b)) => {
+            (a.checked_sub(*b), FactValue::Duration)
+        }
+        _ => return None,
+    };
+    Some(
+        result
+            .map(wrap)
+            .ok_or_else(|| EngineError::InvalidNumber("time overflow".to_string())),
+    )
+}
+
+/// Arithmetic and comparisons involving `Money`, or `None` if neither operand
+/// is money or `op` is not one of them. Amounts in one currency add, subtract
+/// and divide to give money (or a ratio, dividing by money), and multiply or
+/// divide by numbers; mixing currencies is an error.
+fn money(
+    op: BinaryOp,
+    left: &FactValue,
+    right: &FactValue,
+) -> Option<Result<FactValue, EngineError>> {
+    let wrap = |amount: Option<Decimal>, currency: &str| {
+        amount
+            .map(|amount| FactValue::Money {
+                amount,
+                currency: currency.to_string(),
+            })
+            .ok_or_else(|| EngineError::InvalidNumber("decimal overflow".to_string()))
+    };
+    let result = match (left, right) {
+        (
+            FactValue::Money {
+                amount: a,
+                currency: a_currency,
+            },
+            FactValue::Money {
+                amount: b,
+                currency: b_currency,
+            },
+        ) => {
+            if a_currency != b_currency && !matches!(op, BinaryOp::And | BinaryOp::Or) {
+                return Some(Err(EngineError::CurrencyMismatch(
+                    a_currency.clone(),
+                    b_currency.clone(),
+                )));
+            }
+            match op {
+                BinaryOp::Add => wrap(a.checked_add(*b), a_currency),
+                BinaryOp::Subtract => wrap(a.checked_sub(*b), a_currency),
+                BinaryOp::Divide if b.is_zero() => Err(EngineError::DivisionByZero),
+                BinaryOp::Divide => a
+                    .checked_div(*b)
+                    .map(FactValue::Decimal)
+                    .ok_or_else(|| EngineError::InvalidNumber("decimal overflow".to_string())),
+                _ => return None,
+            }
+        }
+        (FactValue::Money { amount, currency }, other)
+        | (other, FactValue::Money { amount, currency })
+            if op == BinaryOp::Multiply =>
+        {
+            let (a, b) = decimal_operands(&FactValue::Decimal(*amount), other)?;
+            wrap(a.checked_mul(b), currency)
+        }
+        (FactValue::Money { amount, currency }, other) if op == BinaryOp::Divide => {
+            let (a, b) = decimal_operands(&FactValue::Decimal(*amount), other)?;
+            if b.is_zero() {
+                return Some(Err(EngineError::DivisionByZero));
+            }
+            wrap(a.checked_div(b), currency)
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Exact arithmetic when either operand is a `Decimal`, or `None` if `op` is
+/// not arithmetic or the operands are not numbers
+fn decimal_arithmetic(
+    op: BinaryOp,
+    left: &FactValue,
+    right: &FactValue,
+) -> Option<Result<FactValue, EngineError>> {
+    let (a, b) = decimal_operands(left, right)?;
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Subtract => a.checked_sub(b),
+        BinaryOp::Multiply => a.checked_mul(b),
+        BinaryOp::Divide | BinaryOp::Modulo if b.is_zero() => {
+            return Some(Err(EngineError::DivisionByZero))
+        }
+        BinaryOp::Divide => a.checked_div(b),
+        BinaryOp::Modulo => a.checked_rem(b),
+        _ => return None,
+    };
+    Some(
+        result
+            .map(FactValue::Decimal)
+            .ok_or_else(|| EngineError::InvalidNumber("decimal overflow".to_string())),
+    )
+}
+
+/// Apply an arithmetic operator to two numbers. Integer operands (see
+/// `integer_operands`) give an `Integer`, failing on overflow; anything else
+/// is computed in `f64`. `None` if either side is not a number.
+fn arithmetic(
+    left: &FactValue,
+    right: &FactValue,
+    integer: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Option<Result<FactValue, EngineError>> {
+    if let Some((a, b)) = integer_operands(left, right) {
+        return Some(
+            integer(a, b)
+                .map(FactValue::Integer)
+                .ok_or_else(|| EngineError::InvalidNumber("integer overflow".to_string())),
+        );
+    }
+    Some(Ok(FactValue::Number(float(
+        left.as_number()?,
+        right.as_number()?,
+    ))))
+}
+
+fn check_number(n: f64, policy: &NumericPolicy) -> Result<(), EngineError> {
+    if n.is_nan() && policy.nan == NanHandling::Error {
+        Err(EngineError::InvalidNumber("NaN".to_string()))
+    } else if n.is_infinite() && policy.reject_infinity {
+        Err(EngineError::InvalidNumber(format!("{} is not finite", n)))
+    } else {
+        Ok(())
+    }
+}
+
+/// A rule's activations along with the type errors suppressed while finding them
+#[cfg(feature = "parallel")]
+type RuleMatch = (Vec<Scope>, Vec<EngineError>);
+
+/// A fact or field written by an action
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Write {
+    pub(crate) target: FieldRef,
+    /// Whether rules reading the target should be re-evaluated
+    pub(crate) notifies: bool,
+}
+
+/// State shared by every activation within one execution
+#[derive(Debug, Default)]
+pub(crate) struct Execution {
+    context: Arc<EvalContext>,
+    function_calls: AtomicUsize,
+    // Facts fetched from the fact resolver, including ones it did not find
+    resolved: Mutex<HashMap<String, Option<FactValue>>>,
+}
+
+/// Names visible to a rule activation beyond the facts in working memory
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scope {
+    // Pattern binding -> name of the matched fact
+    facts: HashMap<String, String>,
+    // Values bound by the rule's `name := expr` bindings
+    values: HashMap<String, FactValue>,
+    execution: Arc<Execution>,
+}
+
+impl Scope {
+    /// An empty scope for an execution run with `context`
+    pub(crate) fn new(context: Arc<EvalContext>) -> Self {
+        Self {
+            execution: Arc::new(Execution {
+                context,
+                ..Execution::default()
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// The working-memory name a rule refers to `name` by
+    pub(crate) fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.facts.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    fn bind_fact(&self, binding: &str, fact_name: &str) -> Self {
+        let mut scope = self.clone();
+        scope
+            .facts
+            .insert(binding.to_string(), fact_name.to_string());
+        scope
+    }
+
+    fn value(&self, name: &str) -> Option<&FactValue> {
+        self.values.get(name)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ExecutionResult {
     pub rules_fired: Vec<String>,
+    /// `rules_fired` split up by the cycle each rule fired in
+    pub rules_fired_by_cycle: Vec<Vec<String>>,
     pub facts_modified: Vec<String>,
+    pub facts_retracted: Vec<String>,
+    /// Type errors treated as a non-match because strict typing is disabled
+    pub suppressed_errors: Vec<SuppressedError>,
+    /// Every value the execution changed, when `EngineConfig::track_changes`
+    /// is set
+    pub changes: Vec<FactChange>,
+    pub cycles: usize,
     pub execution_time_ms: u128,
 }
 
@@ -28,10 +511,23 @@ impl ExecutionResult {
     pub fn new() -> Self {
         Self {
             rules_fired: Vec::new(),
+            rules_fired_by_cycle: Vec::new(),
             facts_modified: Vec::new(),
+            facts_retracted: Vec::new(),
+            suppressed_errors: Vec::new(),
+            changes: Vec::new(),
+            cycles: 0,
             execution_time_ms: 0,
         }
     }
+
+    pub(crate) fn record_suppressed(&mut self, rule: &Rule, errors: Vec<EngineError>) {
+        self.suppressed_errors
+            .extend(errors.into_iter().map(|error| SuppressedError {
+                rule: rule.name.clone(),
+                message: error.to_string(),
+            }));
+    }
 }
 
 impl Default for ExecutionResult {
@@ -40,236 +536,1658 @@ impl Default for ExecutionResult {
     }
 }
 
+/// Where the match-fire loop of `RuleEngine::run` stands between rules, so a
+/// debug session can pause it before a rule fires
+pub(crate) struct Cycles<'a> {
+    rules: Vec<&'a Rule>,
+    root: Scope,
+    // Index of the next rule to consider in the current cycle
+    position: usize,
+    in_cycle: bool,
+    // Facts and fields written during the previous cycle. After the first
+    // cycle only rules whose conditions read one of them are re-evaluated.
+    dirty: Vec<FieldRef>,
+    // Facts and fields written during the current cycle
+    modified: Vec<FieldRef>,
+    fired: bool,
+    fired_before: usize,
+    done: bool,
+    result: ExecutionResult,
+}
+
+impl<'a> Cycles<'a> {
+    /// A loop over `rules`, which are considered in order. Activations are
+    /// found by extending `root`.
+    pub(crate) fn new(rules: Vec<&'a Rule>, root: Scope) -> Self {
+        Self {
+            rules,
+            root,
+            position: 0,
+            in_cycle: false,
+            dirty: Vec::new(),
+            modified: Vec::new(),
+            fired: false,
+            fired_before: 0,
+            done: false,
+            result: ExecutionResult::new(),
+        }
+    }
+
+    /// The result so far, counting the rules fired in an unfinished cycle
+    pub(crate) fn result(&self) -> ExecutionResult {
+        let mut result = self.result.clone();
+        if self.in_cycle {
+            result
+                .rules_fired_by_cycle
+                .push(result.rules_fired[self.fired_before..].to_vec());
+        }
+        result
+    }
+
+    fn end_cycle(&mut self) {
+        self.result
+            .rules_fired_by_cycle
+            .push(self.result.rules_fired[self.fired_before..].to_vec());
+        self.in_cycle = false;
+        self.position = 0;
+        if !self.fired && self.modified.is_empty() {
+            self.done = true;
+        }
+    }
+}
+
+/// The outcome of evaluating one fact set in `RuleEngine::evaluate_batch`.
+/// An `ExecutionResult` alone would lose both the values the rules computed,
+/// which live in the fact set, and the error of a set that failed.
+#[derive(Debug)]
+pub struct BatchResult {
+    /// The fact set after execution (or unchanged, if execution failed)
+    pub facts: WorkingMemory,
+    pub outcome: crate::Result<ExecutionResult>,
+}
+
+/// Cloning an engine is cheap: clones share the same knowledge base until one
+/// of them adds a rule, at which point that clone gets its own copy.
+#[derive(Clone)]
 pub struct RuleEngine {
-    knowledge_base: KnowledgeBase,
+    knowledge_base: Arc<KnowledgeBase>,
+    config: EngineConfig,
+    functions: HashMap<String, CustomFunction>,
+    globals: HashMap<String, FactValue>,
+    schemas: HashMap<String, FactSchema>,
+    enums: HashMap<String, EnumType>,
+    middleware: Vec<Middleware>,
 }
 
 impl RuleEngine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Self {
-            knowledge_base: KnowledgeBase::new(),
+            knowledge_base: Arc::new(KnowledgeBase::new()),
+            config,
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            schemas: HashMap::new(),
+            enums: HashMap::new(),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// An engine serving an already built knowledge base, which may be shared
+    /// with other engines. Its rules are checked as `add_rule` would.
+    pub fn with_knowledge_base(
+        knowledge_base: Arc<KnowledgeBase>,
+        config: EngineConfig,
+    ) -> crate::Result<Self> {
+        let mut engine = Self::with_config(config);
+        engine.set_knowledge_base(knowledge_base)?;
+        Ok(engine)
+    }
+
+    /// An engine running the rules of `compiled`, shared rather than copied
+    pub fn with_compiled(
+        compiled: &CompiledKnowledgeBase,
+        config: EngineConfig,
+    ) -> crate::Result<Self> {
+        Self::with_knowledge_base(compiled.shared(), config)
+    }
+
+    pub fn builder() -> RuleEngineBuilder {
+        RuleEngineBuilder::new()
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Add a rule, returning any warnings found while preparing it
+    pub fn add_rule(&mut self, rule: Rule) -> crate::Result<Vec<RuleWarning>> {
+        self.check_rule(&rule)?;
+        Ok(Arc::make_mut(&mut self.knowledge_base).add_rule(rule)?)
+    }
+
+    /// Check `rule` against the expression depth limit, the declared enums and
+    /// the registered schemas
+    fn check_rule(&self, rule: &Rule) -> crate::Result<()> {
+        if let Some(max_depth) = self.config.limits.max_expression_depth {
+            for expr in rule.expressions() {
+                if expr.depth() > max_depth {
+                    return Err(EngineError::ResourceLimit(format!(
+                        "rule '{}' has an expression deeper than {}",
+                        rule.name, max_depth
+                    ))
+                    .into());
+                }
+            }
+        }
+        for expr in rule.expressions() {
+            self.check_enum_variants(expr)?;
+        }
+        let lenient = self.config.coercion == CoercionPolicy::Lenient;
+        typecheck::check_rule(rule, &self.schemas, lenient).map_err(|reason| {
+            KnowledgeBaseError::InvalidRule {
+                rule: rule.name.clone(),
+                reason,
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Check every rule of `knowledge_base` as `add_rule` would
+    fn check_rules(&self, knowledge_base: &KnowledgeBase) -> crate::Result<()> {
+        knowledge_base
+            .get_rules()
+            .iter()
+            .try_for_each(|rule| self.check_rule(rule))
+    }
+
+    /// Add `rules` as the package `package`. Either every rule is added or,
+    /// if one fails, none are.
+    pub fn add_package(
+        &mut self,
+        package: &str,
+        rules: impl IntoIterator<Item = Rule>,
+    ) -> crate::Result<Vec<RuleWarning>> {
+        let mut warnings = Vec::new();
+        let mut added = Vec::new();
+        for rule in rules {
+            let name = rule.name.clone();
+            match self.add_rule(rule.with_package(package)) {
+                Ok(rule_warnings) => {
+                    warnings.extend(rule_warnings);
+                    added.push(name);
+                }
+                Err(e) => {
+                    let knowledge_base = Arc::make_mut(&mut self.knowledge_base);
+                    for name in &added {
+                        knowledge_base.remove_rule(name);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Remove every rule in `package`
+    pub fn remove_package(&mut self, package: &str) -> Vec<Rule> {
+        Arc::make_mut(&mut self.knowledge_base).remove_package(package)
+    }
+
+    /// See `KnowledgeBase::define_ruleset`
+    pub fn define_ruleset<I, S>(&mut self, name: impl Into<String>, rules: I) -> crate::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Ok(Arc::make_mut(&mut self.knowledge_base).define_ruleset(name, rules)?)
+    }
+
+    /// Declare an enumeration. Rules refer to its variants as
+    /// `OrderStatus.PAID`, and adding a rule that names an undeclared variant
+    /// fails, as does comparing a variant with a string naming an undeclared
+    /// one or assigning such a string to a field holding a variant.
+    pub fn register_enum(&mut self, enum_type: EnumType) {
+        let variants = enum_type
+            .variants()
+            .iter()
+            .filter_map(|variant| Some((variant.clone(), enum_type.value(variant)?)))
+            .collect();
+        self.set_global(enum_type.name(), FactValue::Object(variants));
+        self.enums.insert(enum_type.name().to_string(), enum_type);
+    }
+
+    /// `Enum.VARIANT` references in `expr` must name declared variants
+    fn check_enum_variants(&self, expr: &Expression) -> Result<(), EngineError> {
+        if let Expression::FieldAccess(object, variant) = expr {
+            if let Expression::Variable(name) = object.as_ref() {
+                if let Some(enum_type) = self.enums.get(name) {
+                    self.check_variant(enum_type, variant)?;
+                }
+            }
+        }
+        expr.children()
+            .into_iter()
+            .try_for_each(|child| self.check_enum_variants(child))
+    }
+
+    fn check_variant(&self, enum_type: &EnumType, variant: &str) -> Result<(), EngineError> {
+        if enum_type.has_variant(variant) {
+            Ok(())
+        } else {
+            Err(EngineError::UnknownEnumVariant {
+                enum_type: enum_type.name().to_string(),
+                variant: variant.to_string(),
+            })
+        }
+    }
+
+    /// A string compared with or assigned in place of a variant must name one
+    /// of its enum's variants. Enums that are not registered are not checked.
+    fn check_enum_string(&self, variant: &FactValue, value: &FactValue) -> Result<(), EngineError> {
+        if let (FactValue::Enum { enum_type, .. }, FactValue::String(s)) = (variant, value) {
+            if let Some(enum_type) = self.enums.get(enum_type) {
+                self.check_variant(enum_type, s)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A string assigned to a field holding a variant becomes the variant of
+    /// that name
+    fn enum_assignment(
+        &self,
+        current: &FactValue,
+        value: FactValue,
+    ) -> Result<FactValue, EngineError> {
+        self.check_enum_string(current, &value)?;
+        Ok(match (current, value) {
+            (FactValue::Enum { enum_type, .. }, FactValue::String(variant))
+                if self.enums.contains_key(enum_type) =>
+            {
+                FactValue::Enum {
+                    enum_type: enum_type.clone(),
+                    variant,
+                }
+            }
+            (_, value) => value,
+        })
+    }
+
+    /// Apply a binary operator under the engine's configuration
+    pub(crate) fn binary(
+        &self,
+        op: BinaryOp,
+        left: FactValue,
+        right: FactValue,
+    ) -> Result<FactValue, EngineError> {
+        if matches!(op, BinaryOp::Equal | BinaryOp::NotEqual) {
+            self.check_enum_string(&left, &right)?;
+            self.check_enum_string(&right, &left)?;
+        }
+        if self.config.three_valued_logic {
+            if let Some(result) = kleene(op, &left, &right) {
+                return Ok(result);
+            }
+        }
+        self.within_limits(op.apply(
+            left,
+            right,
+            &self.config.numeric,
+            self.config.collation,
+            self.config.coercion,
+        )?)
+    }
+
+    /// Logical negation, leaving an unknown value unknown under three-valued
+    /// logic
+    pub(crate) fn not(&self, value: FactValue) -> FactValue {
+        match value {
+            FactValue::Null if self.config.three_valued_logic => FactValue::Null,
+            value => FactValue::Boolean(!value.is_truthy()),
         }
     }
 
-    pub fn add_rule(&mut self, rule: Rule) -> Result<(), String> {
-        self.knowledge_base.add_rule(rule)
+    /// Numeric negation, leaving an unknown value unknown under three-valued
+    /// logic
+    pub(crate) fn negate(&self, value: FactValue) -> Result<FactValue, EngineError> {
+        match value {
+            FactValue::Null if self.config.three_valued_logic => Ok(FactValue::Null),
+            value => negate(value),
+        }
+    }
+
+    /// Wrap the firing of every rule activation in `middleware`, for logging,
+    /// metrics or policy checks. Middleware registered first runs outermost.
+    pub fn use_middleware<F>(&mut self, middleware: F)
+    where
+        F: Fn(&FireContext<'_>, Next<'_>) -> Result<(), EngineError> + Send + Sync + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Set a value every rule can read by name but none may write, such as a
+    /// configuration constant. Globals hide facts of the same name.
+    pub fn set_global(&mut self, name: impl Into<String>, value: FactValue) {
+        self.globals.insert(name.into(), value);
+    }
+
+    pub fn global(&self, name: &str) -> Option<&FactValue> {
+        self.globals.get(name)
+    }
+
+    /// Register a function rules can call as `name(args)`. The function also
+    /// receives the `EvalContext` of the execution calling it.
+    pub fn register_function<F>(&mut self, name: impl Into<String>, function: F)
+    where
+        F: Fn(&EvalContext, &[FactValue]) -> Result<FactValue, EngineError> + Send + Sync + 'static,
+    {
+        self.functions.insert(name.into(), Arc::new(function));
+    }
+
+    /// Require the fact named `fact` to match `schema`, both when an execution
+    /// starts and whenever a rule writes to it. Rules are checked against the
+    /// schema's field types, whether added before or after it; if one fails,
+    /// the schema is not registered.
+    pub fn register_schema(
+        &mut self,
+        fact: impl Into<String>,
+        schema: FactSchema,
+    ) -> crate::Result<()> {
+        let fact = fact.into();
+        let previous = self.schemas.insert(fact.clone(), schema);
+        if let Err(e) = self.check_rules(&self.knowledge_base) {
+            match previous {
+                Some(previous) => self.schemas.insert(fact, previous),
+                None => self.schemas.remove(&fact),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Check the named fact against its schema, if it has one and still exists
+    fn validate(&self, name: &str, facts: &WorkingMemory) -> Result<(), EngineError> {
+        let (Some(schema), Some(fact)) = (self.schemas.get(name), facts.get(name)) else {
+            return Ok(());
+        };
+        schema
+            .validate(&fact.value)
+            .map_err(|reason| EngineError::Validation {
+                fact: name.to_string(),
+                reason,
+            })
+    }
+
+    /// Execute all rules against working memory. If any rule fails, memory is
+    /// restored to its state before execution.
+    pub fn execute(&self, facts: &mut WorkingMemory) -> crate::Result<ExecutionResult> {
+        Ok(self.execute_atomically(facts, |engine, facts| {
+            engine.run(facts, engine.agenda(), None, &Scope::default())
+        })?)
+    }
+
+    /// Like `execute`, but passes `context` to every custom function called
+    pub fn execute_with_context(
+        &self,
+        facts: &mut WorkingMemory,
+        context: EvalContext,
+    ) -> crate::Result<ExecutionResult> {
+        let root = Scope::new(Arc::new(context));
+        Ok(self.execute_atomically(facts, |engine, facts| {
+            engine.run(facts, engine.agenda(), None, &root)
+        })?)
+    }
+
+    /// Execute the rules against many independent fact sets. The agenda is
+    /// worked out once and shared, and a failure in one set does not affect
+    /// the others. Each set comes back as a `BatchResult` holding the
+    /// resulting facts next to the set's `ExecutionResult` or error.
+    pub fn evaluate_batch<I, T>(&self, fact_sets: I) -> Vec<BatchResult>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<WorkingMemory>,
+    {
+        let agenda = self.agenda();
+        fact_sets
+            .into_iter()
+            .map(|fact_set| {
+                let mut facts = fact_set.into();
+                let outcome = self
+                    .execute_atomically(&mut facts, |engine, facts| {
+                        engine.run(facts, agenda.clone(), None, &Scope::default())
+                    })
+                    .map_err(Into::into);
+                BatchResult { facts, outcome }
+            })
+            .collect()
+    }
+
+    /// Like `execute`, but only considers rules for which `filter` returns true
+    pub fn execute_filtered<F>(
+        &self,
+        facts: &mut WorkingMemory,
+        filter: F,
+    ) -> crate::Result<ExecutionResult>
+    where
+        F: Fn(&Rule) -> bool,
+    {
+        Ok(self.execute_atomically(facts, |engine, facts| {
+            let rules = engine
+                .agenda()
+                .into_iter()
+                .filter(|rule| filter(rule))
+                .collect();
+            engine.run(facts, rules, None, &Scope::default())
+        })?)
+    }
+
+    /// Execute only the rules tagged with `group`
+    pub fn execute_group(
+        &self,
+        group: &str,
+        facts: &mut WorkingMemory,
+    ) -> crate::Result<ExecutionResult> {
+        self.execute_filtered(facts, |rule| rule.tags.contains(group))
+    }
+
+    /// Execute only the rules in `package`
+    pub fn execute_package(
+        &self,
+        package: &str,
+        facts: &mut WorkingMemory,
+    ) -> crate::Result<ExecutionResult> {
+        self.execute_filtered(facts, |rule| rule.package.as_deref() == Some(package))
     }
 
-    pub fn execute(
+    /// Execute only the rules of ruleset `name`, see
+    /// `KnowledgeBase::define_ruleset`
+    pub fn execute_ruleset(
         &self,
-        facts: &mut HashMap<String, Fact>,
+        name: &str,
+        facts: &mut WorkingMemory,
+    ) -> crate::Result<ExecutionResult> {
+        let rules = self
+            .knowledge_base
+            .ruleset(name)
+            .ok_or_else(|| KnowledgeBaseError::RulesetNotFound(name.to_string()))?;
+        self.execute_filtered(facts, |rule| rules.contains(&rule.name))
+    }
+
+    /// Like `execute`, but stops with `EngineError::Cancelled` once `token` is
+    /// cancelled. The token is checked before each rule is evaluated, and memory
+    /// is restored to its state before execution.
+    pub fn execute_cancellable(
+        &self,
+        facts: &mut WorkingMemory,
+        token: &CancelToken,
+    ) -> crate::Result<ExecutionResult> {
+        Ok(self.execute_atomically(facts, |engine, facts| {
+            engine.run(facts, engine.agenda(), Some(token), &Scope::default())
+        })?)
+    }
+
+    fn execute_atomically<F>(
+        &self,
+        facts: &mut WorkingMemory,
+        run: F,
+    ) -> Result<ExecutionResult, EngineError>
+    where
+        F: FnOnce(&Self, &mut WorkingMemory) -> Result<ExecutionResult, EngineError>,
+    {
+        let audit = self
+            .config
+            .audit_log
+            .as_ref()
+            .map(|audit_log| (audit_log, self.config.clock.now_millis(), facts.clone()));
+        let tracked = self.config.track_changes.then(|| facts.clone());
+        facts.checkpoint();
+
+        let mut outcome = self
+            .schemas
+            .keys()
+            .try_for_each(|name| self.validate(name, facts))
+            .and_then(|()| run(self, facts));
+        if let (Ok(result), Some(before)) = (&mut outcome, &tracked) {
+            result.changes = facts::diff(before, facts);
+        }
+        if let Some((audit_log, timestamp_ms, before)) = audit {
+            if let Err(e) = Self::audit(audit_log, timestamp_ms, &before, facts, &outcome) {
+                outcome = Err(e);
+            }
+        }
+        if outcome.is_ok() {
+            facts.commit();
+        } else {
+            facts.rollback();
+        }
+
+        outcome
+    }
+
+    fn audit(
+        audit_log: &AuditLog,
+        timestamp_ms: i64,
+        before: &WorkingMemory,
+        after: &WorkingMemory,
+        outcome: &Result<ExecutionResult, EngineError>,
+    ) -> Result<(), EngineError> {
+        let input = before
+            .iter()
+            .map(|fact| (fact.name.clone(), fact.value.clone()))
+            .collect();
+        let record = match outcome {
+            Ok(result) => AuditRecord {
+                timestamp_ms,
+                input,
+                rules_fired: result.rules_fired.clone(),
+                changes: report::diff(before, after),
+                error: None,
+            },
+            Err(e) => AuditRecord {
+                timestamp_ms,
+                input,
+                rules_fired: Vec::new(),
+                changes: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        };
+        audit_log
+            .record(&record)
+            .map_err(|e| EngineError::Audit(e.to_string()))
+    }
+
+    /// The match-fire loop over `rules`, which are considered in order.
+    /// Activations are found by extending `root`.
+    fn run(
+        &self,
+        facts: &mut WorkingMemory,
+        rules: Vec<&Rule>,
+        cancel: Option<&CancelToken>,
+        root: &Scope,
     ) -> Result<ExecutionResult, EngineError> {
         let start_time = std::time::Instant::now();
-        let mut result = ExecutionResult::new();
+        let mut cycles = Cycles::new(rules, root.clone());
+        while let Some((rule, activations)) = self.next_activations(&mut cycles, facts, cancel)? {
+            self.fire_next(&mut cycles, rule, activations, facts)?;
+        }
+        let mut result = cycles.result;
+        result.execution_time_ms = start_time.elapsed().as_millis();
+        Ok(result)
+    }
 
-        // Get rules sorted by salience (priority)
-        let rules = self.knowledge_base.get_rules_sorted_by_salience();
+    /// Advance `cycles` to the next rule that has activations, starting a new
+    /// cycle as needed, or `None` once the loop is over. Facts inserted
+    /// logically by a rule that no longer holds are retracted on the way.
+    pub(crate) fn next_activations<'a>(
+        &self,
+        cycles: &mut Cycles<'a>,
+        facts: &mut WorkingMemory,
+        cancel: Option<&CancelToken>,
+    ) -> Result<Option<(&'a Rule, Vec<Scope>)>, EngineError> {
+        while !cycles.done {
+            if !cycles.in_cycle {
+                if cycles.result.cycles >= self.config.max_cycles {
+                    cycles.done = true;
+                    break;
+                }
+                cycles.result.cycles += 1;
+                cycles.in_cycle = true;
+                cycles.dirty = std::mem::take(&mut cycles.modified);
+                cycles.fired = false;
+                cycles.fired_before = cycles.result.rules_fired.len();
+            }
+            let Some(&rule) = cycles.rules.get(cycles.position) else {
+                cycles.end_cycle();
+                continue;
+            };
+            cycles.position += 1;
 
-        // Execute rules in order of salience
-        for rule in rules {
-            if self.evaluate_condition(&rule.when_condition, facts)? {
-                // Execute rule actions
-                for action in &rule.then_actions {
-                    self.execute_action(action, facts)?;
+            if cancel.is_some_and(CancelToken::is_cancelled) {
+                return Err(EngineError::Cancelled);
+            }
+            if cycles.result.cycles > 1 {
+                let affected = self
+                    .knowledge_base
+                    .get_dependencies(&rule.name)
+                    .is_none_or(|deps| deps.condition_affected_by(cycles.dirty.iter()));
+                if !affected {
+                    continue;
                 }
+            }
+
+            let mut suppressed = Vec::new();
+            let activations = self.activations(rule, facts, &cycles.root, &mut suppressed)?;
+            cycles.result.record_suppressed(rule, suppressed);
+            if !activations.is_empty() {
+                return Ok(Some((rule, activations)));
+            }
+            // The rule no longer holds, so neither do the facts it inserted
+            let unsupported: Vec<String> = facts
+                .logical_support
+                .iter()
+                .filter(|(_, supporting_rule)| **supporting_rule == rule.name)
+                .map(|(fact_name, _)| fact_name.clone())
+                .collect();
+            for fact_name in unsupported {
+                if facts.retract(&fact_name).is_some() {
+                    cycles.modified.push(FieldRef::fact(fact_name.clone()));
+                    cycles.result.facts_retracted.push(fact_name);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Fire the activations `next_activations` found for `rule`, returning
+    /// whether any fired
+    pub(crate) fn fire_next(
+        &self,
+        cycles: &mut Cycles,
+        rule: &Rule,
+        activations: Vec<Scope>,
+        facts: &mut WorkingMemory,
+    ) -> Result<bool, EngineError> {
+        let mut suppressed = Vec::new();
+        let fired = self.fire_activations(rule, activations, facts, &mut suppressed)?;
+        let any_fired = !fired.is_empty();
+        for writes in fired {
+            self.record_provenance(rule, cycles.result.cycles, &writes, facts);
+            for write in writes {
+                if !cycles.result.facts_modified.contains(&write.target.fact) {
+                    cycles.result.facts_modified.push(write.target.fact.clone());
+                }
+                if write.notifies {
+                    cycles.modified.push(write.target);
+                }
+            }
+            cycles.result.rules_fired.push(rule.name.clone());
+            cycles.fired = true;
+        }
+        cycles.result.record_suppressed(rule, suppressed);
+        Ok(any_fired)
+    }
+
+    /// Like `execute`, but also returns a serializable report of the run,
+    /// including the before and after values of everything that changed
+    pub fn execute_with_report(
+        &self,
+        facts: &mut WorkingMemory,
+    ) -> crate::Result<(ExecutionResult, ExecutionReport)> {
+        let before = facts.clone();
+        let result = self.execute(facts)?;
+        let report = ExecutionReport::new(&result, &before, facts);
+        Ok((result, report))
+    }
+
+    /// Evaluate the named rule and fire it (once per activation) if it matches,
+    /// returning whether it fired. Changes are rolled back if an action fails.
+    pub fn fire_rule(&self, name: &str, facts: &mut WorkingMemory) -> crate::Result<bool> {
+        let rule = self
+            .knowledge_base
+            .get_rule(name)
+            .ok_or_else(|| KnowledgeBaseError::RuleNotFound(name.to_string()))?;
+        Ok(self.run_single(rule, facts)?)
+    }
+
+    pub(crate) fn run_single(
+        &self,
+        rule: &Rule,
+        facts: &mut WorkingMemory,
+    ) -> Result<bool, EngineError> {
+        if !rule.is_effective_at(self.config.clock.now_millis()) {
+            return Ok(false);
+        }
+        let mut fired = false;
+        self.execute_atomically(facts, |engine, facts| {
+            let mut result = ExecutionResult::new();
+            let mut suppressed = Vec::new();
+            let activations =
+                engine.activations(rule, facts, &Scope::default(), &mut suppressed)?;
+            for writes in engine.fire_activations(rule, activations, facts, &mut suppressed)? {
+                engine.record_provenance(rule, 1, &writes, facts);
+                fired = true;
+            }
+            result.cycles = 1;
+            Ok(result)
+        })?;
+        Ok(fired)
+    }
+
+    /// The rules in effect now, in the order they are tried
+    pub(crate) fn agenda(&self) -> Vec<&Rule> {
+        let mut rules = match self.config.conflict_resolution {
+            ConflictResolution::Salience => self.knowledge_base.get_rules_sorted_by_salience(),
+            ConflictResolution::InsertionOrder => self.knowledge_base.get_rules().iter().collect(),
+        };
+        let now = self.config.clock.now_millis();
+        rules.retain(|rule| rule.is_effective_at(now));
+        rules
+    }
+
+    /// Every way the rule currently matches working memory: one scope per
+    /// combination of facts satisfying its patterns and condition. A rule
+    /// without patterns has at most one activation.
+    ///
+    /// Activations extend `root`. Type errors that lenient typing turned into a
+    /// non-match are pushed to `suppressed`.
+    pub(crate) fn activations(
+        &self,
+        rule: &Rule,
+        facts: &WorkingMemory,
+        root: &Scope,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<Vec<Scope>, EngineError> {
+        let mut activations = Vec::new();
+        self.match_patterns(
+            rule,
+            &rule.patterns,
+            root.clone(),
+            facts,
+            &mut activations,
+            suppressed,
+        )?;
+        Ok(activations)
+    }
+
+    fn match_patterns(
+        &self,
+        rule: &Rule,
+        patterns: &[Pattern],
+        scope: Scope,
+        facts: &WorkingMemory,
+        activations: &mut Vec<Scope>,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<(), EngineError> {
+        let Some((pattern, rest)) = patterns.split_first() else {
+            activations.extend(self.complete_activation(rule, scope, facts, suppressed)?);
+            return Ok(());
+        };
+
+        for fact_name in self.candidates(pattern, facts, &scope) {
+            let candidate_scope = scope.bind_fact(&pattern.binding, fact_name);
+            let satisfied = match &pattern.constraint {
+                Some(constraint) => {
+                    self.condition_holds(constraint, facts, &candidate_scope, suppressed)?
+                }
+                None => true,
+            };
+            if satisfied {
+                self.match_patterns(rule, rest, candidate_scope, facts, activations, suppressed)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the facts a pattern may match, in a stable order regardless of
+    /// hash map iteration order. Facts are looked up in a working memory index
+    /// when the constraint requires an indexed field to equal a known value.
+    fn candidates<'a>(
+        &self,
+        pattern: &Pattern,
+        facts: &'a WorkingMemory,
+        scope: &Scope,
+    ) -> Vec<&'a str> {
+        // Indexes group values equal under the default comparison, which other
+        // policies may consider equal or fail on
+        if self.config.coercion == CoercionPolicy::Numeric && self.config.numeric.epsilon.is_none()
+        {
+            for (field, value) in indexable_equalities(pattern) {
+                // A value that fails to evaluate fails the constraint itself
+                let Ok(value) = self.evaluate_expression(value, facts, scope) else {
+                    continue;
+                };
+                // Null equals nothing, not even null, under three-valued logic
+                if self.config.three_valued_logic && value == FactValue::Null {
+                    continue;
+                }
+                if let Some(names) = facts.indexed(&pattern.fact_type, &field, &value) {
+                    return names;
+                }
+            }
+        }
+        let mut candidates: Vec<&str> = facts
+            .iter()
+            .filter(|fact| fact.fact_type.as_deref() == Some(&pattern.fact_type))
+            .map(|fact| fact.name.as_str())
+            .collect();
+        candidates.sort_unstable();
+        candidates
+    }
+
+    /// Bind the rule's values in `scope` and check its condition, returning the
+    /// completed scope if the rule matches
+    fn complete_activation(
+        &self,
+        rule: &Rule,
+        mut scope: Scope,
+        facts: &WorkingMemory,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<Option<Scope>, EngineError> {
+        for accumulate in &rule.accumulates {
+            let outcome = self.accumulate(accumulate, facts, &scope, suppressed);
+            if !self.bind_value(&mut scope, &accumulate.name, outcome, suppressed)? {
+                return Ok(None);
+            }
+        }
+        for binding in &rule.bindings {
+            let outcome = self.evaluate_expression(&binding.value, facts, &scope);
+            if !self.bind_value(&mut scope, &binding.name, outcome, suppressed)? {
+                return Ok(None);
+            }
+        }
+        let outcome = match self.knowledge_base.get_program(&rule.name) {
+            Some(program) => program
+                .execute(self, facts, &scope)
+                .map(|value| value.is_truthy()),
+            None => self.evaluate_condition(&rule.when_condition, facts, &scope),
+        };
+        Ok(self.lenient(outcome, suppressed)?.then_some(scope))
+    }
+
+    /// Bind a computed value in `scope`, returning false if computing it
+    /// failed with a type error that lenient typing turns into a non-match
+    fn bind_value(
+        &self,
+        scope: &mut Scope,
+        name: &str,
+        outcome: Result<FactValue, EngineError>,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<bool, EngineError> {
+        match outcome {
+            Ok(value) => {
+                scope.values.insert(name.to_string(), value);
+                Ok(true)
+            }
+            Err(e @ EngineError::TypeError(_)) if !self.config.strict_types => {
+                suppressed.push(e);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply an accumulate's function to its value for every matching fact
+    fn accumulate(
+        &self,
+        accumulate: &Accumulate,
+        facts: &WorkingMemory,
+        scope: &Scope,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<FactValue, EngineError> {
+        let pattern = &accumulate.pattern;
+        let mut values = Vec::new();
+        for fact_name in self.candidates(pattern, facts, scope) {
+            let fact_scope = scope.bind_fact(&pattern.binding, fact_name);
+            if let Some(constraint) = &pattern.constraint {
+                if !self.condition_holds(constraint, facts, &fact_scope, suppressed)? {
+                    continue;
+                }
+            }
+            values.push(self.evaluate_expression(&accumulate.value, facts, &fact_scope)?);
+        }
+        let values = self.within_limits(FactValue::Array(values))?;
+        self.call_function(&accumulate.function, &[values], scope)
+    }
+
+    /// Re-match a previously found activation against the current facts,
+    /// recomputing its bound values
+    fn recheck_activation(
+        &self,
+        rule: &Rule,
+        scope: &Scope,
+        facts: &WorkingMemory,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<Option<Scope>, EngineError> {
+        let scope = Scope {
+            facts: scope.facts.clone(),
+            values: HashMap::new(),
+            execution: Arc::clone(&scope.execution),
+        };
+        for pattern in &rule.patterns {
+            let bound = facts.get(scope.resolve(&pattern.binding));
+            if bound.and_then(|fact| fact.fact_type.as_deref()) != Some(&pattern.fact_type) {
+                return Ok(None);
+            }
+            if let Some(constraint) = &pattern.constraint {
+                if !self.condition_holds(constraint, facts, &scope, suppressed)? {
+                    return Ok(None);
+                }
+            }
+        }
+        self.complete_activation(rule, scope, facts, suppressed)
+    }
+
+    /// Evaluate a condition, treating type errors as a non-match unless
+    /// strict typing is enabled
+    fn condition_holds(
+        &self,
+        condition: &Expression,
+        facts: &WorkingMemory,
+        scope: &Scope,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<bool, EngineError> {
+        self.lenient(self.evaluate_condition(condition, facts, scope), suppressed)
+    }
+
+    /// Treat a type error as a non-match unless strict typing is enabled
+    fn lenient(
+        &self,
+        outcome: Result<bool, EngineError>,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<bool, EngineError> {
+        match outcome {
+            Err(e @ EngineError::TypeError(_)) if !self.config.strict_types => {
+                suppressed.push(e);
+                Ok(false)
+            }
+            outcome => outcome,
+        }
+    }
+
+    /// Fire each activation in turn, skipping any that an earlier one
+    /// invalidated. Returns the writes made by each activation that fired.
+    pub(crate) fn fire_activations(
+        &self,
+        rule: &Rule,
+        activations: Vec<Scope>,
+        facts: &mut WorkingMemory,
+        suppressed: &mut Vec<EngineError>,
+    ) -> Result<Vec<Vec<Write>>, EngineError> {
+        let mut fired = Vec::new();
+        for (i, scope) in activations.into_iter().enumerate() {
+            let scope = if i == 0 {
+                scope
+            } else {
+                match self.recheck_activation(rule, &scope, facts, suppressed)? {
+                    Some(scope) => scope,
+                    None => continue,
+                }
+            };
+            let firing = FireContext {
+                rule,
+                context: &scope.execution.context,
+            };
+            let mut writes = None;
+            let mut fire = || {
+                writes = Some(self.fire_activation(rule, &scope, facts)?);
+                Ok(())
+            };
+            self.run_middleware(&self.middleware, &firing, &mut fire)?;
+            // Middleware may have skipped the activation
+            fired.extend(writes);
+        }
+        Ok(fired)
+    }
+
+    fn run_middleware(
+        &self,
+        chain: &[Middleware],
+        firing: &FireContext<'_>,
+        fire: &mut dyn FnMut() -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        match chain.split_first() {
+            Some((middleware, rest)) => middleware(
+                firing,
+                Next {
+                    run: &mut || self.run_middleware(rest, firing, fire),
+                },
+            ),
+            None => fire(),
+        }
+    }
+
+    /// Run a rule's actions for one activation, returning the facts and fields written
+    fn fire_activation(
+        &self,
+        rule: &Rule,
+        scope: &Scope,
+        facts: &mut WorkingMemory,
+    ) -> Result<Vec<Write>, EngineError> {
+        let mut writes = Vec::new();
+        // Actions may assign to bound values, which stay local to this activation
+        let mut scope = scope.clone();
+        for action in &rule.then_actions {
+            self.perform(rule, action, &mut scope, facts, &mut writes)?;
+        }
+        Ok(writes)
+    }
+
+    /// Run one action, including the actions of any branch it takes, and
+    /// record what it wrote in `writes`
+    fn perform(
+        &self,
+        rule: &Rule,
+        action: &Expression,
+        scope: &mut Scope,
+        facts: &mut WorkingMemory,
+        writes: &mut Vec<Write>,
+    ) -> Result<(), EngineError> {
+        let targets = match action {
+            Expression::If(condition, then_actions, else_actions) => {
+                let branch = if self.evaluate_condition(condition, facts, scope)? {
+                    then_actions
+                } else {
+                    else_actions
+                };
+                for action in branch {
+                    self.perform(rule, action, scope, facts, writes)?;
+                }
+                return Ok(());
+            }
+            Expression::ForEach(item, collection, actions) => {
+                let FactValue::Array(items) = self.evaluate_expression(collection, facts, scope)?
+                else {
+                    return Err(EngineError::TypeError(
+                        "foreach expects an array".to_string(),
+                    ));
+                };
+                // The item shadows any value of the same name for the loop's duration
+                let shadowed = scope.values.remove(item);
+                let mut updated = Vec::with_capacity(items.len());
+                for value in &items {
+                    scope.values.insert(item.clone(), value.clone());
+                    for action in actions {
+                        self.perform(rule, action, scope, facts, writes)?;
+                    }
+                    updated.push(scope.values.remove(item).unwrap_or(FactValue::Null));
+                }
+                if let Some(value) = shadowed {
+                    scope.values.insert(item.clone(), value);
+                }
+                if updated == items {
+                    return Ok(());
+                }
+
+                // Write the modified items back in place
+                let (name, field) = match collection.as_ref() {
+                    Expression::Variable(name) => (name, None),
+                    Expression::FieldAccess(object, field) => match object.as_ref() {
+                        Expression::Variable(name) => (name, Some(field)),
+                        _ => {
+                            return Err(EngineError::EvaluationError(
+                                "foreach can only modify a fact or a field of a fact".to_string(),
+                            ))
+                        }
+                    },
+                    _ => {
+                        return Err(EngineError::EvaluationError(
+                            "foreach can only modify a fact or a field of a fact".to_string(),
+                        ))
+                    }
+                };
+                let wrote = self.assign(name, field, FactValue::Array(updated), facts, scope)?;
+                match (wrote, field) {
+                    (false, _) => Vec::new(),
+                    (true, Some(field)) => vec![FieldRef::field(name.clone(), field.clone())],
+                    (true, None) => vec![FieldRef::fact(name.clone())],
+                }
+            }
+            #[cfg(feature = "script")]
+            Expression::Script(source) => self.run_script(source, facts)?,
+            #[cfg(not(feature = "script"))]
+            Expression::Script(_) => {
+                return Err(EngineError::EvaluationError(
+                    "script actions require the `script` feature".to_string(),
+                ))
+            }
+            _ => {
+                if self.execute_action(action, facts, scope)? {
+                    analysis::written_field(action).into_iter().collect()
+                } else {
+                    Vec::new()
+                }
+            }
+        };
+        let wrote = !targets.is_empty();
+        for mut target in targets {
+            target.fact = scope.resolve(&target.fact).to_string();
+            self.validate(&target.fact, facts)?;
+            facts.stamp(&target.fact, self.config.clock.now_millis(), &rule.name);
+            let notifies = !self.config.explicit_updates
+                || matches!(
+                    action,
+                    Expression::Update(_) | Expression::InsertLogical(_, _)
+                );
+            writes.push(Write { target, notifies });
+        }
+        if let (Expression::InsertLogical(name, _), true) = (action, wrote) {
+            facts
+                .logical_support
+                .insert(scope.resolve(name).to_string(), rule.name.clone());
+        }
+        Ok(())
+    }
+
+    /// Run a script action against a copy of working memory, then write its
+    /// changes back. Returns the facts that changed.
+    #[cfg(feature = "script")]
+    fn run_script(
+        &self,
+        source: &str,
+        facts: &mut WorkingMemory,
+    ) -> Result<Vec<FieldRef>, EngineError> {
+        let mut values: HashMap<String, FactValue> = facts
+            .iter()
+            .map(|fact| (fact.name.clone(), fact.value.clone()))
+            .collect();
+        match &self.config.script_engine {
+            Some(script_engine) => script_engine.run(source, &mut values)?,
+            None => ScriptInterpreter::new()
+                .clock(self.config.clock.clone())
+                .run(source, &mut values)?,
+        }
+
+        let mut changed: Vec<String> = facts
+            .names()
+            .filter(|name| !values.contains_key(*name))
+            .map(str::to_string)
+            .collect();
+        for name in &changed {
+            facts.retract(name);
+        }
+        for (name, value) in values {
+            if facts.get(&name).is_some_and(|fact| fact.value == value) {
+                continue;
+            }
+            if self.globals.contains_key(&name) {
+                return Err(EngineError::ReadOnlyGlobal(name));
+            }
+            if facts.update(&name, value.clone()).is_none() {
+                facts.insert(self.created_fact(name.clone(), value));
+            }
+            changed.push(name);
+        }
+        // Report changes in a stable order regardless of hash map iteration order
+        changed.sort_unstable();
+        Ok(changed.into_iter().map(FieldRef::fact).collect())
+    }
+
+    fn record_provenance(
+        &self,
+        rule: &Rule,
+        cycle: usize,
+        writes: &[Write],
+        facts: &mut WorkingMemory,
+    ) {
+        if !self.config.track_provenance {
+            return;
+        }
+        for write in writes {
+            let provenance = Provenance {
+                rule: rule.name.clone(),
+                cycle,
+            };
+            facts.record_provenance(&write.target, provenance);
+        }
+    }
+
+    /// Single-pass execution that evaluates every condition up front, in parallel,
+    /// against the facts as they were before any rule fired. Matched rules are then
+    /// fired serially in salience order.
+    #[cfg(feature = "parallel")]
+    pub fn execute_parallel(&self, facts: &mut WorkingMemory) -> crate::Result<ExecutionResult> {
+        Ok(self.execute_atomically(facts, |engine, facts| engine.run_parallel(facts))?)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn run_parallel(&self, facts: &mut WorkingMemory) -> Result<ExecutionResult, EngineError> {
+        let start_time = std::time::Instant::now();
+        let mut result = ExecutionResult::new();
+        result.cycles = 1;
+
+        let rules = self.agenda();
+        let matched = self.evaluate_conditions_parallel(&rules, facts)?;
+
+        for (rule, (activations, mut suppressed)) in rules.iter().zip(matched) {
+            for writes in self.fire_activations(rule, activations, facts, &mut suppressed)? {
+                self.record_provenance(rule, 1, &writes, facts);
                 result.rules_fired.push(rule.name.clone());
             }
+            result.record_suppressed(rule, suppressed);
         }
+        result.rules_fired_by_cycle.push(result.rules_fired.clone());
 
         result.execution_time_ms = start_time.elapsed().as_millis();
         Ok(result)
     }
 
-    fn evaluate_condition(
+    #[cfg(feature = "parallel")]
+    fn evaluate_conditions_parallel(
+        &self,
+        rules: &[&Rule],
+        facts: &WorkingMemory,
+    ) -> Result<Vec<RuleMatch>, EngineError> {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk_size = rules.len().div_ceil(threads).max(1);
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = rules
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|rule| {
+                                let mut suppressed = Vec::new();
+                                let activations = self.activations(
+                                    rule,
+                                    facts,
+                                    &Scope::default(),
+                                    &mut suppressed,
+                                )?;
+                                Ok((activations, suppressed))
+                            })
+                            .collect::<Result<Vec<_>, EngineError>>()
+                    })
+                })
+                .collect();
+
+            // Join in order so the first error reported is the one from the
+            // highest-priority rule
+            let mut matched = Vec::with_capacity(rules.len());
+            for handle in handles {
+                let chunk = handle.join().map_err(|_| {
+                    EngineError::EvaluationError("Condition evaluation panicked".to_string())
+                })?;
+                matched.extend(chunk?);
+            }
+            Ok(matched)
+        })
+    }
+
+    /// Start a stateful session with its own working memory
+    pub fn session(&self) -> Session {
+        Session::new(self.clone())
+    }
+
+    /// Start a debug session that executes the rules step by step
+    pub fn debug<'a>(&'a self, facts: &'a mut WorkingMemory) -> DebugSession<'a> {
+        DebugSession::new(self, facts)
+    }
+
+    pub(crate) fn evaluate_condition(
         &self,
         expr: &Expression,
-        facts: &HashMap<String, Fact>,
+        facts: &WorkingMemory,
+        scope: &Scope,
     ) -> Result<bool, EngineError> {
-        let value = self.evaluate_expression(expr, facts)?;
+        let value = self.evaluate_expression(expr, facts, scope)?;
         Ok(value.is_truthy())
     }
 
     fn evaluate_expression(
         &self,
         expr: &Expression,
-        facts: &HashMap<String, Fact>,
+        facts: &WorkingMemory,
+        scope: &Scope,
     ) -> std::result::Result<FactValue, EngineError> {
+        if let Some((op, left, right)) = BinaryOp::split(expr) {
+            let left_val = self.evaluate_expression(left, facts, scope)?;
+            let right_val = self.evaluate_expression(right, facts, scope)?;
+            return self.binary(op, left_val, right_val);
+        }
+
         match expr {
             Expression::String(s) => Ok(FactValue::String(s.clone())),
             Expression::Number(n) => Ok(FactValue::Number(*n)),
             Expression::Boolean(b) => Ok(FactValue::Boolean(*b)),
+            Expression::Duration(ms) => i64::try_from(*ms).map(FactValue::Duration).map_err(|_| {
+                EngineError::EvaluationError(format!("duration of {}ms is too long", ms))
+            }),
+            Expression::Null => Ok(FactValue::Null),
 
-            Expression::Variable(name) => facts
-                .get(name)
-                .map(|fact| fact.value.clone())
-                .ok_or_else(|| EngineError::UnknownVariable(name.clone())),
+            Expression::Variable(name) => self.lookup(name, facts, scope).map(Cow::into_owned),
 
             Expression::FieldAccess(obj_expr, field) => {
-                match self.evaluate_expression(obj_expr, facts)? {
-                    FactValue::Object(obj) => obj.get(field).cloned().ok_or_else(|| {
-                        EngineError::EvaluationError(format!("Field '{}' not found", field))
-                    }),
+                let object = self.evaluate_expression(obj_expr, facts, scope)?;
+                self.access_field(&object, field)
+            }
+
+            Expression::OptionalFieldAccess(obj_expr, field) => {
+                match self.evaluate_optional(obj_expr, facts, scope)? {
+                    FactValue::Null => Ok(FactValue::Null),
+                    FactValue::Object(obj) => {
+                        Ok(obj.get(field).cloned().unwrap_or(FactValue::Null))
+                    }
                     _ => Err(EngineError::TypeError(
                         "Cannot access field on non-object".to_string(),
                     )),
                 }
             }
 
-            Expression::Add(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Number(a + b)),
-                    (FactValue::String(a), FactValue::String(b)) => Ok(FactValue::String(a + &b)),
-                    _ => Err(EngineError::TypeError("Cannot add these types".to_string())),
-                }
+            Expression::Index(array_expr, index_expr) => {
+                let array = self.evaluate_expression(array_expr, facts, scope)?;
+                let index = self.evaluate_expression(index_expr, facts, scope)?;
+                index_array(array, &index)
             }
 
-            Expression::Subtract(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Number(a - b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot subtract these types".to_string(),
-                    )),
+            Expression::Coalesce(value, default) => {
+                match self.evaluate_optional(value, facts, scope)? {
+                    FactValue::Null => self.evaluate_expression(default, facts, scope),
+                    value => Ok(value),
                 }
             }
 
-            Expression::Multiply(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Number(a * b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot multiply these types".to_string(),
-                    )),
+            Expression::In(needle, haystack) => {
+                let needle = self.evaluate_expression(needle, facts, scope)?;
+                let haystack = self.evaluate_expression(haystack, facts, scope)?;
+                if self.config.three_valued_logic
+                    && (needle == FactValue::Null || haystack == FactValue::Null)
+                {
+                    return Ok(FactValue::Null);
                 }
+                builtins::contains(&[haystack, needle])
             }
 
-            Expression::Divide(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => {
-                        if b == 0.0 {
-                            Err(EngineError::DivisionByZero)
-                        } else {
-                            Ok(FactValue::Number(a / b))
-                        }
+            Expression::Matches(value, pattern) => {
+                let text = match self.evaluate_expression(value, facts, scope)? {
+                    FactValue::String(text) => text,
+                    FactValue::Null if self.config.three_valued_logic => {
+                        return Ok(FactValue::Null)
+                    }
+                    _ => {
+                        return Err(EngineError::TypeError(
+                            "matches expects a string".to_string(),
+                        ))
+                    }
+                };
+                if let Expression::String(source) = pattern.as_ref() {
+                    if let Some(regex) = self.knowledge_base.regex(source) {
+                        return Ok(FactValue::Boolean(regex.is_match(&text)));
                     }
-                    _ => Err(EngineError::TypeError(
-                        "Cannot divide these types".to_string(),
-                    )),
                 }
+                let FactValue::String(source) = self.evaluate_expression(pattern, facts, scope)?
+                else {
+                    return Err(EngineError::TypeError(
+                        "matches expects a string pattern".to_string(),
+                    ));
+                };
+                let regex = Regex::new(&source).map_err(|e| {
+                    EngineError::EvaluationError(format!("Invalid pattern '{}': {}", source, e))
+                })?;
+                Ok(FactValue::Boolean(regex.is_match(&text)))
             }
 
-            Expression::Equal(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                Ok(FactValue::Boolean(self.values_equal(&left_val, &right_val)))
+            Expression::Not(expr) => {
+                let val = self.evaluate_expression(expr, facts, scope)?;
+                Ok(self.not(val))
             }
 
-            Expression::NotEqual(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                Ok(FactValue::Boolean(
-                    !self.values_equal(&left_val, &right_val),
-                ))
+            Expression::LetIn(name, value, body) => {
+                let value = self.evaluate_expression(value, facts, scope)?;
+                let mut scope = scope.clone();
+                scope.values.insert(name.clone(), value);
+                self.evaluate_expression(body, facts, &scope)
             }
 
-            Expression::LessThan(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Boolean(a < b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot compare these types".to_string(),
-                    )),
-                }
+            Expression::Negate(expr) => {
+                let val = self.evaluate_expression(expr, facts, scope)?;
+                self.negate(val)
             }
 
-            Expression::LessEqual(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Boolean(a <= b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot compare these types".to_string(),
-                    )),
-                }
+            Expression::EventCount(event_type, window_ms) => {
+                Ok(self.event_count(event_type, *window_ms, facts))
             }
 
-            Expression::GreaterThan(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Boolean(a > b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot compare these types".to_string(),
-                    )),
-                }
+            Expression::FunctionCall(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate_expression(arg, facts, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call_function(name, &args, scope)
             }
 
-            Expression::GreaterEqual(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                match (left_val, right_val) {
-                    (FactValue::Number(a), FactValue::Number(b)) => Ok(FactValue::Boolean(a >= b)),
-                    _ => Err(EngineError::TypeError(
-                        "Cannot compare these types".to_string(),
+            Expression::MethodCall(receiver, name, args) => {
+                let args = std::iter::once(receiver.as_ref())
+                    .chain(args)
+                    .map(|arg| self.evaluate_expression(arg, facts, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let value = builtins::call_method(name, &args, self.config.clock.as_ref())
+                    .unwrap_or_else(|| {
+                        Err(EngineError::TypeError(format!(
+                            "{} has no method '{}'",
+                            args[0].type_name(),
+                            name
+                        )))
+                    })?;
+                self.within_limits(value)
+            }
+
+            Expression::Array(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.evaluate_expression(item, facts, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.within_limits(FactValue::Array(items))
+            }
+
+            // As a value, an `if` gives the expression of the branch it takes,
+            // or null when that branch is empty
+            Expression::If(condition, then_branch, else_branch) => {
+                let branch = if self.evaluate_condition(condition, facts, scope)? {
+                    then_branch
+                } else {
+                    else_branch
+                };
+                match branch.as_slice() {
+                    [] => Ok(FactValue::Null),
+                    [value] => self.evaluate_expression(value, facts, scope),
+                    _ => Err(EngineError::EvaluationError(
+                        "An if used as a value needs one expression per branch".to_string(),
                     )),
                 }
             }
 
-            Expression::And(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                Ok(FactValue::Boolean(
-                    left_val.is_truthy() && right_val.is_truthy(),
-                ))
+            _ => Err(EngineError::EvaluationError(
+                "Unsupported expression type".to_string(),
+            )),
+        }
+    }
+
+    /// Evaluate an expression, treating a missing variable as null
+    fn evaluate_optional(
+        &self,
+        expr: &Expression,
+        facts: &WorkingMemory,
+        scope: &Scope,
+    ) -> Result<FactValue, EngineError> {
+        match self.evaluate_expression(expr, facts, scope) {
+            Err(EngineError::UnknownVariable(_)) => Ok(FactValue::Null),
+            outcome => outcome,
+        }
+    }
+
+    /// Check a value produced by an expression against the configured limits
+    pub(crate) fn within_limits(&self, value: FactValue) -> Result<FactValue, EngineError> {
+        let limits = &self.config.limits;
+        match &value {
+            FactValue::String(s) if limits.max_string_length.is_some_and(|max| s.len() > max) => {
+                Err(EngineError::ResourceLimit(format!(
+                    "string of length {} produced",
+                    s.len()
+                )))
+            }
+            FactValue::Array(items)
+                if limits.max_array_size.is_some_and(|max| items.len() > max) =>
+            {
+                Err(EngineError::ResourceLimit(format!(
+                    "array of size {} produced",
+                    items.len()
+                )))
             }
+            _ => Ok(value),
+        }
+    }
 
-            Expression::Or(left, right) => {
-                let left_val = self.evaluate_expression(left, facts)?;
-                let right_val = self.evaluate_expression(right, facts)?;
-                Ok(FactValue::Boolean(
-                    left_val.is_truthy() || right_val.is_truthy(),
-                ))
+    /// Call a registered function with the context of the current execution,
+    /// or else the builtin of that name
+    pub(crate) fn call_function(
+        &self,
+        name: &str,
+        args: &[FactValue],
+        scope: &Scope,
+    ) -> Result<FactValue, EngineError> {
+        let Some(function) = self.functions.get(name) else {
+            let value = builtins::call(name, args, self.config.clock.as_ref())
+                .ok_or_else(|| EngineError::UnknownFunction(name.to_string()))??;
+            return self.within_limits(value);
+        };
+        let calls = scope
+            .execution
+            .function_calls
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if let Some(max) = self.config.limits.max_function_calls {
+            if calls > max {
+                return Err(EngineError::ResourceLimit(format!(
+                    "more than {} function calls",
+                    max
+                )));
             }
+        }
+        self.within_limits(function(&scope.execution.context, args)?)
+    }
 
-            Expression::Not(expr) => {
-                let val = self.evaluate_expression(expr, facts)?;
-                Ok(FactValue::Boolean(!val.is_truthy()))
+    /// The value a name refers to: a bound value, a global, or the (possibly
+    /// pattern-bound) fact, fetched from the fact resolver if it is not in
+    /// working memory
+    pub(crate) fn lookup<'a>(
+        &'a self,
+        name: &str,
+        facts: &'a WorkingMemory,
+        scope: &'a Scope,
+    ) -> Result<Cow<'a, FactValue>, EngineError> {
+        if let Some(value) = scope.value(name) {
+            return Ok(Cow::Borrowed(value));
+        }
+        let fact_name = scope.resolve(name);
+        if fact_name == name {
+            if let Some(value) = self.globals.get(name) {
+                return Ok(Cow::Borrowed(value));
             }
+        }
+        if let Some(fact) = facts.get(fact_name) {
+            return Ok(Cow::Borrowed(&fact.value));
+        }
+        self.resolve_fact(fact_name, scope)?
+            .map(Cow::Owned)
+            .ok_or_else(|| EngineError::UnknownVariable(name.to_string()))
+    }
 
-            _ => Err(EngineError::EvaluationError(
-                "Unsupported expression type".to_string(),
+    /// Ask the fact resolver for a fact, once per execution
+    fn resolve_fact(&self, name: &str, scope: &Scope) -> Result<Option<FactValue>, EngineError> {
+        let Some(resolver) = &self.config.fact_resolver else {
+            return Ok(None);
+        };
+        let resolved = || {
+            scope
+                .execution
+                .resolved
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+        };
+        if let Some(value) = resolved().get(name) {
+            return Ok(value.clone());
+        }
+        // Not holding the lock while resolving lets other threads carry on
+        let value = resolver.resolve(name)?;
+        resolved().insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    pub(crate) fn access_field(
+        &self,
+        object: &FactValue,
+        field: &str,
+    ) -> Result<FactValue, EngineError> {
+        match object {
+            // The parser keeps `a.b.c` as a single dotted field of `a`
+            FactValue::Object(_) => match object.get_path(field) {
+                Some(value) => Ok(value.clone()),
+                None if self.config.three_valued_logic => Ok(FactValue::Null),
+                None => Err(EngineError::EvaluationError(format!(
+                    "Field '{}' not found",
+                    field
+                ))),
+            },
+            _ => Err(EngineError::TypeError(
+                "Cannot access field on non-object".to_string(),
             )),
         }
     }
 
-    fn execute_action(
+    /// Number of events of `event_type` within the last `window_ms` milliseconds
+    pub(crate) fn event_count(
+        &self,
+        event_type: &str,
+        window_ms: u64,
+        facts: &WorkingMemory,
+    ) -> FactValue {
+        let now = self.config.clock.now_millis();
+        let window_start = now.saturating_sub(i64::try_from(window_ms).unwrap_or(i64::MAX));
+        let count = facts
+            .iter()
+            .filter(|fact| fact.fact_type.as_deref() == Some(event_type))
+            .filter_map(|fact| fact.timestamp)
+            .filter(|&timestamp| timestamp >= window_start && timestamp <= now)
+            .count();
+        FactValue::Number(count as f64)
+    }
+
+    /// Run a single action, returning whether it wrote to working memory
+    /// rather than to a local value
+    pub(crate) fn execute_action(
         &self,
         action: &Expression,
-        facts: &mut HashMap<String, Fact>,
-    ) -> std::result::Result<(), EngineError> {
+        facts: &mut WorkingMemory,
+        scope: &mut Scope,
+    ) -> std::result::Result<bool, EngineError> {
         match action {
             Expression::Assignment(var_name, value_expr) => {
-                let value = self.evaluate_expression(value_expr, facts)?;
-                facts.insert(var_name.clone(), Fact::new(var_name.clone(), value));
-                Ok(())
+                let value = self.evaluate_expression(value_expr, facts, scope)?;
+                self.assign(var_name, None, value, facts, scope)
+            }
+
+            Expression::InsertLogical(var_name, value_expr) => {
+                let value = self.evaluate_expression(value_expr, facts, scope)?;
+                let fact_name = scope.resolve(var_name);
+                if fact_name == var_name && self.globals.contains_key(fact_name) {
+                    return Err(EngineError::ReadOnlyGlobal(var_name.clone()));
+                }
+                // A fact stated outside the rules is neither overwritten nor
+                // taken over, so it outlives the rule's support
+                if facts.contains(fact_name) && !facts.logical_support.contains_key(fact_name) {
+                    return Ok(false);
+                }
+                if facts.update(fact_name, value.clone()).is_none() {
+                    facts.insert(self.created_fact(fact_name.to_string(), value));
+                }
+                Ok(true)
             }
 
             Expression::FieldAssignment(obj_name, field_name, value_expr) => {
-                let value = self.evaluate_expression(value_expr, facts)?;
-                if let Some(fact) = facts.get_mut(obj_name) {
-                    fact.set_field(field_name.clone(), value)
-                        .map_err(EngineError::EvaluationError)?;
+                let value = self.evaluate_expression(value_expr, facts, scope)?;
+                self.assign(obj_name, Some(field_name), value, facts, scope)
+            }
+
+            Expression::Let(name, value_expr) => {
+                let value = self.evaluate_expression(value_expr, facts, scope)?;
+                scope.values.insert(name.clone(), value);
+                Ok(false)
+            }
+
+            Expression::Update(name) => {
+                if facts.contains(scope.resolve(name)) {
+                    Ok(true)
                 } else {
-                    return Err(EngineError::UnknownVariable(obj_name.clone()));
+                    Err(EngineError::UnknownVariable(name.clone()))
                 }
-                Ok(())
             }
 
             _ => Err(EngineError::EvaluationError(
@@ -278,21 +2196,180 @@ impl RuleEngine {
         }
     }
 
-    fn values_equal(&self, left: &FactValue, right: &FactValue) -> bool {
-        match (left, right) {
-            (FactValue::String(a), FactValue::String(b)) => a == b,
-            (FactValue::Number(a), FactValue::Number(b)) => a == b,
-            (FactValue::Boolean(a), FactValue::Boolean(b)) => a == b,
-            (FactValue::Null, FactValue::Null) => true,
-            _ => false,
+    /// A fact created by a rule, stamped with the current time
+    fn created_fact(&self, name: String, value: FactValue) -> Fact {
+        let mut fact = Fact::new(name, value);
+        fact.metadata.created_at = Some(self.config.clock.now_millis());
+        fact
+    }
+
+    /// Store `value` in the local value or fact `name` refers to, or in one of
+    /// its fields. Returns whether working memory was written.
+    fn assign(
+        &self,
+        name: &str,
+        field: Option<&String>,
+        value: FactValue,
+        facts: &mut WorkingMemory,
+        scope: &mut Scope,
+    ) -> Result<bool, EngineError> {
+        if let Some(local) = scope.values.get_mut(name) {
+            match (field, local) {
+                (None, local) => *local = value,
+                (Some(field), local) => local
+                    .set_path(field, value, true)
+                    .map_err(EngineError::TypeError)?,
+            }
+            return Ok(false);
+        }
+
+        let fact_name = scope.resolve(name);
+        if fact_name == name && self.globals.contains_key(name) {
+            return Err(EngineError::ReadOnlyGlobal(name.to_string()));
+        }
+        match field {
+            None => {
+                if facts.update(fact_name, value.clone()).is_none() {
+                    facts.insert(self.created_fact(fact_name.to_string(), value));
+                }
+            }
+            Some(field) => {
+                let Some(fact) = facts.get(fact_name) else {
+                    return Err(EngineError::UnknownVariable(name.to_string()));
+                };
+                let value = match fact.get_path(field) {
+                    Some(current) => self.enum_assignment(current, value)?,
+                    None => value,
+                };
+                facts
+                    .set_path(fact_name, field, value)
+                    .map_err(EngineError::EvaluationError)?;
+            }
         }
+        Ok(true)
     }
 
     pub fn get_knowledge_base(&self) -> &KnowledgeBase {
         &self.knowledge_base
     }
+
+    /// Replace every rule at once with those of `knowledge_base`, checked as
+    /// `add_rule` would. If one fails, the current rules are kept.
+    pub fn set_knowledge_base(&mut self, knowledge_base: Arc<KnowledgeBase>) -> crate::Result<()> {
+        self.replace_knowledge_base(knowledge_base)?;
+        Ok(())
+    }
+
+    /// A handle to the knowledge base that can be passed to other engines
+    pub fn shared_knowledge_base(&self) -> Arc<KnowledgeBase> {
+        Arc::clone(&self.knowledge_base)
+    }
+
+    /// Swap in a new version of the knowledge base, returning the previous one.
+    /// Executions already running on clones of this engine are unaffected.
+    /// The new rules are checked as `add_rule` would; if one fails, nothing
+    /// is swapped.
+    pub fn replace_knowledge_base(
+        &mut self,
+        knowledge_base: Arc<KnowledgeBase>,
+    ) -> crate::Result<Arc<KnowledgeBase>> {
+        self.check_rules(&knowledge_base)?;
+        Ok(std::mem::replace(&mut self.knowledge_base, knowledge_base))
+    }
 }
 
+/// The conjuncts of `pattern`'s constraint of the form `binding.field == value`
+/// (either way round) where `value` does not depend on the matched fact
+fn indexable_equalities(pattern: &Pattern) -> Vec<(String, &Expression)> {
+    fn collect<'a>(
+        expr: &'a Expression,
+        binding: &str,
+        equalities: &mut Vec<(String, &'a Expression)>,
+    ) {
+        match expr {
+            Expression::And(left, right) => {
+                collect(left, binding, equalities);
+                collect(right, binding, equalities);
+            }
+            Expression::Equal(left, right) => {
+                for (side, value) in [(left, right), (right, left)] {
+                    if let Expression::FieldAccess(object, field) = side.as_ref() {
+                        if matches!(object.as_ref(), Expression::Variable(name) if name == binding)
+                            && !analysis::reads_fact(value, binding)
+                        {
+                            equalities.push((field.clone(), value));
+                            return;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut equalities = Vec::new();
+    if let Some(constraint) = &pattern.constraint {
+        collect(constraint, &pattern.binding, &mut equalities);
+    }
+    equalities
+}
+
+/// The element of an array at a whole-number index, counting from the end
+/// when negative
+pub(crate) fn index_array(array: FactValue, index: &FactValue) -> Result<FactValue, EngineError> {
+    let FactValue::Array(mut items) = array else {
+        return Err(EngineError::TypeError(
+            "Cannot index a non-array".to_string(),
+        ));
+    };
+    let position = array_position(items.len(), index)?;
+    Ok(items.swap_remove(position))
+}
+
+/// Where a whole-number index falls in an array of `len` items, counting
+/// from the end when negative
+pub(crate) fn array_position(len: usize, index: &FactValue) -> Result<usize, EngineError> {
+    let index = builtins::index(index)?;
+    let position = if index < 0 {
+        len.checked_sub(index.unsigned_abs() as usize)
+    } else {
+        Some(index as usize).filter(|&position| position < len)
+    };
+    position.ok_or_else(|| EngineError::EvaluationError(format!("Index {} out of bounds", index)))
+}
+
+/// Numeric negation of a number, duration or amount of money
+pub(crate) fn negate(value: FactValue) -> Result<FactValue, EngineError> {
+    let overflow = || EngineError::InvalidNumber("integer overflow".to_string());
+    match value {
+        FactValue::Number(n) => Ok(FactValue::Number(-n)),
+        FactValue::Integer(n) => n.checked_neg().map(FactValue::Integer).ok_or_else(overflow),
+        FactValue::Decimal(d) => d.checked_neg().map(FactValue::Decimal).ok_or_else(overflow),
+        FactValue::Duration(ms) => ms
+            .checked_neg()
+            .map(FactValue::Duration)
+            .ok_or_else(overflow),
+        FactValue::Money { amount, currency } => amount
+            .checked_neg()
+            .map(|amount| FactValue::Money { amount, currency })
+            .ok_or_else(overflow),
+        value => Err(EngineError::TypeError(format!(
+            "Cannot negate a {}",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Equality as for `==`, see `FactValue::compare`
+pub(crate) fn values_equal(left: &FactValue, right: &FactValue) -> bool {
+    left.compare(right, CoercionPolicy::default()) == Some(std::cmp::Ordering::Equal)
+}
+
+// Engines are shared across worker threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RuleEngine>();
+};
+
 impl Default for RuleEngine {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,15 @@
+use crate::engine::EngineError;
+use crate::knowledge_base::KnowledgeBaseError;
+use crate::parser::ParseError;
+use thiserror::Error;
+
+/// Crate-wide error type returned by the public API
+#[derive(Error, Debug)]
+pub enum RunesError {
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    KnowledgeBase(#[from] KnowledgeBaseError),
+}
@@ -1,13 +1,56 @@
+use crate::base64;
+use crate::datetime;
+use crate::decimal::Decimal;
+use crate::working_memory::WorkingMemory;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FactValue {
     String(String),
     Number(f64),
+    /// A whole number, exact where `Number` would lose precision (IDs, large
+    /// counters). Arithmetic with another `Integer` stays integral; mixing in a
+    /// `Number` promotes to `f64`.
+    Integer(i64),
+    /// An exact decimal for money. Arithmetic with `Integer`s and `Number`s
+    /// stays exact, reading a `Number` as the shortest decimal that round-trips
+    /// to it (`0.1` is exactly `0.1`).
+    Decimal(Decimal),
+    /// A UTC instant in milliseconds since the Unix epoch, like
+    /// `Fact::timestamp`. Created in rules with `date("2024-03-01")` or `now()`.
+    DateTime(i64),
+    /// A signed span of time in milliseconds, written `30d` or `2h` in rules.
+    /// Adding one to a `DateTime` gives a `DateTime`; subtracting two
+    /// `DateTime`s gives a `Duration`.
+    Duration(i64),
     Boolean(bool),
     Object(HashMap<String, FactValue>),
     Array(Vec<FactValue>),
+    /// Binary data such as a payload or checksum. Converted to JSON as a
+    /// base64 string.
+    Bytes(Vec<u8>),
+    /// A variant of an enumeration declared with `RuleEngine::register_enum`,
+    /// written `OrderStatus.PAID` in rules. Equal to a string of the variant's
+    /// name, and converted to JSON as one.
+    Enum {
+        enum_type: String,
+        variant: String,
+    },
+    /// An exact amount in a currency, such as `money("19.99", "USD")`. Amounts
+    /// in different currencies cannot be added, subtracted or compared.
+    Money {
+        amount: Decimal,
+        currency: String,
+    },
+    /// A location in degrees of latitude and longitude, created in rules with
+    /// `geopoint(lat, lon)` and measured with `distanceKm(a, b)`
+    GeoPoint {
+        lat: f64,
+        lon: f64,
+    },
     Null,
 }
 
@@ -15,10 +58,39 @@ impl FactValue {
     pub fn as_number(&self) -> Option<f64> {
         match self {
             FactValue::Number(n) => Some(*n),
+            FactValue::Integer(n) => Some(*n as f64),
+            FactValue::Decimal(d) => Some(d.to_f64()),
             _ => None,
         }
     }
 
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            FactValue::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The variant's name, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            FactValue::String(_) => "String",
+            FactValue::Number(_) => "Number",
+            FactValue::Integer(_) => "Integer",
+            FactValue::Decimal(_) => "Decimal",
+            FactValue::DateTime(_) => "DateTime",
+            FactValue::Duration(_) => "Duration",
+            FactValue::Boolean(_) => "Boolean",
+            FactValue::Object(_) => "Object",
+            FactValue::Array(_) => "Array",
+            FactValue::Bytes(_) => "Bytes",
+            FactValue::Enum { .. } => "Enum",
+            FactValue::Money { .. } => "Money",
+            FactValue::GeoPoint { .. } => "GeoPoint",
+            FactValue::Null => "Null",
+        }
+    }
+
     pub fn as_string(&self) -> Option<&str> {
         match self {
             FactValue::String(s) => Some(s),
@@ -40,33 +112,478 @@ impl FactValue {
         }
     }
 
+    /// The value at a dot-separated path through nested objects, e.g.
+    /// `"address.zip"`
+    pub fn get_path(&self, path: &str) -> Option<&FactValue> {
+        path.split('.').try_fold(self, |value, field| match value {
+            FactValue::Object(obj) => obj.get(field),
+            _ => None,
+        })
+    }
+
+    /// Set the value at a dot-separated path through nested objects. Missing
+    /// intermediate objects are created when `create` is true and are an
+    /// error otherwise.
+    pub fn set_path(&mut self, path: &str, value: FactValue, create: bool) -> Result<(), String> {
+        let (parents, field) = match path.rsplit_once('.') {
+            Some((parents, field)) => (Some(parents), field),
+            None => (None, path),
+        };
+        let mut target = self;
+        for parent in parents.into_iter().flat_map(|parents| parents.split('.')) {
+            let FactValue::Object(obj) = target else {
+                return Err("Cannot set field on non-object".to_string());
+            };
+            target = match obj.entry(parent.to_string()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) if create => entry.insert(FactValue::Object(HashMap::new())),
+                Entry::Vacant(_) => return Err(format!("Field '{}' not found", parent)),
+            };
+        }
+        match target {
+            FactValue::Object(obj) => {
+                obj.insert(field.to_string(), value);
+                Ok(())
+            }
+            _ => Err("Cannot set field on non-object".to_string()),
+        }
+    }
+
+    /// How `self` orders against `other`, or `None` if they are unordered.
+    ///
+    /// - Values of the same type compare naturally: strings by code point,
+    ///   bytes lexicographically, `false` before `true`, date-times
+    ///   chronologically, durations by length.
+    /// - Numbers compare by value across `Number`, `Integer` and `Decimal`,
+    ///   exactly where either side is an `Integer` or `Decimal`. NaN is
+    ///   unordered.
+    /// - Arrays and objects are only ever equal (element by element and field
+    ///   by field) or unordered, as are two nulls. So are enum variants, which
+    ///   equal the same variant of the same enum or a string of its name.
+    /// - Values of other different types are unordered, unless `policy` allows
+    ///   coercing one to the other's type.
+    pub fn compare(&self, other: &FactValue, policy: CoercionPolicy) -> Option<Ordering> {
+        let equal = |equal: bool| equal.then_some(Ordering::Equal);
+        match (self, other) {
+            (FactValue::String(a), FactValue::String(b)) => Some(a.cmp(b)),
+            (FactValue::Boolean(a), FactValue::Boolean(b)) => Some(a.cmp(b)),
+            (FactValue::Bytes(a), FactValue::Bytes(b)) => Some(a.cmp(b)),
+            (FactValue::DateTime(a), FactValue::DateTime(b))
+            | (FactValue::Duration(a), FactValue::Duration(b)) => Some(a.cmp(b)),
+            (FactValue::Null, FactValue::Null) => Some(Ordering::Equal),
+            (
+                FactValue::Enum {
+                    enum_type: a_type,
+                    variant: a,
+                },
+                FactValue::Enum {
+                    enum_type: b_type,
+                    variant: b,
+                },
+            ) => equal(a_type == b_type && a == b),
+            (FactValue::Enum { variant, .. }, FactValue::String(s))
+            | (FactValue::String(s), FactValue::Enum { variant, .. }) => equal(variant == s),
+            (
+                FactValue::Money {
+                    amount: a,
+                    currency: a_currency,
+                },
+                FactValue::Money {
+                    amount: b,
+                    currency: b_currency,
+                },
+            ) if a_currency == b_currency => Some(a.cmp(b)),
+            (
+                FactValue::GeoPoint { lat, lon },
+                FactValue::GeoPoint {
+                    lat: b_lat,
+                    lon: b_lon,
+                },
+            ) => equal(lat == b_lat && lon == b_lon),
+            (FactValue::Array(a), FactValue::Array(b)) => equal(
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|(a, b)| a.compare(b, policy) == Some(Ordering::Equal)),
+            ),
+            (FactValue::Object(a), FactValue::Object(b)) => equal(
+                a.len() == b.len()
+                    && a.iter().all(|(field, a)| {
+                        b.get(field)
+                            .is_some_and(|b| a.compare(b, policy) == Some(Ordering::Equal))
+                    }),
+            ),
+            _ if self.as_number().is_some() && other.as_number().is_some() => {
+                if let Some((a, b)) = decimal_operands(self, other) {
+                    Some(a.cmp(&b))
+                } else if let Some((a, b)) = integer_operands(self, other) {
+                    Some(a.cmp(&b))
+                } else {
+                    self.as_number()?.partial_cmp(&other.as_number()?)
+                }
+            }
+            _ if policy == CoercionPolicy::Lenient => self.compare_coerced(other),
+            _ => None,
+        }
+    }
+
+    /// `compare` for values of different types, coercing one to the other
+    fn compare_coerced(&self, other: &FactValue) -> Option<Ordering> {
+        match (self, other) {
+            (FactValue::String(s), FactValue::DateTime(_)) => {
+                FactValue::DateTime(datetime::parse(s)?).compare(other, CoercionPolicy::Numeric)
+            }
+            (FactValue::DateTime(_), FactValue::String(s)) => self.compare(
+                &FactValue::DateTime(datetime::parse(s)?),
+                CoercionPolicy::Numeric,
+            ),
+            _ => self
+                .coerce_to_number()?
+                .compare(&other.coerce_to_number()?, CoercionPolicy::Numeric),
+        }
+    }
+
+    fn coerce_to_number(&self) -> Option<FactValue> {
+        match self {
+            FactValue::Number(_) | FactValue::Integer(_) | FactValue::Decimal(_) => {
+                Some(self.clone())
+            }
+            FactValue::Boolean(b) => Some(FactValue::Integer(i64::from(*b))),
+            FactValue::String(s) => {
+                let s = s.trim();
+                s.parse()
+                    .map(FactValue::Integer)
+                    .or_else(|_| s.parse().map(FactValue::Number))
+                    .ok()
+            }
+            _ => None,
+        }
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
             FactValue::Boolean(b) => *b,
             FactValue::Number(n) => *n != 0.0,
+            FactValue::Integer(n) => *n != 0,
+            FactValue::Decimal(d) => !d.is_zero(),
+            FactValue::DateTime(_) => true,
+            FactValue::Duration(ms) => *ms != 0,
             FactValue::String(s) => !s.is_empty(),
             FactValue::Array(arr) => !arr.is_empty(),
+            FactValue::Bytes(bytes) => !bytes.is_empty(),
+            FactValue::Enum { .. } => true,
+            FactValue::Money { amount, .. } => !amount.is_zero(),
+            FactValue::GeoPoint { .. } => true,
             FactValue::Object(obj) => !obj.is_empty(),
             FactValue::Null => false,
         }
     }
 }
 
+/// Which values of different types `FactValue::compare` may compare, and how
+/// the engine treats comparisons of values that do not compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoercionPolicy {
+    /// Only numbers of different kinds (`Number`, `Integer`, `Decimal`) compare
+    /// with each other. Other values of different types are unequal, and
+    /// ordering them is a type error.
+    #[default]
+    Numeric,
+    /// Like `Numeric`, but comparing values of different types with `==` or
+    /// `!=` is a type error too, except with null
+    Strict,
+    /// Also compare numbers with booleans (as 0 and 1) and numeric strings
+    /// (`"5" == 5`), and date-times with date strings, like JavaScript. Values
+    /// that still do not compare are unequal and unordered, never an error.
+    Lenient,
+}
+
+/// JSON integers that fit an `i64` become `Integer`s, other numbers `Number`s
+impl From<serde_json::Value> for FactValue {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => FactValue::Null,
+            serde_json::Value::Bool(b) => FactValue::Boolean(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => FactValue::Integer(i),
+                None => FactValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => FactValue::String(s),
+            serde_json::Value::Array(items) => {
+                FactValue::Array(items.into_iter().map(FactValue::from).collect())
+            }
+            serde_json::Value::Object(fields) => FactValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Non-finite numbers become `null`, decimals strings (to stay exact),
+/// date-times RFC 3339 strings, durations milliseconds and bytes base64
+/// strings
+impl From<FactValue> for serde_json::Value {
+    fn from(value: FactValue) -> Self {
+        match value {
+            FactValue::Null => serde_json::Value::Null,
+            FactValue::Boolean(b) => serde_json::Value::Bool(b),
+            FactValue::Number(n) => serde_json::Number::from_f64(n)
+                .map_or(serde_json::Value::Null, serde_json::Value::Number),
+            FactValue::Integer(n) | FactValue::Duration(n) => serde_json::Value::from(n),
+            FactValue::Decimal(d) => serde_json::Value::String(d.to_string()),
+            FactValue::DateTime(millis) => serde_json::Value::String(datetime::format(millis)),
+            FactValue::String(s) | FactValue::Enum { variant: s, .. } => {
+                serde_json::Value::String(s)
+            }
+            FactValue::Bytes(bytes) => serde_json::Value::String(base64::encode(&bytes)),
+            FactValue::Money { amount, currency } => serde_json::json!({
+                "amount": amount.to_string(),
+                "currency": currency,
+            }),
+            FactValue::GeoPoint { lat, lon } => serde_json::json!({ "lat": lat, "lon": lon }),
+            FactValue::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(Into::into).collect())
+            }
+            FactValue::Object(fields) => serde_json::Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&str> for FactValue {
+    fn from(value: &str) -> Self {
+        FactValue::String(value.to_string())
+    }
+}
+
+impl From<String> for FactValue {
+    fn from(value: String) -> Self {
+        FactValue::String(value)
+    }
+}
+
+impl From<i64> for FactValue {
+    fn from(value: i64) -> Self {
+        FactValue::Integer(value)
+    }
+}
+
+impl From<i32> for FactValue {
+    fn from(value: i32) -> Self {
+        FactValue::Integer(value.into())
+    }
+}
+
+impl From<f64> for FactValue {
+    fn from(value: f64) -> Self {
+        FactValue::Number(value)
+    }
+}
+
+impl From<bool> for FactValue {
+    fn from(value: bool) -> Self {
+        FactValue::Boolean(value)
+    }
+}
+
+impl From<Decimal> for FactValue {
+    fn from(value: Decimal) -> Self {
+        FactValue::Decimal(value)
+    }
+}
+
+impl<T: Into<FactValue>> From<Vec<T>> for FactValue {
+    fn from(items: Vec<T>) -> Self {
+        FactValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<FactValue>> From<HashMap<String, T>> for FactValue {
+    fn from(fields: HashMap<String, T>) -> Self {
+        FactValue::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect(),
+        )
+    }
+}
+
 impl From<FactValue> for std::result::Result<FactValue, String> {
     fn from(value: FactValue) -> Self {
         Ok(value)
     }
 }
 
+/// Both operands as decimals, if at least one is a `Decimal` and the other is
+/// any number
+pub(crate) fn decimal_operands(left: &FactValue, right: &FactValue) -> Option<(Decimal, Decimal)> {
+    fn decimal(value: &FactValue) -> Option<Decimal> {
+        match value {
+            FactValue::Decimal(d) => Some(*d),
+            FactValue::Integer(n) => Some(Decimal::from(*n)),
+            FactValue::Number(n) => Decimal::from_f64(*n),
+            _ => None,
+        }
+    }
+    if !matches!(left, FactValue::Decimal(_)) && !matches!(right, FactValue::Decimal(_)) {
+        return None;
+    }
+    Some((decimal(left)?, decimal(right)?))
+}
+
+/// Both operands as integers, if at least one is an `Integer` and the other is
+/// an `Integer` or a whole `Number` (so `id + 1` stays exact)
+pub(crate) fn integer_operands(left: &FactValue, right: &FactValue) -> Option<(i64, i64)> {
+    fn whole(value: &FactValue) -> Option<i64> {
+        match value {
+            FactValue::Integer(n) => Some(*n),
+            // i64::MAX as f64 rounds up to 2^63, which is out of range
+            FactValue::Number(n)
+                if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 =>
+            {
+                Some(*n as i64)
+            }
+            _ => None,
+        }
+    }
+    if !matches!(left, FactValue::Integer(_)) && !matches!(right, FactValue::Integer(_)) {
+        return None;
+    }
+    Some((whole(left)?, whole(right)?))
+}
+
+/// Where and when a fact's value came from, for audit and debugging tools.
+/// Times are milliseconds since the Unix epoch, by the engine's clock.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FactMetadata {
+    /// When a rule created the fact, or as set by the application
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    /// When a rule last wrote the fact
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+    /// The rule that last wrote the fact, or a label set by the application
+    /// (e.g. `"orders-api"`)
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// The rule that last wrote each field, by dot-separated path, when
+    /// `EngineConfig::track_provenance` is set. A write to the whole fact is
+    /// recorded under `""`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provenance: BTreeMap<String, Provenance>,
+}
+
+impl FactMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Record that `provenance` wrote `field`, or the whole fact for `None`,
+    /// replacing what was recorded for anything inside it
+    pub(crate) fn record(&mut self, field: Option<&str>, provenance: Provenance) {
+        let field = field.unwrap_or_default();
+        self.provenance.retain(|path, _| {
+            !(field.is_empty()
+                || path == field
+                || path
+                    .strip_prefix(field)
+                    .is_some_and(|rest| rest.starts_with('.')))
+        });
+        self.provenance.insert(field.to_string(), provenance);
+    }
+}
+
+/// Which rule wrote a value, and in which cycle of its execution
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub rule: String,
+    pub cycle: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fact {
     pub name: String,
     pub value: FactValue,
+    #[serde(default)]
+    pub fact_type: Option<String>,
+    /// Occurrence time in milliseconds since the Unix epoch. Facts with a
+    /// timestamp are events and can be matched by temporal conditions.
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "FactMetadata::is_empty")]
+    pub metadata: FactMetadata,
 }
 
 impl Fact {
     pub fn new(name: String, value: FactValue) -> Self {
-        Self { name, value }
+        Self {
+            name,
+            value,
+            fact_type: None,
+            timestamp: None,
+            metadata: FactMetadata::default(),
+        }
+    }
+
+    pub fn event(name: String, event_type: String, timestamp: i64, value: FactValue) -> Self {
+        Self {
+            name,
+            value,
+            fact_type: Some(event_type),
+            timestamp: Some(timestamp),
+            metadata: FactMetadata::default(),
+        }
+    }
+
+    pub fn with_type(mut self, fact_type: String) -> Self {
+        self.fact_type = Some(fact_type);
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.metadata.source = Some(source.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.metadata.tags.insert(tag.into());
+        self
+    }
+
+    /// The rule that last wrote `field` (a dot-separated path), or the object
+    /// or fact containing it. Only recorded when
+    /// `EngineConfig::track_provenance` is set.
+    pub fn provenance(&self, field: &str) -> Option<&Provenance> {
+        let provenance = &self.metadata.provenance;
+        let mut path = field;
+        loop {
+            if let Some(found) = provenance.get(path) {
+                return Some(found);
+            }
+            match path.rfind('.') {
+                Some(dot) => path = &path[..dot],
+                None => return provenance.get(""),
+            }
+        }
+    }
+
+    /// The namespace of a fact named like `billing::invoice`, e.g. `billing`
+    pub fn namespace(&self) -> Option<&str> {
+        split_namespace(&self.name).0
+    }
+
+    pub fn is_event(&self) -> bool {
+        self.timestamp.is_some()
     }
 
     pub fn get_field(&self, field_name: &str) -> Option<&FactValue> {
@@ -85,6 +602,16 @@ impl Fact {
             _ => Err("Cannot set field on non-object fact".to_string()),
         }
     }
+
+    /// The field at a dot-separated path such as `"customer.address.zip"`
+    pub fn get_path(&self, path: &str) -> Option<&FactValue> {
+        self.value.get_path(path)
+    }
+
+    /// Set the field at a dot-separated path, see `FactValue::set_path`
+    pub fn set_path(&mut self, path: &str, value: FactValue, create: bool) -> Result<(), String> {
+        self.value.set_path(path, value, create)
+    }
 }
 
 // Convenience methods for creating facts
@@ -93,6 +620,12 @@ impl Fact {
         Self::new(name, FactValue::Object(obj))
     }
 
+    /// A fact holding a JSON document, e.g. a request payload
+    pub fn from_json(name: String, json: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        Ok(Self::new(name, value.into()))
+    }
+
     pub fn string_fact(name: String, value: String) -> Self {
         Self::new(name, FactValue::String(value))
     }
@@ -101,7 +634,80 @@ impl Fact {
         Self::new(name, FactValue::Number(value))
     }
 
+    pub fn integer_fact(name: String, value: i64) -> Self {
+        Self::new(name, FactValue::Integer(value))
+    }
+
     pub fn boolean_fact(name: String, value: bool) -> Self {
         Self::new(name, FactValue::Boolean(value))
     }
 }
+
+/// Split a name such as `billing::invoice` into its namespace and local name.
+/// Namespaces nest (`eu::billing::invoice` is in `eu::billing`).
+pub fn split_namespace(name: &str) -> (Option<&str>, &str) {
+    match name.rsplit_once("::") {
+        Some((namespace, local)) => (Some(namespace), local),
+        None => (None, name),
+    }
+}
+
+/// A value that differs between two states of working memory. `path` is the
+/// dot-separated path of a nested field that changed, or `None` when the whole
+/// fact did; `None` values mean the fact or field did not exist.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FactChange {
+    pub fact: String,
+    pub path: Option<String>,
+    pub old: Option<FactValue>,
+    pub new: Option<FactValue>,
+}
+
+/// Every value that differs between `before` and `after`, ordered by fact name
+/// and path. Objects are compared field by field at any depth; other values,
+/// including arrays, are compared whole.
+pub fn diff(before: &WorkingMemory, after: &WorkingMemory) -> Vec<FactChange> {
+    let names: BTreeSet<&str> = before.names().chain(after.names()).collect();
+    let mut changes = Vec::new();
+    for name in names {
+        let old = before.get(name).map(|fact| &fact.value);
+        let new = after.get(name).map(|fact| &fact.value);
+        value_changes(name, None, old, new, &mut changes);
+    }
+    changes
+}
+
+fn value_changes(
+    fact: &str,
+    path: Option<String>,
+    old: Option<&FactValue>,
+    new: Option<&FactValue>,
+    changes: &mut Vec<FactChange>,
+) {
+    if old == new {
+        return;
+    }
+    if let (Some(FactValue::Object(old_fields)), Some(FactValue::Object(new_fields))) = (old, new) {
+        let fields: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+        for field in fields {
+            let field_path = match &path {
+                Some(path) => format!("{}.{}", path, field),
+                None => field.clone(),
+            };
+            value_changes(
+                fact,
+                Some(field_path),
+                old_fields.get(field),
+                new_fields.get(field),
+                changes,
+            );
+        }
+    } else {
+        changes.push(FactChange {
+            fact: fact.to_string(),
+            path,
+            old: old.cloned(),
+            new: new.cloned(),
+        });
+    }
+}
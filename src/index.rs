@@ -0,0 +1,121 @@
+use crate::decimal::Decimal;
+use crate::facts::{Fact, FactValue};
+use std::collections::{BTreeSet, HashMap};
+
+/// A field value in the form facts are indexed by. Values that are `==` have
+/// the same key, so `Integer(5)`, `Number(5.0)` and `Decimal(5.00)` share one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum IndexKey {
+    String(String),
+    /// A normalized decimal's mantissa and scale
+    Number(i128, u32),
+    Boolean(bool),
+    DateTime(i64),
+    Duration(i64),
+    Bytes(Vec<u8>),
+    Money(i128, u32, String),
+    Null,
+}
+
+impl IndexKey {
+    /// `None` for values that are not indexed: arrays, objects, geo points
+    /// and numbers that have no exact decimal form, such as NaN
+    fn of(value: &FactValue) -> Option<Self> {
+        let number = |decimal: Decimal| {
+            let decimal = decimal.normalize();
+            IndexKey::Number(decimal.mantissa(), decimal.scale())
+        };
+        Some(match value {
+            // Variants equal strings of their name
+            FactValue::String(s) | FactValue::Enum { variant: s, .. } => {
+                IndexKey::String(s.clone())
+            }
+            FactValue::Number(n) => number(Decimal::from_f64(*n)?),
+            FactValue::Integer(n) => number(Decimal::from(*n)),
+            FactValue::Decimal(d) => number(*d),
+            FactValue::Boolean(b) => IndexKey::Boolean(*b),
+            FactValue::DateTime(millis) => IndexKey::DateTime(*millis),
+            FactValue::Duration(millis) => IndexKey::Duration(*millis),
+            FactValue::Bytes(bytes) => IndexKey::Bytes(bytes.clone()),
+            FactValue::Money { amount, currency } => {
+                let amount = amount.normalize();
+                IndexKey::Money(amount.mantissa(), amount.scale(), currency.clone())
+            }
+            FactValue::Null => IndexKey::Null,
+            FactValue::Array(_) | FactValue::Object(_) | FactValue::GeoPoint { .. } => return None,
+        })
+    }
+}
+
+/// Names of the facts of one type by the value of one of their fields
+#[derive(Debug, Clone)]
+pub(crate) struct FieldIndex {
+    pub(crate) fact_type: String,
+    /// A dot-separated path, as for `FactValue::get_path`
+    pub(crate) field: String,
+    entries: HashMap<IndexKey, BTreeSet<String>>,
+    keys: HashMap<String, IndexKey>,
+}
+
+impl FieldIndex {
+    pub(crate) fn new<'a>(
+        fact_type: String,
+        field: String,
+        facts: impl Iterator<Item = &'a Fact>,
+    ) -> Self {
+        let mut index = Self {
+            fact_type,
+            field,
+            entries: HashMap::new(),
+            keys: HashMap::new(),
+        };
+        for fact in facts {
+            index.add(fact);
+        }
+        index
+    }
+
+    /// Bring the entry for fact `name` up to date, `fact` being its current
+    /// state or `None` once it is retracted
+    pub(crate) fn refresh(&mut self, name: &str, fact: Option<&Fact>) {
+        if let Some(key) = self.keys.remove(name) {
+            if let Some(names) = self.entries.get_mut(&key) {
+                names.remove(name);
+                if names.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+        if let Some(fact) = fact {
+            self.add(fact);
+        }
+    }
+
+    fn add(&mut self, fact: &Fact) {
+        if fact.fact_type.as_deref() != Some(&self.fact_type) {
+            return;
+        }
+        let Some(key) = fact.get_path(&self.field).and_then(IndexKey::of) else {
+            return;
+        };
+        self.entries
+            .entry(key.clone())
+            .or_default()
+            .insert(fact.name.clone());
+        self.keys.insert(fact.name.clone(), key);
+    }
+
+    /// Names of the facts whose field equals `value`, in name order, or `None`
+    /// if `value` is not indexed and the facts must be scanned instead
+    pub(crate) fn lookup(&self, value: &FactValue) -> Option<Vec<&str>> {
+        let key = IndexKey::of(value)?;
+        Some(
+            self.entries
+                .get(&key)
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+        )
+    }
+}
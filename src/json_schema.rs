@@ -0,0 +1,185 @@
+use crate::ast::Expression;
+use crate::migration::FORMAT_VERSION;
+use serde_json::{json, Map, Value};
+
+/// How a field of an `Expression` variant is encoded in JSON
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Expr,
+    Exprs,
+    Text,
+    Float,
+    Bool,
+    /// A non-negative integer
+    Count,
+}
+
+use Field::*;
+
+const BINARY: &[Field] = &[Expr, Expr];
+
+/// Every `Expression` variant with its fields, in declaration order. Each is
+/// encoded as serde encodes an externally tagged enum: a variant without
+/// fields as its name (`"Null"`), one with a single field as an object with
+/// the name as its only key (`{"Number": 5.0}`), and one with several fields
+/// as an object holding an array of them (`{"Add": [.., ..]}`).
+const VARIANTS: &[(&str, &[Field])] = &[
+    ("String", &[Text]),
+    ("Number", &[Float]),
+    ("Boolean", &[Bool]),
+    ("Duration", &[Count]),
+    ("Null", &[]),
+    ("Variable", &[Text]),
+    ("FieldAccess", &[Expr, Text]),
+    ("OptionalFieldAccess", &[Expr, Text]),
+    ("Index", BINARY),
+    ("Array", &[Exprs]),
+    ("Add", BINARY),
+    ("Subtract", BINARY),
+    ("Multiply", BINARY),
+    ("Divide", BINARY),
+    ("Modulo", BINARY),
+    ("Equal", BINARY),
+    ("NotEqual", BINARY),
+    ("LessThan", BINARY),
+    ("LessEqual", BINARY),
+    ("GreaterThan", BINARY),
+    ("GreaterEqual", BINARY),
+    ("And", BINARY),
+    ("Or", BINARY),
+    ("Not", &[Expr]),
+    ("Negate", &[Expr]),
+    ("Coalesce", BINARY),
+    ("In", BINARY),
+    ("Matches", BINARY),
+    ("EventCount", &[Text, Count]),
+    ("FunctionCall", &[Text, Exprs]),
+    ("MethodCall", &[Expr, Text, Exprs]),
+    ("Assignment", &[Text, Expr]),
+    ("FieldAssignment", &[Text, Text, Expr]),
+    ("InsertLogical", &[Text, Expr]),
+    ("Update", &[Text]),
+    ("Let", &[Text, Expr]),
+    ("LetIn", &[Text, Expr, Expr]),
+    ("Script", &[Text]),
+    ("If", &[Expr, Exprs, Exprs]),
+    ("ForEach", &[Text, Expr, Exprs]),
+];
+
+// A new variant fails to compile here until it is added to `VARIANTS`
+const _: fn(&Expression) = |expr| match expr {
+    Expression::String(_)
+    | Expression::Number(_)
+    | Expression::Boolean(_)
+    | Expression::Duration(_)
+    | Expression::Null
+    | Expression::Variable(_)
+    | Expression::FieldAccess(..)
+    | Expression::OptionalFieldAccess(..)
+    | Expression::Index(..)
+    | Expression::Array(_)
+    | Expression::Add(..)
+    | Expression::Subtract(..)
+    | Expression::Multiply(..)
+    | Expression::Divide(..)
+    | Expression::Modulo(..)
+    | Expression::Equal(..)
+    | Expression::NotEqual(..)
+    | Expression::LessThan(..)
+    | Expression::LessEqual(..)
+    | Expression::GreaterThan(..)
+    | Expression::GreaterEqual(..)
+    | Expression::And(..)
+    | Expression::Or(..)
+    | Expression::Not(_)
+    | Expression::Negate(_)
+    | Expression::Coalesce(..)
+    | Expression::In(..)
+    | Expression::Matches(..)
+    | Expression::EventCount(..)
+    | Expression::FunctionCall(..)
+    | Expression::MethodCall(..)
+    | Expression::Assignment(..)
+    | Expression::FieldAssignment(..)
+    | Expression::InsertLogical(..)
+    | Expression::Update(_)
+    | Expression::Let(..)
+    | Expression::LetIn(..)
+    | Expression::Script(_)
+    | Expression::If(..)
+    | Expression::ForEach(..) => {}
+};
+
+/// A JSON Schema (draft 2020-12) of how `Expression` is serialized in the
+/// current `FORMAT_VERSION`, for systems that store or generate rule ASTs.
+/// The encoding only changes along with `FORMAT_VERSION`, and
+/// `KnowledgeBase::from_json_with` upgrades older documents with `Migrations`.
+pub fn expression_schema() -> Value {
+    document("Expression", json!({ "$ref": "#/$defs/Expression" }))
+}
+
+/// A JSON Schema of how `RuleAst` is serialized, see `expression_schema`
+pub fn rule_ast_schema() -> Value {
+    document(
+        "RuleAst",
+        json!({
+            "type": "object",
+            "properties": {
+                "format_version": { "const": FORMAT_VERSION },
+                "name": { "type": "string" },
+                "description": { "type": ["string", "null"] },
+                "salience": { "type": "integer" },
+                "when_condition": { "$ref": "#/$defs/Expression" },
+                "then_actions": field_schema(Exprs),
+            },
+            "required": ["name", "salience", "when_condition", "then_actions"],
+        }),
+    )
+}
+
+fn document(title: &str, root: Value) -> Value {
+    let variants: Vec<Value> = VARIANTS
+        .iter()
+        .map(|(name, fields)| variant_schema(name, fields))
+        .collect();
+    let mut document = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": title,
+        "description": format!("rust-runes {}, format version {}", title, FORMAT_VERSION),
+        "$defs": { "Expression": { "oneOf": variants } },
+    });
+    if let (Value::Object(document), Value::Object(root)) = (&mut document, root) {
+        document.extend(root);
+    }
+    document
+}
+
+fn variant_schema(name: &str, fields: &[Field]) -> Value {
+    let value = match fields {
+        [] => return json!({ "const": name }),
+        [field] => field_schema(*field),
+        fields => json!({
+            "type": "array",
+            "prefixItems": fields.iter().map(|f| field_schema(*f)).collect::<Vec<_>>(),
+            "minItems": fields.len(),
+            "maxItems": fields.len(),
+        }),
+    };
+    json!({
+        "type": "object",
+        "properties": Map::from_iter([(name.to_string(), value)]),
+        "required": [name],
+        "additionalProperties": false,
+    })
+}
+
+fn field_schema(field: Field) -> Value {
+    match field {
+        Expr => json!({ "$ref": "#/$defs/Expression" }),
+        Exprs => json!({ "type": "array", "items": { "$ref": "#/$defs/Expression" } }),
+        Text => json!({ "type": "string" }),
+        Float => json!({ "type": "number" }),
+        Bool => json!({ "type": "boolean" }),
+        Count => json!({ "type": "integer", "minimum": 0 }),
+    }
+}
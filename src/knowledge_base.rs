@@ -1,10 +1,76 @@
+use crate::analysis::{self, DependencyGraph, FieldRef, KnowledgeBaseStats, RuleDependencies};
+use crate::migration::{Migrations, FORMAT_VERSION};
+use crate::optimize::{PassManager, RuleWarning};
 use crate::rule::Rule;
-use std::collections::HashMap;
+use crate::validate;
+use crate::vm::Program;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Deref;
+use std::sync::Arc;
+use thiserror::Error;
 
-#[derive(Debug, Default)]
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum KnowledgeBaseError {
+    #[error("Rule '{0}' already exists")]
+    DuplicateRule(String),
+    #[error("Rule '{0}' not found")]
+    RuleNotFound(String),
+    #[error("Ruleset '{0}' not found")]
+    RulesetNotFound(String),
+    #[error("Invalid serialized knowledge base: {0}")]
+    InvalidFormat(String),
+    #[error("Rule '{rule}' is invalid: {reason}")]
+    InvalidRule { rule: String, reason: String },
+    #[error("Knowledge base format {0} is newer than this version supports")]
+    UnsupportedVersion(u32),
+    #[error("No migration registered from knowledge base format {0}")]
+    MissingMigration(u32),
+}
+
+/// What `KnowledgeBase::merge` does with a rule whose name is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Fail with `DuplicateRule`, merging nothing
+    #[default]
+    Error,
+    /// Keep whichever rule has the higher salience, the existing one on a tie
+    KeepHigherSalience,
+    /// Replace the existing rule with the incoming one
+    PreferOther,
+}
+
+/// The serialized form of a knowledge base, see `KnowledgeBase::to_json`
+#[derive(Serialize, Deserialize)]
+struct Document<R = serde_json::Value> {
+    format_version: u32,
+    #[serde(default)]
+    version: u32,
+    rules: R,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    rulesets: BTreeMap<String, BTreeSet<String>>,
+}
+
+fn invalid_format(e: serde_json::Error) -> KnowledgeBaseError {
+    KnowledgeBaseError::InvalidFormat(e.to_string())
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct KnowledgeBase {
+    version: u32,
     rules: Vec<Rule>,
     rule_index: HashMap<String, usize>,
+    // Read/write sets of each rule, parallel to `rules`
+    dependencies: Vec<RuleDependencies>,
+    // Compiled `when` condition of each rule, parallel to `rules`
+    programs: Vec<Option<Program>>,
+    // Names of the rules in each ruleset
+    rulesets: BTreeMap<String, BTreeSet<String>>,
+    // Literal patterns of the rules' `matches` expressions, compiled, by source
+    regexes: HashMap<String, Regex>,
+    passes: PassManager,
 }
 
 impl KnowledgeBase {
@@ -12,17 +78,245 @@ impl KnowledgeBase {
         Self::default()
     }
 
-    pub fn add_rule(&mut self, rule: Rule) -> Result<(), String> {
+    /// Add a rule, running the knowledge base's passes over it first. Returns
+    /// any warnings found about the rule.
+    pub fn add_rule(&mut self, rule: Rule) -> Result<Vec<RuleWarning>, KnowledgeBaseError> {
         if self.rule_index.contains_key(&rule.name) {
-            return Err(format!("Rule '{}' already exists", rule.name));
+            return Err(KnowledgeBaseError::DuplicateRule(rule.name));
         }
 
+        let mut warnings = validate::validate_rule(&rule)?;
+        let (rule, pass_warnings) = self.passes.run(rule);
+        warnings.extend(pass_warnings);
         let index = self.rules.len();
         self.rule_index.insert(rule.name.clone(), index);
+        self.dependencies.push(RuleDependencies::of(&rule));
+        self.programs.push(Program::compile(&rule.when_condition));
+        self.compile_patterns(&rule);
         self.rules.push(rule);
+        Ok(warnings)
+    }
+
+    /// The application's version of this rule set, saved by `to_json`
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    /// The passes run over rules added from now on, `ConstantFolding` alone
+    /// by default
+    pub fn set_passes(&mut self, passes: PassManager) {
+        self.passes = passes;
+    }
+
+    /// Serialize the rules, as simplified when added, so a compiled rule set
+    /// can be stored or sent to another service and loaded with `from_json`
+    /// without parsing GRL again. The output is tagged with the rule format's
+    /// `FORMAT_VERSION`.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.document())
+    }
+
+    /// Like `to_json`, in a compact binary form
+    #[cfg(feature = "binary")]
+    pub fn to_binary(&self) -> Result<Vec<u8>, serde_json::Error> {
+        Ok(crate::binary::encode(&serde_json::to_value(
+            self.document(),
+        )?))
+    }
+
+    fn document(&self) -> Document<&[Rule]> {
+        Document {
+            format_version: FORMAT_VERSION,
+            version: self.version,
+            rules: &self.rules,
+            rulesets: self.rulesets.clone(),
+        }
+    }
+
+    /// Load rules saved with `to_json`, in their original order
+    pub fn from_json(json: &str) -> Result<Self, KnowledgeBaseError> {
+        Self::from_json_with(json, &Migrations::default())
+    }
+
+    /// Like `from_json`, upgrading rules saved in an older format with
+    /// `migrations`. A bare array of rules is read as the current format.
+    pub fn from_json_with(json: &str, migrations: &Migrations) -> Result<Self, KnowledgeBaseError> {
+        let value = serde_json::from_str(json).map_err(invalid_format)?;
+        Self::from_value(value, migrations)
+    }
+
+    /// Load rules saved with `to_binary`, in their original order
+    #[cfg(feature = "binary")]
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, KnowledgeBaseError> {
+        Self::from_binary_with(bytes, &Migrations::default())
+    }
+
+    /// Like `from_binary`, upgrading rules saved in an older format with
+    /// `migrations`
+    #[cfg(feature = "binary")]
+    pub fn from_binary_with(
+        bytes: &[u8],
+        migrations: &Migrations,
+    ) -> Result<Self, KnowledgeBaseError> {
+        let value = crate::binary::decode(bytes).map_err(invalid_format)?;
+        Self::from_value(value, migrations)
+    }
+
+    fn from_value(
+        value: serde_json::Value,
+        migrations: &Migrations,
+    ) -> Result<Self, KnowledgeBaseError> {
+        let document = match value {
+            serde_json::Value::Array(rules) => Document {
+                format_version: FORMAT_VERSION,
+                version: 0,
+                rules: serde_json::Value::Array(rules),
+                rulesets: BTreeMap::new(),
+            },
+            document => serde_json::from_value(document).map_err(invalid_format)?,
+        };
+        let rules = migrations.upgrade(document.format_version, document.rules)?;
+        let rules: Vec<Rule> = serde_json::from_value(rules).map_err(invalid_format)?;
+        let mut knowledge_base = Self::new();
+        knowledge_base.version = document.version;
+        for rule in rules {
+            knowledge_base.add_rule(rule)?;
+        }
+        for (name, rules) in document.rulesets {
+            knowledge_base.define_ruleset(name, rules)?;
+        }
+        Ok(knowledge_base)
+    }
+
+    /// Define a named subset of the rules, e.g. `checkout` or `fraud`, that
+    /// `RuleEngine::execute_ruleset` runs on its own. Redefining a ruleset
+    /// replaces its rules. Rules can belong to any number of rulesets, and
+    /// leave them when removed.
+    pub fn define_ruleset<I, S>(
+        &mut self,
+        name: impl Into<String>,
+        rules: I,
+    ) -> Result<(), KnowledgeBaseError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let rules: BTreeSet<String> = rules.into_iter().map(Into::into).collect();
+        if let Some(missing) = rules
+            .iter()
+            .find(|rule| !self.rule_index.contains_key(*rule))
+        {
+            return Err(KnowledgeBaseError::RuleNotFound(missing.clone()));
+        }
+        self.rulesets.insert(name.into(), rules);
+        Ok(())
+    }
+
+    /// The names of the rules in ruleset `name`
+    pub fn ruleset(&self, name: &str) -> Option<&BTreeSet<String>> {
+        self.rulesets.get(name)
+    }
+
+    /// The names of the defined rulesets
+    pub fn rulesets(&self) -> impl Iterator<Item = &str> {
+        self.rulesets.keys().map(String::as_str)
+    }
+
+    pub fn remove_ruleset(&mut self, name: &str) -> Option<BTreeSet<String>> {
+        self.rulesets.remove(name)
+    }
+
+    /// Add the rules of `other`, e.g. a rule set maintained by another team,
+    /// resolving rules with the same name by `policy`. Replaced rules keep
+    /// their position; new rules are added after the existing ones.
+    pub fn merge(
+        &mut self,
+        other: KnowledgeBase,
+        policy: MergePolicy,
+    ) -> Result<(), KnowledgeBaseError> {
+        if policy == MergePolicy::Error {
+            if let Some(rule) = other
+                .rules
+                .iter()
+                .find(|rule| self.rule_index.contains_key(&rule.name))
+            {
+                return Err(KnowledgeBaseError::DuplicateRule(rule.name.clone()));
+            }
+        }
+        for (name, rules) in other.rulesets {
+            self.rulesets.entry(name).or_default().extend(rules);
+        }
+        for rule in other.rules {
+            match self.rule_index.get(&rule.name) {
+                None => {
+                    self.add_rule(rule)?;
+                }
+                Some(&index) => {
+                    let replace = match policy {
+                        MergePolicy::KeepHigherSalience => {
+                            rule.salience > self.rules[index].salience
+                        }
+                        _ => true,
+                    };
+                    if replace {
+                        self.replace_at(index, rule);
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    /// Put `rule`, already simplified, in place of the rule at `index`
+    fn replace_at(&mut self, index: usize, rule: Rule) -> Rule {
+        let old_name = &self.rules[index].name;
+        self.rule_index.remove(old_name);
+        if *old_name != rule.name {
+            for rules in self.rulesets.values_mut() {
+                if rules.remove(old_name) {
+                    rules.insert(rule.name.clone());
+                }
+            }
+        }
+        self.rule_index.insert(rule.name.clone(), index);
+        self.dependencies[index] = RuleDependencies::of(&rule);
+        self.programs[index] = Program::compile(&rule.when_condition);
+        self.compile_patterns(&rule);
+        std::mem::replace(&mut self.rules[index], rule)
+    }
+
+    pub fn get_dependencies(&self, name: &str) -> Option<&RuleDependencies> {
+        self.rule_index
+            .get(name)
+            .and_then(|&index| self.dependencies.get(index))
+    }
+
+    /// Compile the rule's literal patterns that are not compiled yet
+    fn compile_patterns(&mut self, rule: &Rule) {
+        for pattern in analysis::constant_patterns(rule) {
+            if let Entry::Vacant(entry) = self.regexes.entry(pattern) {
+                if let Ok(regex) = Regex::new(entry.key()) {
+                    entry.insert(regex);
+                }
+            }
+        }
+    }
+
+    /// The compiled form of a literal pattern in one of the rules
+    pub(crate) fn regex(&self, pattern: &str) -> Option<&Regex> {
+        self.regexes.get(pattern)
+    }
+
+    pub(crate) fn get_program(&self, name: &str) -> Option<&Program> {
+        self.rule_index
+            .get(name)
+            .and_then(|&index| self.programs.get(index)?.as_ref())
+    }
+
     pub fn get_rule(&self, name: &str) -> Option<&Rule> {
         self.rule_index
             .get(name)
@@ -33,16 +327,106 @@ impl KnowledgeBase {
         &self.rules
     }
 
+    /// Rules tagged with `tag`, in the order they were added
+    pub fn rules_with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.tags.contains(tag))
+    }
+
+    /// Rules reading or writing `field`, e.g. `"TestCar.Speed"` or a whole
+    /// fact `"TestCar"`, through a fact's name or a pattern matching its type.
+    /// Rules referencing a parent or child path count too.
+    pub fn rules_referencing<'a>(&'a self, field: &str) -> impl Iterator<Item = &'a Rule> + 'a {
+        let target = match field.split_once('.') {
+            Some((fact, field)) => FieldRef::field(fact, field),
+            None => FieldRef::fact(field),
+        };
+        self.rules
+            .iter()
+            .zip(&self.dependencies)
+            .filter(move |(rule, deps)| {
+                deps.reads
+                    .iter()
+                    .chain(&deps.writes)
+                    .any(|used| used.overlaps(&target))
+                    || analysis::matched_reads(rule)
+                        .iter()
+                        .any(|used| used.overlaps(&target))
+            })
+            .map(|(rule, _)| rule)
+    }
+
+    /// The names of the packages rules belong to
+    pub fn packages(&self) -> BTreeSet<&str> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.package.as_deref())
+            .collect()
+    }
+
+    /// Rules in `package`, in the order they were added
+    pub fn rules_in_package<'a>(&'a self, package: &'a str) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.package.as_deref() == Some(package))
+    }
+
+    /// Remove every rule in `package`, returning them in the order they were
+    /// added
+    pub fn remove_package(&mut self, package: &str) -> Vec<Rule> {
+        let names: Vec<String> = self
+            .rules_in_package(package)
+            .map(|rule| rule.name.clone())
+            .collect();
+        names
+            .iter()
+            .filter_map(|name| self.remove_rule(name))
+            .collect()
+    }
+
+    /// Rules whose metadata maps `key` to `value`
+    pub fn rules_with_metadata<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.metadata.get(key).is_some_and(|v| v == value))
+    }
+
     pub fn get_rules_sorted_by_salience(&self) -> Vec<&Rule> {
         let mut rules: Vec<&Rule> = self.rules.iter().collect();
-        rules.sort_by(|a, b| b.salience.cmp(&a.salience)); // Higher salience first
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.salience)); // Higher salience first
         rules
     }
 
+    /// Which rules produce the facts and fields other rules consume
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        DependencyGraph::build(self)
+    }
+
+    /// Rule counts and complexity figures, for keeping a rule base healthy
+    pub fn stats(&self) -> KnowledgeBaseStats {
+        KnowledgeBaseStats::collect(self)
+    }
+
+    /// The dependency graph in Graphviz DOT format, see
+    /// `DependencyGraph::to_dot`
+    pub fn to_dot(&self) -> String {
+        self.dependency_graph().to_dot()
+    }
+
     pub fn remove_rule(&mut self, name: &str) -> Option<Rule> {
         if let Some(&index) = self.rule_index.get(name) {
             let rule = self.rules.remove(index);
+            self.dependencies.remove(index);
+            self.programs.remove(index);
             self.rule_index.remove(name);
+            for rules in self.rulesets.values_mut() {
+                rules.remove(name);
+            }
 
             // Update indices for rules that came after the removed rule
             for (_, rule_index) in self.rule_index.iter_mut() {
@@ -57,9 +441,41 @@ impl KnowledgeBase {
         }
     }
 
+    /// Replace the rule called `name` with `rule`, which keeps its place in
+    /// the firing order and may carry a new name. Returns the previous
+    /// version.
+    pub fn update_rule(&mut self, name: &str, rule: Rule) -> Result<Rule, KnowledgeBaseError> {
+        let index = *self
+            .rule_index
+            .get(name)
+            .ok_or_else(|| KnowledgeBaseError::RuleNotFound(name.to_string()))?;
+        if rule.name != name && self.rule_index.contains_key(&rule.name) {
+            return Err(KnowledgeBaseError::DuplicateRule(rule.name));
+        }
+
+        validate::validate_rule(&rule)?;
+        let (rule, _) = self.passes.run(rule);
+        Ok(self.replace_at(index, rule))
+    }
+
+    /// Rename the rule called `name`, keeping its place in the firing order.
+    /// Returns the rule as it was before.
+    pub fn rename_rule(&mut self, name: &str, new_name: &str) -> Result<Rule, KnowledgeBaseError> {
+        let mut rule = self
+            .get_rule(name)
+            .cloned()
+            .ok_or_else(|| KnowledgeBaseError::RuleNotFound(name.to_string()))?;
+        rule.name = new_name.to_string();
+        self.update_rule(name, rule)
+    }
+
     pub fn clear(&mut self) {
         self.rules.clear();
         self.rule_index.clear();
+        self.dependencies.clear();
+        self.programs.clear();
+        self.rulesets.clear();
+        self.regexes.clear();
     }
 
     pub fn len(&self) -> usize {
@@ -70,3 +486,83 @@ impl KnowledgeBase {
         self.rules.is_empty()
     }
 }
+
+/// Collects rules to be compiled into a `CompiledKnowledgeBase`
+#[derive(Debug, Clone, Default)]
+pub struct KnowledgeBaseBuilder {
+    version: u32,
+    rules: Vec<Rule>,
+    passes: PassManager,
+}
+
+impl KnowledgeBaseBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// The passes run over each rule when compiling, see
+    /// `KnowledgeBase::set_passes`
+    pub fn passes(mut self, passes: PassManager) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn rules(mut self, rules: impl IntoIterator<Item = Rule>) -> Self {
+        self.rules.extend(rules);
+        self
+    }
+
+    /// Validate, simplify and index every rule, failing on the first rule
+    /// `KnowledgeBase::add_rule` rejects
+    pub fn compile(self) -> Result<CompiledKnowledgeBase, KnowledgeBaseError> {
+        let mut knowledge_base = KnowledgeBase::new();
+        knowledge_base.set_version(self.version);
+        knowledge_base.set_passes(self.passes);
+        let mut warnings = Vec::new();
+        for rule in self.rules {
+            warnings.extend(knowledge_base.add_rule(rule)?);
+        }
+        Ok(CompiledKnowledgeBase {
+            knowledge_base: Arc::new(knowledge_base),
+            warnings,
+        })
+    }
+}
+
+/// A validated, optimized knowledge base that can no longer change. Cloning
+/// it is cheap, and engines created from it share its rules; an engine
+/// adding or removing rules works on its own copy.
+#[derive(Debug, Clone)]
+pub struct CompiledKnowledgeBase {
+    knowledge_base: Arc<KnowledgeBase>,
+    warnings: Vec<RuleWarning>,
+}
+
+impl CompiledKnowledgeBase {
+    /// Warnings found about the rules while compiling
+    pub fn warnings(&self) -> &[RuleWarning] {
+        &self.warnings
+    }
+
+    pub(crate) fn shared(&self) -> Arc<KnowledgeBase> {
+        Arc::clone(&self.knowledge_base)
+    }
+}
+
+impl Deref for CompiledKnowledgeBase {
+    type Target = KnowledgeBase;
+
+    fn deref(&self) -> &KnowledgeBase {
+        &self.knowledge_base
+    }
+}
@@ -1,28 +1,109 @@
-pub mod ast;
+pub mod analysis;
+pub mod audit;
+mod base64;
+#[cfg(feature = "binary")]
+mod binary;
+mod builtins;
+pub mod cancel;
+pub mod clock;
+pub mod config;
+pub mod context;
+pub mod convert;
+mod datetime;
+pub mod debugger;
+pub mod decimal;
 pub mod engine;
+pub mod error;
 pub mod facts;
+mod index;
+pub mod json_schema;
 pub mod knowledge_base;
-pub mod parser;
-pub mod rule;
+pub mod loader;
+mod markdown;
+pub mod middleware;
+pub mod migration;
+pub mod optimize;
+#[cfg(feature = "hot-reload")]
+pub mod reload;
+pub mod report;
+pub mod resolver;
+pub mod rule_diff;
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+pub mod schema;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod session;
+pub mod store;
+mod typecheck;
+mod validate;
+pub mod visit;
+mod vm;
+pub mod working_memory;
 
-pub use engine::{ExecutionResult, RuleEngine};
-pub use facts::{Fact, FactValue};
-pub use knowledge_base::KnowledgeBase;
-pub use rule::Rule;
+pub use rust_runes_grl::{ast, expr, parser, rule};
+
+pub use analysis::{
+    DependencyGraph, FieldRef, KnowledgeBaseStats, RuleDependencies, SALIENCE_BAND_WIDTH,
+};
+pub use audit::{AuditLog, AuditRecord, AuditWriter, JsonLinesWriter};
+pub use cancel::CancelToken;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::{
+    Collation, ConflictResolution, EngineConfig, NanHandling, NumericPolicy, ResourceLimits,
+    RuleEngineBuilder,
+};
+pub use context::{CustomFunction, EvalContext};
+pub use convert::{FromFact, FromFactError, IntoFact};
+pub use debugger::{Breakpoint, DebugEvent, DebugSession};
+pub use decimal::Decimal;
+pub use engine::{BatchResult, EngineError, ExecutionResult, RuleEngine};
+pub use error::RunesError;
+pub use facts::{CoercionPolicy, Fact, FactChange, FactMetadata, FactValue, Provenance};
+pub use knowledge_base::{
+    CompiledKnowledgeBase, KnowledgeBase, KnowledgeBaseBuilder, KnowledgeBaseError, MergePolicy,
+};
+pub use loader::{FileError, LoadError};
+pub use middleware::{FireContext, Middleware, Next};
+pub use migration::{Migration, Migrations, FORMAT_VERSION};
+pub use optimize::{Pass, PassManager, RuleWarning};
+pub use parser::ParseError;
+#[cfg(feature = "hot-reload")]
+pub use reload::{Reload, ReloadError, RuleWatcher, WatchHandle};
+pub use report::{ExecutionReport, FieldProvenance, SuppressedError, ValueChange};
+pub use resolver::FactResolver;
+pub use rule::{Accumulate, Binding, Cron, Pattern, Rule, Schedule};
+pub use rule_diff::{RuleChange, RuleEdit};
+#[cfg(feature = "derive")]
+pub use rust_runes_derive::{grl, IntoFact};
+#[cfg(feature = "scheduler")]
+pub use scheduler::{ScheduledRun, Scheduler, SchedulerHandle};
+pub use schema::{EnumType, FactSchema, FieldType};
+#[cfg(feature = "script")]
+pub use script::{ScriptEngine, ScriptInterpreter};
+pub use session::Session;
+pub use store::{FileStore, MemoryStore, RuleStore, StoreError};
+pub use visit::{ExpressionFolder, ExpressionVisitor};
+pub use working_memory::{Change, ChangeListener, WorkingMemory};
+
+// Lets derive macro output, which names `::rust_runes`, compile in this crate's tests
+#[cfg(test)]
+extern crate self as rust_runes;
 
 // Re-export main types
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub type Result<T> = std::result::Result<T, RunesError>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ast::Expression;
     use std::collections::HashMap;
+    use std::sync::Arc;
 
     #[test]
     fn test_rule_engine_basic() {
         let mut engine = RuleEngine::new();
-        let mut facts = HashMap::new();
+        let mut facts = WorkingMemory::new();
 
         // Simple rule: if x > 5 then y = 10
         let rule = Rule::new(
@@ -39,7 +120,7 @@ mod tests {
         );
 
         engine.add_rule(rule).unwrap();
-        facts.insert("x".to_string(), Fact::number_fact("x".to_string(), 7.0));
+        facts.insert(Fact::number_fact("x".to_string(), 7.0));
 
         let result = engine.execute(&mut facts).unwrap();
 
@@ -260,6 +341,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_grl_parser_logical_precedence() {
+        use crate::expr::{call, var};
+
+        let parser = parser::GrlParser::new();
+        let condition = |text: &str| {
+            parser
+                .parse_rule(&format!("rule R {{ when {} then x = 1; }}", text))
+                .unwrap()
+                .when_condition
+        };
+
+        // `||` binds looser than `&&`, and chains associate to the left
+        assert_eq!(
+            condition("a && b || c"),
+            var("a").and(var("b")).or(var("c"))
+        );
+        assert_eq!(
+            condition("a || b && c"),
+            var("a").or(var("b").and(var("c")))
+        );
+        assert_eq!(
+            condition("a && b && c"),
+            var("a").and(var("b")).and(var("c"))
+        );
+        assert_eq!(
+            condition("(a || b) && c"),
+            var("a").or(var("b")).and(var("c"))
+        );
+
+        // `&&` and `||` inside arguments and strings do not split the condition
+        assert_eq!(
+            condition(r#"f(y, "a && b") && s == "a || b" || z > 1"#),
+            call("f", [var("y"), "a && b".into()])
+                .and(var("s").equals("a || b"))
+                .or(var("z").gt(1.0))
+        );
+
+        // Two-character comparisons are not read as `<` or `>`
+        assert_eq!(
+            condition("x <= 1 || y >= 2"),
+            var("x").le(1.0).or(var("y").ge(2.0))
+        );
+
+        for expr in [
+            var("a").or(var("b")).and(var("c")),
+            var("a").and(var("b").or(var("c"))),
+            var("a").or(var("b").or(var("c"))),
+        ] {
+            assert_eq!(condition(&expr.to_grl_string()), expr);
+        }
+    }
+
     #[test]
     fn test_grl_parser_arithmetic_expressions() {
         let parser = parser::GrlParser::new();
@@ -296,4 +430,4877 @@ mod tests {
             _ => panic!("Expected Assignment expression for action"),
         }
     }
+
+    #[test]
+    fn test_debug_session_breakpoints() {
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(Rule::new(
+                "first".to_string(),
+                10,
+                Expression::Boolean(true),
+                vec![Expression::Assignment(
+                    "a".to_string(),
+                    Box::new(Expression::Number(1.0)),
+                )],
+            ))
+            .unwrap();
+        engine
+            .add_rule(Rule::new(
+                "second".to_string(),
+                5,
+                Expression::Boolean(true),
+                vec![Expression::Assignment(
+                    "b".to_string(),
+                    Box::new(Expression::Number(2.0)),
+                )],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        let mut session = engine.debug(&mut facts);
+        session.add_breakpoint(Breakpoint::FieldWrite {
+            fact: "b".to_string(),
+            field: None,
+        });
+
+        match session.resume().unwrap() {
+            DebugEvent::Paused { rule, .. } => assert_eq!(rule, "second"),
+            other => panic!("Expected pause, got {:?}", other),
+        }
+        assert!(session.memory().contains("a"));
+        assert!(!session.memory().contains("b"));
+
+        match session.resume().unwrap() {
+            DebugEvent::Finished(result) => assert_eq!(result.rules_fired, vec!["first", "second"]),
+            other => panic!("Expected finish, got {:?}", other),
+        }
+
+        let mut facts = WorkingMemory::new();
+        let mut session = engine.debug(&mut facts);
+        session.add_breakpoint(Breakpoint::Rule("first".to_string()));
+        assert!(matches!(
+            session.resume().unwrap(),
+            DebugEvent::Paused { .. }
+        ));
+        let result = session.abort();
+        assert!(result.rules_fired.is_empty());
+        assert!(facts.is_empty());
+
+        // Writes nested in `if` and `foreach` hit field breakpoints
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Nested { when x > 0 then if (x > 0) { b = 2; } }")
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 1.0));
+        let mut session = engine.debug(&mut facts);
+        session.add_breakpoint(Breakpoint::FieldWrite {
+            fact: "b".to_string(),
+            field: None,
+        });
+        assert!(matches!(
+            session.resume().unwrap(),
+            DebugEvent::Paused { rule, .. } if rule == "Nested"
+        ));
+
+        // A session runs the same cycles as `execute`, retracting facts whose
+        // logical support is gone
+        let mut engine = RuleEngine::builder().max_cycles(10).build();
+        for grl in [
+            "rule A salience 10 { when x > 0 then y = x + 1; }",
+            "rule B salience 5 { when y > 0 then z = y; }",
+            "rule Raise salience 3 { when x > 0 then insertLogical(alert, true); }",
+            "rule Reset salience 1 { when z > 0 then x = 0; }",
+        ] {
+            engine.add_rule(parser.parse_rule(grl).unwrap()).unwrap();
+        }
+        let start = || {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::number_fact("x".to_string(), 1.0));
+            facts
+        };
+        let mut executed = start();
+        let expected = engine.execute(&mut executed).unwrap();
+        assert_eq!(expected.facts_retracted, ["alert"]);
+
+        let mut facts = start();
+        let mut session = engine.debug(&mut facts);
+        let DebugEvent::Finished(result) = session.resume().unwrap() else {
+            panic!("Expected finish");
+        };
+        assert_eq!(result.rules_fired, expected.rules_fired);
+        assert_eq!(result.rules_fired_by_cycle, expected.rules_fired_by_cycle);
+        assert_eq!(result.facts_retracted, expected.facts_retracted);
+        assert_eq!(result.cycles, expected.cycles);
+        let values = |facts: &WorkingMemory| -> HashMap<String, FactValue> {
+            facts
+                .iter()
+                .map(|fact| (fact.name.clone(), fact.value.clone()))
+                .collect()
+        };
+        assert_eq!(values(&facts), values(&executed));
+
+        // Stepping fires the same rules one at a time
+        let mut facts = start();
+        let mut session = engine.debug(&mut facts);
+        let mut stepped = Vec::new();
+        while let DebugEvent::Stepped { rule, .. } = session.step().unwrap() {
+            stepped.push(rule);
+        }
+        assert_eq!(stepped, expected.rules_fired);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_execute_parallel() {
+        let mut engine = RuleEngine::new();
+        for i in 0..100 {
+            engine
+                .add_rule(Rule::new(
+                    format!("rule{}", i),
+                    i,
+                    Expression::GreaterThan(
+                        Box::new(Expression::Variable("x".to_string())),
+                        Box::new(Expression::Number(i as f64)),
+                    ),
+                    vec![Expression::Assignment(
+                        format!("y{}", i),
+                        Box::new(Expression::Boolean(true)),
+                    )],
+                ))
+                .unwrap();
+        }
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 50.0));
+
+        let result = engine.execute_parallel(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired.len(), 50);
+        assert_eq!(result.rules_fired[0], "rule49");
+        assert!(facts.contains("y0"));
+        assert!(!facts.contains("y50"));
+    }
+
+    #[test]
+    fn test_execute_rolls_back_on_error() {
+        let mut engine = RuleEngine::new();
+        let rule = Rule::new(
+            "half_applied".to_string(),
+            0,
+            Expression::Boolean(true),
+            vec![
+                Expression::Assignment("y".to_string(), Box::new(Expression::Number(1.0))),
+                Expression::Assignment(
+                    "z".to_string(),
+                    Box::new(Expression::Add(
+                        Box::new(Expression::Number(1.0)),
+                        Box::new(Expression::Boolean(true)),
+                    )),
+                ),
+            ],
+        );
+        engine.add_rule(rule).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 7.0));
+
+        assert!(engine.execute(&mut facts).is_err());
+        assert_eq!(facts.len(), 1);
+        assert!(!facts.contains("y"));
+
+        facts.checkpoint();
+        facts.insert(Fact::number_fact("y".to_string(), 2.0));
+        assert!(facts.rollback());
+        assert!(!facts.contains("y"));
+        assert!(!facts.rollback());
+    }
+
+    #[test]
+    fn test_typed_errors() {
+        let mut engine = RuleEngine::new();
+        let rule = Rule::new("dup".to_string(), 0, Expression::Boolean(true), vec![]);
+        engine.add_rule(rule.clone()).unwrap();
+
+        match engine.add_rule(rule) {
+            Err(RunesError::KnowledgeBase(KnowledgeBaseError::DuplicateRule(name))) => {
+                assert_eq!(name, "dup")
+            }
+            other => panic!("Expected duplicate rule error, got {:?}", other),
+        }
+
+        let parser = parser::GrlParser::new();
+        assert_eq!(
+            parser.parse_rule("not a rule").unwrap_err(),
+            ParseError::InvalidSyntax
+        );
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(Rule::new(
+                "unknown".to_string(),
+                0,
+                Expression::Variable("missing".to_string()),
+                vec![],
+            ))
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut WorkingMemory::new()),
+            Err(RunesError::Engine(EngineError::UnknownVariable(_)))
+        ));
+    }
+
+    #[test]
+    fn test_engine_builder() {
+        let mut engine = RuleEngine::builder()
+            .max_cycles(3)
+            .strict_types(false)
+            .conflict_resolution(ConflictResolution::InsertionOrder)
+            .build();
+
+        // Fires every cycle until max_cycles is reached
+        engine
+            .add_rule(Rule::new(
+                "count".to_string(),
+                0,
+                Expression::LessThan(
+                    Box::new(Expression::Variable("n".to_string())),
+                    Box::new(Expression::Number(10.0)),
+                ),
+                vec![Expression::Assignment(
+                    "n".to_string(),
+                    Box::new(Expression::Add(
+                        Box::new(Expression::Variable("n".to_string())),
+                        Box::new(Expression::Number(1.0)),
+                    )),
+                )],
+            ))
+            .unwrap();
+        // Type error in the condition is ignored in lenient mode
+        engine
+            .add_rule(Rule::new(
+                "mistyped".to_string(),
+                10,
+                Expression::GreaterThan(
+                    Box::new(Expression::String("a".to_string())),
+                    Box::new(Expression::Number(1.0)),
+                ),
+                vec![],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("n".to_string(), 0.0));
+
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.cycles, 3);
+        assert_eq!(result.rules_fired, vec!["count", "count", "count"]);
+        assert_eq!(facts.get("n").unwrap().value, FactValue::Number(3.0));
+    }
+
+    #[test]
+    fn test_logical_insertion_is_retracted() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder().max_cycles(5).build();
+
+        let raise_alert = parser
+            .parse_rule(
+                r#"
+                rule RaiseAlert salience 10 {
+                    when
+                        x > 5
+                    then
+                        insertLogical(alert, true);
+                }
+            "#,
+            )
+            .unwrap();
+        assert_eq!(
+            raise_alert.then_actions[0],
+            Expression::InsertLogical("alert".to_string(), Box::new(Expression::Boolean(true)))
+        );
+        engine.add_rule(raise_alert).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Reset".to_string(),
+                5,
+                Expression::GreaterThan(
+                    Box::new(Expression::Variable("x".to_string())),
+                    Box::new(Expression::Number(5.0)),
+                ),
+                vec![Expression::Assignment(
+                    "x".to_string(),
+                    Box::new(Expression::Number(0.0)),
+                )],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 7.0));
+
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["RaiseAlert", "Reset"]);
+        assert_eq!(result.facts_retracted, vec!["alert"]);
+        assert!(!facts.contains("alert"));
+
+        // A fact inserted by the caller is left alone, and survives the rule
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 7.0));
+        facts.insert(Fact::string_fact("alert".to_string(), "manual".to_string()));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, vec!["RaiseAlert", "Reset"]);
+        assert!(result.facts_retracted.is_empty());
+        assert_eq!(
+            facts.get("alert").unwrap().value,
+            FactValue::String("manual".to_string())
+        );
+    }
+
+    #[test]
+    fn test_event_count_window() {
+        let clock = std::sync::Arc::new(ManualClock::new(10 * 60_000));
+        let mut engine = RuleEngine::builder().clock(clock.clone()).build();
+
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"
+                rule TooManyLogins {
+                    when
+                        count of Login events in last 5m > 2
+                    then
+                        locked = true;
+                }
+            "#,
+            )
+            .unwrap();
+        assert!(matches!(
+            &rule.when_condition,
+            Expression::GreaterThan(left, _) if **left == Expression::EventCount("Login".to_string(), 300_000)
+        ));
+        engine.add_rule(rule).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        // One login outside the window, three inside
+        for (i, minute) in [1, 6, 8, 9].iter().enumerate() {
+            let name = format!("login{}", i);
+            facts.insert(Fact::event(
+                name,
+                "Login".to_string(),
+                minute * 60_000,
+                FactValue::Null,
+            ));
+        }
+
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, vec!["TooManyLogins"]);
+
+        // Two minutes later only two logins remain in the window
+        clock.advance(2 * 60_000);
+        facts.retract("locked");
+        let result = engine.execute(&mut facts).unwrap();
+        assert!(result.rules_fired.is_empty());
+    }
+
+    #[cfg(feature = "scheduler")]
+    #[test]
+    fn test_scheduler_runs_interval_rules() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"
+                rule Heartbeat every 10ms {
+                    when
+                        beats < 3
+                    then
+                        beats = beats + 1;
+                }
+            "#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.schedule,
+            Some(Schedule::Interval(Duration::from_millis(10)))
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("beats".to_string(), 0.0));
+        let facts = Arc::new(Mutex::new(facts));
+
+        let (handle, runs) = Scheduler::new(Arc::new(engine), facts.clone())
+            .tick(Duration::from_millis(1))
+            .start_with_channel();
+
+        let fired = (0..3)
+            .map(|_| runs.recv_timeout(Duration::from_secs(5)).unwrap())
+            .filter(|run| matches!(run.outcome, Ok(true)))
+            .count();
+        handle.stop();
+
+        assert_eq!(fired, 3);
+        assert_eq!(
+            facts.lock().unwrap().get("beats").unwrap().value,
+            FactValue::Number(3.0)
+        );
+    }
+
+    #[cfg(feature = "scheduler")]
+    #[test]
+    fn test_scheduler_runs_cron_rules() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let weekdays = Cron::parse("*/15 9-17 * * 1-5").unwrap();
+        assert!(weekdays.matches(45, 17, 12, 3, 5));
+        assert!(!weekdays.matches(50, 17, 12, 3, 5));
+        assert!(!weekdays.matches(45, 17, 12, 3, 0));
+        // Either day field matching is enough when both are restricted
+        let either = Cron::parse("0 0 1,15 * 7").unwrap();
+        assert!(either.matches(0, 0, 15, 6, 2));
+        assert!(either.matches(0, 0, 9, 6, 0));
+        assert!(!either.matches(0, 0, 9, 6, 1));
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+        ] {
+            assert_eq!(Cron::parse(invalid), None, "{}", invalid);
+        }
+
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"rule Report cron "30 9 * * *" { when reports < 5 then reports = reports + 1; }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.schedule,
+            Some(Schedule::Cron(Cron::parse("30 9 * * *").unwrap()))
+        );
+        assert!(parser
+            .parse_rule(r#"rule Bad cron "30 25 * * *" { when x > 1 then x = 1; }"#)
+            .is_err());
+
+        // 1970-01-01T09:29:00Z
+        let clock = Arc::new(ManualClock::new(9 * 3_600_000 + 29 * 60_000));
+        let mut engine = RuleEngine::builder().clock(clock.clone()).build();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("reports".to_string(), 0.0));
+        let facts = Arc::new(Mutex::new(facts));
+
+        let (handle, runs) = Scheduler::new(Arc::new(engine), facts.clone())
+            .tick(Duration::from_millis(1))
+            .start_with_channel();
+        assert!(runs.recv_timeout(Duration::from_millis(50)).is_err());
+
+        // Runs once in the matching minute, and again the next day
+        clock.advance(60_000);
+        assert!(runs
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .outcome
+            .unwrap());
+        assert!(runs.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(86_400_000);
+        assert!(runs
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .outcome
+            .unwrap());
+        handle.stop();
+
+        assert_eq!(
+            facts.lock().unwrap().get("reports").unwrap().value,
+            FactValue::Number(2.0)
+        );
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        kb.add_rule(
+            parser
+                .parse_rule(
+                    r#"rule SpeedUp { when TestCar.SpeedUp == true then TestCar.Speed = TestCar.Speed + TestCar.SpeedIncrement; }"#,
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        kb.add_rule(
+            parser
+                .parse_rule(
+                    r#"rule Record { when TestCar.Speed > 10 then DistanceRecord.TotalDistance = TestCar.Speed; }"#,
+                )
+                .unwrap(),
+        )
+        .unwrap();
+
+        let graph = kb.dependency_graph();
+
+        let speed_up = &graph.rules["SpeedUp"];
+        assert!(speed_up
+            .reads
+            .contains(&FieldRef::field("TestCar", "SpeedIncrement")));
+        assert!(speed_up
+            .writes
+            .contains(&FieldRef::field("TestCar", "Speed")));
+
+        assert!(graph.consumers_of("SpeedUp").contains("Record"));
+        assert!(graph.producers_of("Record").contains("SpeedUp"));
+        assert!(graph.consumers_of("Record").is_empty());
+    }
+
+    #[test]
+    fn test_incremental_reevaluation() {
+        let mut engine = RuleEngine::builder().max_cycles(10).build();
+
+        // Reads nothing that is ever modified, so it only fires in the first cycle
+        engine
+            .add_rule(Rule::new(
+                "static".to_string(),
+                10,
+                Expression::Boolean(true),
+                vec![Expression::Assignment(
+                    "seen".to_string(),
+                    Box::new(Expression::Boolean(true)),
+                )],
+            ))
+            .unwrap();
+        // Re-evaluated after every cycle that modified x
+        engine
+            .add_rule(Rule::new(
+                "increment".to_string(),
+                0,
+                Expression::LessThan(
+                    Box::new(Expression::Variable("x".to_string())),
+                    Box::new(Expression::Number(3.0)),
+                ),
+                vec![Expression::Assignment(
+                    "x".to_string(),
+                    Box::new(Expression::Add(
+                        Box::new(Expression::Variable("x".to_string())),
+                        Box::new(Expression::Number(1.0)),
+                    )),
+                )],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 0.0));
+
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(
+            result.rules_fired,
+            vec!["static", "increment", "increment", "increment"]
+        );
+        assert_eq!(result.cycles, 4);
+        assert_eq!(result.facts_modified, vec!["seen", "x"]);
+        assert_eq!(facts.get("x").unwrap().value, FactValue::Number(3.0));
+    }
+
+    #[test]
+    fn test_working_memory_tracks_changes() {
+        let mut memory = WorkingMemory::from(HashMap::from([(
+            "x".to_string(),
+            Fact::number_fact("x".to_string(), 1.0),
+        )]));
+
+        memory.insert(Fact::boolean_fact("flag".to_string(), true));
+        assert_eq!(
+            memory.update("x", FactValue::Number(2.0)),
+            Some(FactValue::Number(1.0))
+        );
+        assert_eq!(memory.update("missing", FactValue::Null), None);
+        assert!(memory.retract("flag").is_some());
+
+        assert_eq!(
+            memory.take_changes(),
+            vec![
+                Change::Inserted("flag".to_string()),
+                Change::Updated("x".to_string()),
+                Change::Retracted("flag".to_string()),
+            ]
+        );
+        assert_eq!(memory.names().collect::<Vec<_>>(), vec!["x"]);
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(Rule::new(
+                "double".to_string(),
+                0,
+                Expression::Boolean(true),
+                vec![Expression::Assignment(
+                    "x".to_string(),
+                    Box::new(Expression::Multiply(
+                        Box::new(Expression::Variable("x".to_string())),
+                        Box::new(Expression::Number(2.0)),
+                    )),
+                )],
+            ))
+            .unwrap();
+        engine.execute(&mut memory).unwrap();
+
+        assert_eq!(memory.changes(), &[Change::Updated("x".to_string())]);
+        assert_eq!(memory.get("x").unwrap().value, FactValue::Number(4.0));
+    }
+
+    #[test]
+    fn test_pattern_fires_per_matching_instance() {
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"
+                rule SlowDown {
+                    when
+                        c: Car(c.Speed > 100)
+                    then
+                        c.Speed = 100;
+                }
+            "#,
+            )
+            .unwrap();
+        assert_eq!(rule.patterns.len(), 1);
+        assert_eq!(rule.patterns[0].binding, "c");
+        assert_eq!(rule.patterns[0].fact_type, "Car");
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        for (name, speed) in [("car1", 120.0), ("car2", 80.0), ("car3", 150.0)] {
+            facts.insert(
+                Fact::from_object(
+                    name.to_string(),
+                    HashMap::from([("Speed".to_string(), FactValue::Number(speed))]),
+                )
+                .with_type("Car".to_string()),
+            );
+        }
+
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["SlowDown", "SlowDown"]);
+        assert_eq!(result.facts_modified, vec!["car1", "car3"]);
+        for (name, speed) in [("car1", 100.0), ("car2", 80.0), ("car3", 100.0)] {
+            assert_eq!(
+                facts.get(name).unwrap().get_field("Speed"),
+                Some(&FactValue::Number(speed))
+            );
+        }
+    }
+
+    #[test]
+    fn test_when_clause_bindings() {
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"
+                rule OrderFee {
+                    when
+                        total := order.price * order.qty;
+                        total > 100
+                    then
+                        fee = total * 0.01;
+                }
+            "#,
+            )
+            .unwrap();
+        assert_eq!(rule.bindings.len(), 1);
+        assert_eq!(rule.bindings[0].name, "total");
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([
+                ("price".to_string(), FactValue::Number(25.0)),
+                ("qty".to_string(), FactValue::Number(8.0)),
+            ]),
+        ));
+
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["OrderFee"]);
+        assert_eq!(facts.get("fee").unwrap().value, FactValue::Number(2.0));
+        assert!(!facts.contains("total"));
+    }
+
+    #[test]
+    fn test_compiled_conditions_match_interpreter() {
+        let parser = parser::GrlParser::new();
+        let engine = RuleEngine::new();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([
+                ("price".to_string(), FactValue::Number(25.0)),
+                ("qty".to_string(), FactValue::Number(4.0)),
+                ("status".to_string(), FactValue::String("open".to_string())),
+            ]),
+        ));
+        let scope = engine::Scope::default();
+
+        for condition in [
+            "order.price > 20 && order.qty < 5",
+            "order.status == \"closed\" || order.qty > 3",
+            "order.price != 25",
+        ] {
+            let rule = parser
+                .parse_rule(&format!("rule R {{ when {} then x = 1; }}", condition))
+                .unwrap();
+            let program = vm::Program::compile(&rule.when_condition).unwrap();
+            assert_eq!(
+                program
+                    .execute(&engine, &facts, &scope)
+                    .unwrap()
+                    .is_truthy(),
+                engine
+                    .evaluate_condition(&rule.when_condition, &facts, &scope)
+                    .unwrap(),
+                "{}",
+                condition
+            );
+        }
+
+        let divide = Expression::Divide(
+            Box::new(Expression::Number(1.0)),
+            Box::new(Expression::Number(0.0)),
+        );
+        assert!(matches!(
+            vm::Program::compile(&divide)
+                .unwrap()
+                .execute(&engine, &facts, &scope),
+            Err(EngineError::DivisionByZero)
+        ));
+        assert!(vm::Program::compile(&Expression::Assignment(
+            "x".to_string(),
+            Box::new(Expression::Number(1.0))
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_constant_folding_on_add_rule() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+
+        let warnings = engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Folded { when x > 1 + 2 && 3 > 2 then y = 10 / 0; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(warnings.is_empty());
+        let folded = engine.get_knowledge_base().get_rule("Folded").unwrap();
+        assert_eq!(
+            folded.when_condition,
+            Expression::GreaterThan(
+                Box::new(Expression::Variable("x".to_string())),
+                Box::new(Expression::Number(3.0)),
+            )
+        );
+        // Failing sub-expressions are left for the engine to report
+        assert!(matches!(
+            folded.then_actions[0],
+            Expression::Assignment(_, ref value) if matches!(**value, Expression::Divide(_, _))
+        ));
+
+        let warnings = engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Never { when x > 1 && 2 < 1 then y = 1; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            warnings,
+            vec![RuleWarning::NeverMatches("Never".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_execution_report() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .max_cycles(3)
+            .strict_types(false)
+            .build();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Promote { when user.score > 10 then user.level = 2; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Broken { when user.name > 3 then flag = true; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "user".to_string(),
+            HashMap::from([
+                ("name".to_string(), FactValue::String("ada".to_string())),
+                ("score".to_string(), FactValue::Number(12.0)),
+                ("level".to_string(), FactValue::Number(1.0)),
+            ]),
+        ));
+
+        let (_, report) = engine.execute_with_report(&mut facts).unwrap();
+
+        assert_eq!(report.cycles[0].rules_fired, vec!["Promote"]);
+        assert_eq!(
+            report.changes,
+            vec![ValueChange {
+                fact: "user".to_string(),
+                field: Some("level".to_string()),
+                before: Some(FactValue::Number(1.0)),
+                after: Some(FactValue::Number(2.0)),
+            }]
+        );
+        assert_eq!(report.suppressed_errors[0].rule, "Broken");
+
+        let json = serde_json::to_string(&report).unwrap();
+        let restored: ExecutionReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, report);
+    }
+
+    #[test]
+    fn test_execute_cancellable() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder().max_cycles(usize::MAX).build();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Forever { when n > -1 then n = n + 1; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("n".to_string(), 0.0));
+
+        let token = CancelToken::new();
+        let canceller = {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                token.cancel();
+            })
+        };
+
+        let outcome = engine.execute_cancellable(&mut facts, &token);
+        canceller.join().unwrap();
+
+        assert!(matches!(
+            outcome,
+            Err(RunesError::Engine(EngineError::Cancelled))
+        ));
+        assert_eq!(facts.get("n").unwrap().value, FactValue::Number(0.0));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .limits(ResourceLimits {
+                max_expression_depth: Some(3),
+                max_string_length: Some(8),
+                max_array_size: Some(2),
+                ..ResourceLimits::default()
+            })
+            .build();
+
+        let deep = parser
+            .parse_rule("rule Deep { when x > 1 then y = x + 1 + 2 + 3; }")
+            .unwrap();
+        assert!(matches!(
+            engine.add_rule(deep),
+            Err(RunesError::Engine(EngineError::ResourceLimit(_)))
+        ));
+
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Grow { when x > 1 then s = s + s; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("x".to_string(), 2.0));
+        facts.insert(Fact::string_fact("s".to_string(), "abcdef".to_string()));
+
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::ResourceLimit(_)))
+        ));
+        assert_eq!(
+            facts.get("s").unwrap().value,
+            FactValue::String("abcdef".to_string())
+        );
+
+        // Builtins are held to the same limits
+        for (grl, fact) in [
+            ("rule Push { when x > 1 then a = push(a, 3); }", "a"),
+            (
+                "rule Encode { when x > 1 then t = base64_encode(bytes(t)); }",
+                "t",
+            ),
+            ("rule Upper { when x > 1 then t = t.toUpper(); }", "t"),
+        ] {
+            let mut engine = RuleEngine::builder()
+                .limits(ResourceLimits {
+                    max_string_length: Some(8),
+                    max_array_size: Some(2),
+                    ..ResourceLimits::default()
+                })
+                .build();
+            engine.add_rule(parser.parse_rule(grl).unwrap()).unwrap();
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::number_fact("x".to_string(), 2.0));
+            facts.insert(Fact::new(
+                "a".to_string(),
+                FactValue::Array(vec![1.into(), 2.into()]),
+            ));
+            facts.insert(Fact::string_fact("t".to_string(), "abcdefghi".to_string()));
+            let before = facts.get(fact).unwrap().value.clone();
+            assert!(matches!(
+                engine.execute(&mut facts),
+                Err(RunesError::Engine(EngineError::ResourceLimit(_)))
+            ));
+            assert_eq!(facts.get(fact).unwrap().value, before);
+        }
+    }
+
+    #[test]
+    fn test_engine_shares_knowledge_base_across_threads() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Double { when x > 0 then y = x * 2; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let workers: Vec<_> = (1..=4)
+            .map(|i| {
+                let engine = engine.clone();
+                std::thread::spawn(move || {
+                    let mut facts = WorkingMemory::new();
+                    facts.insert(Fact::number_fact("x".to_string(), i as f64));
+                    engine.execute(&mut facts).unwrap();
+                    facts.get("y").unwrap().value.clone()
+                })
+            })
+            .collect();
+        for (i, worker) in workers.into_iter().enumerate() {
+            assert_eq!(
+                worker.join().unwrap(),
+                FactValue::Number((i + 1) as f64 * 2.0)
+            );
+        }
+
+        // Adding a rule to a clone leaves the shared knowledge base untouched
+        let shared = engine.shared_knowledge_base();
+        let mut next =
+            RuleEngine::with_knowledge_base(Arc::clone(&shared), EngineConfig::default()).unwrap();
+        next.add_rule(
+            parser
+                .parse_rule("rule Triple { when x > 0 then z = x * 3; }")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(next.get_knowledge_base().len(), 2);
+
+        let previous = engine
+            .replace_knowledge_base(next.shared_knowledge_base())
+            .unwrap();
+        assert!(Arc::ptr_eq(&previous, &shared));
+        assert_eq!(engine.get_knowledge_base().len(), 2);
+    }
+
+    #[test]
+    fn test_fire_rule() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for grl in [
+            "rule SpeedUp { when speed < 100 then speed = speed + 10; }",
+            "rule Brake { when speed > 50 then speed = 0; }",
+        ] {
+            engine.add_rule(parser.parse_rule(grl).unwrap()).unwrap();
+        }
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("speed".to_string(), 60.0));
+
+        assert!(engine.fire_rule("SpeedUp", &mut facts).unwrap());
+        assert_eq!(facts.get("speed").unwrap().value, FactValue::Number(70.0));
+
+        facts.update("speed", FactValue::Number(120.0));
+        assert!(!engine.fire_rule("SpeedUp", &mut facts).unwrap());
+        assert_eq!(facts.get("speed").unwrap().value, FactValue::Number(120.0));
+
+        assert!(matches!(
+            engine.fire_rule("Missing", &mut facts),
+            Err(RunesError::KnowledgeBase(KnowledgeBaseError::RuleNotFound(name))) if name == "Missing"
+        ));
+    }
+
+    #[test]
+    fn test_execute_group() {
+        let mut engine = RuleEngine::new();
+        let set = |name: &str, value: f64| {
+            Rule::new(
+                name.to_string(),
+                0,
+                Expression::Boolean(true),
+                vec![Expression::Assignment(
+                    name.to_string(),
+                    Box::new(Expression::Number(value)),
+                )],
+            )
+        };
+        engine
+            .add_rule(set("discount", 0.1).with_tag("pricing".to_string()))
+            .unwrap();
+        engine
+            .add_rule(set("tax", 0.2).with_tag("pricing".to_string()))
+            .unwrap();
+        engine
+            .add_rule(set("alert", 1.0).with_tag("monitoring".to_string()))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        let result = engine.execute_group("pricing", &mut facts).unwrap();
+        assert_eq!(result.rules_fired, vec!["discount", "tax"]);
+        assert!(!facts.contains("alert"));
+
+        let mut facts = WorkingMemory::new();
+        let result = engine
+            .execute_filtered(&mut facts, |rule| !rule.tags.contains("pricing"))
+            .unwrap();
+        assert_eq!(result.rules_fired, vec!["alert"]);
+    }
+
+    #[test]
+    fn test_stateful_session() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Score { when hits > 0 then score = score + hits; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut session = engine.session();
+        session.insert(Fact::number_fact("score".to_string(), 0.0));
+        session.insert(Fact::number_fact("hits".to_string(), 2.0));
+        session.fire_all_rules().unwrap();
+        assert!(session.memory().changes().is_empty());
+
+        session.update("hits", FactValue::Number(3.0));
+        session.fire_all_rules().unwrap();
+        assert_eq!(session.get("score").unwrap().value, FactValue::Number(5.0));
+
+        session.retract("hits");
+        assert!(session.fire_all_rules().is_err());
+        assert_eq!(session.get("score").unwrap().value, FactValue::Number(5.0));
+    }
+
+    #[test]
+    fn test_evaluate_batch() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Score { when income > 1000 then score = income / debt; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let applicant = |income: f64, debt: f64| {
+            HashMap::from([
+                (
+                    "income".to_string(),
+                    Fact::number_fact("income".to_string(), income),
+                ),
+                (
+                    "debt".to_string(),
+                    Fact::number_fact("debt".to_string(), debt),
+                ),
+            ])
+        };
+        let results = engine.evaluate_batch(vec![
+            applicant(5000.0, 2.0),
+            applicant(3000.0, 0.0),
+            applicant(500.0, 1.0),
+        ]);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].facts.get("score").unwrap().value,
+            FactValue::Number(2500.0)
+        );
+        assert!(matches!(
+            results[1].outcome,
+            Err(RunesError::Engine(EngineError::DivisionByZero))
+        ));
+        assert!(!results[1].facts.contains("score"));
+        assert!(results[2].outcome.as_ref().unwrap().rules_fired.is_empty());
+    }
+
+    #[test]
+    fn test_explicit_updates() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .max_cycles(10)
+            .explicit_updates(true)
+            .build();
+        for grl in [
+            "rule Silent { when n < 3 then n = n + 1; }",
+            "rule Declared { when m < 3 then m = m + 1; update(m); }",
+        ] {
+            engine.add_rule(parser.parse_rule(grl).unwrap()).unwrap();
+        }
+        assert_eq!(
+            engine
+                .get_knowledge_base()
+                .get_rule("Declared")
+                .unwrap()
+                .then_actions[1],
+            Expression::Update("m".to_string())
+        );
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("n".to_string(), 0.0));
+        facts.insert(Fact::number_fact("m".to_string(), 0.0));
+        engine.execute(&mut facts).unwrap();
+
+        assert_eq!(facts.get("n").unwrap().value, FactValue::Number(1.0));
+        assert_eq!(facts.get("m").unwrap().value, FactValue::Number(3.0));
+    }
+
+    #[test]
+    fn test_custom_functions_receive_eval_context() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine.register_function("discount", |context, args| {
+            let rate = match context.value("tenant") {
+                Some(FactValue::String(tenant)) if tenant == "acme" => 0.2,
+                _ => 0.1,
+            };
+            match args {
+                [FactValue::Number(total)] => Ok(FactValue::Number(total * rate)),
+                _ => Err(EngineError::TypeError(
+                    "discount expects a number".to_string(),
+                )),
+            }
+        });
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        "rule Discount { when discount(order.total) >= 20 then order.discount = discount(order.total); }",
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let order = || {
+            Fact::from_object(
+                "order".to_string(),
+                HashMap::from([("total".to_string(), FactValue::Number(100.0))]),
+            )
+        };
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(order());
+        let context =
+            EvalContext::new().with_value("tenant", FactValue::String("acme".to_string()));
+        let result = engine.execute_with_context(&mut facts, context).unwrap();
+        assert_eq!(result.rules_fired, vec!["Discount"]);
+        let discount = engine.access_field(&facts.get("order").unwrap().value, "discount");
+        assert_eq!(discount.unwrap(), FactValue::Number(20.0));
+
+        // Without the tenant the discount is too small to fire
+        let mut facts = WorkingMemory::new();
+        facts.insert(order());
+        let result = engine.execute(&mut facts).unwrap();
+        assert!(result.rules_fired.is_empty());
+
+        let mut limited = RuleEngine::builder()
+            .limits(ResourceLimits {
+                max_function_calls: Some(1),
+                ..ResourceLimits::default()
+            })
+            .build();
+        limited.register_function("discount", |_, _| Ok(FactValue::Number(50.0)));
+        for rule in engine.get_knowledge_base().get_rules() {
+            limited.add_rule(rule.clone()).unwrap();
+        }
+        let mut facts = WorkingMemory::new();
+        facts.insert(order());
+        assert!(matches!(
+            limited.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::ResourceLimit(_)))
+        ));
+    }
+
+    #[test]
+    fn test_fact_resolver_loads_missing_facts_once() {
+        #[derive(Debug, Default)]
+        struct Customers {
+            lookups: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl FactResolver for Customers {
+            fn resolve(&self, name: &str) -> std::result::Result<Option<FactValue>, EngineError> {
+                self.lookups.lock().unwrap().push(name.to_string());
+                Ok((name == "customer").then(|| {
+                    FactValue::Object(HashMap::from([(
+                        "tier".to_string(),
+                        FactValue::String("gold".to_string()),
+                    )]))
+                }))
+            }
+        }
+
+        let customers = Arc::new(Customers::default());
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .fact_resolver(customers.clone())
+            .build();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Gold { when customer.tier == "gold" && order.total > 10 then discount = customer.tier; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::Number(50.0))]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["Gold"]);
+        assert_eq!(
+            facts.get("discount").unwrap().value,
+            FactValue::String("gold".to_string())
+        );
+        assert!(!facts.contains("customer"));
+        assert_eq!(*customers.lookups.lock().unwrap(), vec!["customer"]);
+
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Missing { when supplier.rating > 3 then x = 1; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::UnknownVariable(name))) if name == "supplier"
+        ));
+    }
+
+    #[test]
+    fn test_globals_are_readable_but_not_writable() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine.set_global("TAX_RATE", FactValue::Number(0.5));
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Tax { when price > 0 then tax = price * TAX_RATE; }")
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::new("price".to_string(), FactValue::Number(10.0)));
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(facts.get("tax").unwrap().value, FactValue::Number(5.0));
+        assert!(!facts.contains("TAX_RATE"));
+
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Cheat { when price > 0 then TAX_RATE = 0; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::ReadOnlyGlobal(name))) if name == "TAX_RATE"
+        ));
+        assert_eq!(engine.global("TAX_RATE"), Some(&FactValue::Number(0.5)));
+    }
+
+    #[test]
+    fn test_middleware_wraps_rule_firing() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for rule in [
+            "rule Audit salience 10 { when amount > 0 then audited = true; }",
+            "rule Refund { when amount > 0 then refunded = true; }",
+        ] {
+            engine.add_rule(parser.parse_rule(rule).unwrap()).unwrap();
+        }
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let entries = log.clone();
+        engine.use_middleware(move |firing, next| {
+            entries
+                .lock()
+                .unwrap()
+                .push(format!("before {}", firing.rule.name));
+            next.run()?;
+            entries
+                .lock()
+                .unwrap()
+                .push(format!("after {}", firing.rule.name));
+            Ok(())
+        });
+        // Refunds are not allowed for trial tenants
+        engine.use_middleware(|firing, next| {
+            let trial =
+                firing.context.value("plan") == Some(&FactValue::String("trial".to_string()));
+            if trial && firing.rule.name == "Refund" {
+                return Ok(());
+            }
+            next.run()
+        });
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::new("amount".to_string(), FactValue::Number(5.0)));
+        let context = EvalContext::new().with_value("plan", FactValue::String("trial".to_string()));
+        let result = engine.execute_with_context(&mut facts, context).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["Audit"]);
+        assert!(facts.contains("audited"));
+        assert!(!facts.contains("refunded"));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "before Audit",
+                "after Audit",
+                "before Refund",
+                "after Refund"
+            ]
+        );
+    }
+
+    #[cfg(feature = "script")]
+    #[test]
+    fn test_script_actions_run_in_script_engine() {
+        // Understands `name = other * factor` and `drop name`, one per line
+        #[derive(Debug)]
+        struct Arithmetic;
+
+        impl ScriptEngine for Arithmetic {
+            fn run(
+                &self,
+                source: &str,
+                facts: &mut HashMap<String, FactValue>,
+            ) -> std::result::Result<(), EngineError> {
+                for line in source.lines().map(str::trim) {
+                    if let Some(name) = line.strip_prefix("drop ") {
+                        facts.remove(name);
+                    } else if let Some((name, expr)) = line.split_once(" = ") {
+                        let (other, factor) = expr.split_once(" * ").unwrap();
+                        let value =
+                            facts[other].as_number().unwrap() * factor.parse::<f64>().unwrap();
+                        facts.insert(name.to_string(), FactValue::Number(value));
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Scripted {
+                    when price > 0
+                    then
+                        script """
+                            doubled = price * 2
+                            drop coupon
+                        """;
+                        done = true;
+                }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.then_actions[0],
+            Expression::Script(
+                "doubled = price * 2\n                            drop coupon".to_string()
+            )
+        );
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::new("price".to_string(), FactValue::Number(4.0)));
+        facts.insert(Fact::new("coupon".to_string(), FactValue::Boolean(true)));
+
+        // Not a script the built-in interpreter understands
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule.clone()).unwrap();
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::EvaluationError(message)))
+                if message.starts_with("Invalid script at line 2")
+        ));
+
+        let mut engine = RuleEngine::builder()
+            .script_engine(Arc::new(Arithmetic))
+            .build();
+        engine.add_rule(rule).unwrap();
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.facts_modified, vec!["coupon", "doubled", "done"]);
+        assert_eq!(facts.get("doubled").unwrap().value, FactValue::Number(8.0));
+        assert!(!facts.contains("coupon"));
+        assert!(facts.contains("done"));
+    }
+
+    #[cfg(feature = "script")]
+    #[test]
+    fn test_script_interpreter() {
+        let run = |source: &str, facts: &mut HashMap<String, FactValue>| {
+            ScriptInterpreter::new()
+                .max_operations(1000)
+                .run(source, facts)
+        };
+        let item = |price: i64, qty: i64| {
+            FactValue::Object(HashMap::from([
+                ("price".to_string(), FactValue::Integer(price)),
+                ("qty".to_string(), FactValue::Integer(qty)),
+            ]))
+        };
+        let mut facts = HashMap::from([
+            (
+                "order".to_string(),
+                FactValue::Object(HashMap::from([
+                    (
+                        "items".to_string(),
+                        FactValue::Array(vec![item(10, 2), item(99, 0), item(5, 3)]),
+                    ),
+                    ("tags".to_string(), FactValue::Array(Vec::new())),
+                ])),
+            ),
+            ("coupon".to_string(), FactValue::Boolean(true)),
+        ]);
+        run(
+            r#"
+            // Skips empty lines and stops at the first cheap item
+            let total = 0;
+            for item in order.items {
+                if item.qty == 0 { continue; }
+                total += item.price * item.qty;
+                if item.price < 10 { break }
+            }
+            order.total = total;
+            order.tags.push("priced");
+            order["note"] = "x".toUpper();
+            if total > 1000 { size = "large"; } else if total > 20 { size = "medium"; } else { size = "small"; }
+            let n = 0;
+            let squares = [];
+            while n < 4 { squares.push(n * n); n += 1; }
+            for i in 0..=2 { squares[i] -= 1; }
+            summary = #{ "squares": squares, count: len(squares), odd: 3 % 2 == 1 && !false };
+            retract("coupon");
+            "#,
+            &mut facts,
+        )
+        .unwrap();
+
+        let order = &facts["order"];
+        assert_eq!(order.get_path("total"), Some(&FactValue::Integer(35)));
+        assert_eq!(
+            order.get_path("tags"),
+            Some(&FactValue::Array(vec![FactValue::String(
+                "priced".to_string()
+            )]))
+        );
+        assert_eq!(
+            order.get_path("note"),
+            Some(&FactValue::String("X".to_string()))
+        );
+        assert_eq!(facts["size"], FactValue::String("medium".to_string()));
+        assert_eq!(
+            facts["summary"],
+            FactValue::Object(HashMap::from([
+                (
+                    "squares".to_string(),
+                    FactValue::Array([-1, 0, 3, 9].into_iter().map(FactValue::Integer).collect()),
+                ),
+                ("count".to_string(), FactValue::Integer(4)),
+                ("odd".to_string(), FactValue::Boolean(true)),
+            ]))
+        );
+        // `let` variables do not become facts
+        assert!(!facts.contains_key("total") && !facts.contains_key("n"));
+        assert!(!facts.contains_key("coupon"));
+
+        // Keywords are only keywords on their own
+        run("letter = 1; iffy = letter + 1", &mut facts).unwrap();
+        assert_eq!(facts["iffy"], FactValue::Integer(2));
+
+        assert!(matches!(
+            run("while true { }", &mut facts),
+            Err(EngineError::ResourceLimit(_))
+        ));
+        assert!(matches!(
+            run("x = missing + 1", &mut facts),
+            Err(EngineError::UnknownVariable(name)) if name == "missing"
+        ));
+        assert!(matches!(
+            run("x = nope(1)", &mut facts),
+            Err(EngineError::UnknownFunction(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            run("break;", &mut facts),
+            Err(EngineError::EvaluationError(_))
+        ));
+        assert!(matches!(
+            run("x = 1 +;", &mut facts),
+            Err(EngineError::EvaluationError(message)) if message.starts_with("Invalid script at line 1, column 8")
+        ));
+    }
+
+    #[cfg(not(feature = "script"))]
+    #[test]
+    fn test_script_actions_need_script_feature() {
+        assert_eq!(
+            parser::GrlParser::new()
+                .parse_rule(r#"rule Scripted { when x > 1 then script """y = 2"""; }"#),
+            Err(ParseError::FeatureDisabled(
+                "script actions".to_string(),
+                "script".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_aggregate_builtins_over_arrays() {
+        let item = |price: f64| {
+            FactValue::Object(HashMap::from([(
+                "price".to_string(),
+                FactValue::Number(price),
+            )]))
+        };
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "cart".to_string(),
+            HashMap::from([
+                (
+                    "items".to_string(),
+                    FactValue::Array(vec![item(30.0), item(90.0), item(60.0)]),
+                ),
+                ("coupons".to_string(), FactValue::Array(Vec::new())),
+            ]),
+        ));
+
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Stats { when sum(cart.items, "price") >= 180 && count(cart.items) == 3 then total = sum(cart.items, "price"); average = avg(cart.items, "price"); cheapest = min(cart.items, "price"); priciest = max(cart.items, "price"); discount = avg(cart.coupons); }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["Stats"]);
+        for (name, value) in [
+            ("total", FactValue::Number(180.0)),
+            ("average", FactValue::Number(60.0)),
+            ("cheapest", FactValue::Number(30.0)),
+            ("priciest", FactValue::Number(90.0)),
+            ("discount", FactValue::Null),
+        ] {
+            assert_eq!(facts.get(name).unwrap().value, value, "{}", name);
+        }
+
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Bad { when sum(cart.items) > 0 then x = 1; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::TypeError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_accumulate_aggregates_matching_facts() {
+        let mut facts = WorkingMemory::new();
+        for (name, id) in [("alice", "a"), ("bob", "b")] {
+            facts.insert(
+                Fact::from_object(
+                    name.to_string(),
+                    HashMap::from([("id".to_string(), FactValue::String(id.to_string()))]),
+                )
+                .with_type("Customer".to_string()),
+            );
+        }
+        for (name, customer, amount) in [("o1", "a", 80.0), ("o2", "a", 40.0), ("o3", "b", 70.0)] {
+            facts.insert(
+                Fact::from_object(
+                    name.to_string(),
+                    HashMap::from([
+                        (
+                            "customer".to_string(),
+                            FactValue::String(customer.to_string()),
+                        ),
+                        ("amount".to_string(), FactValue::Number(amount)),
+                    ]),
+                )
+                .with_type("Order".to_string()),
+            );
+        }
+
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                "rule Vip { when c: Customer; total := accumulate(o: Order(o.customer == c.id), sum(o.amount)); orders := accumulate(o: Order(o.customer == c.id), count()); total > 100 then c.total = total; c.orders = orders; }",
+            )
+            .unwrap();
+        assert_eq!(rule.accumulates.len(), 2);
+        assert_eq!(
+            rule.accumulates[1].value,
+            Expression::Variable("o".to_string())
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.rules_fired, vec!["Vip"]);
+        let alice = facts.get("alice").unwrap();
+        assert_eq!(alice.get_field("total"), Some(&FactValue::Number(120.0)));
+        assert_eq!(alice.get_field("orders"), Some(&FactValue::Number(2.0)));
+        assert_eq!(facts.get("bob").unwrap().get_field("total"), None);
+    }
+
+    #[test]
+    fn test_numeric_policy() {
+        let parser = parser::GrlParser::new();
+        let rules = [
+            "rule Balanced { when debit + credit == 0.3 then balanced = true; }",
+            "rule Ratio { when debit / credit > 0 then ratio = debit / credit; }",
+        ];
+        let facts = || {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::new("debit".to_string(), FactValue::Number(0.1)));
+            facts.insert(Fact::new("credit".to_string(), FactValue::Number(0.2)));
+            facts
+        };
+        let engine_with = |numeric: NumericPolicy| {
+            let mut engine = RuleEngine::builder().numeric(numeric).build();
+            for rule in rules {
+                engine.add_rule(parser.parse_rule(rule).unwrap()).unwrap();
+            }
+            engine
+        };
+
+        // 0.1 + 0.2 != 0.3 exactly
+        let mut exact = facts();
+        engine_with(NumericPolicy::default())
+            .execute(&mut exact)
+            .unwrap();
+        assert!(!exact.contains("balanced"));
+
+        let mut tolerant = facts();
+        let engine = engine_with(NumericPolicy {
+            epsilon: Some(1e-9),
+            ..NumericPolicy::default()
+        });
+        engine.execute(&mut tolerant).unwrap();
+        assert!(tolerant.contains("balanced"));
+
+        let mut nan = facts();
+        nan.update("debit", FactValue::Number(f64::NAN));
+        engine_with(NumericPolicy::default())
+            .execute(&mut nan)
+            .unwrap();
+        assert!(!nan.contains("ratio"));
+        let strict = engine_with(NumericPolicy {
+            nan: NanHandling::Error,
+            ..NumericPolicy::default()
+        });
+        assert!(matches!(
+            strict.execute(&mut nan),
+            Err(RunesError::Engine(EngineError::InvalidNumber(_)))
+        ));
+
+        let mut overflow = facts();
+        overflow.update("debit", FactValue::Number(f64::MAX));
+        overflow.update("credit", FactValue::Number(0.5));
+        let finite = engine_with(NumericPolicy {
+            reject_infinity: true,
+            ..NumericPolicy::default()
+        });
+        assert!(matches!(
+            finite.execute(&mut overflow),
+            Err(RunesError::Engine(EngineError::InvalidNumber(_)))
+        ));
+    }
+
+    #[test]
+    fn test_string_ordering_comparisons() {
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Range { when code >= "A100" && code < "B000" then in_range = true; }"#,
+            )
+            .unwrap();
+        let run = |collation: Collation, code: &str| {
+            let mut engine = RuleEngine::builder().collation(collation).build();
+            engine.add_rule(rule.clone()).unwrap();
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::string_fact("code".to_string(), code.to_string()));
+            engine.execute(&mut facts).unwrap();
+            facts.contains("in_range")
+        };
+
+        assert!(run(Collation::Binary, "A100"));
+        assert!(run(Collation::Binary, "AZ"));
+        assert!(!run(Collation::Binary, "B000"));
+        assert!(!run(Collation::Binary, "a500"));
+        assert!(run(Collation::CaseInsensitive, "a500"));
+    }
+
+    #[test]
+    fn test_optional_chaining_and_null_coalescing() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Shipping { when customer?.address?.zip ?? "none" != "none" then zip = customer?.address?.zip; fee = surcharge ?? 0; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let customer = |address: Option<&str>| {
+            let mut fields = HashMap::new();
+            if let Some(zip) = address {
+                fields.insert(
+                    "address".to_string(),
+                    FactValue::Object(HashMap::from([(
+                        "zip".to_string(),
+                        FactValue::String(zip.to_string()),
+                    )])),
+                );
+            }
+            Fact::from_object("customer".to_string(), fields)
+        };
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(customer(Some("12345")));
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            facts.get("zip").unwrap().value,
+            FactValue::String("12345".to_string())
+        );
+        assert_eq!(facts.get("fee").unwrap().value, FactValue::Number(0.0));
+
+        // No address, and then no customer at all: no errors, the rule just doesn't match
+        let mut facts = WorkingMemory::new();
+        facts.insert(customer(None));
+        engine.execute(&mut facts).unwrap();
+        assert!(!facts.contains("zip"));
+        let mut facts = WorkingMemory::new();
+        engine.execute(&mut facts).unwrap();
+        assert!(!facts.contains("zip"));
+    }
+
+    #[test]
+    fn test_if_actions_in_then_block() {
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Tier {
+                    when order.total > 0
+                    then
+                        if (order.total > 100) { tier = "gold"; perk = true; }
+                        else if (order.total > 50) { tier = "silver"; }
+                        else { tier = "bronze"; }
+                        checked = true;
+                }"#,
+            )
+            .unwrap();
+        assert_eq!(rule.then_actions.len(), 2);
+        assert!(
+            matches!(&rule.then_actions[0], Expression::If(_, then_actions, else_actions)
+            if then_actions.len() == 2 && matches!(else_actions[..], [Expression::If(_, _, _)]))
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        for (total, tier) in [(150.0, "gold"), (75.0, "silver"), (10.0, "bronze")] {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::from_object(
+                "order".to_string(),
+                HashMap::from([("total".to_string(), FactValue::Number(total))]),
+            ));
+            let result = engine.execute(&mut facts).unwrap();
+
+            assert_eq!(
+                facts.get("tier").unwrap().value,
+                FactValue::String(tier.to_string())
+            );
+            assert_eq!(facts.contains("perk"), tier == "gold");
+            assert!(facts.contains("checked"));
+            assert_eq!(
+                result.facts_modified.contains(&"perk".to_string()),
+                tier == "gold"
+            );
+        }
+    }
+
+    #[test]
+    fn test_foreach_modifies_array_items_in_place() {
+        let item = |price: f64| {
+            FactValue::Object(HashMap::from([(
+                "price".to_string(),
+                FactValue::Number(price),
+            )]))
+        };
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "cart".to_string(),
+            HashMap::from([(
+                "items".to_string(),
+                FactValue::Array(vec![item(10.0), item(200.0)]),
+            )]),
+        ));
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                "rule Tax { when count(cart.items) > 0 then foreach item in cart.items { item.taxed = true; if (item.price > 100) { item.luxury = true; } } done = true; }",
+            )
+            .unwrap();
+        assert!(RuleDependencies::of(&rule)
+            .writes
+            .iter()
+            .all(|write| write.fact != "item"));
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(result.facts_modified, vec!["cart", "done"]);
+        let Some(FactValue::Array(items)) = facts.get("cart").unwrap().get_field("items") else {
+            panic!("items should still be an array");
+        };
+        for (item, luxury) in items.iter().zip([false, true]) {
+            let item = item.as_object().unwrap();
+            assert_eq!(item.get("taxed"), Some(&FactValue::Boolean(true)));
+            assert_eq!(item.contains_key("luxury"), luxury);
+        }
+        assert!(!facts.contains("item"));
+    }
+
+    #[test]
+    fn test_let_declares_locals_in_then_block() {
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                "rule Discount { when order.total > 50 then let discount = order.total * 0.1; let discount = discount + 1; order.total = order.total - discount; }",
+            )
+            .unwrap();
+        let deps = RuleDependencies::of(&rule);
+        assert!(deps.reads.iter().all(|read| read.fact != "discount"));
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::Number(100.0))]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+
+        assert_eq!(
+            facts.get("order").unwrap().get_field("total"),
+            Some(&FactValue::Number(89.0))
+        );
+        assert!(!facts.contains("discount"));
+        assert_eq!(result.facts_modified, vec!["order"]);
+    }
+
+    #[test]
+    fn test_audit_log_records_every_execution() {
+        #[derive(Clone, Default)]
+        struct Recorder {
+            records: Arc<std::sync::Mutex<Vec<AuditRecord>>>,
+            fail: bool,
+        }
+
+        impl AuditWriter for Recorder {
+            fn write(&self, record: &AuditRecord) -> std::io::Result<()> {
+                if self.fail {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                self.records.lock().unwrap().push(record.clone());
+                Ok(())
+            }
+        }
+
+        let rule = parser::GrlParser::new()
+            .parse_rule("rule Approve { when score > 700 then approved = true; }")
+            .unwrap();
+        let recorder = Recorder::default();
+        let mut engine = RuleEngine::builder()
+            .clock(Arc::new(ManualClock::new(1_000)))
+            .audit_log(AuditLog::new(recorder.clone()))
+            .build();
+        engine.add_rule(rule.clone()).unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("score".to_string(), 720.0));
+        engine.execute(&mut facts).unwrap();
+
+        let records = recorder.records.lock().unwrap().clone();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].timestamp_ms, 1_000);
+        assert_eq!(
+            records[0].input,
+            std::collections::BTreeMap::from([("score".to_string(), FactValue::Number(720.0))])
+        );
+        assert_eq!(records[0].rules_fired, vec!["Approve"]);
+        assert_eq!(records[0].changes.len(), 1);
+        assert_eq!(records[0].changes[0].fact, "approved");
+        assert_eq!(records[0].error, None);
+
+        let writer = JsonLinesWriter::new(Vec::new());
+        writer.write(&records[0]).unwrap();
+        let line = String::from_utf8(writer.into_inner()).unwrap();
+        let parsed: AuditRecord = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(parsed, records[0]);
+
+        // Decisions that cannot be audited are not made
+        let mut engine = RuleEngine::builder()
+            .audit_log(AuditLog::new(Recorder {
+                fail: true,
+                ..Recorder::default()
+            }))
+            .build();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::number_fact("score".to_string(), 720.0));
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::Audit(_)))
+        ));
+        assert!(!facts.contains("approved"));
+    }
+
+    #[test]
+    fn test_integer_facts_keep_precision() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for source in [
+            "rule Next { when id > 0 then next = id + 1; }",
+            "rule Parity { when id % 2 == 1 then odd = true; }",
+            "rule Mixed { when id > 1.5 then half = id / 2; scaled = id * 1.5; }",
+        ] {
+            engine.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::integer_fact("id".to_string(), 9_007_199_254_740_993));
+        engine.execute(&mut facts).unwrap();
+
+        // Integer arithmetic is exact beyond f64's 2^53 limit...
+        assert_eq!(
+            facts.get("next").unwrap().value,
+            FactValue::Integer(9_007_199_254_740_994)
+        );
+        assert_eq!(facts.get("odd").unwrap().value, FactValue::Boolean(true));
+        // ...while dividing, or mixing in a fractional Number, promotes to f64
+        assert_eq!(
+            facts.get("half").unwrap().value,
+            FactValue::Number(9_007_199_254_740_993.0 / 2.0)
+        );
+        assert_eq!(
+            facts.get("scaled").unwrap().value,
+            FactValue::Number(9_007_199_254_740_993.0 * 1.5)
+        );
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule("rule Overflow { when big > 0 then big = big * 2; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::integer_fact("big".to_string(), i64::MAX));
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::InvalidNumber(_)))
+        ));
+    }
+
+    #[test]
+    fn test_datetime_values_and_builtins() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .clock(Arc::new(ManualClock::new(1_709_290_800_000))) // 2024-03-01T11:00:00Z
+            .build();
+        for source in [
+            "rule Overdue { when invoice.due < now() then overdue = true; }",
+            r#"rule Leap { when date("2024-02-29T12:00:00+02:00") == invoice.due then parts = year(invoice.due) * 10000 + month(invoice.due) * 100 + day(invoice.due); hour = hour(invoice.due); }"#,
+            r#"rule Weekday { when weekday(now()) == 5 then friday = true; }"#,
+        ] {
+            engine.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut invoice = HashMap::new();
+        invoice.insert(
+            "due".to_string(),
+            FactValue::DateTime(1_709_200_800_000), // 2024-02-29T10:00:00Z
+        );
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object("invoice".to_string(), invoice));
+        engine.execute(&mut facts).unwrap();
+
+        assert_eq!(
+            facts.get("overdue").unwrap().value,
+            FactValue::Boolean(true)
+        );
+        assert_eq!(
+            facts.get("parts").unwrap().value,
+            FactValue::Integer(20240229)
+        );
+        assert_eq!(facts.get("hour").unwrap().value, FactValue::Integer(10));
+        assert_eq!(facts.get("friday").unwrap().value, FactValue::Boolean(true));
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(r#"rule Bad { when date("2023-02-29") == now() then x = 1; }"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(engine.execute(&mut WorkingMemory::new()).is_err());
+
+        // Out-of-range input is an invalid date, not a panic
+        for text in [
+            "99999999999-01-01",
+            "2024-01-01T00:00:00+99999999999999:00",
+            "2024-01-01T00:00:00+24:00",
+            "2024-01-01T-1:00:00Z",
+        ] {
+            assert!(
+                crate::builtins::call("date", &[text.into()], &SystemClock)
+                    .unwrap()
+                    .is_err(),
+                "{}",
+                text
+            );
+        }
+        for millis in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300, -1e19] {
+            assert!(
+                crate::builtins::call("date", &[FactValue::Number(millis)], &SystemClock)
+                    .unwrap()
+                    .is_err(),
+                "{}",
+                millis
+            );
+        }
+        assert_eq!(
+            crate::builtins::call("date", &[FactValue::Number(1.5e12)], &SystemClock)
+                .unwrap()
+                .unwrap(),
+            FactValue::DateTime(1_500_000_000_000)
+        );
+    }
+
+    #[test]
+    fn test_duration_arithmetic_and_literals() {
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                "rule Sla { when now() - ticket.opened > 2h then breached = true; \
+                 deadline = ticket.opened + 30d; waited = now() - ticket.opened - 90m; }",
+            )
+            .unwrap();
+        let mut engine = RuleEngine::builder()
+            .clock(Arc::new(ManualClock::new(3 * 3_600_000)))
+            .build();
+        engine.add_rule(rule).unwrap();
+
+        let mut ticket = HashMap::new();
+        ticket.insert("opened".to_string(), FactValue::DateTime(0));
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object("ticket".to_string(), ticket));
+        engine.execute(&mut facts).unwrap();
+
+        assert_eq!(
+            facts.get("breached").unwrap().value,
+            FactValue::Boolean(true)
+        );
+        assert_eq!(
+            facts.get("deadline").unwrap().value,
+            FactValue::DateTime(30 * 86_400_000)
+        );
+        assert_eq!(
+            facts.get("waited").unwrap().value,
+            FactValue::Duration(90 * 60_000)
+        );
+
+        // Durations do not mix with plain numbers
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser::GrlParser::new()
+                    .parse_rule("rule Bad { when 2h > 7200000 then x = 1; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut WorkingMemory::new()),
+            Err(RunesError::Engine(EngineError::TypeError(_)))
+        ));
+
+        // Literals too long for a duration are rejected rather than wrapped
+        assert_eq!(
+            parser::parse_duration_millis("9223372036854775807ms"),
+            Some(i64::MAX as u64)
+        );
+        assert_eq!(parser::parse_duration_millis("9223372036854775808ms"), None);
+        assert!(parser::GrlParser::new()
+            .parse_rule("rule Far { when x > 200000000000000d then y = 1; }")
+            .is_err());
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(Rule::new(
+                "Far".to_string(),
+                0,
+                Expression::GreaterThan(
+                    Box::new(Expression::Duration(u64::MAX)),
+                    Box::new(Expression::Duration(0)),
+                ),
+                vec![Expression::Assignment(
+                    "y".to_string(),
+                    Box::new(Expression::Number(1.0)),
+                )],
+            ))
+            .unwrap();
+        assert!(matches!(
+            engine.execute(&mut WorkingMemory::new()),
+            Err(RunesError::Engine(EngineError::EvaluationError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_decimal_arithmetic_is_exact() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for source in [
+            r#"rule Total { when order.price > 0 then total = order.price * 3 + decimal("0.20"); }"#,
+            "rule Exact { when order.price + 0.2 == 0.3 then exact = true; }",
+            "rule Split { when order.price > 0 then share = order.price / 3; }",
+        ] {
+            engine.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut order = HashMap::new();
+        order.insert(
+            "price".to_string(),
+            FactValue::Decimal("0.10".parse().unwrap()),
+        );
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object("order".to_string(), order));
+        engine.execute(&mut facts).unwrap();
+
+        let total = facts.get("total").unwrap().value.clone();
+        assert_eq!(total, FactValue::Decimal("0.5".parse().unwrap()));
+        let FactValue::Decimal(total) = total else {
+            unreachable!()
+        };
+        assert_eq!(total.to_string(), "0.50");
+        assert_eq!(facts.get("exact").unwrap().value, FactValue::Boolean(true));
+        assert_eq!(
+            facts.get("share").unwrap().value,
+            FactValue::Decimal("0.0333333333333333333333333333".parse().unwrap())
+        );
+
+        // Serialized as a string so no precision is lost in transit
+        let json = serde_json::to_string(&FactValue::Decimal(total)).unwrap();
+        assert_eq!(json, r#"{"Decimal":"0.50"}"#);
+        let parsed: FactValue = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, FactValue::Decimal(total));
+        assert!("1.2.3".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn test_fact_dot_paths() {
+        let mut fact = Fact::from_object("order".to_string(), HashMap::new());
+        assert!(fact
+            .set_path(
+                "customer.address.zip",
+                FactValue::String("02139".to_string()),
+                false
+            )
+            .is_err());
+        fact.set_path(
+            "customer.address.zip",
+            FactValue::String("02139".to_string()),
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            fact.get_path("customer.address.zip"),
+            Some(&FactValue::String("02139".to_string()))
+        );
+        assert!(fact.get_path("customer.address.zip.plus4").is_none());
+        assert!(fact
+            .set_path("customer.address.zip.plus4", FactValue::Null, true)
+            .is_err());
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Ship { when order.customer.address.zip == "02139" then order.shipping.zone = "local"; }"#,
+            )
+            .unwrap();
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(fact);
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            facts.get("order").unwrap().get_path("shipping.zone"),
+            Some(&FactValue::String("local".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_array_operations() {
+        let item = |sku: &str| {
+            FactValue::Object(HashMap::from([(
+                "sku".to_string(),
+                FactValue::String(sku.to_string()),
+            )]))
+        };
+        let tags = |tags: &[&str]| {
+            FactValue::Array(
+                tags.iter()
+                    .map(|t| FactValue::String(t.to_string()))
+                    .collect(),
+            )
+        };
+        let mut cart = HashMap::new();
+        cart.insert(
+            "items".to_string(),
+            FactValue::Array(vec![item("A"), item("B"), item("C")]),
+        );
+        cart.insert("tags".to_string(), tags(&["new", "vip"]));
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Bulk {
+                    when len(cart.items) > 2 && contains(cart.tags, "vip") && cart.items[-1].sku == "C"
+                    then push(cart.tags, "bulk"); remove(cart.tags, "new");
+                         first = cart.items[0].sku; rest = slice(cart.items, 1);
+                }"#,
+            )
+            .unwrap();
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object("cart".to_string(), cart));
+        engine.execute(&mut facts).unwrap();
+
+        assert_eq!(
+            facts.get("cart").unwrap().get_field("tags"),
+            Some(&tags(&["vip", "bulk"]))
+        );
+        assert_eq!(
+            facts.get("first").unwrap().value,
+            FactValue::String("A".to_string())
+        );
+        assert_eq!(
+            facts.get("rest").unwrap().value,
+            FactValue::Array(vec![item("B"), item("C")])
+        );
+
+        let rule = parser::GrlParser::new()
+            .parse_rule("rule Oob { when cart.items[3 + 1].sku == \"E\" then x = 1; }")
+            .unwrap();
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::EvaluationError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_json_conversion() {
+        let fact = Fact::from_json(
+            "order".to_string(),
+            r#"{"id": 9007199254740993, "total": 12.5, "paid": false, "lines": [{"sku": "A"}], "note": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            fact.get_field("id"),
+            Some(&FactValue::Integer(9_007_199_254_740_993))
+        );
+        assert_eq!(fact.get_field("total"), Some(&FactValue::Number(12.5)));
+        assert_eq!(fact.get_path("note"), Some(&FactValue::Null));
+        assert!(Fact::from_json("bad".to_string(), "{").is_err());
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser::GrlParser::new()
+                    .parse_rule(r#"rule Due { when order.total > 10 && order.paid == false then order.due = date("2024-03-01T09:30:00Z"); }"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(fact);
+        engine.execute(&mut facts).unwrap();
+
+        let json: serde_json::Value = facts.get("order").unwrap().value.clone().into();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "id": 9007199254740993i64,
+                "total": 12.5,
+                "paid": false,
+                "lines": [{"sku": "A"}],
+                "note": null,
+                "due": "2024-03-01T09:30:00Z",
+            })
+        );
+        assert_eq!(
+            serde_json::Value::from(FactValue::Decimal("0.10".parse().unwrap())),
+            serde_json::json!("0.10")
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_into_fact() {
+        #[derive(IntoFact)]
+        struct Wheel {
+            pressure: f64,
+        }
+
+        #[derive(IntoFact)]
+        struct Car {
+            speed: f64,
+            #[fact(rename = "maxSpeed")]
+            max_speed: u32,
+            wheels: Vec<Wheel>,
+            owner: Option<String>,
+        }
+
+        let car = Car {
+            speed: 50.0,
+            max_speed: 120,
+            wheels: vec![Wheel { pressure: 2.2 }],
+            owner: None,
+        };
+        let fact = car.into_fact("car");
+        assert_eq!(fact.name, "car");
+        assert_eq!(fact.fact_type.as_deref(), Some("Car"));
+        assert_eq!(fact.get_field("speed"), Some(&FactValue::Number(50.0)));
+        assert_eq!(fact.get_field("maxSpeed"), Some(&FactValue::Integer(120)));
+        assert_eq!(fact.get_field("owner"), Some(&FactValue::Null));
+        assert_eq!(
+            fact.get_field("wheels"),
+            Some(&FactValue::Array(vec![FactValue::Object(HashMap::from([
+                ("pressure".to_string(), FactValue::Number(2.2))
+            ]))]))
+        );
+    }
+
+    #[test]
+    fn test_from_fact_extracts_structs() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Car {
+            #[serde(rename = "Speed")]
+            speed: f64,
+            #[serde(rename = "Gear")]
+            gear: u8,
+        }
+
+        let rule = parser::GrlParser::new()
+            .parse_rule("rule Accelerate { when car.Speed < 100 then car.Speed = car.Speed + 10; }")
+            .unwrap();
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_json("car".to_string(), r#"{"Speed": 50.0, "Gear": 3}"#).unwrap());
+        engine.execute(&mut facts).unwrap();
+
+        let car = Car::from_fact(facts.get("car").unwrap()).unwrap();
+        assert_eq!(
+            car,
+            Car {
+                speed: 60.0,
+                gear: 3
+            }
+        );
+
+        let error = Car::from_fact_value(&FactValue::Object(HashMap::from([
+            ("Speed".to_string(), FactValue::String("fast".to_string())),
+            ("Gear".to_string(), FactValue::Integer(3)),
+        ])))
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("invalid type: string \"fast\", expected f64"));
+    }
+
+    #[test]
+    fn test_fact_schema_validation() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        let schema = FactSchema::new()
+            .required("speed", FieldType::Number)
+            .optional("owner", FieldType::String);
+        engine.register_schema("car", schema.clone()).unwrap();
+        // The type checker cannot tell what a function returns
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Label { when car.speed > 100 then car.speed = trim("fast"); }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        // A rule the schema rules out is rejected, whether it is added after
+        // the schema or the schema is registered after it
+        let label = parser
+            .parse_rule(r#"rule Label2 { when car.speed > 100 then car.speed = "fast"; }"#)
+            .unwrap();
+        assert!(engine.add_rule(label.clone()).is_err());
+        let mut unchecked = RuleEngine::new();
+        unchecked.add_rule(label).unwrap();
+        assert!(unchecked.register_schema("car", schema.clone()).is_err());
+        let knowledge_base = unchecked.shared_knowledge_base();
+        assert!(engine.set_knowledge_base(knowledge_base).is_err());
+        assert!(engine.get_knowledge_base().get_rule("Label").is_some());
+
+        // A non-conforming input fact is rejected before any rule runs
+        let mut facts = WorkingMemory::new();
+        let mut car = HashMap::new();
+        car.insert("owner".to_string(), FactValue::Null);
+        facts.insert(Fact::from_object("car".to_string(), car.clone()));
+        let error = engine.execute(&mut facts).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Fact 'car' does not match its schema: missing field 'speed'"
+        );
+
+        // So is a rule writing a value of the wrong type, and the write is undone
+        car.insert("speed".to_string(), FactValue::Integer(120));
+        facts.insert(Fact::from_object("car".to_string(), car));
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::Validation { .. }))
+        ));
+        assert_eq!(
+            facts.get("car").unwrap().get_field("speed"),
+            Some(&FactValue::Integer(120))
+        );
+    }
+
+    #[test]
+    fn test_field_change_listeners() {
+        use std::sync::Mutex;
+
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Cap { when car.speed > 100 then car.speed = 100; car.capped = true; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut facts = WorkingMemory::new();
+        let sink = seen.clone();
+        facts.on_field_changed(move |change| sink.lock().unwrap().push(change.clone()));
+
+        // Direct writes are reported as they happen
+        let mut car = HashMap::new();
+        car.insert("speed".to_string(), FactValue::Number(120.0));
+        facts.insert(Fact::from_object("car".to_string(), car));
+        assert_eq!(seen.lock().unwrap().len(), 1);
+        seen.lock().unwrap().clear();
+
+        // Rule writes are reported per field once the execution commits
+        engine.execute(&mut facts).unwrap();
+        let changes = seen.lock().unwrap().clone();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].field.as_deref(), Some("capped"));
+        assert_eq!(changes[1].field.as_deref(), Some("speed"));
+        assert_eq!(changes[1].before, Some(FactValue::Number(120.0)));
+        assert_eq!(changes[1].after, Some(FactValue::Number(100.0)));
+
+        // Rolled back changes are never reported
+        seen.lock().unwrap().clear();
+        facts.checkpoint();
+        facts.update("car", FactValue::Null);
+        facts.rollback();
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fact_metadata() {
+        let parser = parser::GrlParser::new();
+        let clock = Arc::new(ManualClock::new(5_000));
+        let mut engine = RuleEngine::builder().clock(clock.clone()).build();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Approve { when order.total < 100 then order.approved = true; note = "auto"; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut order = HashMap::new();
+        order.insert("total".to_string(), FactValue::Number(50.0));
+        let mut facts = WorkingMemory::new();
+        facts.insert(
+            Fact::from_object("order".to_string(), order)
+                .with_source("orders-api")
+                .with_tag("web"),
+        );
+        engine.execute(&mut facts).unwrap();
+
+        let order = &facts.get("order").unwrap().metadata;
+        assert_eq!(order.created_at, None);
+        assert_eq!(order.updated_at, Some(5_000));
+        assert_eq!(order.source.as_deref(), Some("Approve"));
+        assert!(order.tags.contains("web"));
+
+        let note = &facts.get("note").unwrap().metadata;
+        assert_eq!(note.created_at, Some(5_000));
+        assert_eq!(note.source.as_deref(), Some("Approve"));
+    }
+
+    #[test]
+    fn test_execution_changes() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder().track_changes(true).build();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Ship { when order.status == "paid" then order.shipping.method = "express"; shipped = true; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(
+            Fact::from_json(
+                "order".to_string(),
+                r#"{"status": "paid", "shipping": {"method": "standard"}}"#,
+            )
+            .unwrap(),
+        );
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            result.changes,
+            vec![
+                FactChange {
+                    fact: "order".to_string(),
+                    path: Some("shipping.method".to_string()),
+                    old: Some(FactValue::String("standard".to_string())),
+                    new: Some(FactValue::String("express".to_string())),
+                },
+                FactChange {
+                    fact: "shipped".to_string(),
+                    path: None,
+                    old: None,
+                    new: Some(FactValue::Boolean(true)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fact_value_compare() {
+        use std::cmp::Ordering;
+
+        let numeric = CoercionPolicy::Numeric;
+        let five = FactValue::Integer(5);
+        assert_eq!(
+            five.compare(&FactValue::Number(5.0), numeric),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            FactValue::Decimal("4.99".parse().unwrap()).compare(&five, numeric),
+            Some(Ordering::Less)
+        );
+        assert_eq!(five.compare(&FactValue::Number(f64::NAN), numeric), None);
+        assert_eq!(
+            FactValue::Boolean(false).compare(&FactValue::Boolean(true), numeric),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            FactValue::Array(vec![five.clone()])
+                .compare(&FactValue::Array(vec![FactValue::Number(5.0)]), numeric),
+            Some(Ordering::Equal)
+        );
+
+        // Different types only compare when coercion is allowed
+        let text = FactValue::String(" 5 ".to_string());
+        assert_eq!(text.compare(&five, numeric), None);
+        assert_eq!(
+            text.compare(&five, CoercionPolicy::Lenient),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            FactValue::Boolean(true).compare(&five, CoercionPolicy::Lenient),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            FactValue::DateTime(0).compare(
+                &FactValue::String("1970-01-02".to_string()),
+                CoercionPolicy::Lenient
+            ),
+            Some(Ordering::Less)
+        );
+    }
+
+    #[test]
+    fn test_coercion_policies() {
+        let parser = parser::GrlParser::new();
+        let rule = parser
+            .parse_rule(
+                r#"rule Match { when form.age == 5 && form.agreed > 0 then matched = true; }"#,
+            )
+            .unwrap();
+        let mut form = HashMap::new();
+        form.insert("age".to_string(), FactValue::String("5".to_string()));
+        form.insert("agreed".to_string(), FactValue::Boolean(true));
+        let run = |coercion| {
+            let mut engine = RuleEngine::builder().coercion(coercion).build();
+            engine.add_rule(rule.clone()).unwrap();
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::from_object("form".to_string(), form.clone()));
+            engine
+                .execute(&mut facts)
+                .map(|_| facts.contains("matched"))
+        };
+
+        // "5" == 5 is false by default, and ordering a boolean is an error
+        assert!(matches!(
+            run(CoercionPolicy::Numeric),
+            Err(RunesError::Engine(EngineError::TypeError(_)))
+        ));
+        // Strict mode already rejects the equality
+        match run(CoercionPolicy::Strict) {
+            Err(RunesError::Engine(EngineError::TypeError(message))) => {
+                assert_eq!(message, "Cannot compare String with Number")
+            }
+            other => panic!("expected a type error, got {:?}", other),
+        }
+        assert!(run(CoercionPolicy::Lenient).unwrap());
+    }
+
+    #[test]
+    fn test_fact_value_from() {
+        let customer = FactValue::from(HashMap::from([
+            ("name".to_string(), FactValue::from("Ada")),
+            ("age".to_string(), 36.into()),
+            ("score".to_string(), 4.5.into()),
+            ("vip".to_string(), true.into()),
+            ("tags".to_string(), vec!["new", "web"].into()),
+        ]));
+        assert_eq!(
+            customer.get_path("name"),
+            Some(&FactValue::String("Ada".to_string()))
+        );
+        assert_eq!(customer.get_path("age"), Some(&FactValue::Integer(36)));
+        assert_eq!(customer.get_path("score"), Some(&FactValue::Number(4.5)));
+        assert_eq!(customer.get_path("vip"), Some(&FactValue::Boolean(true)));
+        assert_eq!(
+            customer.get_path("tags"),
+            Some(&FactValue::Array(vec![
+                FactValue::String("new".to_string()),
+                FactValue::String("web".to_string()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_field_indexes() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Link {
+                            when c: Customer(c.vip == true), o: Order(o.customerId == c.id)
+                            then o.priority = true;
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.add_index("Order", "customerId");
+        for (name, id, vip) in [("ada", 1, true), ("bob", 2, false)] {
+            let customer = HashMap::from([
+                ("id".to_string(), FactValue::from(id)),
+                ("vip".to_string(), vip.into()),
+            ]);
+            facts.insert(
+                Fact::from_object(name.to_string(), customer).with_type("Customer".to_string()),
+            );
+        }
+        for (name, customer_id) in [("o1", 1.0), ("o2", 2.0), ("o3", 1.0)] {
+            let order = HashMap::from([("customerId".to_string(), FactValue::from(customer_id))]);
+            facts.insert(Fact::from_object(name.to_string(), order).with_type("Order".to_string()));
+        }
+
+        // Integer ids find orders holding the equal Number
+        let ids = |facts: &WorkingMemory, id: i64| -> Vec<String> {
+            facts
+                .find("Order", "customerId", &FactValue::Integer(id))
+                .into_iter()
+                .map(|fact| fact.name.clone())
+                .collect()
+        };
+        assert_eq!(ids(&facts, 1), ["o1", "o3"]);
+
+        // The index follows writes and rollbacks
+        facts.checkpoint();
+        facts
+            .set_field("o3", "customerId".to_string(), FactValue::Integer(2))
+            .unwrap();
+        assert_eq!(ids(&facts, 1), ["o1"]);
+        facts.rollback();
+        assert_eq!(ids(&facts, 1), ["o1", "o3"]);
+
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Link", "Link"]);
+        assert_eq!(facts.get("o2").unwrap().get_field("priority"), None);
+    }
+
+    #[test]
+    fn test_working_memory_snapshots_are_independent() {
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::from(10))]),
+        ));
+        facts.insert(Fact::integer_fact("count".to_string(), 1));
+
+        let snapshot = facts.clone();
+        facts.checkpoint();
+        facts
+            .set_field("order", "total".to_string(), 20.into())
+            .unwrap();
+        facts.retract("count");
+
+        assert_eq!(
+            snapshot.get("order").unwrap().get_field("total"),
+            Some(&FactValue::Integer(10))
+        );
+        assert!(snapshot.contains("count"));
+
+        facts.rollback();
+        assert_eq!(
+            facts.get("order").unwrap().get_field("total"),
+            Some(&FactValue::Integer(10))
+        );
+        assert_eq!(facts.into_facts().len(), 2);
+    }
+
+    #[test]
+    fn test_namespaced_facts() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Settle {
+                            when billing::invoice.total > shipping::invoice.total
+                            then billing::invoice.settled = true; audit::note = "settled";
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        "rule Flag { when i: billing::Invoice(i.total > 20) then i.large = true; }",
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        for (name, total) in [("billing::invoice", 50), ("shipping::invoice", 10)] {
+            let invoice = HashMap::from([("total".to_string(), FactValue::from(total))]);
+            facts.insert(
+                Fact::from_object(name.to_string(), invoice)
+                    .with_type("billing::Invoice".to_string()),
+            );
+        }
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired.len(), 2);
+
+        let billing: Vec<&str> = facts
+            .in_namespace("billing")
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(billing, ["billing::invoice"]);
+        assert_eq!(
+            facts.get("billing::invoice").unwrap().get_field("settled"),
+            Some(&FactValue::Boolean(true))
+        );
+        assert_eq!(
+            facts.get("billing::invoice").unwrap().get_field("large"),
+            Some(&FactValue::Boolean(true))
+        );
+        assert_eq!(facts.get("audit::note").unwrap().namespace(), Some("audit"));
+        assert_eq!(
+            facts::split_namespace("eu::billing::invoice"),
+            (Some("eu::billing"), "invoice")
+        );
+    }
+
+    #[test]
+    fn test_bytes_values() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Verify {
+                            when len(upload.body) == 3 && sha256(upload.body) == upload.checksum
+                            then upload.encoded = base64_encode(upload.body); upload.decoded = base64_decode("aGk");
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let upload = HashMap::from([
+            ("body".to_string(), FactValue::Bytes(b"abc".to_vec())),
+            (
+                "checksum".to_string(),
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into(),
+            ),
+        ]);
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object("upload".to_string(), upload));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Verify"]);
+
+        let upload = facts.get("upload").unwrap();
+        assert_eq!(upload.get_field("encoded"), Some(&FactValue::from("YWJj")));
+        assert_eq!(
+            upload.get_field("decoded"),
+            Some(&FactValue::Bytes(b"hi".to_vec()))
+        );
+        assert_eq!(
+            serde_json::Value::from(FactValue::Bytes(b"hi!?".to_vec())),
+            serde_json::json!("aGkhPw==")
+        );
+    }
+
+    #[test]
+    fn test_declared_enums() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        let status: EnumType = "OrderStatus: NEW | PAID | SHIPPED".parse().unwrap();
+        assert_eq!(status.variants(), ["NEW", "PAID", "SHIPPED"]);
+        engine.register_enum(status);
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Ship {
+                            when order.status == OrderStatus.PAID
+                            then order.status = "SHIPPED";
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        let typo = parser
+            .parse_rule(
+                "rule Typo { when order.status == OrderStatus.SHIPED then order.done = true; }",
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.add_rule(typo),
+            Err(RunesError::Engine(EngineError::UnknownEnumVariant { .. }))
+        ));
+
+        let paid = FactValue::Enum {
+            enum_type: "OrderStatus".to_string(),
+            variant: "PAID".to_string(),
+        };
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("status".to_string(), paid)]),
+        ));
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            facts.get("order").unwrap().get_path("status"),
+            Some(&FactValue::Enum {
+                enum_type: "OrderStatus".to_string(),
+                variant: "SHIPPED".to_string(),
+            })
+        );
+
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(r#"rule Misspelt { when order.status == "shiped" then order.done = true; }"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(engine.execute(&mut facts).is_err());
+    }
+
+    #[test]
+    fn test_working_memory_json_round_trip() {
+        let mut facts = WorkingMemory::new();
+        let order = HashMap::from([
+            ("total".to_string(), FactValue::Number(0.1 + 0.2)),
+            (
+                "price".to_string(),
+                FactValue::Decimal("19.99".parse().unwrap()),
+            ),
+            ("placed".to_string(), FactValue::DateTime(1_700_000_000_000)),
+            ("digest".to_string(), FactValue::Bytes(vec![0, 255])),
+        ]);
+        facts.insert(
+            Fact::from_object("order".to_string(), order)
+                .with_type("Order".to_string())
+                .with_source("checkout")
+                .with_tag("priority"),
+        );
+        facts.insert(Fact::integer_fact("count".to_string(), i64::MAX));
+
+        let json = facts.to_json().unwrap();
+        let restored = WorkingMemory::from_json(&json).unwrap();
+        assert_eq!(restored.len(), 2);
+        let order = restored.get("order").unwrap();
+        assert_eq!(order.value, facts.get("order").unwrap().value);
+        assert_eq!(order.fact_type.as_deref(), Some("Order"));
+        assert_eq!(order.metadata.source.as_deref(), Some("checkout"));
+        assert!(order.metadata.tags.contains("priority"));
+        assert!(restored.changes().is_empty());
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_working_memory_binary_round_trip() {
+        let mut facts = WorkingMemory::new();
+        let order = HashMap::from([
+            ("total".to_string(), FactValue::Number(0.1 + 0.2)),
+            ("refund".to_string(), FactValue::Number(-1e-300)),
+            ("placed".to_string(), FactValue::DateTime(-1)),
+            ("digest".to_string(), FactValue::Bytes(vec![0, 255])),
+        ]);
+        facts.insert(
+            Fact::from_object("order".to_string(), order)
+                .with_type("Order".to_string())
+                .with_tag("priority"),
+        );
+        facts.insert(Fact::integer_fact("count".to_string(), i64::MIN));
+        facts.insert(Fact::integer_fact("limit".to_string(), i64::MAX));
+
+        let bytes = facts.to_binary().unwrap();
+        assert!(bytes.len() < facts.to_json().unwrap().len());
+        let restored = WorkingMemory::from_binary(&bytes).unwrap();
+        assert_eq!(restored.len(), 3);
+        for name in ["order", "count", "limit"] {
+            assert_eq!(
+                restored.get(name).unwrap().value,
+                facts.get(name).unwrap().value
+            );
+        }
+        let order = restored.get("order").unwrap();
+        assert_eq!(order.fact_type.as_deref(), Some("Order"));
+        assert!(order.metadata.tags.contains("priority"));
+
+        // Truncated, extended or foreign input is an error, not a panic
+        assert!(WorkingMemory::from_binary(&bytes[..bytes.len() - 1]).is_err());
+        assert!(WorkingMemory::from_binary(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(WorkingMemory::from_binary(b"{\"facts\": []}").is_err());
+        assert!(WorkingMemory::from_binary(b"RRB\x01\x07\xff\xff\xff\xff\x0f").is_err());
+    }
+
+    #[test]
+    fn test_field_provenance() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder()
+            .max_cycles(5)
+            .track_provenance(true)
+            .build();
+        for source in [
+            "rule Ship { when order.approved == true && order.status == \"NEW\" then order.status = \"SHIPPED\"; }",
+            "rule Approve { when order.total > 100 && order.approved == false then order.approved = true; }",
+        ] {
+            engine.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut facts = WorkingMemory::new();
+        let order = HashMap::from([
+            ("total".to_string(), FactValue::from(150)),
+            ("approved".to_string(), FactValue::from(false)),
+            ("status".to_string(), FactValue::from("NEW")),
+        ]);
+        facts.insert(Fact::from_object("order".to_string(), order));
+        let (_, report) = engine.execute_with_report(&mut facts).unwrap();
+
+        let order = facts.get("order").unwrap();
+        let approved = order.provenance("approved").unwrap();
+        assert_eq!((approved.rule.as_str(), approved.cycle), ("Approve", 1));
+        let status = order.provenance("status").unwrap();
+        assert_eq!((status.rule.as_str(), status.cycle), ("Ship", 2));
+        assert!(order.provenance("total").is_none());
+
+        let written: Vec<(&str, &str)> = report
+            .provenance
+            .iter()
+            .map(|p| (p.field.as_deref().unwrap(), p.rule.as_str()))
+            .collect();
+        assert_eq!(written, [("approved", "Approve"), ("status", "Ship")]);
+    }
+
+    #[test]
+    fn test_money_values() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Price {
+                            when cart.subtotal >= money("50", "USD")
+                            then cart.total = cart.subtotal * 0.9 + cart.shipping;
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let usd = |amount: &str| FactValue::Money {
+            amount: amount.parse().unwrap(),
+            currency: "USD".to_string(),
+        };
+        let mut facts = WorkingMemory::new();
+        let cart = HashMap::from([
+            ("subtotal".to_string(), usd("100.00")),
+            ("shipping".to_string(), usd("4.99")),
+        ]);
+        facts.insert(Fact::from_object("cart".to_string(), cart));
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            facts.get("cart").unwrap().get_path("total"),
+            Some(&usd("94.99"))
+        );
+
+        let mut facts = WorkingMemory::new();
+        let cart = HashMap::from([
+            ("subtotal".to_string(), usd("100.00")),
+            (
+                "shipping".to_string(),
+                FactValue::Money {
+                    amount: "4.99".parse().unwrap(),
+                    currency: "EUR".to_string(),
+                },
+            ),
+        ]);
+        facts.insert(Fact::from_object("cart".to_string(), cart));
+        assert!(matches!(
+            engine.execute(&mut facts),
+            Err(RunesError::Engine(EngineError::CurrencyMismatch(..)))
+        ));
+    }
+
+    #[test]
+    fn test_geo_distance() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Nearby {
+                            when distanceKm(driver.location, geopoint(48.8566, 2.3522)) < 5
+                            then driver.nearby = true;
+                        }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        for (lat, lon, nearby) in [(48.8606, 2.3376, true), (45.764, 4.8357, false)] {
+            let mut facts = WorkingMemory::new();
+            let location = FactValue::GeoPoint { lat, lon };
+            facts.insert(Fact::from_object(
+                "driver".to_string(),
+                HashMap::from([("location".to_string(), location)]),
+            ));
+            engine.execute(&mut facts).unwrap();
+            let fired = facts.get("driver").unwrap().get_path("nearby").is_some();
+            assert_eq!(fired, nearby);
+        }
+
+        let paris = FactValue::GeoPoint {
+            lat: 48.8566,
+            lon: 2.3522,
+        };
+        let lyon = FactValue::GeoPoint {
+            lat: 45.764,
+            lon: 4.8357,
+        };
+        let distance = builtins::call("distanceKm", &[paris, lyon], &SystemClock)
+            .unwrap()
+            .unwrap()
+            .as_number()
+            .unwrap();
+        assert!((distance - 391.5).abs() < 1.0, "{}", distance);
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::builder().three_valued_logic(true).build();
+        for source in [
+            "rule Adult { when customer.age >= 18 then customer.adult = true; }",
+            "rule Priority { when customer.age >= 65 || customer.vip == true then customer.priority = true; }",
+            "rule Excluded { when customer.age < 18 && customer.vip == true then customer.excluded = true; }",
+            "rule AskAge { when isDefined(customer.age) == false && isNull(customer.name) == false then customer.ask = true; }",
+        ] {
+            engine.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut facts = WorkingMemory::new();
+        let customer = HashMap::from([
+            ("name".to_string(), FactValue::from("Ada")),
+            ("vip".to_string(), FactValue::from(true)),
+        ]);
+        facts.insert(Fact::from_object("customer".to_string(), customer));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Priority", "AskAge"]);
+
+        let mut strict = RuleEngine::new();
+        let adult = parser
+            .parse_rule("rule Adult { when customer.age >= 18 then customer.adult = true; }")
+            .unwrap();
+        strict.add_rule(adult).unwrap();
+        assert!(strict.execute(&mut facts).is_err());
+    }
+
+    #[test]
+    fn test_knowledge_base_json_round_trip() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        for source in [
+            "rule Discount salience 10 { when order.total > 100 then order.discount = 0.1; }",
+            "rule Free { when order.total > 500 then order.shipping = 0; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let json = kb.to_json().unwrap();
+        let restored = Arc::new(KnowledgeBase::from_json(&json).unwrap());
+        assert_eq!(restored.get_rules(), kb.get_rules());
+        assert!(restored.get_dependencies("Free").is_some());
+
+        let engine = RuleEngine::with_knowledge_base(restored, EngineConfig::default()).unwrap();
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::from(600))]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Discount", "Free"]);
+
+        assert!(matches!(
+            KnowledgeBase::from_json("{\"rules\": 1}"),
+            Err(KnowledgeBaseError::InvalidFormat(_))
+        ));
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_knowledge_base_binary_round_trip() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        for source in [
+            "rule Discount salience 10 { when order.total > 100.25 then order.discount = 0.1; }",
+            "rule Free { when order.total > 500 then order.shipping = -1; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+        kb.define_ruleset("checkout", ["Free"]).unwrap();
+
+        let bytes = kb.to_binary().unwrap();
+        assert!(bytes.len() < kb.to_json().unwrap().len());
+        let restored = KnowledgeBase::from_binary(&bytes).unwrap();
+        assert_eq!(restored.get_rules(), kb.get_rules());
+        assert_eq!(restored.to_json().unwrap(), kb.to_json().unwrap());
+
+        assert!(matches!(
+            KnowledgeBase::from_binary(&bytes[..bytes.len() / 2]),
+            Err(KnowledgeBaseError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            KnowledgeBase::from_binary(kb.to_json().unwrap().as_bytes()),
+            Err(KnowledgeBaseError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_knowledge_base_migrations() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        kb.set_version(7);
+        kb.add_rule(
+            parser
+                .parse_rule("rule Discount salience 10 { when order.total > 100 then order.discount = 0.1; }")
+                .unwrap(),
+        )
+        .unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&kb.to_json().unwrap()).unwrap();
+        assert_eq!(saved["format_version"], FORMAT_VERSION);
+        assert_eq!(
+            KnowledgeBase::from_json(&saved.to_string())
+                .unwrap()
+                .version(),
+            7
+        );
+
+        // A rule set from an older format that called salience "priority"
+        let mut old = saved.clone();
+        old["format_version"] = serde_json::json!(FORMAT_VERSION - 1);
+        let rule = old["rules"][0].as_object_mut().unwrap();
+        let salience = rule.remove("salience").unwrap();
+        rule.insert("priority".to_string(), salience);
+
+        assert!(matches!(
+            KnowledgeBase::from_json(&old.to_string()),
+            Err(KnowledgeBaseError::MissingMigration(_))
+        ));
+        let migrations = Migrations::new().register(FORMAT_VERSION - 1, |mut rules| {
+            for rule in rules.as_array_mut().ok_or("expected an array of rules")? {
+                let rule = rule.as_object_mut().ok_or("expected a rule object")?;
+                let priority = rule.remove("priority").ok_or("missing priority")?;
+                rule.insert("salience".to_string(), priority);
+            }
+            Ok(rules)
+        });
+        let upgraded = KnowledgeBase::from_json_with(&old.to_string(), &migrations).unwrap();
+        assert_eq!(upgraded.get_rules(), kb.get_rules());
+        assert_eq!(upgraded.version(), 7);
+
+        let mut future = saved;
+        future["format_version"] = serde_json::json!(FORMAT_VERSION + 1);
+        assert!(matches!(
+            KnowledgeBase::from_json(&future.to_string()),
+            Err(KnowledgeBaseError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_rule_tags_and_metadata() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for (source, tags, owner) in [
+            (
+                "rule EuVat { when order.region == \"EU\" then order.vat = 0.2; }",
+                ["pricing", "eu"],
+                "tax-team",
+            ),
+            (
+                "rule UsShipping { when order.region == \"EU\" then order.shipping = 5; }",
+                ["shipping", "us"],
+                "logistics",
+            ),
+        ] {
+            let rule = parser
+                .parse_rule(source)
+                .unwrap()
+                .with_tags(tags)
+                .with_metadata("owner", owner);
+            engine.add_rule(rule).unwrap();
+        }
+
+        let kb = engine.get_knowledge_base();
+        let pricing: Vec<&str> = kb
+            .rules_with_tag("pricing")
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(pricing, ["EuVat"]);
+        let logistics: Vec<&str> = kb
+            .rules_with_metadata("owner", "logistics")
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(logistics, ["UsShipping"]);
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("region".to_string(), FactValue::from("EU"))]),
+        ));
+        let result = engine.execute_group("eu", &mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["EuVat"]);
+    }
+
+    #[test]
+    fn test_rule_packages() {
+        let parser = parser::GrlParser::new();
+        let rule = |source: &str| parser.parse_rule(source).unwrap();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_package(
+                "pricing",
+                [
+                    rule("rule Discount { when order.total > 100 then order.discount = 10; }"),
+                    rule("rule Vat { when order.total > 0 then order.vat = 20; }"),
+                ],
+            )
+            .unwrap();
+        engine
+            .add_package(
+                "shipping",
+                [rule(
+                    "rule FreeShipping { when order.total > 50 then order.shipping = 0; }",
+                )],
+            )
+            .unwrap();
+
+        // A package with a clashing rule is not added at all
+        let clash = engine.add_package(
+            "returns",
+            [
+                rule("rule Refund { when order.returned == true then order.refund = true; }"),
+                rule("rule Vat { when order.total > 0 then order.vat = 0; }"),
+            ],
+        );
+        assert!(clash.is_err());
+        assert!(engine.get_knowledge_base().get_rule("Refund").is_none());
+
+        let kb = engine.get_knowledge_base();
+        assert_eq!(
+            kb.packages().into_iter().collect::<Vec<_>>(),
+            ["pricing", "shipping"]
+        );
+        assert_eq!(kb.rules_in_package("pricing").count(), 2);
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::from(120))]),
+        ));
+        let result = engine.execute_package("shipping", &mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["FreeShipping"]);
+
+        let removed = engine.remove_package("pricing");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(engine.get_knowledge_base().len(), 1);
+    }
+
+    #[cfg(feature = "hot-reload")]
+    #[test]
+    fn test_hot_reload_from_grl_files() {
+        use std::sync::{mpsc, RwLock};
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("runes-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("pricing.grl"),
+            r#"rule Discount "10% off" { when order.total > 100 then order.discount = 10; }
+               rule Vat { when order.total > 0 then order.vat = 20; }"#,
+        )
+        .unwrap();
+
+        let watcher = KnowledgeBase::watch_dir(&dir).interval(Duration::from_millis(5));
+        let kb = watcher.load().unwrap();
+        assert_eq!(kb.rules_in_package("pricing").count(), 2);
+        let engine = Arc::new(RwLock::new(
+            RuleEngine::with_knowledge_base(Arc::new(kb), EngineConfig::default()).unwrap(),
+        ));
+
+        let (sender, reloads) = mpsc::channel();
+        let handle = watcher.start(engine.clone(), move |reload| {
+            let _ = sender.send(reload);
+        });
+
+        std::fs::write(dir.join("broken.grl"), "rule Broken { when").unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            reload.outcome,
+            Err(ReloadError::Load(LoadError::Files(_)))
+        ));
+        assert_eq!(engine.read().unwrap().get_knowledge_base().len(), 2);
+
+        std::fs::write(
+            dir.join("broken.grl"),
+            "rule Shipping { when order.total > 50 then order.shipping = 0; }",
+        )
+        .unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(reload.outcome.unwrap(), 3);
+
+        // Subdirectories are watched and loaded as `from_dir` loads them
+        std::fs::create_dir_all(dir.join("tax")).unwrap();
+        std::fs::write(
+            dir.join("tax").join("vat.grl"),
+            "rule Reduced { when order.total > 1000 then order.vat = 5; }",
+        )
+        .unwrap();
+        let reload = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(reload.outcome.unwrap(), 4);
+        assert_eq!(
+            engine
+                .read()
+                .unwrap()
+                .get_knowledge_base()
+                .rules_in_package("tax.vat")
+                .count(),
+            1
+        );
+        handle.stop();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::from(120))]),
+        ));
+        let result = engine.read().unwrap().execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired.len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rule_validation() {
+        let mut kb = KnowledgeBase::new();
+
+        let assigns_in_condition = Rule::new(
+            "Sneaky".to_string(),
+            0,
+            Expression::FieldAssignment(
+                "order".to_string(),
+                "total".to_string(),
+                Box::new(Expression::Number(0.0)),
+            ),
+            vec![],
+        );
+        assert!(matches!(
+            kb.add_rule(assigns_in_condition),
+            Err(KnowledgeBaseError::InvalidRule { .. })
+        ));
+
+        let empty = Rule::new(
+            "Empty".to_string(),
+            0,
+            Expression::Equal(
+                Box::new(Expression::String("5".to_string())),
+                Box::new(Expression::Number(5.0)),
+            ),
+            vec![],
+        );
+        assert_eq!(
+            kb.add_rule(empty).unwrap(),
+            [
+                RuleWarning::NoActions("Empty".to_string()),
+                RuleWarning::IncompatibleComparison {
+                    rule: "Empty".to_string(),
+                    left: "String",
+                    right: "Number",
+                },
+            ]
+        );
+
+        let scalar = parser::GrlParser::new()
+            .parse_rule("rule Scalar { when total > 100 then total.discount = 10; }")
+            .unwrap();
+        assert_eq!(
+            kb.add_rule(scalar).unwrap(),
+            [RuleWarning::FieldOfScalar {
+                rule: "Scalar".to_string(),
+                variable: "total".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dependency_graph_dot() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        for source in [
+            "rule Gold { when customer.spent > 1000 then customer.tier = \"gold\"; }",
+            "rule GoldDiscount { when customer.tier == \"gold\" then order.discount = 10; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let dot = kb.to_dot();
+        assert!(dot.starts_with("digraph rules {"));
+        assert!(dot.contains("\"rule Gold\" [shape=box];"));
+        assert!(dot.contains("\"field customer.tier\" [shape=ellipse, label=\"customer.tier\"];"));
+        assert!(dot.contains("\"rule Gold\" -> \"field customer.tier\" [label=\"writes\"];"));
+        assert!(dot.contains("\"field customer.tier\" -> \"rule GoldDiscount\" [label=\"reads\"];"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_knowledge_base_merge() {
+        let parser = parser::GrlParser::new();
+        let kb = |sources: &[&str]| {
+            let mut kb = KnowledgeBase::new();
+            for source in sources {
+                kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+            }
+            kb
+        };
+        let pricing = kb(&[
+            "rule Discount salience 5 { when order.total > 100 then order.discount = 10; }",
+            "rule Vat { when order.total > 0 then order.vat = 20; }",
+        ]);
+        let promotions = kb(&[
+            "rule Discount salience 8 { when order.total > 50 then order.discount = 15; }",
+            "rule Coupon { when order.coupon == \"SAVE\" then order.discount = 5; }",
+        ]);
+
+        let mut merged = pricing.clone();
+        assert_eq!(
+            merged.merge(promotions.clone(), MergePolicy::Error),
+            Err(KnowledgeBaseError::DuplicateRule("Discount".to_string()))
+        );
+        assert_eq!(merged.len(), 2);
+
+        merged
+            .merge(promotions.clone(), MergePolicy::KeepHigherSalience)
+            .unwrap();
+        let names: Vec<&str> = merged.get_rules().iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Discount", "Vat", "Coupon"]);
+        assert_eq!(merged.get_rule("Discount").unwrap().salience, 8);
+
+        let mut merged = promotions;
+        merged.merge(pricing, MergePolicy::PreferOther).unwrap();
+        assert_eq!(merged.get_rule("Discount").unwrap().salience, 5);
+        assert!(merged.get_dependencies("Vat").is_some());
+    }
+
+    #[test]
+    fn test_knowledge_base_diff() {
+        let parser = parser::GrlParser::new();
+        let kb = |sources: &[&str]| {
+            let mut kb = KnowledgeBase::new();
+            for source in sources {
+                kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+            }
+            kb
+        };
+        let old = kb(&[
+            "rule Discount { when order.total > 100 then order.discount = 10; }",
+            "rule Vat { when order.total > 0 then order.vat = 20; }",
+        ]);
+        let new = kb(&[
+            "rule Discount salience 5 { when order.total > 150 then order.discount = 10; order.flagged = true; }",
+            "rule Coupon { when order.coupon == \"SAVE\" then order.discount = 5; }",
+        ]);
+
+        let changes = KnowledgeBase::diff(&old, &new);
+        assert_eq!(changes.len(), 3);
+        let RuleChange::Modified { rule, edits } = &changes[0] else {
+            panic!("expected Discount to be modified: {:?}", changes[0]);
+        };
+        assert_eq!(rule, "Discount");
+        assert_eq!(edits[0], RuleEdit::Property("salience"));
+        assert!(matches!(
+            &edits[1],
+            RuleEdit::Expression { path, old: Some(_), new: Some(_) } if path == "when.1"
+        ));
+        assert!(matches!(
+            &edits[2],
+            RuleEdit::Expression { path, old: None, new: Some(_) } if path == "then[1]"
+        ));
+        assert_eq!(edits.len(), 3);
+        assert_eq!(changes[1], RuleChange::Removed("Vat".to_string()));
+        assert_eq!(changes[2], RuleChange::Added("Coupon".to_string()));
+        assert!(KnowledgeBase::diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_update_and_rename_rule() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        for source in [
+            "rule Discount { when order.total > 100 then order.discount = 10; }",
+            "rule Vat { when order.total > 0 then order.vat = 20; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let original = kb.get_rule("Discount").unwrap().clone();
+        let updated = parser
+            .parse_rule("rule Discount { when order.coupon == \"SAVE\" then order.discount = 15; }")
+            .unwrap();
+        let previous = kb.update_rule("Discount", updated).unwrap();
+        assert_eq!(previous, original);
+        let deps = kb.get_dependencies("Discount").unwrap();
+        assert!(deps
+            .reads
+            .iter()
+            .any(|read| read.field.as_deref() == Some("coupon")));
+
+        let previous = kb.rename_rule("Discount", "Promotion").unwrap();
+        assert_eq!(previous.name, "Discount");
+        assert!(kb.get_rule("Discount").is_none());
+        let names: Vec<&str> = kb.get_rules().iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Promotion", "Vat"]);
+        assert_eq!(kb.get_rule("Promotion").unwrap().salience, 0);
+
+        assert_eq!(
+            kb.rename_rule("Promotion", "Vat"),
+            Err(KnowledgeBaseError::DuplicateRule("Vat".to_string()))
+        );
+        assert_eq!(
+            kb.rename_rule("Missing", "Other").map(|_| ()),
+            Err(KnowledgeBaseError::RuleNotFound("Missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_rules_referencing_field() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        for source in [
+            "rule SpeedLimit { when TestCar.Speed > 100 then TestCar.Alert = true; }",
+            "rule Accelerate { when TestCar.Gear == 5 then TestCar.Speed = TestCar.Speed + 10; }",
+            "rule Fast { when car: TestCar(car.Speed > 200) then Log.fast = true; }",
+            "rule Fuel { when TestCar.Fuel < 10 then TestCar.Alert = true; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let names = |field: &str| -> Vec<String> {
+            kb.rules_referencing(field)
+                .map(|rule| rule.name.clone())
+                .collect()
+        };
+        assert_eq!(names("TestCar.Speed"), ["SpeedLimit", "Accelerate", "Fast"]);
+        assert_eq!(names("TestCar.Alert"), ["SpeedLimit", "Fuel"]);
+        assert_eq!(names("TestCar").len(), 4);
+        assert_eq!(names("Log"), ["Fast"]);
+        assert!(names("TestCar.Color").is_empty());
+    }
+
+    #[test]
+    fn test_knowledge_base_stats() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        assert_eq!(kb.stats(), KnowledgeBaseStats::default());
+        for source in [
+            "rule A salience 15 { when order.total > 100 then order.discount = 10; }",
+            "rule B salience 12 { when order.total > 0 && customer.vip == true then order.vat = 20; }",
+            "rule C { when customer.age > 18 then customer.adult = true; }",
+            "rule D { when log.enabled == true then log.done = true; }",
+        ] {
+            kb.add_rule(parser.parse_rule(source).unwrap()).unwrap();
+        }
+
+        let mut rule = kb.get_rule("C").unwrap().clone();
+        rule.salience = -3;
+        rule.then_actions.clear();
+        kb.update_rule("C", rule).unwrap();
+
+        let stats = kb.stats();
+        assert_eq!(stats.rule_count, 4);
+        assert_eq!(
+            stats.rules_per_salience_band,
+            std::collections::BTreeMap::from([(-10, 1), (0, 1), (10, 2)])
+        );
+        let depths: Vec<usize> = kb
+            .get_rules()
+            .iter()
+            .map(|rule| rule.when_condition.depth())
+            .collect();
+        assert_eq!(stats.max_condition_depth, *depths.iter().max().unwrap());
+        assert_eq!(
+            stats.average_condition_depth,
+            depths.iter().sum::<usize>() as f64 / 4.0
+        );
+        assert_eq!(
+            stats.most_referenced_facts,
+            [
+                ("customer".to_string(), 2),
+                ("order".to_string(), 2),
+                ("log".to_string(), 1)
+            ]
+        );
+        assert_eq!(stats.rules_without_actions, ["C"]);
+    }
+
+    #[test]
+    fn test_compiled_knowledge_base() {
+        let parser = parser::GrlParser::new();
+        let compiled = KnowledgeBaseBuilder::new()
+            .version(3)
+            .rule(
+                parser
+                    .parse_rule("rule Double { when x > 1 + 1 then y = x * 2; }")
+                    .unwrap(),
+            )
+            .compile()
+            .unwrap();
+        assert_eq!(compiled.len(), 1);
+        assert_eq!(compiled.version(), 3);
+        assert!(compiled.get_program("Double").is_some());
+        assert_eq!(
+            compiled.get_rule("Double").unwrap().when_condition,
+            Expression::GreaterThan(
+                Box::new(Expression::Variable("x".to_string())),
+                Box::new(Expression::Number(2.0))
+            )
+        );
+
+        let first = RuleEngine::with_compiled(&compiled, EngineConfig::default()).unwrap();
+        let mut second = RuleEngine::with_compiled(&compiled, EngineConfig::default()).unwrap();
+        assert!(Arc::ptr_eq(
+            &first.shared_knowledge_base(),
+            &second.shared_knowledge_base()
+        ));
+        second
+            .add_rule(
+                parser
+                    .parse_rule("rule Triple { when x > 0 then z = x * 3; }")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(compiled.len(), 1);
+        assert_eq!(first.get_knowledge_base().len(), 1);
+
+        let duplicate = parser
+            .parse_rule("rule Double { when x > 0 then y = 0; }")
+            .unwrap();
+        assert!(matches!(
+            KnowledgeBaseBuilder::new()
+                .rules([compiled.get_rule("Double").unwrap().clone(), duplicate])
+                .compile(),
+            Err(KnowledgeBaseError::DuplicateRule(_))
+        ));
+    }
+
+    #[test]
+    fn test_knowledge_base_from_dir() {
+        let dir = std::env::temp_dir().join(format!("runes-from-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("pricing")).unwrap();
+        std::fs::write(
+            dir.join("shipping.grl"),
+            "rule Free { when order.total > 50 then order.shipping = 0; }",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("pricing").join("vat.grl"),
+            r#"rule Vat { when order.total > 0 then order.vat = 20; }
+               rule ReducedVat { when order.food == true then order.vat = 5; }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not rules").unwrap();
+
+        let kb = KnowledgeBase::from_dir(&dir).unwrap();
+        assert_eq!(kb.len(), 3);
+        assert_eq!(kb.rules_in_package("shipping").count(), 1);
+        assert_eq!(kb.rules_in_package("pricing.vat").count(), 2);
+
+        std::fs::write(dir.join("broken.grl"), "rule Broken { when").unwrap();
+        std::fs::write(
+            dir.join("pricing").join("copy.grl"),
+            "rule Free { when order.total > 10 then order.shipping = 0; }",
+        )
+        .unwrap();
+        let Err(LoadError::Files(errors)) = KnowledgeBase::from_dir(&dir) else {
+            panic!("expected both failing files to be reported");
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        let files: Vec<_> = errors
+            .iter()
+            .map(|e| e.file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files, ["broken.grl", "shipping.grl"]);
+        assert!(matches!(errors[0].error, RunesError::Parse(_)));
+        assert!(matches!(
+            errors[1].error,
+            RunesError::KnowledgeBase(KnowledgeBaseError::DuplicateRule(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_ruleset() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        for grl in [
+            "rule Discount { when order.total > 100 then order.discount = 10; }",
+            "rule Shipping { when order.total > 50 then order.shipping = 0; }",
+            "rule Fraud { when order.total > 110 then order.review = true; }",
+        ] {
+            engine.add_rule(parser.parse_rule(grl).unwrap()).unwrap();
+        }
+        engine
+            .define_ruleset("checkout", ["Discount", "Shipping"])
+            .unwrap();
+        engine.define_ruleset("fraud", ["Fraud"]).unwrap();
+        assert!(matches!(
+            engine.define_ruleset("audit", ["Missing"]),
+            Err(RunesError::KnowledgeBase(KnowledgeBaseError::RuleNotFound(
+                _
+            )))
+        ));
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([("total".to_string(), FactValue::from(120))]),
+        ));
+        let result = engine.execute_ruleset("checkout", &mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Discount", "Shipping"]);
+        assert!(matches!(
+            engine.execute_ruleset("audit", &mut facts),
+            Err(RunesError::KnowledgeBase(
+                KnowledgeBaseError::RulesetNotFound(_)
+            ))
+        ));
+
+        let mut kb = (*engine.shared_knowledge_base()).clone();
+        kb.rename_rule("Discount", "Promotion").unwrap();
+        kb.remove_rule("Shipping");
+        assert_eq!(
+            kb.ruleset("checkout").unwrap(),
+            &std::collections::BTreeSet::from(["Promotion".to_string()])
+        );
+        let restored = KnowledgeBase::from_json(&kb.to_json().unwrap()).unwrap();
+        assert_eq!(
+            restored.rulesets().collect::<Vec<_>>(),
+            ["checkout", "fraud"]
+        );
+    }
+
+    #[test]
+    fn test_rule_effective_and_expiry_dates() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut engine = RuleEngine::builder().clock(clock.clone()).build();
+        let parser = parser::GrlParser::new();
+        let promotion = parser
+            .parse_rule("rule Promotion { when order.total > 0 then order.discount = 5; }")
+            .unwrap()
+            .with_effective_from(2_000)
+            .with_expires_at(3_000);
+        engine.add_rule(promotion).unwrap();
+
+        let run = |engine: &RuleEngine| {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::from_object(
+                "order".to_string(),
+                HashMap::from([("total".to_string(), FactValue::from(10))]),
+            ));
+            let fired = engine.fire_rule("Promotion", &mut facts.clone()).unwrap();
+            let result = engine.execute(&mut facts).unwrap();
+            assert_eq!(fired, !result.rules_fired.is_empty());
+            fired
+        };
+        assert!(!run(&engine));
+        clock.set(2_000);
+        assert!(run(&engine));
+        clock.set(3_000);
+        assert!(!run(&engine));
+    }
+
+    #[test]
+    fn test_rule_stores() {
+        let parser = parser::GrlParser::new();
+        let release = |version: u32, discount: u32| {
+            let mut kb = KnowledgeBase::new();
+            kb.set_version(version);
+            let grl = format!(
+                "rule Discount {{ when order.total > 100 then order.discount = {}; }}",
+                discount
+            );
+            kb.add_rule(parser.parse_rule(&grl).unwrap()).unwrap();
+            kb
+        };
+
+        let dir = std::env::temp_dir().join(format!("runes-store-{}", std::process::id()));
+        let stores: [Box<dyn RuleStore>; 2] =
+            [Box::new(MemoryStore::new()), Box::new(FileStore::new(&dir))];
+        for store in stores {
+            assert!(store.list().unwrap().is_empty());
+            store.save("pricing", &release(1, 10)).unwrap();
+            store.save("pricing", &release(2, 15)).unwrap();
+            store.save("fraud", &release(1, 0)).unwrap();
+            assert!(matches!(
+                store.save("pricing", &release(2, 20)),
+                Err(StoreError::VersionExists { version: 2, .. })
+            ));
+
+            assert_eq!(store.list().unwrap(), ["fraud", "pricing"]);
+            assert_eq!(store.versions("pricing").unwrap(), [1, 2]);
+            let latest = store.load("pricing", None).unwrap();
+            assert_eq!(latest.version(), 2);
+            assert_eq!(latest.get_rules(), release(2, 15).get_rules());
+            assert_eq!(
+                store.load("pricing", Some(1)).unwrap().get_rules(),
+                release(1, 10).get_rules()
+            );
+            assert!(matches!(
+                store.load("pricing", Some(3)),
+                Err(StoreError::VersionNotFound { version: 3, .. })
+            ));
+            assert!(matches!(
+                store.load("missing", None),
+                Err(StoreError::NotFound(_))
+            ));
+        }
+        // Only complete versions are left on disk
+        let mut files: Vec<_> = std::fs::read_dir(dir.join("pricing"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["1.json", "2.json"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_knowledge_base_to_markdown() {
+        let parser = parser::GrlParser::new();
+        let mut kb = KnowledgeBase::new();
+        let discount = parser
+            .parse_rule(
+                r#"rule Discount "10% off large orders" salience 5 {
+                    when order.total > 100 && customer.vip == true
+                    then order.discount = order.total * 0.1; order.reviewed = true;
+                }"#,
+            )
+            .unwrap()
+            .with_tags(["pricing", "eu"]);
+        kb.add_rule(discount).unwrap();
+        kb.add_rule(
+            parser
+                .parse_rule("rule Vat { when order.total > 0 then order.vat = 20; }")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let markdown = kb.to_markdown();
+        assert!(markdown.starts_with("# Rules\n\n## Discount\n\n10% off large orders\n"));
+        assert!(markdown.contains("- **Salience:** 5\n- **Tags:** eu, pricing\n"));
+        assert!(markdown.contains("```\norder.total > 100 && customer.vip == true\n```"));
+        assert!(markdown.contains("order.discount = order.total * 0.1;\norder.reviewed = true;\n"));
+        assert!(markdown.find("## Vat").unwrap() > markdown.find("## Discount").unwrap());
+    }
+
+    #[test]
+    fn test_expression_builders() {
+        use crate::expr::{call, field, var};
+
+        let parser = parser::GrlParser::new();
+        let parsed = parser
+            .parse_rule(
+                "rule R { when Car.Speed < Car.Max && x > 5 then Car.Speed = Car.Speed + Car.Inc; }",
+            )
+            .unwrap();
+        assert_eq!(
+            field("Car", "Speed")
+                .lt(field("Car", "Max"))
+                .and(var("x").gt(5)),
+            parsed.when_condition
+        );
+        assert_eq!(
+            field("Car", "Speed").assign(field("Car", "Speed") + field("Car", "Inc")),
+            parsed.then_actions[0]
+        );
+
+        assert_eq!(
+            var("order").field("customer").field("tier").assign("gold"),
+            Expression::FieldAssignment(
+                "order".to_string(),
+                "customer.tier".to_string(),
+                Box::new(Expression::String("gold".to_string()))
+            )
+        );
+        assert_eq!(
+            var("total").assign(call("sum", [var("items")]) * 1.2),
+            Expression::Assignment(
+                "total".to_string(),
+                Box::new(Expression::Multiply(
+                    Box::new(Expression::FunctionCall(
+                        "sum".to_string(),
+                        vec![Expression::Variable("items".to_string())]
+                    )),
+                    Box::new(Expression::Number(1.2))
+                ))
+            )
+        );
+        assert_eq!((!var("done")).to_string(), "!done");
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_grl_macro() {
+        let rule = grl! {
+            rule Discount "10% off large orders" salience 5 {
+                when order.total >= 100 && order.coupon != "NONE" && c.tier == billing::Gold
+                then order.discount = order.total * 0.1; insertLogical(flag, true);
+            }
+        };
+        let parsed = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Discount "10% off large orders" salience 5 {
+                    when order.total >= 100 && order.coupon != "NONE" && c.tier == billing::Gold
+                    then order.discount = order.total * 0.1; insertLogical(flag, true);
+                }"#,
+            )
+            .unwrap();
+        assert_eq!(rule, parsed);
+
+        #[cfg(feature = "script")]
+        {
+            let rule = grl!(r#"rule Log { when x > 1 then script """print("x")"""; }"#);
+            assert_eq!(rule.then_actions.len(), 1);
+        }
+
+        // The expansion is the parsed rule itself, patterns and all
+        const VIP: &str = r#"rule Vip every 5m { when c: Customer; total := accumulate(o: Order(o.customer == c.id), sum(o.amount)); net := total - -1.5; net > 100 && !(c.note matches "\\d") then if (net > 1000) { c.tier = "gold"; } else { c.tier = null; } }"#;
+        let rule = grl!(
+            r#"rule Vip every 5m { when c: Customer; total := accumulate(o: Order(o.customer == c.id), sum(o.amount)); net := total - -1.5; net > 100 && !(c.note matches "\\d") then if (net > 1000) { c.tier = "gold"; } else { c.tier = null; } }"#
+        );
+        assert_eq!(rule, parser::GrlParser::new().parse_rule(VIP).unwrap());
+        let rule = grl!(r#"rule Report cron "*/15 9-17 * * 1-5" { when x > 1 then x = 1; }"#);
+        assert_eq!(
+            rule.schedule,
+            Some(Schedule::Cron(Cron::parse("*/15 9-17 * * 1-5").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_expression_visitor_and_folder() {
+        use crate::visit::{fold_children, walk_expression};
+
+        struct Variables(Vec<String>);
+        impl ExpressionVisitor for Variables {
+            fn visit_expression(&mut self, expr: &Expression) {
+                if let Expression::Variable(name) = expr {
+                    self.0.push(name.clone());
+                }
+                walk_expression(self, expr);
+            }
+        }
+
+        struct Rename;
+        impl ExpressionFolder for Rename {
+            fn fold_expression(&mut self, expr: Expression) -> Expression {
+                match expr {
+                    Expression::Variable(name) if name == "car" => {
+                        Expression::Variable("vehicle".to_string())
+                    }
+                    other => fold_children(self, other),
+                }
+            }
+        }
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                "rule R { when car.speed > limit && max(car.speed, 0) < 200 then car.alert = true; }",
+            )
+            .unwrap();
+        let mut variables = Variables(Vec::new());
+        variables.visit_expression(&rule.when_condition);
+        assert_eq!(variables.0, ["car", "limit", "car"]);
+
+        let renamed = Rename.fold_expression(rule.when_condition);
+        assert_eq!(
+            renamed.to_string(),
+            "vehicle.speed > limit && max(vehicle.speed, 0) < 200"
+        );
+    }
+
+    #[test]
+    fn test_expression_to_grl_string() {
+        use crate::expr::{array, call, field, var};
+
+        let parser = parser::GrlParser::new();
+        for grl in [
+            "order.total + order.shipping * 2 > 100 && customer.vip == true || order.rush == true",
+            "a - b - c == a * b / c % 2",
+            "total ?? 0 >= limit + 1",
+            "items[0].price > 10 && count of Login events in last 5m > 3",
+        ] {
+            let rule = parser
+                .parse_rule(&format!("rule R {{ when {} then x = 1; }}", grl))
+                .unwrap();
+            assert_eq!(rule.when_condition.to_grl_string(), grl);
+        }
+
+        assert_eq!(
+            (var("a") - (var("b") - var("c"))).to_grl_string(),
+            "a - (b - c)"
+        );
+        assert_eq!(((var("a") + var("b")) * 2).to_grl_string(), "(a + b) * 2");
+        // `||` binds loosest in GRL
+        assert_eq!(
+            var("a").or(var("b")).and(var("c")).to_grl_string(),
+            "(a || b) && c"
+        );
+        assert_eq!(
+            var("a").or(var("b").and(var("c"))).to_grl_string(),
+            "a || b && c"
+        );
+        assert_eq!(
+            (!field("order", "total").gt(call("limit", []))).to_grl_string(),
+            "!(order.total > limit())"
+        );
+        assert_eq!(
+            field("order", "note").assign("say \"hi\"").to_grl_string(),
+            r#"order.note = "say \"hi\"""#
+        );
+
+        // Printed conditions parse back to the same tree
+        let (a, b, c) = (|| var("a"), || var("b"), || var("c"));
+        for condition in [
+            !a().gt(1.0),
+            (a() / (b() * c())).gt(1.0),
+            a().gt(1.0).and(b().gt(2.0)).or(c().gt(3.0)),
+            a().gt(1.0).or(b().gt(2.0)).and(c().gt(3.0)),
+            a().or(b()).or(c()),
+            a().or(b().or(c())),
+            (!a()).and((-(b() + c())).lt(0.0)),
+            a().gt(1.0).equals(!b()),
+            call("f", [a()]).field("y").gt(call("g", []).index(0.0)),
+            a().equals("say \"hi\" \\ to\n\"all\"")
+                .and(b().equals("x && y || z")),
+            call("f", [a(), "(".into(), ")".into()]).and(a().is_in(array([b(), c()]))),
+            a().gt(f64::NEG_INFINITY).and(b().lt(f64::INFINITY)),
+            (a() - f64::NEG_INFINITY).equals(a() * f64::INFINITY),
+        ] {
+            let printed = condition.to_grl_string();
+            let reparsed = parser
+                .parse_rule(&format!("rule R {{ when {} then x = 1; }}", printed))
+                .unwrap_or_else(|e| panic!("{}: {}", printed, e));
+            assert_eq!(reparsed.when_condition, condition, "{}", printed);
+        }
+        let action = field("order", "note").assign("say \"hi\"");
+        let reparsed = parser
+            .parse_rule(&format!("rule R {{ when a > 1 then {}; }}", action))
+            .unwrap();
+        assert_eq!(reparsed.then_actions, [action]);
+
+        // NaN has no GRL literal, so a rule holding one is rejected
+        let nan = Rule::new("NaN".to_string(), 0, a().gt(f64::NAN), vec![]);
+        assert!(matches!(
+            RuleEngine::new().add_rule(nan),
+            Err(RunesError::KnowledgeBase(
+                KnowledgeBaseError::InvalidRule { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_array_literals() {
+        use crate::expr::{array, call, field};
+
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(Rule::new(
+                "Premium".to_string(),
+                0,
+                call(
+                    "contains",
+                    [
+                        array(["gold".into(), "platinum".into()]),
+                        field("customer", "tier"),
+                    ],
+                ),
+                vec![field("customer", "limits")
+                    .assign(array([100.into(), field("customer", "base")]))],
+            ))
+            .unwrap();
+        engine
+            .add_rule(
+                parser::GrlParser::new()
+                    .parse_rule(
+                        r#"rule Second { when contains([1, customer.base, 3], 50) then customer.second = true; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "customer".to_string(),
+            HashMap::from([
+                ("tier".to_string(), FactValue::from("gold")),
+                ("base".to_string(), FactValue::from(50)),
+            ]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Premium", "Second"]);
+        let customer = facts.get("customer").unwrap();
+        assert_eq!(
+            customer.get_field("limits"),
+            Some(&FactValue::Array(vec![
+                FactValue::from(100.0),
+                FactValue::from(50)
+            ]))
+        );
+        assert_eq!(array([1.into(), "a".into()]).to_grl_string(), r#"[1, "a"]"#);
+    }
+
+    #[test]
+    fn test_conditional_values() {
+        use crate::expr::{field, if_else};
+
+        let grl = r#"
+            rule Discount salience 10 {
+                when order.total * if (customer.vip == true) { 0.5 } else { 1 } > 40
+                then
+                    order.discount = if (customer.vip == true) { 0.2 } else if (order.total > 100) { 0.1 } else { 0 };
+                    order.note = if (order.total > 1000) { "large" };
+            }
+        "#;
+        let rule = parser::GrlParser::new().parse_rule(grl).unwrap();
+        let reparsed = parser::GrlParser::new()
+            .parse_rule(&format!(
+                "rule Again {{ when {} then {}; }}",
+                rule.when_condition, rule.then_actions[0]
+            ))
+            .unwrap();
+        assert_eq!(reparsed.when_condition, rule.when_condition);
+        assert_eq!(reparsed.then_actions[0], rule.then_actions[0]);
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Shipping".to_string(),
+                0,
+                field("order", "total").gt(0.0),
+                vec![field("order", "shipping").assign(if_else(
+                    field("customer", "vip"),
+                    0.0,
+                    4.99,
+                ))],
+            ))
+            .unwrap();
+
+        let run = |vip: bool, total: f64| {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::from_object(
+                "customer".to_string(),
+                HashMap::from([("vip".to_string(), FactValue::Boolean(vip))]),
+            ));
+            facts.insert(Fact::from_object(
+                "order".to_string(),
+                HashMap::from([("total".to_string(), FactValue::Number(total))]),
+            ));
+            engine.execute(&mut facts).unwrap();
+            let order = facts.get("order").unwrap();
+            ["discount", "note", "shipping"].map(|name| order.get_field(name).cloned())
+        };
+
+        assert_eq!(
+            run(true, 120.0),
+            [
+                Some(FactValue::Number(0.2)),
+                Some(FactValue::Null),
+                Some(FactValue::Number(0.0))
+            ]
+        );
+        assert_eq!(
+            run(false, 150.0),
+            [
+                Some(FactValue::Number(0.1)),
+                Some(FactValue::Null),
+                Some(FactValue::Number(4.99))
+            ]
+        );
+        // 60 * 0.5 passes for a VIP, but not 30 for anyone else
+        assert_eq!(
+            run(false, 30.0),
+            [None, None, Some(FactValue::Number(4.99))]
+        );
+    }
+
+    #[test]
+    fn test_method_calls() {
+        let grl = r#"
+            rule Greet {
+                when cart.items.len() > 1 && customer.name.trim().startsWith("Ada")
+                then
+                    customer.shout = customer.name.trim().toUpper();
+                    cart.total = cart.items.sum("price") + cart.items[0].price;
+            }
+        "#;
+        let rule = parser::GrlParser::new().parse_rule(grl).unwrap();
+        assert_eq!(
+            rule.then_actions[0].to_string(),
+            "customer.shout = customer.name.trim().toUpper()"
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        let item = |price: f64| {
+            FactValue::Object(HashMap::from([(
+                "price".to_string(),
+                FactValue::Number(price),
+            )]))
+        };
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "customer".to_string(),
+            HashMap::from([("name".to_string(), FactValue::from(" Ada Lovelace "))]),
+        ));
+        facts.insert(Fact::from_object(
+            "cart".to_string(),
+            HashMap::from([(
+                "items".to_string(),
+                FactValue::Array(vec![item(10.0), item(5.0)]),
+            )]),
+        ));
+        engine.execute(&mut facts).unwrap();
+        assert_eq!(
+            facts.get("customer").unwrap().get_field("shout"),
+            Some(&FactValue::from("ADA LOVELACE"))
+        );
+        assert_eq!(
+            facts.get("cart").unwrap().get_field("total"),
+            Some(&FactValue::Number(25.0))
+        );
+
+        // Methods belong to the receiver's type
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser::GrlParser::new()
+                    .parse_rule(r#"rule Bad { when customer.name.sum() > 0 then x = 1; }"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        let err = engine.execute(&mut facts).unwrap_err();
+        assert!(
+            err.to_string().contains("String has no method 'sum'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_in_expressions() {
+        use crate::expr::{array, field};
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Premium { when customer.tier in ["gold", "platinum"] && "VIP" in customer.note then customer.premium = true; }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.when_condition.to_string(),
+            r#"customer.tier in ["gold", "platinum"] && "VIP" in customer.note"#
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Region".to_string(),
+                0,
+                field("customer", "country").is_in(array(["DE".into(), "FR".into()])),
+                vec![field("customer", "eu").assign(true)],
+            ))
+            .unwrap();
+
+        let run = |tier: &str, note: &str, country: &str| {
+            let mut facts = WorkingMemory::new();
+            facts.insert(Fact::from_object(
+                "customer".to_string(),
+                HashMap::from([
+                    ("tier".to_string(), FactValue::from(tier)),
+                    ("note".to_string(), FactValue::from(note)),
+                    ("country".to_string(), FactValue::from(country)),
+                ]),
+            ));
+            engine.execute(&mut facts).unwrap().rules_fired
+        };
+        assert_eq!(run("gold", "VIP since 2020", "FR"), ["Premium", "Region"]);
+        assert_eq!(run("silver", "VIP since 2020", "US"), Vec::<String>::new());
+        assert_eq!(run("platinum", "regular", "DE"), ["Region"]);
+    }
+
+    #[test]
+    fn test_regex_matches() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Staff { when customer.email matches "@example\.com$" then customer.staff = true; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        engine
+            .add_rule(
+                parser
+                    .parse_rule(
+                        r#"rule Coupon { when order.code matches order.format then order.valid = true; }"#,
+                    )
+                    .unwrap(),
+            )
+            .unwrap();
+        // The literal pattern is compiled once, when the rule is added
+        assert!(engine
+            .get_knowledge_base()
+            .regex(r"@example\.com$")
+            .is_some());
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "customer".to_string(),
+            HashMap::from([("email".to_string(), FactValue::from("ada@example.com"))]),
+        ));
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([
+                ("code".to_string(), FactValue::from("SAVE-2024")),
+                ("format".to_string(), FactValue::from("^[A-Z]+-[0-9]{4}$")),
+            ]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Staff", "Coupon"]);
+
+        let err = engine
+            .add_rule(
+                parser
+                    .parse_rule(r#"rule Broken { when customer.email matches "(" then x = 1; }"#)
+                    .unwrap(),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RunesError::KnowledgeBase(KnowledgeBaseError::InvalidRule { ref rule, .. })
+                    if rule == "Broken"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_null_literal() {
+        use crate::expr::field;
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Unassigned { when ticket.owner == null && ticket.queue != null then ticket.queue = null; }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.when_condition.to_string(),
+            "ticket.owner == null && ticket.queue != null"
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Escalate".to_string(),
+                0,
+                field("ticket", "queue").equals(Expression::Null),
+                vec![field("ticket", "escalated").assign(true)],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "ticket".to_string(),
+            HashMap::from([
+                ("owner".to_string(), FactValue::Null),
+                ("queue".to_string(), FactValue::from("billing")),
+            ]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Unassigned", "Escalate"]);
+        let ticket = facts.get("ticket").unwrap();
+        assert_eq!(ticket.get_field("queue"), Some(&FactValue::Null));
+        assert_eq!(
+            ticket.get_field("escalated"),
+            Some(&FactValue::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn test_negate_expression() {
+        use crate::expr::field;
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Refund { when -account.balance > 10 then account.refund = -account.balance * 2; account.credit = 5 - -account.balance; }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.then_actions[1].to_string(),
+            "account.credit = 5 - -account.balance"
+        );
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Flip".to_string(),
+                0,
+                field("account", "refund").gt(0.0),
+                vec![field("account", "debit").assign(-field("account", "refund"))],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "account".to_string(),
+            HashMap::from([("balance".to_string(), FactValue::Integer(-20))]),
+        ));
+        engine.execute(&mut facts).unwrap();
+        let account = facts.get("account").unwrap();
+        assert_eq!(account.get_field("refund"), Some(&FactValue::Integer(40)));
+        assert_eq!(account.get_field("credit"), Some(&FactValue::Integer(-15)));
+        assert_eq!(account.get_field("debit"), Some(&FactValue::Integer(-40)));
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "account".to_string(),
+            HashMap::from([("balance".to_string(), FactValue::from("low"))]),
+        ));
+        let err = engine.execute(&mut facts).unwrap_err();
+        assert!(
+            err.to_string().contains("Cannot negate a String"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_let_in_expressions() {
+        use crate::expr::{field, let_in, var};
+
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Bulk { when let total = order.qty * order.price in total > 100 && total < 500 then order.tax = let net = order.qty * order.price in net * 0.2; }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            rule.when_condition.to_string(),
+            "let total = order.qty * order.price in total > 100 && total < 500"
+        );
+        // The bound name is local, so only the order's fields are read
+        let deps = analysis::RuleDependencies::of(&rule);
+        assert!(deps.condition_reads.iter().all(|read| read.fact == "order"));
+
+        let mut engine = RuleEngine::new();
+        engine.add_rule(rule).unwrap();
+        engine
+            .add_rule(Rule::new(
+                "Shipping".to_string(),
+                0,
+                let_in("w", field("order", "qty") * 2, var("w").gt(10.0)),
+                vec![field("order", "heavy").assign(true)],
+            ))
+            .unwrap();
+
+        let mut facts = WorkingMemory::new();
+        facts.insert(Fact::from_object(
+            "order".to_string(),
+            HashMap::from([
+                ("qty".to_string(), FactValue::Integer(10)),
+                ("price".to_string(), FactValue::Number(20.0)),
+            ]),
+        ));
+        let result = engine.execute(&mut facts).unwrap();
+        assert_eq!(result.rules_fired, ["Bulk", "Shipping"]);
+        assert_eq!(
+            facts.get("order").unwrap().get_field("tax"),
+            Some(&FactValue::Number(40.0))
+        );
+        assert_eq!(
+            (let_in("x", 1, var("x")) + 2).to_grl_string(),
+            "(let x = 1 in x) + 2"
+        );
+    }
+
+    #[test]
+    fn test_static_type_checking() {
+        let parser = parser::GrlParser::new();
+        let mut engine = RuleEngine::new();
+        engine
+            .register_schema(
+                "order",
+                FactSchema::new()
+                    .required("total", FieldType::Number)
+                    .required("express", FieldType::Boolean)
+                    .optional("code", FieldType::String)
+                    .optional("placed", FieldType::DateTime),
+            )
+            .unwrap();
+        let add = |engine: &mut RuleEngine, grl: &str| {
+            engine
+                .add_rule(parser.parse_rule(grl).unwrap())
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(
+            add(&mut engine, r#"rule A { when order.code > 5 then x = 1; }"#).unwrap_err(),
+            "Rule 'A' is invalid: `order.code > 5` compares String and Number"
+        );
+        assert_eq!(
+            add(
+                &mut engine,
+                r#"rule B { when order.total > 0 then order.total = order.total + order.express; }"#
+            )
+            .unwrap_err(),
+            "Rule 'B' is invalid: `order.total + order.express` adds Number and Boolean"
+        );
+        assert_eq!(
+            add(
+                &mut engine,
+                r#"rule C { when order.total > 0 then order.code = 7; }"#
+            )
+            .unwrap_err(),
+            "Rule 'C' is invalid: `order.code = 7` assigns Number to a String field"
+        );
+        // Types the schemas agree with, or cannot tell, are accepted
+        add(
+            &mut engine,
+            r#"rule D { when order.total * 2 >= 100 && order.placed + 2d > now() && o.anything == "x" then order.total = order.total - 5; order.code = "BIG"; }"#,
+        )
+        .unwrap();
+        add(
+            &mut engine,
+            r#"rule E { when let order = 5 in order > 1 then x = 1; }"#,
+        )
+        .unwrap();
+
+        // Lenient coercion compares across types
+        let mut engine = RuleEngine::builder()
+            .coercion(CoercionPolicy::Lenient)
+            .build();
+        engine
+            .register_schema(
+                "order",
+                FactSchema::new().optional("code", FieldType::String),
+            )
+            .unwrap();
+        add(&mut engine, r#"rule A { when order.code > 5 then x = 1; }"#).unwrap();
+    }
+
+    #[test]
+    fn test_expression_json_encoding() {
+        use crate::expr::{field, var};
+
+        // The encoding is pinned: changing it needs a new FORMAT_VERSION
+        let json = r#"{"And":[{"GreaterThan":[{"FieldAccess":[{"Variable":"order"},"total"]},{"Number":100.0}]},{"Not":{"In":["Null",{"Array":[{"String":"a"},{"Duration":60000}]}]}}]}"#;
+        let expected = field("order", "total").gt(100.0).and(!Expression::In(
+            Box::new(Expression::Null),
+            Box::new(Expression::Array(vec![
+                "a".into(),
+                Expression::Duration(60_000),
+            ])),
+        ));
+        let decoded: Expression = serde_json::from_str(json).unwrap();
+        assert_eq!(decoded, expected);
+        assert_eq!(serde_json::to_string(&expected).unwrap(), json);
+
+        // Every node of a varied rule is encoded as the schema describes
+        let schema = json_schema::expression_schema();
+        assert_eq!(
+            schema["description"],
+            format!("rust-runes Expression, format version {}", FORMAT_VERSION)
+        );
+        let variants = schema["$defs"]["Expression"]["oneOf"].as_array().unwrap();
+        let rule = parser::GrlParser::new()
+            .parse_rule(
+                r#"rule Varied { when let t = order.items.len() in t > 1 && order?.code ?? "" matches "^A" && -order.total % 2 != 0 then order.tags[0] = upper(order.code); foreach item in order.items { item.seen = true; } if (order.total >= 5) { let x = [1, null]; } else { update(order); } }"#,
+            )
+            .unwrap();
+        let mut nodes: Vec<&Expression> = std::iter::once(&rule.when_condition)
+            .chain(&rule.then_actions)
+            .collect();
+        let extra = var("x").assign(Expression::Boolean(false).or(var("y") / 2));
+        nodes.push(&extra);
+        let mut seen = std::collections::HashSet::new();
+        while let Some(node) = nodes.pop() {
+            nodes.extend(node.children());
+            let encoded = serde_json::to_value(node).unwrap();
+            let (name, value) = match &encoded {
+                serde_json::Value::String(name) => (name.clone(), None),
+                serde_json::Value::Object(object) => {
+                    let (name, value) = object.iter().next().unwrap();
+                    (name.clone(), Some(value))
+                }
+                other => panic!("unexpected encoding {}", other),
+            };
+            let variant = variants
+                .iter()
+                .find(|v| v["const"] == name.as_str() || v["required"][0] == name.as_str())
+                .unwrap_or_else(|| panic!("{} is not in the schema", name));
+            match (value, &variant["properties"][&name]["maxItems"]) {
+                (None, _) => assert_eq!(variant["const"], name.as_str()),
+                (Some(serde_json::Value::Array(items)), serde_json::Value::Number(n)) => {
+                    assert_eq!(items.len() as u64, n.as_u64().unwrap(), "{}", name)
+                }
+                (Some(_), max_items) => assert!(max_items.is_null(), "{}", name),
+            }
+            seen.insert(name);
+        }
+        assert!(seen.len() >= 20, "{:?}", seen);
+    }
+
+    #[test]
+    fn test_optimization_passes() {
+        use crate::expr::field;
+        use crate::optimize::{CommonSubexpressionExtraction, DeMorgan, DeadBranchElimination};
+
+        let grl = r#"
+            rule Band {
+                when order.total > 0
+                then
+                    if (1 > 2) { order.band = "never"; } else { order.band = "mid"; }
+                    if (1 > 2) { order.never = true; }
+                    order.label = if (2 > 1) { "checked" } else { "unchecked" };
+            }
+        "#;
+        let mut rule = parser::GrlParser::new().parse_rule(grl).unwrap();
+        let doubled = || field("order", "total") * 2.0;
+        rule.when_condition = !(doubled()
+            .lt(10.0)
+            .or(doubled().gt(100.0))
+            .or(field("order", "status").equals("void")));
+        let compiled = KnowledgeBaseBuilder::new()
+            .passes(
+                PassManager::default()
+                    .pass(DeMorgan)
+                    .pass(DeadBranchElimination)
+                    .pass(CommonSubexpressionExtraction),
+            )
+            .rule(rule.clone())
+            .compile()
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", PassManager::default().pass(DeMorgan)),
+            r#"["constant-folding", "de-morgan"]"#
+        );
+
+        let optimized = compiled.get_rule("Band").unwrap();
+        assert_eq!(
+            optimized.when_condition.to_string(),
+            r#"let _cse0 = order.total * 2 in !(_cse0 < 10) && !(_cse0 > 100) && order.status != "void""#
+        );
+        assert_eq!(optimized.then_actions.len(), 2);
+        assert_eq!(
+            optimized.then_actions[0].to_string(),
+            r#"order.band = "mid""#
+        );
+        assert_eq!(
+            optimized.then_actions[1].to_string(),
+            r#"order.label = "checked""#
+        );
+
+        // The optimized rule does what the rule as written does
+        let unoptimized = KnowledgeBaseBuilder::new()
+            .passes(PassManager::new())
+            .rule(rule)
+            .compile()
+            .unwrap();
+        for (total, status) in [(1.0, "open"), (5.0, "open"), (30.0, "void"), (50.0, "open")] {
+            let run = |compiled: &CompiledKnowledgeBase| {
+                let engine = RuleEngine::with_compiled(compiled, EngineConfig::default()).unwrap();
+                let mut facts = WorkingMemory::new();
+                facts.insert(Fact::from_object(
+                    "order".to_string(),
+                    HashMap::from([
+                        ("total".to_string(), FactValue::Number(total)),
+                        ("status".to_string(), FactValue::from(status)),
+                    ]),
+                ));
+                engine.execute(&mut facts).unwrap();
+                facts.get("order").unwrap().value.clone()
+            };
+            assert_eq!(run(&compiled), run(&unoptimized));
+        }
+    }
 }
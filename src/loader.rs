@@ -0,0 +1,93 @@
+use crate::error::RunesError;
+use crate::knowledge_base::KnowledgeBase;
+use crate::parser::GrlParser;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Cannot read rules: {0}")]
+    Io(#[from] io::Error),
+    #[error("{}", describe(.0))]
+    Files(Vec<FileError>),
+}
+
+/// Why the rules of one `.grl` file could not be loaded
+#[derive(Debug)]
+pub struct FileError {
+    pub file: PathBuf,
+    pub error: RunesError,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.error)
+    }
+}
+
+fn describe(errors: &[FileError]) -> String {
+    let errors: Vec<String> = errors.iter().map(FileError::to_string).collect();
+    format!("Cannot load rules: {}", errors.join("; "))
+}
+
+impl KnowledgeBase {
+    /// Parse every `.grl` file under `dir`, including subdirectories. Each
+    /// file's rules form a package named after its path, e.g. `pricing` for
+    /// `pricing.grl` and `pricing.vat` for `pricing/vat.grl`. Every file is
+    /// tried, and the errors of all files that fail are reported together.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<KnowledgeBase, LoadError> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+        grl_files(dir, &mut files)?;
+        files.sort();
+
+        let parser = GrlParser::new();
+        let mut knowledge_base = KnowledgeBase::new();
+        let mut errors = Vec::new();
+        for file in files {
+            let package = package_name(dir, &file);
+            let outcome = parser
+                .parse_rules(&fs::read_to_string(&file)?)
+                .map_err(RunesError::from)
+                .and_then(|rules| {
+                    rules.into_iter().try_for_each(|rule| {
+                        knowledge_base.add_rule(rule.with_package(package.as_str()))?;
+                        Ok(())
+                    })
+                });
+            if let Err(error) = outcome {
+                errors.push(FileError { file, error });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(knowledge_base)
+        } else {
+            Err(LoadError::Files(errors))
+        }
+    }
+}
+
+/// The `.grl` files under `dir`, including subdirectories
+pub(crate) fn grl_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            grl_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "grl") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The file's path below `dir`, without extension, joined with `.`
+fn package_name(dir: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(dir).unwrap_or(file).with_extension("");
+    let parts: Vec<_> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    parts.join(".")
+}
@@ -1,4 +1,4 @@
-use rust_runes::ast;
+use rust_runes::expr::field;
 use rust_runes::*;
 use std::collections::HashMap;
 
@@ -10,54 +10,14 @@ fn main() -> Result<()> {
     let speed_up_rule = Rule::new(
         "SpeedUp".to_string(),
         10,
-        ast::Expression::And(
-            Box::new(ast::Expression::Equal(
-                Box::new(ast::Expression::FieldAccess(
-                    Box::new(ast::Expression::Variable("TestCar".to_string())),
-                    "SpeedUp".to_string(),
-                )),
-                Box::new(ast::Expression::Boolean(true)),
-            )),
-            Box::new(ast::Expression::LessThan(
-                Box::new(ast::Expression::FieldAccess(
-                    Box::new(ast::Expression::Variable("TestCar".to_string())),
-                    "Speed".to_string(),
-                )),
-                Box::new(ast::Expression::FieldAccess(
-                    Box::new(ast::Expression::Variable("TestCar".to_string())),
-                    "MaxSpeed".to_string(),
-                )),
-            )),
-        ),
+        field("TestCar", "SpeedUp")
+            .equals(true)
+            .and(field("TestCar", "Speed").lt(field("TestCar", "MaxSpeed"))),
         vec![
-            ast::Expression::FieldAssignment(
-                "TestCar".to_string(),
-                "Speed".to_string(),
-                Box::new(ast::Expression::Add(
-                    Box::new(ast::Expression::FieldAccess(
-                        Box::new(ast::Expression::Variable("TestCar".to_string())),
-                        "Speed".to_string(),
-                    )),
-                    Box::new(ast::Expression::FieldAccess(
-                        Box::new(ast::Expression::Variable("TestCar".to_string())),
-                        "SpeedIncrement".to_string(),
-                    )),
-                )),
-            ),
-            ast::Expression::FieldAssignment(
-                "DistanceRecord".to_string(),
-                "TotalDistance".to_string(),
-                Box::new(ast::Expression::Add(
-                    Box::new(ast::Expression::FieldAccess(
-                        Box::new(ast::Expression::Variable("DistanceRecord".to_string())),
-                        "TotalDistance".to_string(),
-                    )),
-                    Box::new(ast::Expression::FieldAccess(
-                        Box::new(ast::Expression::Variable("TestCar".to_string())),
-                        "Speed".to_string(),
-                    )),
-                )),
-            ),
+            field("TestCar", "Speed")
+                .assign(field("TestCar", "Speed") + field("TestCar", "SpeedIncrement")),
+            field("DistanceRecord", "TotalDistance")
+                .assign(field("DistanceRecord", "TotalDistance") + field("TestCar", "Speed")),
         ],
     )
     .with_description("When testcar is speeding up we keep increase the speed.".to_string());
@@ -65,7 +25,7 @@ fn main() -> Result<()> {
     engine.add_rule(speed_up_rule)?;
 
     // Create facts
-    let mut facts = HashMap::new();
+    let mut facts = WorkingMemory::new();
 
     let mut test_car_fields = HashMap::new();
     test_car_fields.insert("SpeedUp".to_string(), FactValue::Boolean(true));
@@ -76,14 +36,11 @@ fn main() -> Result<()> {
     let mut distance_record_fields = HashMap::new();
     distance_record_fields.insert("TotalDistance".to_string(), FactValue::Number(0.0));
 
-    facts.insert(
-        "TestCar".to_string(),
-        Fact::from_object("TestCar".to_string(), test_car_fields),
-    );
-    facts.insert(
+    facts.insert(Fact::from_object("TestCar".to_string(), test_car_fields));
+    facts.insert(Fact::from_object(
         "DistanceRecord".to_string(),
-        Fact::from_object("DistanceRecord".to_string(), distance_record_fields),
-    );
+        distance_record_fields,
+    ));
 
     // Execute rules
     println!("Before execution:");
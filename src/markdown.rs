@@ -0,0 +1,58 @@
+use crate::knowledge_base::KnowledgeBase;
+use crate::rule::{Pattern, Rule};
+
+impl KnowledgeBase {
+    /// Documentation of every rule for review by people who do not read GRL:
+    /// a section per rule, in firing order by salience, with its description,
+    /// salience, tags, condition and actions
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from("# Rules\n");
+        for rule in self.get_rules_sorted_by_salience() {
+            write_rule(&mut out, rule);
+        }
+        out
+    }
+}
+
+fn write_rule(out: &mut String, rule: &Rule) {
+    *out += &format!("\n## {}\n\n", rule.name);
+    if let Some(description) = &rule.description {
+        *out += &format!("{}\n\n", description);
+    }
+    *out += &format!("- **Salience:** {}\n", rule.salience);
+    if !rule.tags.is_empty() {
+        let tags: Vec<&str> = rule.tags.iter().map(String::as_str).collect();
+        *out += &format!("- **Tags:** {}\n", tags.join(", "));
+    }
+
+    *out += "\n**When**\n\n```\n";
+    for pattern in &rule.patterns {
+        *out += &format!("{}\n", pattern_text(pattern));
+    }
+    for accumulate in &rule.accumulates {
+        *out += &format!(
+            "{} := accumulate({}, {}({}))\n",
+            accumulate.name,
+            pattern_text(&accumulate.pattern),
+            accumulate.function,
+            accumulate.value
+        );
+    }
+    for binding in &rule.bindings {
+        *out += &format!("{} := {}\n", binding.name, binding.value);
+    }
+    *out += &format!("{}\n```\n", rule.when_condition);
+
+    *out += "\n**Then**\n\n```\n";
+    for action in &rule.then_actions {
+        *out += &format!("{};\n", action);
+    }
+    *out += "```\n";
+}
+
+fn pattern_text(pattern: &Pattern) -> String {
+    match &pattern.constraint {
+        Some(constraint) => format!("{}: {}({})", pattern.binding, pattern.fact_type, constraint),
+        None => format!("{}: {}", pattern.binding, pattern.fact_type),
+    }
+}
@@ -0,0 +1,29 @@
+use crate::context::EvalContext;
+use crate::engine::EngineError;
+use crate::rule::Rule;
+use std::sync::Arc;
+
+/// Wraps the firing of every rule activation, registered with
+/// `RuleEngine::use_middleware`. A middleware fires the activation by calling
+/// `next.run()`, and may skip it by not doing so.
+pub type Middleware =
+    Arc<dyn Fn(&FireContext<'_>, Next<'_>) -> Result<(), EngineError> + Send + Sync>;
+
+/// The activation about to fire
+#[derive(Debug)]
+pub struct FireContext<'a> {
+    pub rule: &'a Rule,
+    /// The context the execution was started with
+    pub context: &'a EvalContext,
+}
+
+/// The rest of the middleware chain, ending with the rule's actions
+pub struct Next<'a> {
+    pub(crate) run: &'a mut dyn FnMut() -> Result<(), EngineError>,
+}
+
+impl Next<'_> {
+    pub fn run(self) -> Result<(), EngineError> {
+        (self.run)()
+    }
+}
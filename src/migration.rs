@@ -0,0 +1,62 @@
+use crate::knowledge_base::KnowledgeBaseError;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The version of the rule format written by `KnowledgeBase::to_json`,
+/// increased whenever `Rule` or `Expression` change shape
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Upgrades the JSON array of a knowledge base's rules by one format version
+pub type Migration = Arc<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+/// Migrations from older rule formats, applied by
+/// `KnowledgeBase::from_json_with` to knowledge bases serialized before the
+/// format last changed
+#[derive(Clone, Default)]
+pub struct Migrations {
+    steps: BTreeMap<u32, Migration>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the migration from format version `from` to `from + 1`
+    pub fn register<F>(mut self, from: u32, migration: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.steps.insert(from, Arc::new(migration));
+        self
+    }
+
+    /// Bring `rules` from format `version` up to `FORMAT_VERSION`
+    pub(crate) fn upgrade(
+        &self,
+        version: u32,
+        mut rules: Value,
+    ) -> Result<Value, KnowledgeBaseError> {
+        if version > FORMAT_VERSION {
+            return Err(KnowledgeBaseError::UnsupportedVersion(version));
+        }
+        for from in version..FORMAT_VERSION {
+            let migration = self
+                .steps
+                .get(&from)
+                .ok_or(KnowledgeBaseError::MissingMigration(from))?;
+            rules = migration(rules).map_err(KnowledgeBaseError::InvalidFormat)?;
+        }
+        Ok(rules)
+    }
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migrations")
+            .field("steps", &self.steps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
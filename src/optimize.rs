@@ -0,0 +1,570 @@
+use crate::ast::Expression;
+use crate::config::{Collation, NumericPolicy};
+use crate::engine::BinaryOp;
+use crate::facts::{CoercionPolicy, FactValue};
+use crate::rule::Rule;
+use crate::visit::{fold_children, ExpressionFolder};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Something suspicious found about a rule when it was added
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleWarning {
+    /// The rule's condition can never be true
+    NeverMatches(String),
+    /// The rule has no actions, so firing it does nothing
+    NoActions(String),
+    /// Literals of different types are compared, e.g. `"5" > 3`
+    IncompatibleComparison {
+        rule: String,
+        left: &'static str,
+        right: &'static str,
+    },
+    /// A field is assigned on a variable the rule otherwise only uses as a
+    /// plain value, never as an object
+    FieldOfScalar { rule: String, variable: String },
+}
+
+impl fmt::Display for RuleWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleWarning::NeverMatches(rule) => {
+                write!(f, "Rule '{}' has a condition that is always false", rule)
+            }
+            RuleWarning::NoActions(rule) => write!(f, "Rule '{}' has no actions", rule),
+            RuleWarning::IncompatibleComparison { rule, left, right } => {
+                write!(f, "Rule '{}' compares a {} with a {}", rule, left, right)
+            }
+            RuleWarning::FieldOfScalar { rule, variable } => write!(
+                f,
+                "Rule '{}' assigns a field of '{}', which it only uses as a value",
+                rule, variable
+            ),
+        }
+    }
+}
+
+/// A rewrite of a rule, run when the rule is added to a knowledge base. A
+/// pass may change how a rule is evaluated but never what it does.
+pub trait Pass: Send + Sync {
+    /// A short name, shown in `PassManager`'s `Debug` output
+    fn name(&self) -> &str;
+
+    /// Rewrite `rule`, adding anything suspicious found to `warnings`
+    fn run(&self, rule: Rule, warnings: &mut Vec<RuleWarning>) -> Rule;
+}
+
+/// The passes a knowledge base runs over each rule added to it, in order.
+/// The default runs `ConstantFolding` alone; others are opted into with
+/// `pass`, e.g. `PassManager::default().pass(DeadBranchElimination)`.
+#[derive(Clone)]
+pub struct PassManager {
+    passes: Vec<Arc<dyn Pass>>,
+}
+
+impl PassManager {
+    /// A pipeline without passes, which keeps rules as written
+    pub fn new() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    /// Run `pass` after the ones added so far
+    pub fn pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Arc::new(pass));
+        self
+    }
+
+    pub fn run(&self, rule: Rule) -> (Rule, Vec<RuleWarning>) {
+        let mut warnings = Vec::new();
+        let rule = self
+            .passes
+            .iter()
+            .fold(rule, |rule, pass| pass.run(rule, &mut warnings));
+        (rule, warnings)
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new().pass(ConstantFolding)
+    }
+}
+
+impl fmt::Debug for PassManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.passes.iter().map(|pass| pass.name()))
+            .finish()
+    }
+}
+
+/// Rewrite each expression of a rule: those only used for their truthiness
+/// with `condition`, the others with `value`
+fn rewrite_rule(
+    mut rule: Rule,
+    condition: impl Fn(Expression) -> Expression,
+    value: impl Fn(Expression) -> Expression,
+) -> Rule {
+    let take = |expr: &mut Expression| std::mem::replace(expr, Expression::Null);
+    rule.when_condition = condition(take(&mut rule.when_condition));
+    for pattern in &mut rule.patterns {
+        pattern.constraint = pattern.constraint.take().map(&condition);
+    }
+    for accumulate in &mut rule.accumulates {
+        let pattern = &mut accumulate.pattern;
+        pattern.constraint = pattern.constraint.take().map(&condition);
+        accumulate.value = value(take(&mut accumulate.value));
+    }
+    for binding in &mut rule.bindings {
+        binding.value = value(take(&mut binding.value));
+    }
+    rule.then_actions = rule.then_actions.into_iter().map(value).collect();
+    rule
+}
+
+/// Evaluates literal sub-expressions ahead of execution. Folding never
+/// changes what a rule does: sub-expressions that would fail at runtime
+/// (division by zero, type errors) are left for the engine to report.
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &str {
+        "constant-folding"
+    }
+
+    fn run(&self, rule: Rule, warnings: &mut Vec<RuleWarning>) -> Rule {
+        let rule = rewrite_rule(rule, fold_condition, fold);
+        if never_true(&rule.when_condition) {
+            warnings.push(RuleWarning::NeverMatches(rule.name.clone()));
+        }
+        rule
+    }
+}
+
+/// Moves `!` inward through `&&`, `||` and equality in conditions, so
+/// `!(a && b != c)` becomes `!a || b == c` and `!!a` becomes `a`
+pub struct DeMorgan;
+
+impl Pass for DeMorgan {
+    fn name(&self) -> &str {
+        "de-morgan"
+    }
+
+    fn run(&self, rule: Rule, _: &mut Vec<RuleWarning>) -> Rule {
+        rewrite_rule(rule, push_negations, |expr| {
+            NegationPusher.fold_expression(expr)
+        })
+    }
+}
+
+/// Rewrite a condition so `!` applies to no `&&`, `||`, `!`, `==` or `!=`
+fn push_negations(condition: Expression) -> Expression {
+    match condition {
+        Expression::Not(inner) => negation(*inner),
+        Expression::And(l, r) => {
+            Expression::And(Box::new(push_negations(*l)), Box::new(push_negations(*r)))
+        }
+        Expression::Or(l, r) => {
+            Expression::Or(Box::new(push_negations(*l)), Box::new(push_negations(*r)))
+        }
+        other => NegationPusher.fold_expression(other),
+    }
+}
+
+/// A condition with the same truthiness as `!condition`
+fn negation(condition: Expression) -> Expression {
+    let fold = |expr: Box<Expression>| Box::new(NegationPusher.fold_expression(*expr));
+    match condition {
+        Expression::Not(inner) => push_negations(*inner),
+        Expression::And(l, r) => Expression::Or(Box::new(negation(*l)), Box::new(negation(*r))),
+        Expression::Or(l, r) => Expression::And(Box::new(negation(*l)), Box::new(negation(*r))),
+        Expression::Equal(l, r) => Expression::NotEqual(fold(l), fold(r)),
+        Expression::NotEqual(l, r) => Expression::Equal(fold(l), fold(r)),
+        other => Expression::Not(Box::new(NegationPusher.fold_expression(other))),
+    }
+}
+
+struct NegationPusher;
+
+impl ExpressionFolder for NegationPusher {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        match expr {
+            Expression::If(condition, then_actions, else_actions) => Expression::If(
+                Box::new(push_negations(*condition)),
+                then_actions
+                    .into_iter()
+                    .map(|e| self.fold_expression(e))
+                    .collect(),
+                else_actions
+                    .into_iter()
+                    .map(|e| self.fold_expression(e))
+                    .collect(),
+            ),
+            other => fold_children(self, other),
+        }
+    }
+}
+
+/// Replaces an `if` whose condition is a literal with the branch taken. Run
+/// it after `ConstantFolding`, which turns conditions such as `1 > 2` into
+/// literals.
+pub struct DeadBranchElimination;
+
+impl Pass for DeadBranchElimination {
+    fn name(&self) -> &str {
+        "dead-branch-elimination"
+    }
+
+    fn run(&self, mut rule: Rule, _: &mut Vec<RuleWarning>) -> Rule {
+        // Actions are statements: a dead `if` among them is dropped, not
+        // replaced by its value
+        let actions = std::mem::take(&mut rule.then_actions);
+        let live = |expr| BranchEliminator.fold_expression(expr);
+        let mut rule = rewrite_rule(rule, live, live);
+        rule.then_actions = live_actions(actions);
+        rule
+    }
+}
+
+/// Splice the taken branch of each `if` action with a literal condition into
+/// the surrounding actions
+fn live_actions(actions: Vec<Expression>) -> Vec<Expression> {
+    let mut live = Vec::with_capacity(actions.len());
+    for action in actions {
+        match action {
+            Expression::If(condition, then_actions, else_actions) => {
+                let condition = BranchEliminator.fold_expression(*condition);
+                match truthiness(&condition) {
+                    Some(true) => live.extend(live_actions(then_actions)),
+                    Some(false) => live.extend(live_actions(else_actions)),
+                    None => live.push(Expression::If(
+                        Box::new(condition),
+                        live_actions(then_actions),
+                        live_actions(else_actions),
+                    )),
+                }
+            }
+            other => live.push(BranchEliminator.fold_expression(other)),
+        }
+    }
+    live
+}
+
+struct BranchEliminator;
+
+impl ExpressionFolder for BranchEliminator {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        match expr {
+            // An `if` used as a value is the value of its taken branch
+            Expression::If(condition, then_actions, else_actions) => {
+                let condition = self.fold_expression(*condition);
+                let (then_actions, else_actions) =
+                    (live_actions(then_actions), live_actions(else_actions));
+                match (truthiness(&condition), then_actions, else_actions) {
+                    (Some(true), mut taken, _) | (Some(false), _, mut taken)
+                        if taken.len() <= 1 =>
+                    {
+                        taken.pop().unwrap_or(Expression::Null)
+                    }
+                    (_, then_actions, else_actions) => {
+                        Expression::If(Box::new(condition), then_actions, else_actions)
+                    }
+                }
+            }
+            Expression::ForEach(name, collection, body) => Expression::ForEach(
+                name,
+                Box::new(self.fold_expression(*collection)),
+                live_actions(body),
+            ),
+            other => fold_children(self, other),
+        }
+    }
+}
+
+/// Evaluates a sub-expression repeated within a condition once, binding it
+/// with `let ... in`: `a.x * 2 > 10 && a.x * 2 < 20` becomes
+/// `let _cse0 = a.x * 2 in _cse0 > 10 && _cse0 < 20`. Calls to functions,
+/// which may not return the same value twice, are never extracted.
+pub struct CommonSubexpressionExtraction;
+
+impl Pass for CommonSubexpressionExtraction {
+    fn name(&self) -> &str {
+        "common-subexpression-extraction"
+    }
+
+    fn run(&self, rule: Rule, _: &mut Vec<RuleWarning>) -> Rule {
+        rewrite_rule(rule, extract_common, |expr| expr)
+    }
+}
+
+fn extract_common(condition: Expression) -> Expression {
+    let mut nodes = Vec::new();
+    collect_nodes(&condition, &mut nodes, false);
+    if nodes
+        .iter()
+        .any(|expr| matches!(expr, Expression::LetIn(..)))
+    {
+        return condition;
+    }
+    let taken: BTreeSet<String> = nodes
+        .iter()
+        .filter_map(|expr| match expr {
+            Expression::Variable(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut names = (0..)
+        .map(|n| format!("_cse{}", n))
+        .filter(|name| !taken.contains(name));
+
+    let mut bindings = Vec::new();
+    let mut body = condition;
+    while let Some(common) = repeated(&body) {
+        let name = names.next().unwrap_or_default();
+        body = substitute(body, &common, &name);
+        bindings.push((name, common));
+    }
+    bindings
+        .into_iter()
+        .rev()
+        .fold(body, |body, (name, value)| {
+            Expression::LetIn(name, Box::new(value), Box::new(body))
+        })
+}
+
+/// The largest sub-expression worth extracting that occurs more than once
+/// in `condition` and is evaluated whenever `condition` is
+fn repeated(condition: &Expression) -> Option<Expression> {
+    let mut all = Vec::new();
+    collect_nodes(condition, &mut all, false);
+    let mut evaluated = Vec::new();
+    collect_nodes(condition, &mut evaluated, true);
+    evaluated
+        .into_iter()
+        .filter(|expr| worth_extracting(expr))
+        .filter(|expr| all.iter().filter(|other| *other == expr).count() > 1)
+        .fold(None, |largest: Option<&Expression>, expr| match largest {
+            Some(largest) if size(largest) >= size(expr) => Some(largest),
+            _ => Some(expr),
+        })
+        .cloned()
+}
+
+/// Collect `expr` and its descendants, or with `evaluated_only` those always
+/// evaluated along with it: not the branches of an `if`, nor the
+/// operands of `??` and `?.`, where an unknown variable is not an error
+fn collect_nodes<'a>(expr: &'a Expression, nodes: &mut Vec<&'a Expression>, evaluated_only: bool) {
+    nodes.push(expr);
+    match expr {
+        Expression::If(condition, _, _) | Expression::ForEach(_, condition, _)
+            if evaluated_only =>
+        {
+            collect_nodes(condition, nodes, evaluated_only)
+        }
+        Expression::Coalesce(..) | Expression::OptionalFieldAccess(..) if evaluated_only => {}
+        _ => {
+            for child in expr.children() {
+                collect_nodes(child, nodes, evaluated_only);
+            }
+        }
+    }
+}
+
+fn worth_extracting(expr: &Expression) -> bool {
+    let field_path = |mut expr: &Expression| loop {
+        match expr {
+            Expression::FieldAccess(object, _) => expr = object,
+            other => return matches!(other, Expression::Variable(_)),
+        }
+    };
+    !expr.children().is_empty()
+        && !field_path(expr)
+        && !any_node(expr, &|expr| matches!(expr, Expression::FunctionCall(..)))
+}
+
+fn size(expr: &Expression) -> usize {
+    1 + expr.children().into_iter().map(size).sum::<usize>()
+}
+
+fn any_node(expr: &Expression, predicate: &dyn Fn(&Expression) -> bool) -> bool {
+    predicate(expr)
+        || expr
+            .children()
+            .into_iter()
+            .any(|child| any_node(child, predicate))
+}
+
+/// Replace every occurrence of `target` in `expr` with the variable `name`
+fn substitute(expr: Expression, target: &Expression, name: &str) -> Expression {
+    struct Substitute<'a>(&'a Expression, &'a str);
+
+    impl ExpressionFolder for Substitute<'_> {
+        fn fold_expression(&mut self, expr: Expression) -> Expression {
+            if expr == *self.0 {
+                Expression::Variable(self.1.to_string())
+            } else {
+                fold_children(self, expr)
+            }
+        }
+    }
+
+    Substitute(target, name).fold_expression(expr)
+}
+
+/// Fold an expression whose value is only used for its truthiness, which also
+/// allows dropping `true &&` and `false ||` operands
+fn fold_condition(expr: Expression) -> Expression {
+    match expr {
+        Expression::And(left, right) => {
+            let (left, right) = (fold_condition(*left), fold_condition(*right));
+            match (truthiness(&left), truthiness(&right)) {
+                (Some(true), _) => right,
+                (_, Some(true)) => left,
+                _ => fold(Expression::And(Box::new(left), Box::new(right))),
+            }
+        }
+        Expression::Or(left, right) => {
+            let (left, right) = (fold_condition(*left), fold_condition(*right));
+            match (truthiness(&left), truthiness(&right)) {
+                (Some(false), _) => right,
+                (_, Some(false)) => left,
+                _ => fold(Expression::Or(Box::new(left), Box::new(right))),
+            }
+        }
+        Expression::Not(inner) => fold(Expression::Not(Box::new(fold_condition(*inner)))),
+        other => fold(other),
+    }
+}
+
+fn fold(expr: Expression) -> Expression {
+    if let Some((op, _, _)) = BinaryOp::split(&expr) {
+        let (left, right) = match expr {
+            Expression::Add(l, r)
+            | Expression::Subtract(l, r)
+            | Expression::Multiply(l, r)
+            | Expression::Divide(l, r)
+            | Expression::Modulo(l, r)
+            | Expression::Equal(l, r)
+            | Expression::NotEqual(l, r)
+            | Expression::LessThan(l, r)
+            | Expression::LessEqual(l, r)
+            | Expression::GreaterThan(l, r)
+            | Expression::GreaterEqual(l, r)
+            | Expression::And(l, r)
+            | Expression::Or(l, r) => (fold(*l), fold(*r)),
+            _ => unreachable!("split only matches binary expressions"),
+        };
+        if let (Some(l), Some(r)) = (literal(&left), literal(&right)) {
+            if policy_independent(op, &l, &r) {
+                if let Some(folded) = op
+                    .apply(
+                        l,
+                        r,
+                        &NumericPolicy::default(),
+                        Collation::default(),
+                        CoercionPolicy::default(),
+                    )
+                    .ok()
+                    .filter(|value| value.as_number().is_none_or(f64::is_finite))
+                    .and_then(into_literal)
+                {
+                    return folded;
+                }
+            }
+        }
+        return op.join(left, right);
+    }
+
+    match expr {
+        Expression::Not(inner) => {
+            let inner = fold(*inner);
+            match truthiness(&inner) {
+                Some(truthy) => Expression::Boolean(!truthy),
+                None => Expression::Not(Box::new(inner)),
+            }
+        }
+        Expression::If(condition, then_actions, else_actions) => Expression::If(
+            Box::new(fold_condition(*condition)),
+            then_actions.into_iter().map(fold).collect(),
+            else_actions.into_iter().map(fold).collect(),
+        ),
+        // Calls are never folded away, since functions may depend on the context
+        other => fold_children(&mut ConstantFolder, other),
+    }
+}
+
+struct ConstantFolder;
+
+impl ExpressionFolder for ConstantFolder {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold(expr)
+    }
+}
+
+/// Whether applying `op` to the literals gives the same result under every
+/// numeric policy, collation and coercion policy (provided the result is
+/// finite), since rules are folded before the engine's configuration is known
+fn policy_independent(op: BinaryOp, left: &FactValue, right: &FactValue) -> bool {
+    if let (Some(a), Some(b)) = (left.as_number(), right.as_number()) {
+        return a.is_finite()
+            && b.is_finite()
+            && !matches!(op, BinaryOp::Equal | BinaryOp::NotEqual);
+    }
+    let comparison = matches!(
+        op,
+        BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::LessThan
+            | BinaryOp::LessEqual
+            | BinaryOp::GreaterThan
+            | BinaryOp::GreaterEqual
+    );
+    if comparison && std::mem::discriminant(left) != std::mem::discriminant(right) {
+        return false;
+    }
+    match (left, right) {
+        (FactValue::String(_), FactValue::String(_)) => !matches!(
+            op,
+            BinaryOp::LessThan
+                | BinaryOp::LessEqual
+                | BinaryOp::GreaterThan
+                | BinaryOp::GreaterEqual
+        ),
+        _ => true,
+    }
+}
+
+/// Whether a folded condition can never be truthy. `x && false` is not folded
+/// away since evaluating `x` may still fail, but it can never match.
+fn never_true(condition: &Expression) -> bool {
+    match condition {
+        Expression::And(left, right) => never_true(left) || never_true(right),
+        Expression::Or(left, right) => never_true(left) && never_true(right),
+        other => truthiness(other) == Some(false),
+    }
+}
+
+fn literal(expr: &Expression) -> Option<FactValue> {
+    match expr {
+        Expression::String(s) => Some(FactValue::String(s.clone())),
+        Expression::Number(n) => Some(FactValue::Number(*n)),
+        Expression::Boolean(b) => Some(FactValue::Boolean(*b)),
+        Expression::Duration(ms) => i64::try_from(*ms).ok().map(FactValue::Duration),
+        _ => None,
+    }
+}
+
+fn into_literal(value: FactValue) -> Option<Expression> {
+    match value {
+        FactValue::String(s) => Some(Expression::String(s)),
+        FactValue::Number(n) => Some(Expression::Number(n)),
+        FactValue::Boolean(b) => Some(Expression::Boolean(b)),
+        FactValue::Duration(ms) => u64::try_from(ms).ok().map(Expression::Duration),
+        _ => None,
+    }
+}
+
+fn truthiness(expr: &Expression) -> Option<bool> {
+    literal(expr).map(|value| value.is_truthy())
+}
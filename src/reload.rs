@@ -0,0 +1,142 @@
+use crate::engine::RuleEngine;
+use crate::error::RunesError;
+use crate::knowledge_base::KnowledgeBase;
+use crate::loader::{self, LoadError};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Load(#[from] LoadError),
+    /// The engine rejected the loaded rules, e.g. one fails its schemas
+    #[error(transparent)]
+    Rejected(#[from] RunesError),
+}
+
+/// Outcome of reloading the rules after a `.grl` file changed: the number of
+/// rules now loaded, or why the engine kept its previous rules
+#[derive(Debug)]
+pub struct Reload {
+    pub outcome: Result<usize, ReloadError>,
+}
+
+/// Loads the `.grl` files under a directory, and reloads them into an engine
+/// whenever one is added, changed or removed
+#[derive(Debug, Clone)]
+pub struct RuleWatcher {
+    dir: PathBuf,
+    interval: Duration,
+}
+
+impl KnowledgeBase {
+    /// Watch `dir` and its subdirectories for changes to `.grl` files
+    pub fn watch_dir(dir: impl Into<PathBuf>) -> RuleWatcher {
+        RuleWatcher {
+            dir: dir.into(),
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Modification time and length of each `.grl` file under the directory
+type Snapshot = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+impl RuleWatcher {
+    /// How often the directory is checked for changes
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Load the directory's rules as `KnowledgeBase::from_dir` does
+    pub fn load(&self) -> Result<KnowledgeBase, LoadError> {
+        KnowledgeBase::from_dir(&self.dir)
+    }
+
+    /// Check the directory on a background thread, swapping a freshly loaded
+    /// knowledge base into `engine` after every change and reporting each
+    /// reload to `on_reload`. Executions in progress finish with the rules
+    /// they started with. If a file fails to load, or the engine rejects a
+    /// rule, the engine keeps its current rules until the files are fixed.
+    pub fn start<F>(self, engine: Arc<RwLock<RuleEngine>>, mut on_reload: F) -> WatchHandle
+    where
+        F: FnMut(Reload) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let mut seen = snapshot(&self.dir).ok();
+
+        let thread = thread::spawn(move || {
+            while !stop_flag.load(Ordering::SeqCst) {
+                thread::sleep(self.interval);
+                let current = snapshot(&self.dir).ok();
+                if current == seen {
+                    continue;
+                }
+                seen = current;
+
+                let outcome = self
+                    .load()
+                    .map_err(ReloadError::from)
+                    .and_then(|knowledge_base| {
+                        let rules = knowledge_base.len();
+                        engine
+                            .write()
+                            .unwrap_or_else(|p| p.into_inner())
+                            .set_knowledge_base(Arc::new(knowledge_base))?;
+                        Ok(rules)
+                    });
+                on_reload(Reload { outcome });
+            }
+        });
+
+        WatchHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+fn snapshot(dir: &Path) -> io::Result<Snapshot> {
+    let mut files = Vec::new();
+    loader::grl_files(dir, &mut files)?;
+    files
+        .into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path)?;
+            Ok((path, (metadata.modified()?, metadata.len())))
+        })
+        .collect()
+}
+
+/// Stops watching when `stop` is called or the handle is dropped
+pub struct WatchHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
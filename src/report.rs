@@ -0,0 +1,141 @@
+use crate::engine::ExecutionResult;
+use crate::facts::FactValue;
+use crate::working_memory::WorkingMemory;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// A type error that was treated as a non-match because strict typing is disabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuppressedError {
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CycleReport {
+    pub cycle: usize,
+    pub rules_fired: Vec<String>,
+}
+
+/// A fact, or a field of an object fact, whose value changed. `None` means
+/// the fact or field did not exist.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueChange {
+    pub fact: String,
+    pub field: Option<String>,
+    pub before: Option<FactValue>,
+    pub after: Option<FactValue>,
+}
+
+/// The rule that wrote a changed value, see `Fact::provenance`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldProvenance {
+    pub fact: String,
+    pub field: Option<String>,
+    pub rule: String,
+    pub cycle: usize,
+}
+
+/// A record of a single execution, suitable for storing as an audit trail
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    pub cycles: Vec<CycleReport>,
+    pub changes: Vec<ValueChange>,
+    /// Which rule wrote each of `changes`, when
+    /// `EngineConfig::track_provenance` is set
+    #[serde(default)]
+    pub provenance: Vec<FieldProvenance>,
+    pub suppressed_errors: Vec<SuppressedError>,
+    pub execution_time_ms: u128,
+}
+
+impl ExecutionReport {
+    pub(crate) fn new(
+        result: &ExecutionResult,
+        before: &WorkingMemory,
+        after: &WorkingMemory,
+    ) -> Self {
+        let cycles = result
+            .rules_fired_by_cycle
+            .iter()
+            .enumerate()
+            .map(|(i, rules_fired)| CycleReport {
+                cycle: i + 1,
+                rules_fired: rules_fired.clone(),
+            })
+            .collect();
+
+        let changes = diff(before, after);
+        let provenance = changes
+            .iter()
+            .filter_map(|change| {
+                let fact = after.get(&change.fact)?;
+                let written = fact.provenance(change.field.as_deref().unwrap_or_default())?;
+                Some(FieldProvenance {
+                    fact: change.fact.clone(),
+                    field: change.field.clone(),
+                    rule: written.rule.clone(),
+                    cycle: written.cycle,
+                })
+            })
+            .collect();
+
+        Self {
+            cycles,
+            changes,
+            provenance,
+            suppressed_errors: result.suppressed_errors.clone(),
+            execution_time_ms: result.execution_time_ms,
+        }
+    }
+}
+
+/// Changed values between two states of working memory, ordered by fact and
+/// field name. Object facts are compared field by field.
+pub(crate) fn diff(before: &WorkingMemory, after: &WorkingMemory) -> Vec<ValueChange> {
+    let names: BTreeSet<&str> = before.names().chain(after.names()).collect();
+    let mut changes = Vec::new();
+
+    for name in names {
+        let old = before.get(name).map(|fact| &fact.value);
+        let new = after.get(name).map(|fact| &fact.value);
+        fact_changes(name, old, new, &mut changes);
+    }
+
+    changes
+}
+
+/// The changes between two values of one fact: one per changed field when
+/// both are objects, otherwise one for the whole fact
+pub(crate) fn fact_changes(
+    name: &str,
+    old: Option<&FactValue>,
+    new: Option<&FactValue>,
+    changes: &mut Vec<ValueChange>,
+) {
+    if old == new {
+        return;
+    }
+
+    if let (Some(FactValue::Object(old_fields)), Some(FactValue::Object(new_fields))) = (old, new) {
+        let fields: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+        for field in fields {
+            let (old_value, new_value) = (old_fields.get(field), new_fields.get(field));
+            if old_value != new_value {
+                changes.push(ValueChange {
+                    fact: name.to_string(),
+                    field: Some(field.clone()),
+                    before: old_value.cloned(),
+                    after: new_value.cloned(),
+                });
+            }
+        }
+    } else {
+        changes.push(ValueChange {
+            fact: name.to_string(),
+            field: None,
+            before: old.cloned(),
+            after: new.cloned(),
+        });
+    }
+}
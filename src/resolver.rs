@@ -0,0 +1,13 @@
+use crate::engine::EngineError;
+use crate::facts::FactValue;
+use std::fmt::Debug;
+
+/// Source of facts that are not in working memory, such as a database or an
+/// API. The engine only asks for a fact when a rule reads it and it is not
+/// in working memory, and asks at most once per fact per execution.
+///
+/// Resolved facts can be read by rules but are not added to working memory.
+pub trait FactResolver: Debug + Send + Sync {
+    /// The value of the named fact, or `None` if it does not exist
+    fn resolve(&self, name: &str) -> Result<Option<FactValue>, EngineError>;
+}
@@ -0,0 +1,177 @@
+use crate::ast::Expression;
+use crate::knowledge_base::KnowledgeBase;
+use crate::rule::Rule;
+
+/// How a rule differs between two knowledge bases, see `KnowledgeBase::diff`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleChange {
+    Added(String),
+    Removed(String),
+    Modified { rule: String, edits: Vec<RuleEdit> },
+}
+
+/// One difference within a modified rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleEdit {
+    /// A property other than an expression changed: `salience`,
+    /// `description`, `schedule`, `tags`, `package`, `metadata`,
+    /// `effective_from`, `expires_at`, `patterns`, `accumulates` or `bindings`
+    Property(&'static str),
+    /// The smallest sub-expression that changed. `path` names where it is,
+    /// e.g. `when.1` for the right operand of the condition, `then[2]` for the
+    /// third action or `patterns[0].constraint`. A missing side means an
+    /// action was added or removed.
+    Expression {
+        path: String,
+        old: Option<Expression>,
+        new: Option<Expression>,
+    },
+}
+
+impl KnowledgeBase {
+    /// The rules added to, removed from or modified between `old` and `new`,
+    /// e.g. for reviewing a deployment. Removed and modified rules come in
+    /// `old`'s order, followed by added rules in `new`'s order.
+    pub fn diff(old: &KnowledgeBase, new: &KnowledgeBase) -> Vec<RuleChange> {
+        let mut changes = Vec::new();
+        for old_rule in old.get_rules() {
+            match new.get_rule(&old_rule.name) {
+                None => changes.push(RuleChange::Removed(old_rule.name.clone())),
+                Some(new_rule) if new_rule != old_rule => changes.push(RuleChange::Modified {
+                    rule: old_rule.name.clone(),
+                    edits: rule_edits(old_rule, new_rule),
+                }),
+                Some(_) => {}
+            }
+        }
+        for new_rule in new.get_rules() {
+            if old.get_rule(&new_rule.name).is_none() {
+                changes.push(RuleChange::Added(new_rule.name.clone()));
+            }
+        }
+        changes
+    }
+}
+
+fn rule_edits(old: &Rule, new: &Rule) -> Vec<RuleEdit> {
+    let mut edits = Vec::new();
+    let properties = [
+        ("salience", old.salience == new.salience),
+        ("description", old.description == new.description),
+        ("schedule", old.schedule == new.schedule),
+        ("tags", old.tags == new.tags),
+        ("package", old.package == new.package),
+        ("metadata", old.metadata == new.metadata),
+        ("effective_from", old.effective_from == new.effective_from),
+        ("expires_at", old.expires_at == new.expires_at),
+        ("accumulates", old.accumulates == new.accumulates),
+        ("bindings", old.bindings == new.bindings),
+    ];
+    edits.extend(
+        properties
+            .into_iter()
+            .filter(|(_, same)| !same)
+            .map(|(name, _)| RuleEdit::Property(name)),
+    );
+
+    let same_patterns = old.patterns.len() == new.patterns.len()
+        && old
+            .patterns
+            .iter()
+            .zip(&new.patterns)
+            .all(|(a, b)| a.binding == b.binding && a.fact_type == b.fact_type);
+    if same_patterns {
+        for (i, (a, b)) in old.patterns.iter().zip(&new.patterns).enumerate() {
+            let path = format!("patterns[{}].constraint", i);
+            diff_optional(
+                &path,
+                a.constraint.as_ref(),
+                b.constraint.as_ref(),
+                &mut edits,
+            );
+        }
+    } else {
+        edits.push(RuleEdit::Property("patterns"));
+    }
+
+    diff_expression("when", &old.when_condition, &new.when_condition, &mut edits);
+    for i in 0..old.then_actions.len().max(new.then_actions.len()) {
+        let path = format!("then[{}]", i);
+        diff_optional(
+            &path,
+            old.then_actions.get(i),
+            new.then_actions.get(i),
+            &mut edits,
+        );
+    }
+    edits
+}
+
+fn diff_optional(
+    path: &str,
+    old: Option<&Expression>,
+    new: Option<&Expression>,
+    edits: &mut Vec<RuleEdit>,
+) {
+    match (old, new) {
+        (Some(old), Some(new)) => diff_expression(path, old, new, edits),
+        (None, None) => {}
+        (old, new) => edits.push(RuleEdit::Expression {
+            path: path.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+    }
+}
+
+fn diff_expression(path: &str, old: &Expression, new: &Expression, edits: &mut Vec<RuleEdit>) {
+    if old == new {
+        return;
+    }
+    if !same_node(old, new) {
+        edits.push(RuleEdit::Expression {
+            path: path.to_string(),
+            old: Some(old.clone()),
+            new: Some(new.clone()),
+        });
+        return;
+    }
+    for (i, (old, new)) in old.children().into_iter().zip(new.children()).enumerate() {
+        diff_expression(&format!("{}.{}", path, i), old, new, edits);
+    }
+}
+
+/// Whether two expressions differ only in their children, so a change can be
+/// narrowed down to the children that differ
+fn same_node(a: &Expression, b: &Expression) -> bool {
+    if std::mem::discriminant(a) != std::mem::discriminant(b) {
+        return false;
+    }
+    match (a, b) {
+        (Expression::FieldAccess(_, a), Expression::FieldAccess(_, b))
+        | (Expression::OptionalFieldAccess(_, a), Expression::OptionalFieldAccess(_, b))
+        | (Expression::Assignment(a, _), Expression::Assignment(b, _))
+        | (Expression::InsertLogical(a, _), Expression::InsertLogical(b, _))
+        | (Expression::Let(a, _), Expression::Let(b, _))
+        | (Expression::LetIn(a, _, _), Expression::LetIn(b, _, _)) => a == b,
+        (
+            Expression::FieldAssignment(a, a_field, _),
+            Expression::FieldAssignment(b, b_field, _),
+        ) => a == b && a_field == b_field,
+        (Expression::FunctionCall(a, a_args), Expression::FunctionCall(b, b_args)) => {
+            a == b && a_args.len() == b_args.len()
+        }
+        (Expression::MethodCall(_, a, a_args), Expression::MethodCall(_, b, b_args)) => {
+            a == b && a_args.len() == b_args.len()
+        }
+        (Expression::Array(a), Expression::Array(b)) => a.len() == b.len(),
+        (Expression::If(_, a_then, a_else), Expression::If(_, b_then, b_else)) => {
+            a_then.len() == b_then.len() && a_else.len() == b_else.len()
+        }
+        (Expression::ForEach(a, _, a_actions), Expression::ForEach(b, _, b_actions)) => {
+            a == b && a_actions.len() == b_actions.len()
+        }
+        // Leaves are the same only if equal
+        _ => !a.children().is_empty(),
+    }
+}
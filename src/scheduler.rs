@@ -0,0 +1,147 @@
+use crate::datetime::Civil;
+use crate::engine::RuleEngine;
+use crate::rule::Schedule;
+use crate::working_memory::WorkingMemory;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Outcome of one scheduled evaluation of a rule
+#[derive(Debug)]
+pub struct ScheduledRun {
+    pub rule: String,
+    pub outcome: crate::Result<bool>,
+}
+
+/// Re-runs rules that carry a `Schedule` against a shared working memory on a
+/// background thread
+pub struct Scheduler {
+    engine: Arc<RuleEngine>,
+    facts: Arc<Mutex<WorkingMemory>>,
+    tick: Duration,
+}
+
+impl Scheduler {
+    pub fn new(engine: Arc<RuleEngine>, facts: Arc<Mutex<WorkingMemory>>) -> Self {
+        Self {
+            engine,
+            facts,
+            tick: Duration::from_millis(100),
+        }
+    }
+
+    /// How often the scheduler checks for due rules
+    pub fn tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Start the scheduler, reporting every run to `on_run`
+    pub fn start<F>(self, mut on_run: F) -> SchedulerHandle
+    where
+        F: FnMut(ScheduledRun) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+
+        let thread = thread::spawn(move || {
+            let mut next_due: HashMap<String, Instant> = HashMap::new();
+            // Minute since the epoch of each cron rule's last run
+            let mut last_minute: HashMap<String, i64> = HashMap::new();
+            let started = Instant::now();
+
+            while !stop_flag.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                for rule in self
+                    .engine
+                    .get_knowledge_base()
+                    .get_rules_sorted_by_salience()
+                {
+                    match &rule.schedule {
+                        Some(Schedule::Interval(interval)) => {
+                            let due = next_due
+                                .entry(rule.name.clone())
+                                .or_insert(started + *interval);
+                            if *due > now {
+                                continue;
+                            }
+                            *due = now + *interval;
+                        }
+                        // Once in each minute of the engine's clock that matches
+                        Some(Schedule::Cron(cron)) => {
+                            let minute = self.engine.config().clock.now_millis().div_euclid(60_000);
+                            let time = Civil::from_millis(minute * 60_000);
+                            let weekday = time.weekday() % 7;
+                            if last_minute.get(&rule.name) == Some(&minute)
+                                || !cron.matches(
+                                    time.minute,
+                                    time.hour,
+                                    time.day,
+                                    time.month,
+                                    weekday,
+                                )
+                            {
+                                continue;
+                            }
+                            last_minute.insert(rule.name.clone(), minute);
+                        }
+                        None => continue,
+                    }
+
+                    let mut facts = self.facts.lock().unwrap_or_else(|p| p.into_inner());
+                    let outcome = self.engine.run_single(rule, &mut facts).map_err(Into::into);
+                    drop(facts);
+
+                    on_run(ScheduledRun {
+                        rule: rule.name.clone(),
+                        outcome,
+                    });
+                }
+                thread::sleep(self.tick);
+            }
+        });
+
+        SchedulerHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Start the scheduler, reporting runs through a channel
+    pub fn start_with_channel(self) -> (SchedulerHandle, Receiver<ScheduledRun>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = self.start(move |run| {
+            // The receiver may have been dropped; the scheduler keeps running until stopped
+            let _ = sender.send(run);
+        });
+        (handle, receiver)
+    }
+}
+
+/// Stops the scheduler thread when `stop` is called or the handle is dropped
+pub struct SchedulerHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
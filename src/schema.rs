@@ -0,0 +1,176 @@
+use crate::facts::FactValue;
+use crate::parser::ParseError;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The type a schema field must have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    /// Any numeric value: `Number`, `Integer` or `Decimal`
+    Number,
+    Integer,
+    Decimal,
+    Boolean,
+    DateTime,
+    Duration,
+    Bytes,
+    Money,
+    GeoPoint,
+    Array,
+    Object,
+    Any,
+}
+
+impl FieldType {
+    pub fn accepts(&self, value: &FactValue) -> bool {
+        matches!(
+            (self, value),
+            (FieldType::Any, _)
+                | (FieldType::String, FactValue::String(_))
+                | (
+                    FieldType::Number,
+                    FactValue::Number(_) | FactValue::Integer(_) | FactValue::Decimal(_)
+                )
+                | (FieldType::Integer, FactValue::Integer(_))
+                | (FieldType::Decimal, FactValue::Decimal(_))
+                | (FieldType::Boolean, FactValue::Boolean(_))
+                | (FieldType::DateTime, FactValue::DateTime(_))
+                | (FieldType::Duration, FactValue::Duration(_))
+                | (FieldType::Bytes, FactValue::Bytes(_))
+                | (FieldType::Money, FactValue::Money { .. })
+                | (FieldType::GeoPoint, FactValue::GeoPoint { .. })
+                | (FieldType::Array, FactValue::Array(_))
+                | (FieldType::Object, FactValue::Object(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FieldSpec {
+    field_type: FieldType,
+    required: bool,
+}
+
+/// The fields an object fact must have, registered for a fact name with
+/// `RuleEngine::register_schema`. Fields not declared are allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FactSchema {
+    fields: BTreeMap<String, FieldSpec>,
+}
+
+impl FactSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn required(mut self, field: impl Into<String>, field_type: FieldType) -> Self {
+        self.fields.insert(
+            field.into(),
+            FieldSpec {
+                field_type,
+                required: true,
+            },
+        );
+        self
+    }
+
+    /// A field that may be missing or null, but has `field_type` otherwise
+    pub fn optional(mut self, field: impl Into<String>, field_type: FieldType) -> Self {
+        self.fields.insert(
+            field.into(),
+            FieldSpec {
+                field_type,
+                required: false,
+            },
+        );
+        self
+    }
+
+    /// The declared type of a field, or `None` if the schema does not declare it
+    pub fn field_type(&self, field: &str) -> Option<FieldType> {
+        self.fields.get(field).map(|spec| spec.field_type)
+    }
+
+    /// Check a value against the schema, describing the first mismatch
+    pub fn validate(&self, value: &FactValue) -> Result<(), String> {
+        let FactValue::Object(object) = value else {
+            return Err(format!("expected an object, found {}", value.type_name()));
+        };
+        for (field, spec) in &self.fields {
+            match object.get(field) {
+                None if spec.required => return Err(format!("missing field '{}'", field)),
+                None | Some(FactValue::Null) if !spec.required => {}
+                Some(value) if !spec.field_type.accepts(value) => {
+                    return Err(format!(
+                        "field '{}' should be {:?}, found {}",
+                        field,
+                        spec.field_type,
+                        value.type_name()
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An enumeration of named variants, registered with
+/// `RuleEngine::register_enum` so rules can only use declared variants.
+/// Parses from a declaration such as `"OrderStatus: NEW | PAID | SHIPPED"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumType {
+    name: String,
+    variants: Vec<String>,
+}
+
+impl EnumType {
+    pub fn new<I, S>(name: impl Into<String>, variants: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            variants: variants.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variants(&self) -> &[String] {
+        &self.variants
+    }
+
+    pub fn has_variant(&self, variant: &str) -> bool {
+        self.variants.iter().any(|v| v == variant)
+    }
+
+    /// The `FactValue::Enum` for a declared variant
+    pub fn value(&self, variant: &str) -> Option<FactValue> {
+        self.has_variant(variant).then(|| FactValue::Enum {
+            enum_type: self.name.clone(),
+            variant: variant.to_string(),
+        })
+    }
+}
+
+impl FromStr for EnumType {
+    type Err = ParseError;
+
+    fn from_str(declaration: &str) -> Result<Self, Self::Err> {
+        let is_name =
+            |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        let (name, variants) = declaration
+            .split_once(':')
+            .ok_or_else(|| ParseError::InvalidValue(declaration.to_string()))?;
+        let variants: Vec<&str> = variants.split('|').map(str::trim).collect();
+        if !is_name(name.trim()) || !variants.iter().all(|v| is_name(v)) {
+            return Err(ParseError::InvalidValue(declaration.to_string()));
+        }
+        Ok(Self::new(name.trim(), variants))
+    }
+}
@@ -0,0 +1,671 @@
+use crate::builtins;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Collation, NumericPolicy};
+use crate::engine::{array_position, index_array, negate, BinaryOp, EngineError};
+use crate::facts::{CoercionPolicy, FactValue};
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use pest::Parser;
+use pest_derive::Parser;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, OnceLock};
+
+/// Runs the body of `script """ ... """` actions, for logic that is awkward
+/// to express in GRL.
+///
+/// A script sees every fact in working memory by name. Values it changes or
+/// adds are written back, and facts it removes are retracted.
+pub trait ScriptEngine: Debug + Send + Sync {
+    fn run(&self, source: &str, facts: &mut HashMap<String, FactValue>) -> Result<(), EngineError>;
+}
+
+/// The script engine used when none is configured: a small imperative
+/// language over fact values.
+///
+/// ```text
+/// let total = 0;
+/// for item in order.items {
+///     if item.qty <= 0 { continue; }
+///     total += item.price * item.qty;
+/// }
+/// order.total = total;
+/// order.tags.push("priced");
+/// if total > 1000 { retract("coupon"); }
+/// ```
+///
+/// Facts are read and written by name, and `let` declares a variable local
+/// to its block. Statements are `let`, assignment (`=`, `+=`, `-=`, `*=`,
+/// `/=`, `%=`) to a variable, field or array element, `if` / `else`,
+/// `while`, `for name in array` (or a range, `0..n` or `0..=n`), `break` and
+/// `continue`. Expressions take GRL's operators and literals, arrays
+/// `[1, 2]` and objects `#{ name: "x" }`. Every GRL builtin can be called as
+/// a function or a method, so `items.len()` is `len(items)`, and a statement
+/// `items.push(x)` or `items.remove(x)` updates `items` in place.
+/// `retract("name")` removes a fact.
+#[derive(Debug, Clone)]
+pub struct ScriptInterpreter {
+    clock: Arc<dyn Clock>,
+    max_operations: u64,
+}
+
+impl Default for ScriptInterpreter {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            max_operations: 100_000,
+        }
+    }
+}
+
+impl ScriptInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The clock read by `now()`
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Most statements and loop iterations one run may execute, so a script
+    /// that never ends fails with `EngineError::ResourceLimit`
+    pub fn max_operations(mut self, max_operations: u64) -> Self {
+        self.max_operations = max_operations;
+        self
+    }
+}
+
+impl ScriptEngine for ScriptInterpreter {
+    fn run(&self, source: &str, facts: &mut HashMap<String, FactValue>) -> Result<(), EngineError> {
+        let statements = parse(source)?;
+        let mut run = Run {
+            interpreter: self,
+            facts,
+            scopes: Vec::new(),
+            operations: 0,
+        };
+        match run.block(&statements)? {
+            Flow::Next => Ok(()),
+            Flow::Break | Flow::Continue => Err(EngineError::EvaluationError(
+                "break and continue must be inside a loop".to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[grammar = "script.pest"]
+struct ScriptParser;
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Let(String, Expr),
+    Assign(Place, Option<BinaryOp>, Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    While(Expr, Vec<Statement>),
+    For(String, Expr, Vec<Statement>),
+    Break,
+    Continue,
+    Expr(Expr),
+}
+
+/// Where an assignment writes: a variable or fact, then the fields and
+/// indices within it
+#[derive(Debug, Clone)]
+struct Place {
+    name: String,
+    path: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Field(String),
+    Index(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Value(FactValue),
+    Array(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+    Variable(String),
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Method(Box<Expr>, String, Vec<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Range(Box<Expr>, Box<Expr>, bool),
+}
+
+impl Expr {
+    /// The place this expression reads, if it is a variable and the fields
+    /// and indices within it
+    fn place(&self) -> Option<Place> {
+        match self {
+            Expr::Variable(name) => Some(Place {
+                name: name.clone(),
+                path: Vec::new(),
+            }),
+            Expr::Field(object, field) => {
+                let mut place = object.place()?;
+                place.path.push(Step::Field(field.clone()));
+                Some(place)
+            }
+            Expr::Index(array, index) => {
+                let mut place = array.place()?;
+                place.path.push(Step::Index(index.as_ref().clone()));
+                Some(place)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Statement>, EngineError> {
+    let mut pairs = ScriptParser::parse(Rule::script, source).map_err(|error| {
+        let (line, column) = match error.line_col {
+            pest::error::LineColLocation::Pos(position)
+            | pest::error::LineColLocation::Span(position, _) => position,
+        };
+        EngineError::EvaluationError(format!(
+            "Invalid script at line {}, column {}: {}",
+            line,
+            column,
+            error.variant.message()
+        ))
+    })?;
+    statements(pairs.next().unwrap().into_inner())
+}
+
+fn statements(pairs: Pairs<Rule>) -> Result<Vec<Statement>, EngineError> {
+    pairs
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(statement)
+        .collect()
+}
+
+fn statement(pair: Pair<Rule>) -> Result<Statement, EngineError> {
+    let rule = pair.as_rule();
+    let mut inner = pair.into_inner();
+    Ok(match rule {
+        Rule::let_statement => {
+            let name = inner.next().unwrap().as_str().to_string();
+            Statement::Let(name, expression(inner.next().unwrap())?)
+        }
+        Rule::if_statement => {
+            let condition = expression(inner.next().unwrap())?;
+            let then = statements(inner.next().unwrap().into_inner())?;
+            let otherwise = match inner.next() {
+                Some(pair) if pair.as_rule() == Rule::block => statements(pair.into_inner())?,
+                Some(pair) => vec![statement(pair)?],
+                None => Vec::new(),
+            };
+            Statement::If(condition, then, otherwise)
+        }
+        Rule::while_statement => {
+            let condition = expression(inner.next().unwrap())?;
+            Statement::While(condition, statements(inner.next().unwrap().into_inner())?)
+        }
+        Rule::for_statement => {
+            let name = inner.next().unwrap().as_str().to_string();
+            let items = expression(inner.next().unwrap())?;
+            Statement::For(name, items, statements(inner.next().unwrap().into_inner())?)
+        }
+        Rule::break_statement => Statement::Break,
+        Rule::continue_statement => Statement::Continue,
+        Rule::assignment => {
+            let mut place_pairs = inner.next().unwrap().into_inner();
+            let name = place_pairs.next().unwrap().as_str().to_string();
+            let path = place_pairs
+                .map(|step| match step.as_rule() {
+                    Rule::field => Ok(Step::Field(
+                        step.into_inner().next().unwrap().as_str().to_string(),
+                    )),
+                    _ => Ok(Step::Index(expression(step.into_inner().next().unwrap())?)),
+                })
+                .collect::<Result<_, EngineError>>()?;
+            let op = match inner.next().unwrap().as_str() {
+                "+=" => Some(BinaryOp::Add),
+                "-=" => Some(BinaryOp::Subtract),
+                "*=" => Some(BinaryOp::Multiply),
+                "/=" => Some(BinaryOp::Divide),
+                "%=" => Some(BinaryOp::Modulo),
+                _ => None,
+            };
+            let value = expression(inner.next().unwrap())?;
+            Statement::Assign(Place { name, path }, op, value)
+        }
+        _ => {
+            let expr = expression(inner.next().unwrap())?;
+            // `items.push(x)` on its own updates `items`
+            match &expr {
+                Expr::Method(receiver, method, _) if method == "push" || method == "remove" => {
+                    match receiver.place() {
+                        Some(place) => Statement::Assign(place, None, expr),
+                        None => Statement::Expr(expr),
+                    }
+                }
+                _ => Statement::Expr(expr),
+            }
+        }
+    })
+}
+
+fn pratt() -> &'static PrattParser<Rule> {
+    static PRATT: OnceLock<PrattParser<Rule>> = OnceLock::new();
+    PRATT.get_or_init(|| {
+        PrattParser::new()
+            .op(Op::infix(Rule::or, Assoc::Left))
+            .op(Op::infix(Rule::and, Assoc::Left))
+            .op(Op::infix(Rule::eq, Assoc::Left) | Op::infix(Rule::ne, Assoc::Left))
+            .op(Op::infix(Rule::lt, Assoc::Left)
+                | Op::infix(Rule::le, Assoc::Left)
+                | Op::infix(Rule::gt, Assoc::Left)
+                | Op::infix(Rule::ge, Assoc::Left))
+            .op(Op::infix(Rule::range, Assoc::Left) | Op::infix(Rule::range_inclusive, Assoc::Left))
+            .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+            .op(Op::infix(Rule::mul, Assoc::Left)
+                | Op::infix(Rule::div, Assoc::Left)
+                | Op::infix(Rule::rem, Assoc::Left))
+            .op(Op::prefix(Rule::neg) | Op::prefix(Rule::not))
+            .op(Op::postfix(Rule::method) | Op::postfix(Rule::field) | Op::postfix(Rule::index))
+    })
+}
+
+fn expression(pair: Pair<Rule>) -> Result<Expr, EngineError> {
+    pratt()
+        .map_primary(primary)
+        .map_prefix(|op, operand| {
+            let operand = Box::new(operand?);
+            Ok(match op.as_rule() {
+                Rule::neg => Expr::Negate(operand),
+                _ => Expr::Not(operand),
+            })
+        })
+        .map_postfix(|operand, op| {
+            let operand = Box::new(operand?);
+            let rule = op.as_rule();
+            let mut inner = op.into_inner();
+            Ok(match rule {
+                Rule::method => {
+                    let name = inner.next().unwrap().as_str().to_string();
+                    Expr::Method(operand, name, arguments(inner.next().unwrap())?)
+                }
+                Rule::field => Expr::Field(operand, inner.next().unwrap().as_str().to_string()),
+                _ => Expr::Index(operand, Box::new(expression(inner.next().unwrap())?)),
+            })
+        })
+        .map_infix(|left, op, right| {
+            let (left, right) = (Box::new(left?), Box::new(right?));
+            let op = match op.as_rule() {
+                Rule::range => return Ok(Expr::Range(left, right, false)),
+                Rule::range_inclusive => return Ok(Expr::Range(left, right, true)),
+                Rule::or => BinaryOp::Or,
+                Rule::and => BinaryOp::And,
+                Rule::eq => BinaryOp::Equal,
+                Rule::ne => BinaryOp::NotEqual,
+                Rule::lt => BinaryOp::LessThan,
+                Rule::le => BinaryOp::LessEqual,
+                Rule::gt => BinaryOp::GreaterThan,
+                Rule::ge => BinaryOp::GreaterEqual,
+                Rule::add => BinaryOp::Add,
+                Rule::sub => BinaryOp::Subtract,
+                Rule::mul => BinaryOp::Multiply,
+                Rule::div => BinaryOp::Divide,
+                _ => BinaryOp::Modulo,
+            };
+            Ok(Expr::Binary(op, left, right))
+        })
+        .parse(pair.into_inner())
+}
+
+fn primary(pair: Pair<Rule>) -> Result<Expr, EngineError> {
+    let rule = pair.as_rule();
+    let text = pair.as_str();
+    Ok(match rule {
+        Rule::expression => expression(pair)?,
+        Rule::number => Expr::Value(match text.parse::<i64>() {
+            Ok(n) => FactValue::Integer(n),
+            Err(_) => FactValue::Number(
+                text.parse()
+                    .map_err(|_| EngineError::InvalidNumber(text.to_string()))?,
+            ),
+        }),
+        Rule::string => Expr::Value(FactValue::String(unescape(
+            pair.into_inner().next().unwrap().as_str(),
+        ))),
+        Rule::boolean => Expr::Value(FactValue::Boolean(text == "true")),
+        Rule::null => Expr::Value(FactValue::Null),
+        Rule::array => Expr::Array(
+            pair.into_inner()
+                .map(expression)
+                .collect::<Result<_, _>>()?,
+        ),
+        Rule::map => Expr::Object(
+            pair.into_inner()
+                .map(|entry| {
+                    let mut inner = entry.into_inner();
+                    let key = inner.next().unwrap();
+                    let key = match key.as_rule() {
+                        Rule::string => unescape(key.into_inner().next().unwrap().as_str()),
+                        _ => key.as_str().to_string(),
+                    };
+                    Ok((key, expression(inner.next().unwrap())?))
+                })
+                .collect::<Result<_, EngineError>>()?,
+        ),
+        Rule::call => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            Expr::Call(name, arguments(inner.next().unwrap())?)
+        }
+        _ => Expr::Variable(text.to_string()),
+    })
+}
+
+fn arguments(pair: Pair<Rule>) -> Result<Vec<Expr>, EngineError> {
+    pair.into_inner().map(expression).collect()
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// What a statement leaves its enclosing loop to do
+enum Flow {
+    Next,
+    Break,
+    Continue,
+}
+
+/// The state of one script run
+struct Run<'a> {
+    interpreter: &'a ScriptInterpreter,
+    facts: &'a mut HashMap<String, FactValue>,
+    /// Local variables, innermost block last
+    scopes: Vec<HashMap<String, FactValue>>,
+    operations: u64,
+}
+
+impl Run<'_> {
+    fn tick(&mut self) -> Result<(), EngineError> {
+        self.operations += 1;
+        if self.operations > self.interpreter.max_operations {
+            return Err(EngineError::ResourceLimit(format!(
+                "script ran more than {} operations",
+                self.interpreter.max_operations
+            )));
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<Flow, EngineError> {
+        self.scopes.push(HashMap::new());
+        let flow = statements
+            .iter()
+            .map(|statement| self.statement(statement))
+            .find(|flow| !matches!(flow, Ok(Flow::Next)))
+            .unwrap_or(Ok(Flow::Next));
+        self.scopes.pop();
+        flow
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<Flow, EngineError> {
+        self.tick()?;
+        match statement {
+            Statement::Let(name, value) => {
+                let value = self.evaluate(value)?;
+                self.scopes
+                    .last_mut()
+                    .expect("statements run inside a block")
+                    .insert(name.clone(), value);
+            }
+            Statement::Assign(place, op, value) => {
+                let value = self.evaluate(value)?;
+                self.assign(place, *op, value)?;
+            }
+            Statement::If(condition, then, otherwise) => {
+                return if self.evaluate(condition)?.is_truthy() {
+                    self.block(then)
+                } else {
+                    self.block(otherwise)
+                };
+            }
+            Statement::While(condition, body) => {
+                while self.evaluate(condition)?.is_truthy() {
+                    self.tick()?;
+                    if let Flow::Break = self.block(body)? {
+                        break;
+                    }
+                }
+            }
+            Statement::For(name, items, body) => {
+                let items = match self.evaluate(items)? {
+                    FactValue::Array(items) => items,
+                    value => {
+                        return Err(EngineError::TypeError(format!(
+                            "Cannot loop over a {}",
+                            value.type_name()
+                        )))
+                    }
+                };
+                for item in items {
+                    self.tick()?;
+                    self.scopes.push(HashMap::from([(name.clone(), item)]));
+                    let flow = self.block(body);
+                    self.scopes.pop();
+                    if let Flow::Break = flow? {
+                        break;
+                    }
+                }
+            }
+            Statement::Break => return Ok(Flow::Break),
+            Statement::Continue => return Ok(Flow::Continue),
+            Statement::Expr(expr) => {
+                self.evaluate(expr)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// The variable or fact `name`, innermost first
+    fn lookup(&self, name: &str) -> Result<&FactValue, EngineError> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.facts.get(name))
+            .ok_or_else(|| EngineError::UnknownVariable(name.to_string()))
+    }
+
+    fn assign(
+        &mut self,
+        place: &Place,
+        op: Option<BinaryOp>,
+        value: FactValue,
+    ) -> Result<(), EngineError> {
+        // Indices are evaluated before anything is borrowed for writing
+        let keys = place
+            .path
+            .iter()
+            .map(|step| match step {
+                Step::Field(field) => Ok(FactValue::String(field.clone())),
+                Step::Index(index) => self.evaluate(index),
+            })
+            .collect::<Result<Vec<_>, EngineError>>()?;
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&place.name));
+        let mut target = match local {
+            Some(target) => target,
+            // Plain assignment to a fact that does not exist adds it
+            None if keys.is_empty() && op.is_none() => self
+                .facts
+                .entry(place.name.clone())
+                .or_insert(FactValue::Null),
+            None => self
+                .facts
+                .get_mut(&place.name)
+                .ok_or_else(|| EngineError::UnknownVariable(place.name.clone()))?,
+        };
+        for (position, key) in keys.iter().enumerate() {
+            let last = position + 1 == keys.len();
+            target = match (target, key) {
+                (FactValue::Object(fields), FactValue::String(field)) => {
+                    if last && op.is_none() {
+                        fields.entry(field.clone()).or_insert(FactValue::Null)
+                    } else {
+                        fields.get_mut(field).ok_or_else(|| {
+                            EngineError::EvaluationError(format!("Field '{}' not found", field))
+                        })?
+                    }
+                }
+                (FactValue::Array(items), index) => {
+                    let position = array_position(items.len(), index)?;
+                    &mut items[position]
+                }
+                (target, _) => {
+                    return Err(EngineError::TypeError(format!(
+                        "Cannot assign into a {}",
+                        target.type_name()
+                    )))
+                }
+            };
+        }
+        *target = match op {
+            Some(op) => apply(op, target.clone(), value)?,
+            None => value,
+        };
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<FactValue, EngineError> {
+        Ok(match expr {
+            Expr::Value(value) => value.clone(),
+            Expr::Array(items) => FactValue::Array(
+                items
+                    .iter()
+                    .map(|item| self.evaluate(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Object(fields) => FactValue::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.evaluate(value)?)))
+                    .collect::<Result<_, EngineError>>()?,
+            ),
+            Expr::Variable(name) => self.lookup(name)?.clone(),
+            Expr::Field(object, field) => match self.evaluate(object)? {
+                FactValue::Object(mut fields) => fields.remove(field).ok_or_else(|| {
+                    EngineError::EvaluationError(format!("Field '{}' not found", field))
+                })?,
+                _ => {
+                    return Err(EngineError::TypeError(
+                        "Cannot access field on non-object".to_string(),
+                    ))
+                }
+            },
+            Expr::Index(array, index) => {
+                let array = self.evaluate(array)?;
+                let index = self.evaluate(index)?;
+                match (array, index) {
+                    (FactValue::Object(mut fields), FactValue::String(field)) => {
+                        fields.remove(&field).ok_or_else(|| {
+                            EngineError::EvaluationError(format!("Field '{}' not found", field))
+                        })?
+                    }
+                    (array, index) => index_array(array, &index)?,
+                }
+            }
+            Expr::Call(name, args) if name == "retract" => {
+                let args = self.arguments(args)?;
+                let [FactValue::String(fact)] = args.as_slice() else {
+                    return Err(EngineError::TypeError(
+                        "retract expects the name of a fact".to_string(),
+                    ));
+                };
+                self.facts.remove(fact);
+                FactValue::Null
+            }
+            Expr::Call(name, args) => {
+                let args = self.arguments(args)?;
+                self.call(name, &args)?
+            }
+            Expr::Method(receiver, name, args) => {
+                let mut receiver_and_args = vec![self.evaluate(receiver)?];
+                receiver_and_args.extend(self.arguments(args)?);
+                self.call(name, &receiver_and_args)?
+            }
+            Expr::Not(operand) => FactValue::Boolean(!self.evaluate(operand)?.is_truthy()),
+            Expr::Negate(operand) => negate(self.evaluate(operand)?)?,
+            Expr::Binary(BinaryOp::And, left, right) => FactValue::Boolean(
+                self.evaluate(left)?.is_truthy() && self.evaluate(right)?.is_truthy(),
+            ),
+            Expr::Binary(BinaryOp::Or, left, right) => FactValue::Boolean(
+                self.evaluate(left)?.is_truthy() || self.evaluate(right)?.is_truthy(),
+            ),
+            Expr::Binary(op, left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                apply(*op, left, right)?
+            }
+            Expr::Range(start, end, inclusive) => {
+                let start = builtins::index(&self.evaluate(start)?)?;
+                let end = builtins::index(&self.evaluate(end)?)?;
+                let end = if *inclusive {
+                    end.saturating_add(1)
+                } else {
+                    end
+                };
+                let len = end.saturating_sub(start).max(0) as u64;
+                if len > self.interpreter.max_operations {
+                    return Err(EngineError::ResourceLimit(format!(
+                        "range of {} items is longer than the script may run",
+                        len
+                    )));
+                }
+                FactValue::Array((start..end).map(FactValue::Integer).collect())
+            }
+        })
+    }
+
+    fn arguments(&mut self, args: &[Expr]) -> Result<Vec<FactValue>, EngineError> {
+        args.iter().map(|arg| self.evaluate(arg)).collect()
+    }
+
+    fn call(&self, name: &str, args: &[FactValue]) -> Result<FactValue, EngineError> {
+        builtins::call(name, args, self.interpreter.clock.as_ref())
+            .unwrap_or_else(|| Err(EngineError::UnknownFunction(name.to_string())))
+    }
+}
+
+fn apply(op: BinaryOp, left: FactValue, right: FactValue) -> Result<FactValue, EngineError> {
+    op.apply(
+        left,
+        right,
+        &NumericPolicy::default(),
+        Collation::default(),
+        CoercionPolicy::default(),
+    )
+}
@@ -0,0 +1,60 @@
+use crate::engine::{ExecutionResult, RuleEngine};
+use crate::facts::{Fact, FactValue};
+use crate::working_memory::WorkingMemory;
+
+/// A long-lived pairing of an engine with its own working memory.
+///
+/// Facts are inserted, updated and retracted between calls to
+/// `fire_all_rules`, and everything the rules derive stays in memory for the
+/// next call.
+#[derive(Clone)]
+pub struct Session {
+    engine: RuleEngine,
+    memory: WorkingMemory,
+}
+
+impl Session {
+    pub fn new(engine: RuleEngine) -> Self {
+        Self::with_memory(engine, WorkingMemory::new())
+    }
+
+    pub fn with_memory(engine: RuleEngine, memory: WorkingMemory) -> Self {
+        Self { engine, memory }
+    }
+
+    pub fn insert(&mut self, fact: Fact) -> Option<Fact> {
+        self.memory.insert(fact)
+    }
+
+    pub fn update(&mut self, name: &str, value: FactValue) -> Option<FactValue> {
+        self.memory.update(name, value)
+    }
+
+    pub fn retract(&mut self, name: &str) -> Option<Fact> {
+        self.memory.retract(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Fact> {
+        self.memory.get(name)
+    }
+
+    pub fn memory(&self) -> &WorkingMemory {
+        &self.memory
+    }
+
+    pub fn engine(&self) -> &RuleEngine {
+        &self.engine
+    }
+
+    /// Run the rules against the session's facts. The change log is cleared
+    /// afterwards so it doesn't grow for the lifetime of the session.
+    pub fn fire_all_rules(&mut self) -> crate::Result<ExecutionResult> {
+        let result = self.engine.execute(&mut self.memory);
+        self.memory.clear_changes();
+        result
+    }
+
+    pub fn into_memory(self) -> WorkingMemory {
+        self.memory
+    }
+}
@@ -0,0 +1,232 @@
+use crate::knowledge_base::{KnowledgeBase, KnowledgeBaseError};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::{fs, io};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Storage failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot serialize knowledge base: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error(transparent)]
+    KnowledgeBase(#[from] KnowledgeBaseError),
+    #[error("Knowledge base '{0}' not found")]
+    NotFound(String),
+    #[error("Knowledge base '{name}' has no version {version}")]
+    VersionNotFound { name: String, version: u32 },
+    #[error("Knowledge base '{name}' version {version} is already stored")]
+    VersionExists { name: String, version: u32 },
+    #[error("Invalid knowledge base name '{0}'")]
+    InvalidName(String),
+}
+
+/// A repository of named knowledge bases, keeping every saved version so a
+/// service can load its rules at startup or roll back to an earlier version
+pub trait RuleStore: Send + Sync {
+    /// Store `knowledge_base` as version `knowledge_base.version()` of `name`.
+    /// Stored versions are never overwritten.
+    fn save(&self, name: &str, knowledge_base: &KnowledgeBase) -> Result<(), StoreError>;
+
+    /// Load version `version` of `name`, or the latest when `None`
+    fn load(&self, name: &str, version: Option<u32>) -> Result<KnowledgeBase, StoreError>;
+
+    /// The names of the stored knowledge bases, sorted
+    fn list(&self) -> Result<Vec<String>, StoreError>;
+
+    /// The stored versions of `name`, oldest first
+    fn versions(&self, name: &str) -> Result<Vec<u32>, StoreError>;
+}
+
+/// The entry of `versions` for `version`, or for the latest version
+fn select<'a, T>(
+    name: &str,
+    versions: &'a BTreeMap<u32, T>,
+    version: Option<u32>,
+) -> Result<&'a T, StoreError> {
+    match version {
+        Some(version) => versions
+            .get(&version)
+            .ok_or_else(|| StoreError::VersionNotFound {
+                name: name.to_string(),
+                version,
+            }),
+        None => versions
+            .values()
+            .next_back()
+            .ok_or_else(|| StoreError::NotFound(name.to_string())),
+    }
+}
+
+/// Keeps knowledge bases in memory, e.g. for tests
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    knowledge_bases: Mutex<BTreeMap<String, BTreeMap<u32, String>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RuleStore for MemoryStore {
+    fn save(&self, name: &str, knowledge_base: &KnowledgeBase) -> Result<(), StoreError> {
+        let json = knowledge_base.to_json()?;
+        let mut knowledge_bases = self
+            .knowledge_bases
+            .lock()
+            .unwrap_or_else(|p| p.into_inner());
+        let versions = knowledge_bases.entry(name.to_string()).or_default();
+        let version = knowledge_base.version();
+        if versions.contains_key(&version) {
+            return Err(StoreError::VersionExists {
+                name: name.to_string(),
+                version,
+            });
+        }
+        versions.insert(version, json);
+        Ok(())
+    }
+
+    fn load(&self, name: &str, version: Option<u32>) -> Result<KnowledgeBase, StoreError> {
+        let knowledge_bases = self
+            .knowledge_bases
+            .lock()
+            .unwrap_or_else(|p| p.into_inner());
+        let versions = knowledge_bases
+            .get(name)
+            .ok_or_else(|| StoreError::NotFound(name.to_string()))?;
+        Ok(KnowledgeBase::from_json(select(name, versions, version)?)?)
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let knowledge_bases = self
+            .knowledge_bases
+            .lock()
+            .unwrap_or_else(|p| p.into_inner());
+        Ok(knowledge_bases.keys().cloned().collect())
+    }
+
+    fn versions(&self, name: &str) -> Result<Vec<u32>, StoreError> {
+        let knowledge_bases = self
+            .knowledge_bases
+            .lock()
+            .unwrap_or_else(|p| p.into_inner());
+        let versions = knowledge_bases
+            .get(name)
+            .ok_or_else(|| StoreError::NotFound(name.to_string()))?;
+        Ok(versions.keys().copied().collect())
+    }
+}
+
+/// Keeps each knowledge base in a directory of its own under `root`, one
+/// `<version>.json` file per version as written by `KnowledgeBase::to_json`
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    root: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn dir(&self, name: &str) -> Result<PathBuf, StoreError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(StoreError::InvalidName(name.to_string()));
+        }
+        Ok(self.root.join(name))
+    }
+
+    fn read_versions(&self, name: &str) -> Result<BTreeMap<u32, PathBuf>, StoreError> {
+        let entries = match fs::read_dir(self.dir(name)?) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(StoreError::NotFound(name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut versions = BTreeMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let version = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse().ok());
+                if let Some(version) = version {
+                    versions.insert(version, path);
+                }
+            }
+        }
+        Ok(versions)
+    }
+}
+
+impl RuleStore for FileStore {
+    fn save(&self, name: &str, knowledge_base: &KnowledgeBase) -> Result<(), StoreError> {
+        let dir = self.dir(name)?;
+        let json = knowledge_base.to_json()?;
+        fs::create_dir_all(&dir)?;
+        let version = knowledge_base.version();
+
+        // Write the whole version aside, then link it into place: readers never
+        // see a partial file, and an existing version is never replaced
+        static SAVES: AtomicU64 = AtomicU64::new(0);
+        let temp = dir.join(format!(
+            ".{}.{}.{}.tmp",
+            version,
+            std::process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = fs::write(&temp, json) {
+            let _ = fs::remove_file(&temp);
+            return Err(e.into());
+        }
+        let linked = fs::hard_link(&temp, dir.join(format!("{}.json", version)));
+        fs::remove_file(&temp)?;
+        match linked {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(StoreError::VersionExists {
+                name: name.to_string(),
+                version,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn load(&self, name: &str, version: Option<u32>) -> Result<KnowledgeBase, StoreError> {
+        let versions = self.read_versions(name)?;
+        let json = fs::read_to_string(select(name, &versions, version)?)?;
+        Ok(KnowledgeBase::from_json(&json)?)
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.extend(entry.file_name().to_str().map(str::to_string));
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn versions(&self, name: &str) -> Result<Vec<u32>, StoreError> {
+        Ok(self.read_versions(name)?.into_keys().collect())
+    }
+}
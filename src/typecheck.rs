@@ -0,0 +1,205 @@
+use crate::ast::Expression;
+use crate::rule::Rule;
+use crate::schema::{FactSchema, FieldType};
+use std::collections::{HashMap, HashSet};
+
+/// Check a rule's expressions against the registered fact schemas, so that
+/// comparing a string field with a number or adding a boolean to a number is
+/// reported when the rule is added. Only types the schemas determine are
+/// checked; anything else is assumed to be right.
+pub(crate) fn check_rule(
+    rule: &Rule,
+    schemas: &HashMap<String, FactSchema>,
+    lenient: bool,
+) -> Result<(), String> {
+    if schemas.is_empty() {
+        return Ok(());
+    }
+    let mut checker = TypeChecker {
+        schemas,
+        facts: rule
+            .patterns
+            .iter()
+            .chain(rule.accumulates.iter().map(|a| &a.pattern))
+            .map(|p| (p.binding.as_str(), p.fact_type.as_str()))
+            .collect(),
+        locals: rule
+            .bindings
+            .iter()
+            .map(|b| b.name.clone())
+            .chain(rule.accumulates.iter().map(|a| a.name.clone()))
+            .collect(),
+        lenient,
+    };
+    for expr in rule.expressions() {
+        checker.collect_locals(expr);
+    }
+    rule.expressions()
+        .try_for_each(|expr| checker.infer(expr).map(drop))
+}
+
+struct TypeChecker<'a> {
+    schemas: &'a HashMap<String, FactSchema>,
+    // Pattern binding -> fact type
+    facts: HashMap<&'a str, &'a str>,
+    // Names bound by the rule itself, which never refer to a fact
+    locals: HashSet<String>,
+    // Whether comparisons coerce between types, as under `CoercionPolicy::Lenient`
+    lenient: bool,
+}
+
+impl TypeChecker<'_> {
+    fn collect_locals(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Let(name, _)
+            | Expression::LetIn(name, _, _)
+            | Expression::ForEach(name, _, _) => {
+                self.locals.insert(name.clone());
+            }
+            _ => {}
+        }
+        for child in expr.children() {
+            self.collect_locals(child);
+        }
+    }
+
+    /// The declared type of `fact.field`, if its schema has the field
+    fn field_type(&self, fact: &str, field: &str) -> Option<FieldType> {
+        if self.locals.contains(fact) {
+            return None;
+        }
+        let fact = self.facts.get(fact).copied().unwrap_or(fact);
+        match self.schemas.get(fact)?.field_type(field)? {
+            FieldType::Any => None,
+            field_type => Some(field_type),
+        }
+    }
+
+    /// The type of an expression, or `None` if it is not known, failing on
+    /// the first mismatch found in it
+    fn infer(&self, expr: &Expression) -> Result<Option<FieldType>, String> {
+        let children = expr
+            .children()
+            .into_iter()
+            .map(|child| self.infer(child))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mismatch = |verb: &str, l: FieldType, r: FieldType| {
+            Err(format!("`{}` {} {:?} and {:?}", expr, verb, l, r))
+        };
+        Ok(match (expr, children.as_slice()) {
+            (Expression::String(_), _) => Some(FieldType::String),
+            (Expression::Number(_), _) => Some(FieldType::Number),
+            (Expression::Boolean(_), _) => Some(FieldType::Boolean),
+            (Expression::Duration(_), _) => Some(FieldType::Duration),
+            (Expression::Array(_), _) => Some(FieldType::Array),
+            (Expression::FieldAccess(object, field), _) => match object.as_ref() {
+                Expression::Variable(fact) => self.field_type(fact, field),
+                _ => None,
+            },
+            (
+                Expression::Add(..)
+                | Expression::Subtract(..)
+                | Expression::Multiply(..)
+                | Expression::Divide(..)
+                | Expression::Modulo(..),
+                [Some(l), Some(r)],
+            ) => match arithmetic(expr, *l, *r) {
+                Ok(result) => result,
+                Err(verb) => return mismatch(verb, *l, *r),
+            },
+            (
+                Expression::Equal(..)
+                | Expression::NotEqual(..)
+                | Expression::LessThan(..)
+                | Expression::LessEqual(..)
+                | Expression::GreaterThan(..)
+                | Expression::GreaterEqual(..),
+                [l, r],
+            ) => {
+                if let (Some(l), Some(r)) = (l, r) {
+                    if !self.lenient && !comparable(*l, *r) {
+                        return mismatch("compares", *l, *r);
+                    }
+                }
+                Some(FieldType::Boolean)
+            }
+            (Expression::Matches(..), [Some(l), _]) if *l != FieldType::String => {
+                return Err(format!("`{}` matches a pattern against {:?}", expr, l));
+            }
+            (Expression::Negate(_), [Some(operand)]) => {
+                if !is_numeric(*operand) && *operand != FieldType::Duration {
+                    return Err(format!("`{}` negates {:?}", expr, operand));
+                }
+                Some(*operand)
+            }
+            (Expression::FieldAssignment(fact, field, _), [Some(value)]) => {
+                if let Some(declared) = self.field_type(fact, field) {
+                    if !assignable(declared, *value) {
+                        return Err(format!(
+                            "`{}` assigns {:?} to a {:?} field",
+                            expr, value, declared
+                        ));
+                    }
+                }
+                None
+            }
+            (
+                Expression::And(..)
+                | Expression::Or(..)
+                | Expression::Not(_)
+                | Expression::In(..)
+                | Expression::Matches(..),
+                _,
+            ) => Some(FieldType::Boolean),
+            (Expression::LetIn(..), [_, body]) => *body,
+            _ => None,
+        })
+    }
+}
+
+fn is_numeric(field_type: FieldType) -> bool {
+    matches!(
+        field_type,
+        FieldType::Number | FieldType::Integer | FieldType::Decimal
+    )
+}
+
+/// The type of an arithmetic expression on operands of these types, or the
+/// verb describing the operation if they cannot be combined
+fn arithmetic(
+    expr: &Expression,
+    l: FieldType,
+    r: FieldType,
+) -> Result<Option<FieldType>, &'static str> {
+    use FieldType::*;
+    let verb = match expr {
+        Expression::Add(..) => "adds",
+        Expression::Subtract(..) => "subtracts",
+        Expression::Multiply(..) => "multiplies",
+        Expression::Divide(..) => "divides",
+        _ => "takes the remainder of",
+    };
+    match (verb, l, r) {
+        (_, l, r) if is_numeric(l) && is_numeric(r) => Ok(Some(if l == r { l } else { Number })),
+        ("adds", String, String) => Ok(Some(String)),
+        ("adds" | "subtracts", DateTime, Duration) => Ok(Some(DateTime)),
+        ("adds", Duration, DateTime) => Ok(Some(DateTime)),
+        ("adds" | "subtracts", Duration, Duration) | ("subtracts", DateTime, DateTime) => {
+            Ok(Some(Duration))
+        }
+        ("adds" | "subtracts", Money, Money) => Ok(Some(Money)),
+        ("multiplies" | "divides", Money, r) if is_numeric(r) => Ok(Some(Money)),
+        ("multiplies", l, Money) if is_numeric(l) => Ok(Some(Money)),
+        _ => Err(verb),
+    }
+}
+
+/// Whether values of these types can be compared without coercion
+fn comparable(l: FieldType, r: FieldType) -> bool {
+    l == r || (is_numeric(l) && is_numeric(r))
+}
+
+/// Whether a value of type `value` may be stored in a field declared `declared`
+fn assignable(declared: FieldType, value: FieldType) -> bool {
+    declared == value || (declared == FieldType::Number && is_numeric(value))
+}
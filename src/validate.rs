@@ -0,0 +1,161 @@
+use crate::analysis;
+use crate::ast::Expression;
+use crate::knowledge_base::KnowledgeBaseError;
+use crate::optimize::RuleWarning;
+use crate::rule::Rule;
+use regex::Regex;
+use std::collections::BTreeSet;
+
+/// Check a rule for structural problems before it is added. Actions inside
+/// its condition and invalid literal patterns are errors; suspicious but runnable rules give warnings.
+pub(crate) fn validate_rule(rule: &Rule) -> Result<Vec<RuleWarning>, KnowledgeBaseError> {
+    let conditions: Vec<&Expression> = std::iter::once(&rule.when_condition)
+        .chain(rule.patterns.iter().filter_map(|p| p.constraint.as_ref()))
+        .chain(
+            rule.accumulates
+                .iter()
+                .flat_map(|a| a.pattern.constraint.iter().chain(std::iter::once(&a.value))),
+        )
+        .chain(rule.bindings.iter().map(|b| &b.value))
+        .collect();
+    for condition in &conditions {
+        if let Some(action) = find_action(condition) {
+            return Err(KnowledgeBaseError::InvalidRule {
+                rule: rule.name.clone(),
+                reason: format!("its condition contains the action {:?}", action),
+            });
+        }
+    }
+
+    if rule.expressions().any(has_nan) {
+        return Err(KnowledgeBaseError::InvalidRule {
+            rule: rule.name.clone(),
+            reason: "it contains a NaN literal, which GRL cannot express".to_string(),
+        });
+    }
+
+    for pattern in analysis::constant_patterns(rule) {
+        if let Err(e) = Regex::new(&pattern) {
+            return Err(KnowledgeBaseError::InvalidRule {
+                rule: rule.name.clone(),
+                reason: format!("its pattern \"{}\" is invalid: {}", pattern, e),
+            });
+        }
+    }
+
+    let mut warnings = Vec::new();
+    if rule.then_actions.is_empty() {
+        warnings.push(RuleWarning::NoActions(rule.name.clone()));
+    }
+
+    let mut uses = Uses::default();
+    for pattern in &rule.patterns {
+        uses.objects.insert(&pattern.binding);
+    }
+    for expr in conditions.iter().copied().chain(&rule.then_actions) {
+        uses.collect(expr);
+        check_comparisons(rule, expr, &mut warnings);
+    }
+    for variable in &uses.assigned_fields {
+        if uses.scalars.contains(variable) && !uses.objects.contains(variable) {
+            warnings.push(RuleWarning::FieldOfScalar {
+                rule: rule.name.clone(),
+                variable: variable.to_string(),
+            });
+        }
+    }
+    Ok(warnings)
+}
+
+/// The first action found in a condition. An `if` there is a conditional
+/// value, so only actions in its branches count.
+fn find_action(expr: &Expression) -> Option<&Expression> {
+    match expr {
+        Expression::Assignment(..)
+        | Expression::FieldAssignment(..)
+        | Expression::InsertLogical(..)
+        | Expression::Update(_)
+        | Expression::Let(..)
+        | Expression::Script(_)
+        | Expression::ForEach(..) => Some(expr),
+        _ => expr.children().into_iter().find_map(find_action),
+    }
+}
+
+fn has_nan(expr: &Expression) -> bool {
+    match expr {
+        Expression::Number(n) => n.is_nan(),
+        _ => expr.children().into_iter().any(has_nan),
+    }
+}
+
+/// How a rule uses each variable it names
+#[derive(Default)]
+struct Uses<'a> {
+    /// Read as an object, `v.field`, or bound to matched facts
+    objects: BTreeSet<&'a str>,
+    /// Read as a value in its own right, `v > 5`
+    scalars: BTreeSet<&'a str>,
+    /// Written as an object, `v.field = ...`
+    assigned_fields: BTreeSet<&'a str>,
+}
+
+impl<'a> Uses<'a> {
+    fn collect(&mut self, expr: &'a Expression) {
+        match expr {
+            Expression::FieldAccess(object, _) | Expression::OptionalFieldAccess(object, _)
+                if matches!(object.as_ref(), Expression::Variable(_)) =>
+            {
+                if let Expression::Variable(name) = object.as_ref() {
+                    self.objects.insert(name);
+                }
+                return;
+            }
+            Expression::Variable(name) => {
+                self.scalars.insert(name);
+            }
+            Expression::FieldAssignment(name, _, _) => {
+                self.assigned_fields.insert(name);
+            }
+            _ => {}
+        }
+        for child in expr.children() {
+            self.collect(child);
+        }
+    }
+}
+
+/// Warn about comparisons between literals of different types, which can
+/// only ever be false or fail
+fn check_comparisons(rule: &Rule, expr: &Expression, warnings: &mut Vec<RuleWarning>) {
+    if let Expression::Equal(left, right)
+    | Expression::NotEqual(left, right)
+    | Expression::LessThan(left, right)
+    | Expression::LessEqual(left, right)
+    | Expression::GreaterThan(left, right)
+    | Expression::GreaterEqual(left, right) = expr
+    {
+        if let (Some(left), Some(right)) = (literal_type(left), literal_type(right)) {
+            if left != right {
+                warnings.push(RuleWarning::IncompatibleComparison {
+                    rule: rule.name.clone(),
+                    left,
+                    right,
+                });
+            }
+        }
+    }
+    for child in expr.children() {
+        check_comparisons(rule, child, warnings);
+    }
+}
+
+fn literal_type(expr: &Expression) -> Option<&'static str> {
+    match expr {
+        Expression::String(_) => Some("String"),
+        Expression::Number(_) => Some("Number"),
+        Expression::Boolean(_) => Some("Boolean"),
+        Expression::Duration(_) => Some("Duration"),
+        _ => None,
+    }
+}
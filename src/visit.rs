@@ -0,0 +1,149 @@
+use crate::ast::Expression;
+
+/// Walks an expression tree without changing it. Implementations override
+/// `visit_expression` to handle the variants they care about and call
+/// `walk_expression` to continue into the children of the others.
+pub trait ExpressionVisitor {
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr);
+    }
+}
+
+/// Visit each child of `expr`, see `Expression::children`
+pub fn walk_expression<V: ExpressionVisitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    for child in expr.children() {
+        visitor.visit_expression(child);
+    }
+}
+
+/// Rewrites an expression tree bottom-up or top-down. Implementations
+/// override `fold_expression` to replace the variants they care about and
+/// call `fold_children` to rebuild the others from folded children.
+pub trait ExpressionFolder {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold_children(self, expr)
+    }
+}
+
+/// Rebuild `expr` with each child replaced by `folder`'s fold of it
+pub fn fold_children<F: ExpressionFolder + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::String(_)
+        | Expression::Number(_)
+        | Expression::Boolean(_)
+        | Expression::Duration(_)
+        | Expression::Null
+        | Expression::Variable(_)
+        | Expression::EventCount(_, _)
+        | Expression::Update(_)
+        | Expression::Script(_) => expr,
+        Expression::FieldAccess(object, field) => {
+            Expression::FieldAccess(fold_box(folder, object), field)
+        }
+        Expression::OptionalFieldAccess(object, field) => {
+            Expression::OptionalFieldAccess(fold_box(folder, object), field)
+        }
+        Expression::Index(l, r) => Expression::Index(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Add(l, r) => Expression::Add(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Subtract(l, r) => {
+            Expression::Subtract(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::Multiply(l, r) => {
+            Expression::Multiply(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::Divide(l, r) => Expression::Divide(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Modulo(l, r) => Expression::Modulo(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Equal(l, r) => Expression::Equal(fold_box(folder, l), fold_box(folder, r)),
+        Expression::NotEqual(l, r) => {
+            Expression::NotEqual(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::LessThan(l, r) => {
+            Expression::LessThan(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::LessEqual(l, r) => {
+            Expression::LessEqual(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::GreaterThan(l, r) => {
+            Expression::GreaterThan(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::GreaterEqual(l, r) => {
+            Expression::GreaterEqual(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::And(l, r) => Expression::And(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Or(l, r) => Expression::Or(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Coalesce(l, r) => {
+            Expression::Coalesce(fold_box(folder, l), fold_box(folder, r))
+        }
+        Expression::In(l, r) => Expression::In(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Matches(l, r) => Expression::Matches(fold_box(folder, l), fold_box(folder, r)),
+        Expression::Not(inner) => Expression::Not(fold_box(folder, inner)),
+        Expression::Negate(inner) => Expression::Negate(fold_box(folder, inner)),
+        Expression::Assignment(name, value) => {
+            Expression::Assignment(name, fold_box(folder, value))
+        }
+        Expression::FieldAssignment(name, field, value) => {
+            Expression::FieldAssignment(name, field, fold_box(folder, value))
+        }
+        Expression::InsertLogical(name, value) => {
+            Expression::InsertLogical(name, fold_box(folder, value))
+        }
+        Expression::Let(name, value) => Expression::Let(name, fold_box(folder, value)),
+        Expression::LetIn(name, value, body) => {
+            Expression::LetIn(name, fold_box(folder, value), fold_box(folder, body))
+        }
+        Expression::Array(items) => Expression::Array(
+            items
+                .into_iter()
+                .map(|item| folder.fold_expression(item))
+                .collect(),
+        ),
+        Expression::FunctionCall(name, args) => Expression::FunctionCall(
+            name,
+            args.into_iter()
+                .map(|arg| folder.fold_expression(arg))
+                .collect(),
+        ),
+        Expression::MethodCall(receiver, name, args) => Expression::MethodCall(
+            fold_box(folder, receiver),
+            name,
+            args.into_iter()
+                .map(|arg| folder.fold_expression(arg))
+                .collect(),
+        ),
+        Expression::If(condition, then_actions, else_actions) => {
+            let condition = fold_box(folder, condition);
+            Expression::If(
+                condition,
+                then_actions
+                    .into_iter()
+                    .map(|a| folder.fold_expression(a))
+                    .collect(),
+                else_actions
+                    .into_iter()
+                    .map(|a| folder.fold_expression(a))
+                    .collect(),
+            )
+        }
+        Expression::ForEach(item, collection, actions) => {
+            let collection = fold_box(folder, collection);
+            Expression::ForEach(
+                item,
+                collection,
+                actions
+                    .into_iter()
+                    .map(|a| folder.fold_expression(a))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Fold the boxed expression in place, reusing its allocation
+fn fold_box<F: ExpressionFolder + ?Sized>(
+    folder: &mut F,
+    mut expr: Box<Expression>,
+) -> Box<Expression> {
+    let inner = std::mem::replace(&mut *expr, Expression::Boolean(false));
+    *expr = folder.fold_expression(inner);
+    expr
+}
@@ -0,0 +1,165 @@
+use crate::ast::Expression;
+use crate::engine::{BinaryOp, EngineError, RuleEngine, Scope};
+use crate::facts::FactValue;
+use crate::working_memory::WorkingMemory;
+
+/// A single stack machine instruction
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Op {
+    Push(FactValue),
+    /// Push the value of a variable, bound value or fact
+    Load(String),
+    /// Push a field of a variable without copying the whole object
+    LoadField(String, String),
+    /// Pop a value and bind it to a name until the next `Unbind`
+    Bind(String),
+    Unbind,
+    /// Replace the object on top of the stack with one of its fields
+    Field(String),
+    /// Pop the right then left operand and push the result
+    Binary(BinaryOp),
+    Not,
+    Negate,
+    EventCount(String, u64),
+    /// Pop the given number of arguments and call a custom function
+    Call(String, usize),
+}
+
+/// An expression compiled to a flat sequence of stack operations
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Program {
+    ops: Vec<Op>,
+}
+
+impl Program {
+    /// Compile an expression, or `None` if it contains nodes the VM does not
+    /// support (these are left to the interpreter)
+    pub(crate) fn compile(expr: &Expression) -> Option<Self> {
+        let mut program = Self::default();
+        program.emit(expr)?;
+        Some(program)
+    }
+
+    fn emit(&mut self, expr: &Expression) -> Option<()> {
+        if let Some((op, left, right)) = BinaryOp::split(expr) {
+            self.emit(left)?;
+            self.emit(right)?;
+            self.ops.push(Op::Binary(op));
+            return Some(());
+        }
+
+        match expr {
+            Expression::String(s) => self.ops.push(Op::Push(FactValue::String(s.clone()))),
+            Expression::Number(n) => self.ops.push(Op::Push(FactValue::Number(*n))),
+            Expression::Boolean(b) => self.ops.push(Op::Push(FactValue::Boolean(*b))),
+            Expression::Duration(ms) => self
+                .ops
+                .push(Op::Push(FactValue::Duration(i64::try_from(*ms).ok()?))),
+            Expression::Null => self.ops.push(Op::Push(FactValue::Null)),
+            Expression::Variable(name) => self.ops.push(Op::Load(name.clone())),
+            Expression::FieldAccess(object, field) => match object.as_ref() {
+                Expression::Variable(name) => {
+                    self.ops.push(Op::LoadField(name.clone(), field.clone()))
+                }
+                _ => {
+                    self.emit(object)?;
+                    self.ops.push(Op::Field(field.clone()));
+                }
+            },
+            Expression::Not(inner) => {
+                self.emit(inner)?;
+                self.ops.push(Op::Not);
+            }
+            Expression::Negate(inner) => {
+                self.emit(inner)?;
+                self.ops.push(Op::Negate);
+            }
+            Expression::LetIn(name, value, body) => {
+                self.emit(value)?;
+                self.ops.push(Op::Bind(name.clone()));
+                self.emit(body)?;
+                self.ops.push(Op::Unbind);
+            }
+            Expression::EventCount(event_type, window_ms) => self
+                .ops
+                .push(Op::EventCount(event_type.clone(), *window_ms)),
+            Expression::FunctionCall(name, args) => {
+                for arg in args {
+                    self.emit(arg)?;
+                }
+                self.ops.push(Op::Call(name.clone(), args.len()));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub(crate) fn execute(
+        &self,
+        engine: &RuleEngine,
+        facts: &WorkingMemory,
+        scope: &Scope,
+    ) -> Result<FactValue, EngineError> {
+        let mut stack: Vec<FactValue> = Vec::with_capacity(self.ops.len());
+        // Values bound by `let ... in`, innermost last
+        let mut locals: Vec<(&str, FactValue)> = Vec::new();
+        for op in &self.ops {
+            let value = match op {
+                Op::Push(value) => value.clone(),
+                Op::Load(name) => match local(&locals, name) {
+                    Some(value) => value.clone(),
+                    None => engine.lookup(name, facts, scope)?.into_owned(),
+                },
+                Op::LoadField(name, field) => match local(&locals, name) {
+                    Some(value) => engine.access_field(value, field)?,
+                    None => engine.access_field(&*engine.lookup(name, facts, scope)?, field)?,
+                },
+                Op::Bind(name) => {
+                    locals.push((name, pop(&mut stack)?));
+                    continue;
+                }
+                Op::Unbind => {
+                    locals.pop();
+                    continue;
+                }
+                Op::Field(field) => {
+                    let object = pop(&mut stack)?;
+                    engine.access_field(&object, field)?
+                }
+                Op::Binary(op) => {
+                    let right = pop(&mut stack)?;
+                    let left = pop(&mut stack)?;
+                    engine.binary(*op, left, right)?
+                }
+                Op::Not => engine.not(pop(&mut stack)?),
+                Op::Negate => engine.negate(pop(&mut stack)?)?,
+                Op::EventCount(event_type, window_ms) => {
+                    engine.event_count(event_type, *window_ms, facts)
+                }
+                Op::Call(name, argc) => {
+                    let start = stack.len().checked_sub(*argc).ok_or_else(underflow)?;
+                    let args = stack.split_off(start);
+                    engine.call_function(name, &args, scope)?
+                }
+            };
+            stack.push(value);
+        }
+        pop(&mut stack)
+    }
+}
+
+fn local<'a>(locals: &'a [(&str, FactValue)], name: &str) -> Option<&'a FactValue> {
+    locals
+        .iter()
+        .rev()
+        .find(|(local, _)| *local == name)
+        .map(|(_, value)| value)
+}
+
+fn pop(stack: &mut Vec<FactValue>) -> Result<FactValue, EngineError> {
+    stack.pop().ok_or_else(underflow)
+}
+
+fn underflow() -> EngineError {
+    EngineError::EvaluationError("VM stack underflow".to_string())
+}
@@ -0,0 +1,462 @@
+use crate::analysis::FieldRef;
+use crate::engine::values_equal;
+use crate::facts::{Fact, FactValue, Provenance};
+use crate::index::FieldIndex;
+use crate::report::{self, ValueChange};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Called with each change to a fact or field of working memory, see
+/// `WorkingMemory::on_field_changed`
+pub type ChangeListener = Arc<dyn Fn(&ValueChange) + Send + Sync>;
+
+/// A change made to working memory since change tracking was last cleared
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Inserted(String),
+    Updated(String),
+    Retracted(String),
+}
+
+impl Change {
+    pub fn fact_name(&self) -> &str {
+        match self {
+            Change::Inserted(name) | Change::Updated(name) | Change::Retracted(name) => name,
+        }
+    }
+}
+
+/// Facts shared between working memory, its checkpoints and its clones. Each
+/// is copied on its first write after being shared, so taking a snapshot does
+/// not copy any facts.
+type FactMap = Arc<HashMap<String, Arc<Fact>>>;
+
+/// The persisted form of working memory, see `WorkingMemory::to_json`
+#[derive(Serialize, Deserialize)]
+struct Snapshot<F = Fact> {
+    facts: Vec<F>,
+    #[serde(default)]
+    logical_support: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    facts: FactMap,
+    changes_len: usize,
+    logical_support: HashMap<String, String>,
+}
+
+/// The set of facts rules are evaluated against.
+///
+/// Every insert, update and retract is recorded so callers can see what an
+/// execution touched. Working memory can be checkpointed before a risky
+/// operation and rolled back to that state if it fails. Checkpoints nest:
+/// each `rollback` or `commit` pops the most recent one.
+///
+/// Checkpoints and clones share facts with the original until either writes
+/// to them, so they are cheap to take regardless of how large the facts are.
+#[derive(Clone, Default)]
+pub struct WorkingMemory {
+    facts: FactMap,
+    changes: Vec<Change>,
+    checkpoints: Vec<Checkpoint>,
+    // Logically inserted fact name -> rule supporting it
+    pub(crate) logical_support: HashMap<String, String>,
+    listeners: Vec<ChangeListener>,
+    indexes: Vec<FieldIndex>,
+}
+
+impl WorkingMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Fact> {
+        self.facts.get(name).map(Arc::as_ref)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.facts.contains_key(name)
+    }
+
+    /// Call `listener` with every change to a fact or one of its fields. Changes
+    /// made while a checkpoint is open (e.g. during an execution) are reported
+    /// once the outermost checkpoint commits, and not at all if rolled back.
+    pub fn on_field_changed<F>(&mut self, listener: F)
+    where
+        F: Fn(&ValueChange) + Send + Sync + 'static,
+    {
+        self.listeners.push(Arc::new(listener));
+    }
+
+    /// Index facts of `fact_type` by the value of `field` (a dot-separated
+    /// path), so `find` and rule patterns such as
+    /// `o: Order(o.customerId == c.id)` look matching facts up instead of
+    /// scanning every fact of the type
+    pub fn add_index(&mut self, fact_type: impl Into<String>, field: impl Into<String>) {
+        let (fact_type, field) = (fact_type.into(), field.into());
+        if self.index(&fact_type, &field).is_none() {
+            let index = FieldIndex::new(fact_type, field, self.iter());
+            self.indexes.push(index);
+        }
+    }
+
+    /// Facts of `fact_type` whose `field` equals `value`, in name order
+    pub fn find(&self, fact_type: &str, field: &str, value: &FactValue) -> Vec<&Fact> {
+        match self.indexed(fact_type, field, value) {
+            Some(names) => names
+                .into_iter()
+                .filter_map(|name| self.get(name))
+                .collect(),
+            None => {
+                let mut found: Vec<&Fact> = self
+                    .iter()
+                    .filter(|fact| fact.fact_type.as_deref() == Some(fact_type))
+                    .filter(|fact| {
+                        fact.get_path(field)
+                            .is_some_and(|field| values_equal(field, value))
+                    })
+                    .collect();
+                found.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+                found
+            }
+        }
+    }
+
+    /// Names of the facts `find` would return, or `None` if there is no index
+    /// to look them up in
+    pub(crate) fn indexed(
+        &self,
+        fact_type: &str,
+        field: &str,
+        value: &FactValue,
+    ) -> Option<Vec<&str>> {
+        self.index(fact_type, field)?.lookup(value)
+    }
+
+    fn index(&self, fact_type: &str, field: &str) -> Option<&FieldIndex> {
+        self.indexes
+            .iter()
+            .find(|index| index.fact_type == fact_type && index.field == field)
+    }
+
+    /// A fact to write to, copied first if it is shared
+    fn fact_mut(&mut self, name: &str) -> Option<&mut Fact> {
+        Arc::make_mut(&mut self.facts)
+            .get_mut(name)
+            .map(Arc::make_mut)
+    }
+
+    fn reindex(&mut self, name: &str) {
+        let fact = self.facts.get(name).map(Arc::as_ref);
+        for index in &mut self.indexes {
+            index.refresh(name, fact);
+        }
+    }
+
+    /// Insert a fact, replacing any fact with the same name
+    pub fn insert(&mut self, fact: Fact) -> Option<Fact> {
+        let name = fact.name.clone();
+        let previous = Arc::make_mut(&mut self.facts)
+            .insert(name.clone(), Arc::new(fact))
+            .map(Arc::unwrap_or_clone);
+        self.reindex(&name);
+        self.notify(&name, previous.as_ref().map(|fact| &fact.value));
+        self.changes.push(if previous.is_some() {
+            Change::Updated(name)
+        } else {
+            Change::Inserted(name)
+        });
+        previous
+    }
+
+    /// Replace the value of an existing fact, returning the previous value.
+    /// Returns `None` and leaves memory untouched if the fact does not exist.
+    pub fn update(&mut self, name: &str, value: FactValue) -> Option<FactValue> {
+        let fact = self.fact_mut(name)?;
+        let previous = std::mem::replace(&mut fact.value, value);
+        self.reindex(name);
+        self.notify(name, Some(&previous));
+        self.changes.push(Change::Updated(name.to_string()));
+        Some(previous)
+    }
+
+    /// Set a field on an existing object fact
+    pub fn set_field(
+        &mut self,
+        name: &str,
+        field_name: String,
+        value: FactValue,
+    ) -> Result<(), String> {
+        let notifies = self.notifies_now();
+        let fact = self
+            .fact_mut(name)
+            .ok_or_else(|| format!("Unknown fact '{}'", name))?;
+        let before = notifies.then(|| fact.value.clone());
+        fact.set_field(field_name, value)?;
+        self.reindex(name);
+        if let Some(before) = before {
+            self.notify(name, Some(&before));
+        }
+        self.changes.push(Change::Updated(name.to_string()));
+        Ok(())
+    }
+
+    /// Set a nested field on an existing object fact, creating intermediate
+    /// objects as needed
+    pub fn set_path(&mut self, name: &str, path: &str, value: FactValue) -> Result<(), String> {
+        let notifies = self.notifies_now();
+        let fact = self
+            .fact_mut(name)
+            .ok_or_else(|| format!("Unknown fact '{}'", name))?;
+        let before = notifies.then(|| fact.value.clone());
+        fact.set_path(path, value, true)?;
+        self.reindex(name);
+        if let Some(before) = before {
+            self.notify(name, Some(&before));
+        }
+        self.changes.push(Change::Updated(name.to_string()));
+        Ok(())
+    }
+
+    /// Record a write by `source` at `at` in a fact's metadata. Metadata is not
+    /// part of the value, so this is neither tracked nor reported to listeners.
+    pub(crate) fn stamp(&mut self, name: &str, at: i64, source: &str) {
+        if let Some(fact) = self.fact_mut(name) {
+            let metadata = &mut fact.metadata;
+            metadata.updated_at = Some(at);
+            metadata.source = Some(source.to_string());
+        }
+    }
+
+    pub(crate) fn record_provenance(&mut self, target: &FieldRef, provenance: Provenance) {
+        if let Some(fact) = self.fact_mut(&target.fact) {
+            fact.metadata.record(target.field.as_deref(), provenance);
+        }
+    }
+
+    pub fn retract(&mut self, name: &str) -> Option<Fact> {
+        let fact = Arc::unwrap_or_clone(Arc::make_mut(&mut self.facts).remove(name)?);
+        self.reindex(name);
+        self.notify(name, Some(&fact.value));
+        self.logical_support.remove(name);
+        self.changes.push(Change::Retracted(name.to_string()));
+        Some(fact)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Fact> {
+        self.facts.values().map(Arc::as_ref)
+    }
+
+    /// All facts of the given type
+    pub fn of_type<'a>(&'a self, fact_type: &'a str) -> impl Iterator<Item = &'a Fact> + 'a {
+        self.iter()
+            .filter(move |fact| fact.fact_type.as_deref() == Some(fact_type))
+    }
+
+    /// Facts in `namespace` or one nested inside it, e.g. `billing::invoice`
+    /// and `billing::eu::vat` for `"billing"`
+    pub fn in_namespace<'a>(&'a self, namespace: &'a str) -> impl Iterator<Item = &'a Fact> + 'a {
+        self.iter().filter(move |fact| {
+            fact.namespace().is_some_and(|ns| {
+                ns.strip_prefix(namespace)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+        })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.facts.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.facts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facts.is_empty()
+    }
+
+    /// Changes recorded since tracking was last cleared
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Save the current facts so they can be restored with `rollback`
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            facts: Arc::clone(&self.facts),
+            changes_len: self.changes.len(),
+            logical_support: self.logical_support.clone(),
+        });
+    }
+
+    /// Restore the facts saved by the most recent checkpoint, discarding any
+    /// changes recorded since. Returns false if there was no checkpoint to
+    /// roll back to.
+    pub fn rollback(&mut self) -> bool {
+        match self.checkpoints.pop() {
+            Some(checkpoint) => {
+                self.facts = checkpoint.facts;
+                self.changes.truncate(checkpoint.changes_len);
+                self.logical_support = checkpoint.logical_support;
+                for index in &mut self.indexes {
+                    *index = FieldIndex::new(
+                        std::mem::take(&mut index.fact_type),
+                        std::mem::take(&mut index.field),
+                        self.facts.values().map(Arc::as_ref),
+                    );
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Discard the most recent checkpoint, keeping the current facts.
+    /// Returns false if there was no checkpoint to discard.
+    pub fn commit(&mut self) -> bool {
+        let Some(checkpoint) = self.checkpoints.pop() else {
+            return false;
+        };
+        if self.notifies_now() {
+            let names: BTreeSet<&String> =
+                checkpoint.facts.keys().chain(self.facts.keys()).collect();
+            let mut changes = Vec::new();
+            for name in names {
+                let old = checkpoint.facts.get(name).map(|fact| &fact.value);
+                let new = self.facts.get(name).map(|fact| &fact.value);
+                report::fact_changes(name, old, new, &mut changes);
+            }
+            self.emit(&changes);
+        }
+        true
+    }
+
+    /// Whether changes go to listeners as they are made, rather than when the
+    /// outermost checkpoint commits
+    fn notifies_now(&self) -> bool {
+        !self.listeners.is_empty() && self.checkpoints.is_empty()
+    }
+
+    /// Report the change from `before` to the current value of a fact
+    fn notify(&self, name: &str, before: Option<&FactValue>) {
+        if !self.notifies_now() {
+            return;
+        }
+        let mut changes = Vec::new();
+        let after = self.facts.get(name).map(|fact| &fact.value);
+        report::fact_changes(name, before, after, &mut changes);
+        self.emit(&changes);
+    }
+
+    fn emit(&self, changes: &[ValueChange]) {
+        for change in changes {
+            for listener in &self.listeners {
+                listener(change);
+            }
+        }
+    }
+
+    pub fn checkpoint_depth(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Serialize every fact, with its type, timestamp and metadata, and which
+    /// rule supports each logically inserted fact, so a session's state can be
+    /// persisted between runs and restored exactly with `from_json`. Change
+    /// tracking, checkpoints, listeners and indexes are not included.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.snapshot())
+    }
+
+    /// Restore working memory saved with `to_json`
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self::restore(serde_json::from_str(json)?))
+    }
+
+    /// Like `to_json`, in a compact binary form
+    #[cfg(feature = "binary")]
+    pub fn to_binary(&self) -> Result<Vec<u8>, serde_json::Error> {
+        Ok(crate::binary::encode(&serde_json::to_value(
+            self.snapshot(),
+        )?))
+    }
+
+    /// Restore working memory saved with `to_binary`
+    #[cfg(feature = "binary")]
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        Ok(Self::restore(serde_json::from_value(
+            crate::binary::decode(bytes)?,
+        )?))
+    }
+
+    fn snapshot(&self) -> Snapshot<&Fact> {
+        let mut facts: Vec<&Fact> = self.iter().collect();
+        facts.sort_by(|a, b| a.name.cmp(&b.name));
+        Snapshot {
+            facts,
+            logical_support: self.logical_support.clone().into_iter().collect(),
+        }
+    }
+
+    fn restore(snapshot: Snapshot<Fact>) -> Self {
+        let mut memory: Self = snapshot.facts.into_iter().collect();
+        memory.logical_support = snapshot.logical_support.into_iter().collect();
+        memory
+    }
+
+    pub fn into_facts(self) -> HashMap<String, Fact> {
+        Arc::unwrap_or_clone(self.facts)
+            .into_iter()
+            .map(|(name, fact)| (name, Arc::unwrap_or_clone(fact)))
+            .collect()
+    }
+}
+
+impl fmt::Debug for WorkingMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkingMemory")
+            .field("facts", &self.facts)
+            .field("changes", &self.changes)
+            .field("checkpoints", &self.checkpoints)
+            .field("logical_support", &self.logical_support)
+            .field("listeners", &self.listeners.len())
+            .field("indexes", &self.indexes)
+            .finish()
+    }
+}
+
+impl From<HashMap<String, Fact>> for WorkingMemory {
+    fn from(facts: HashMap<String, Fact>) -> Self {
+        Self {
+            facts: Arc::new(
+                facts
+                    .into_iter()
+                    .map(|(name, fact)| (name, Arc::new(fact)))
+                    .collect(),
+            ),
+            ..Self::default()
+        }
+    }
+}
+
+impl FromIterator<Fact> for WorkingMemory {
+    fn from_iter<I: IntoIterator<Item = Fact>>(iter: I) -> Self {
+        Self::from(
+            iter.into_iter()
+                .map(|fact| (fact.name.clone(), fact))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+}